@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+use specs::prelude::*;
+use rltk::{DijkstraMap, DistanceAlg, Point};
+use super::{CombatStats, Monster, Name, PlayerEntity, Position, Summoned, Viewshed};
+use crate::effects::{EffectQueue, EffectTarget, EffectType};
+use crate::gamelog::GameLog;
+use crate::map::Map;
+use crate::path_cache::PathCache;
+
+// ALLIES ---------------------------------------------------------------------
+// Gli alleati evocati (Summoned) stanno dalla parte del giocatore: attaccano il mostro più
+// vicino che vedono e, se non c'è niente da combattere, gli tornano accanto seguendo una
+// mappa di Dijkstra. Ogni turno ne consuma uno dei loro, e a zero svaniscono.
+
+/// With nothing to fight, an ally farther than this from the player walks back to them.
+const FOLLOW_DISTANCE: f32 = 2.5;
+
+pub struct AllyAI {}
+
+impl<'a> System<'a> for AllyAI {
+    type SystemData = ( ReadExpect<'a, Map>,
+                        ReadExpect<'a, PlayerEntity>,
+                        WriteExpect<'a, GameLog>,
+                        WriteExpect<'a, EffectQueue>,
+                        Entities<'a>,
+                        WriteStorage<'a, Summoned>,
+                        ReadStorage<'a, Monster>,
+                        ReadStorage<'a, Name>,
+                        ReadStorage<'a, CombatStats>,
+                        WriteStorage<'a, Position>,
+                        WriteStorage<'a, Viewshed>,
+                        WriteExpect<'a, PathCache>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (map, player, mut log, mut effects, entities, mut summoned, monsters, names, combat_stats, mut positions, mut viewsheds, mut path_cache) = data;
+
+        let player_pos = match positions.get(player.0) {
+            Some(pos) => Point::new(pos.x, pos.y),
+            None => return,
+        };
+        // the same field the monsters walk (see PathCache)
+        let player_field = &path_cache.fields(&map, map.xy_idx(player_pos.x, player_pos.y)).walking;
+        // every creature blocks its tile, so allies never step onto anybody
+        let mut occupied : HashSet<usize> = (&positions, &combat_stats).join()
+            .map(|(pos, _stats)| map.xy_idx(pos.x, pos.y))
+            .collect();
+        let foes : Vec<(Entity, Point)> = (&entities, &monsters, &positions, &combat_stats).join()
+            .map(|(entity, _monster, pos, _stats)| (entity, Point::new(pos.x, pos.y)))
+            .collect();
+
+        for (entity, ally, name, stats, pos, viewshed) in
+            (&entities, &mut summoned, &names, &combat_stats, &mut positions, (&mut viewsheds).maybe()).join() {
+            ally.turns_left -= 1;
+            if ally.turns_left <= 0 {
+                log.push(format!("The {} fades away.", name.name));
+                occupied.remove(&map.xy_idx(pos.x, pos.y));
+                entities.delete(entity).expect("Unable to delete");
+                continue;
+            }
+
+            let here = Point::new(pos.x, pos.y);
+            let idx = map.xy_idx(pos.x, pos.y);
+            let foe = foes.iter()
+                .filter(|(_foe, at)| viewshed.as_ref().is_some_and(|viewshed| viewshed.visible_tiles.contains(at)))
+                .map(|(foe, at)| (*foe, *at, DistanceAlg::Pythagoras.distance2d(here, *at)))
+                .min_by(|a, b| a.2.total_cmp(&b.2).then(a.0.id().cmp(&b.0.id())));
+
+            let destination = if let Some((foe, at, _distance)) = foe {
+                if crate::combat::within_reach(&map, |p| occupied.contains(&map.xy_idx(p.x, p.y)), here, at, 1) {
+                    if let Some(foe_stats) = combat_stats.get(foe) {
+                        let damage = crate::combat::damage(stats, foe_stats);
+                        effects.push(Some(entity), EffectType::Damage { amount: damage }, EffectTarget::Single(foe));
+                        let foe_name = names.get(foe).map_or("something", |name| name.name.as_str());
+                        log.push(format!("The {} hits the {} for {} hp.", name.name, foe_name, damage));
+                    }
+                    None
+                } else {
+                    let path = rltk::a_star_search(idx, map.xy_idx(at.x, at.y), &*map);
+                    if path.success && path.steps.len() >= 2 { Some(path.steps[1]) } else { None }
+                }
+            } else if DistanceAlg::Pythagoras.distance2d(here, player_pos) > FOLLOW_DISTANCE {
+                DijkstraMap::find_lowest_exit(player_field, idx, &*map)
+            } else {
+                None
+            };
+
+            if let Some(destination) = destination.filter(|destination| !occupied.contains(destination)) {
+                occupied.remove(&idx);
+                occupied.insert(destination);
+                pos.x = destination as i32 % map.width;
+                pos.y = destination as i32 / map.width;
+                if let Some(viewshed) = viewshed {
+                    viewshed.dirty = true;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use specs::prelude::*;
+use rltk::RGB;
+use super::{Animation, CombatStats, Player, Renderable};
+
+/// Makes the player's glyph pulse red while hp is at or below a quarter of the maximum,
+/// and removes the pulse once healed.
+pub struct LowHpAnimationSystem {}
+
+impl<'a> System<'a> for LowHpAnimationSystem {
+    type SystemData = ( Entities<'a>,
+                        ReadStorage<'a, Player>,
+                        ReadStorage<'a, CombatStats>,
+                        ReadStorage<'a, Renderable>,
+                        WriteStorage<'a, Animation>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, players, combat_stats, renderables, mut animations) = data;
+
+        for (entity, _player, stats, render) in (&entities, &players, &combat_stats, &renderables).join() {
+            let low_hp = stats.hp * 4 <= stats.max_hp;
+            let pulsing = animations.get(entity).is_some();
+            if low_hp && !pulsing {
+                let frames = vec![(render.glyph, render.fg), (render.glyph, RGB::named(rltk::RED))];
+                animations.insert(entity, Animation { frames, period_ms: 400.0 }).expect("Unable to insert animation");
+            } else if !low_hp && pulsing {
+                animations.remove(entity);
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use rltk::FontCharType;
+use specs::prelude::*;
+use crate::map::StatusEffect;
+
+// AUDIO ----------------------------------------------------------------------
+// Il gioco non suona niente da solo: annuncia gli eventi sonori a un AudioSink,
+// e chi integra il gioco ci collega il proprio backend (rodio o altro) implementando un solo trait.
+// Di default la risorsa Audio contiene un sink muto.
+
+/// Something that happened and could make a sound.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoundEvent {
+    DoorOpen,
+    DoorSmash,
+    Hit { amount: i32 },
+    Descend,
+    HazardStep { glyph: FontCharType, status: Option<StatusEffect> }, // the kind of tile (its glyph) and what it does, e.g. Burning for lava
+}
+
+/// The audio backend.
+pub trait AudioSink: Send + Sync {
+    fn play(&mut self, event: SoundEvent);
+}
+
+/// A sink that ignores everything.
+pub struct NoAudio;
+
+impl AudioSink for NoAudio {
+    fn play(&mut self, _event: SoundEvent) {}
+}
+
+/// The sink the game talks to (risorsa).
+pub struct Audio {
+    pub sink: Box<dyn AudioSink>,
+}
+
+impl Default for Audio {
+    fn default() -> Audio {
+        Audio { sink: Box::new(NoAudio) }
+    }
+}
+
+/// Sends `event` to the sink, if the world has one.
+pub fn play(ecs: &World, event: SoundEvent) {
+    if let Some(mut audio) = ecs.try_fetch_mut::<Audio>() {
+        audio.sink.play(event);
+    }
+}
@@ -0,0 +1,115 @@
+use specs::prelude::*;
+use crate::components::{DeployableTrap, InBackpack, Name, Position, ThrowableLight};
+use crate::gamelog::GameLog;
+
+// BACKPACK -------------------------------------------------------------------
+// Quello che il giocatore porta nello zaino (vedi InBackpack), diviso per categoria.
+// Da qui si svuota lo zaino a terra in un colpo solo: tutta una categoria, o tutto quanto,
+// sulla casella dove si trova chi lo porta.
+
+/// The kinds of things a backpack holds, in the order the drop menu lists them.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ItemCategory {
+    Traps,
+    Lightsticks,
+    Other,
+}
+
+impl ItemCategory {
+    pub const ALL : [ItemCategory; 3] = [ItemCategory::Traps, ItemCategory::Lightsticks, ItemCategory::Other];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemCategory::Traps => "Traps",
+            ItemCategory::Lightsticks => "Lightsticks",
+            ItemCategory::Other => "Other",
+        }
+    }
+}
+
+/// Everything in `owner`'s backpack with its category, in a stable order (by entity id).
+pub fn carried(ecs: &World, owner: Entity) -> Vec<(Entity, ItemCategory)> {
+    let entities = ecs.entities();
+    let backpacks = ecs.read_storage::<InBackpack>();
+    let traps = ecs.read_storage::<DeployableTrap>();
+    let lights = ecs.read_storage::<ThrowableLight>();
+    let mut items : Vec<(Entity, ItemCategory)> = (&entities, &backpacks).join()
+        .filter(|(_entity, backpack)| backpack.owner == owner)
+        .map(|(entity, _backpack)| {
+            let category = if traps.contains(entity) {
+                ItemCategory::Traps
+            } else if lights.contains(entity) {
+                ItemCategory::Lightsticks
+            } else {
+                ItemCategory::Other
+            };
+            (entity, category)
+        })
+        .collect();
+    items.sort_by_key(|(entity, _category)| entity.id());
+    items
+}
+
+/// Drops on `owner`'s tile everything of `category` in their backpack (everything at all with None),
+/// all the items piling up on the same tile. Returns how many were dropped.
+pub fn drop_all(ecs: &mut World, owner: Entity, category: Option<ItemCategory>) -> usize {
+    let (x, y) = match ecs.read_storage::<Position>().get(owner) {
+        Some(pos) => (pos.x, pos.y),
+        None => return 0,
+    };
+    let dropped : Vec<Entity> = carried(ecs, owner).into_iter()
+        .filter(|(_entity, kind)| category.is_none_or(|wanted| wanted == *kind))
+        .map(|(entity, _kind)| entity)
+        .collect();
+    {
+        let mut backpacks = ecs.write_storage::<InBackpack>();
+        let mut positions = ecs.write_storage::<Position>();
+        for item in dropped.iter() {
+            backpacks.remove(*item);
+            positions.insert(*item, Position { x, y }).expect("Unable to insert position");
+        }
+    }
+    let message = match dropped.as_slice() {
+        [] => return 0,
+        [item] => format!("You drop the {}.", ecs.read_storage::<Name>().get(*item).map_or("item", |name| name.name.as_str())),
+        items => format!("You drop {} items.", items.len()),
+    };
+    ecs.write_resource::<GameLog>().push(message);
+    dropped.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::register_components;
+
+    #[test]
+    fn dropping_a_category_leaves_the_rest_in_the_pack() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 100 });
+
+        let player = ecs.create_entity().with(Position { x: 4, y: 7 }).build();
+        let mut pack = |trap: bool| {
+            let builder = ecs.create_entity().with(InBackpack { owner: player });
+            if trap {
+                builder.with(DeployableTrap { damage: 6 }).build()
+            } else {
+                builder.with(ThrowableLight { radius: 5, turns: 30 }).build()
+            }
+        };
+        let traps = [pack(true), pack(true)];
+        let stick = pack(false);
+
+        assert_eq!(drop_all(&mut ecs, player, Some(ItemCategory::Traps)), 2);
+        let positions = ecs.read_storage::<Position>();
+        assert!(traps.iter().all(|trap| positions.get(*trap).is_some_and(|pos| (pos.x, pos.y) == (4, 7))));
+        assert!(positions.get(stick).is_none());
+        drop(positions);
+        assert_eq!(carried(&ecs, player), vec![(stick, ItemCategory::Lightsticks)]);
+
+        assert_eq!(drop_all(&mut ecs, player, None), 1);
+        assert!(carried(&ecs, player).is_empty());
+        assert_eq!(drop_all(&mut ecs, player, None), 0);
+    }
+}
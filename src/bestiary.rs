@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+use specs::prelude::*;
+use rltk::{FontCharType, RGB};
+use super::{CombatStats, Monster, Name, PlayerEntity, Position, Renderable, Viewshed};
+
+/// A single entry of the bestiary: what the player knows about a monster type.
+/// Stats stay `None` ("???" on screen) until the player kills one of them.
+pub struct BestiaryEntry {
+    pub glyph: FontCharType,
+    pub fg: RGB,
+    pub stats: Option<CombatStats>,
+    pub kills: i32,
+}
+
+/// The "known monsters" resource. Entries are keyed by monster name, so a
+/// BTreeMap gives us a stable alphabetical order for the menu for free.
+#[derive(Default)]
+pub struct Bestiary {
+    pub entries: BTreeMap<String, BestiaryEntry>,
+    pub scroll: usize, // prima riga visibile nel menu scrollabile
+}
+
+impl Bestiary {
+    /// Registers a monster type the first time the player sees it.
+    pub fn record_sighting(&mut self, name: &str, glyph: FontCharType, fg: RGB) {
+        self.entries.entry(name.to_string()).or_insert(BestiaryEntry {
+            glyph,
+            fg,
+            stats: None,
+            kills: 0,
+        });
+    }
+
+    /// Records a kill, unlocking the stats of that monster type.
+    pub fn record_kill(&mut self, name: &str, glyph: FontCharType, fg: RGB, stats: &CombatStats) {
+        self.record_sighting(name, glyph, fg);
+        if let Some(entry) = self.entries.get_mut(name) {
+            entry.stats = Some(*stats);
+            entry.kills += 1;
+        }
+    }
+}
+
+/// Adds to the bestiary every monster that is currently inside the player's viewshed.
+pub struct BestiarySystem {}
+
+impl<'a> System<'a> for BestiarySystem {
+    type SystemData = ( WriteExpect<'a, Bestiary>,
+                        ReadStorage<'a, Viewshed>,
+                        ReadExpect<'a, PlayerEntity>,
+                        ReadStorage<'a, Monster>,
+                        ReadStorage<'a, Name>,
+                        ReadStorage<'a, Position>,
+                        ReadStorage<'a, Renderable>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut bestiary, viewsheds, player, monsters, names, positions, renderables) = data;
+
+        if let Some(viewshed) = viewsheds.get(player.0) {
+            for (_monster, name, pos, render) in (&monsters, &names, &positions, &renderables).join() {
+                if viewshed.visible_tiles.iter().any(|p| p.x == pos.x && p.y == pos.y) {
+                    bestiary.record_sighting(&name.name, render.glyph, render.fg);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use specs::prelude::*;
+use crate::components::{DoorTier, Faction};
+use crate::map::{DoorState, Tile};
+use crate::player::ActionCost;
+
+// BUMP -----------------------------------------------------------------------
+// Cosa succede quando qualcuno prova a entrare in una casella: ci cammina, attacca chi c'è,
+// apre la porta, scambia il posto con un compagno... Giocatore e mostri passano tutti da
+// resolve_bump, che non tocca il mondo: chi chiama raccoglie cosa c'è nella casella,
+// e poi esegue l'azione che ne esce.
+
+/// Who is bumping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bumper {
+    Player,
+    Monster { doors : Option<DoorTier>, faction : Option<Faction> },
+}
+
+/// What stands on the tile being bumped.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Occupant {
+    PartyMember,
+    Monster { faction : Option<Faction> },
+    Barricade,
+    Interactable, // levers, talking NPCs, ...: nothing in the game is one yet
+}
+
+/// What a bump turns into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BumpAction {
+    Move,
+    Attack(Entity),
+    OpenDoor(usize),
+    Interact(Entity),
+    SwapPlaces(Entity),
+    Blocked,
+}
+
+impl BumpAction {
+    /// Everything a bump can turn into takes the turn, except running into a wall.
+    pub fn cost(&self) -> ActionCost {
+        match self {
+            BumpAction::Blocked => ActionCost::Free,
+            _ => ActionCost::Turn,
+        }
+    }
+}
+
+/// Decides what `actor` does by stepping into tile `target_idx`, given the tile and whoever is
+/// standing there. Creatures come first: the player attacks monsters and barricades and swaps
+/// places with the rest of the party; a monster attacks the party and the monsters of hostile
+/// factions and otherwise waits. Then doors: the player opens (or bashes) closed and locked
+/// ones; a monster without a DoorTier can't get through any door, a smart one opens the closed
+/// ones and a strong one also goes at the locked ones (breaking them takes a few blows).
+/// Finally the tile itself: walkable means Move, anything else Blocked.
+pub fn resolve_bump(target_idx: usize, target_tile: &Tile, target_entity: Option<(Entity, Occupant)>, actor: Bumper) -> BumpAction {
+    if let Some((entity, occupant)) = target_entity {
+        return match (actor, occupant) {
+            (Bumper::Player, Occupant::Monster { .. } | Occupant::Barricade) => BumpAction::Attack(entity),
+            (Bumper::Player, Occupant::PartyMember) => BumpAction::SwapPlaces(entity),
+            (Bumper::Player, Occupant::Interactable) => BumpAction::Interact(entity),
+            (Bumper::Monster { .. }, Occupant::PartyMember) => BumpAction::Attack(entity),
+            (Bumper::Monster { faction: Some(mine), .. }, Occupant::Monster { faction: Some(theirs) }) if mine.hostile_to(theirs) => BumpAction::Attack(entity),
+            (Bumper::Monster { .. }, _) => BumpAction::Blocked,
+        };
+    }
+
+    match (actor, target_tile.door_state) {
+        (Bumper::Player, Some(DoorState::Closed | DoorState::Locked)) => return BumpAction::OpenDoor(target_idx),
+        (Bumper::Monster { doors: Some(_), .. }, Some(DoorState::Closed)) => return BumpAction::OpenDoor(target_idx),
+        (Bumper::Monster { doors: Some(DoorTier::Breaks), .. }, Some(DoorState::Locked)) => return BumpAction::OpenDoor(target_idx),
+        _ => {}
+    }
+
+    if target_tile.walkable { BumpAction::Move } else { BumpAction::Blocked }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Biome, Building};
+
+    fn door(state: DoorState) -> Tile {
+        Tile { door_state: Some(state), walkable: state == DoorState::Open, ..Building.get_floor() }
+    }
+
+    #[test]
+    fn the_player_attacks_monsters_and_swaps_with_the_party() {
+        let mut ecs = World::new();
+        let someone = ecs.create_entity().build();
+        let floor = Building.get_floor();
+
+        assert_eq!(resolve_bump(7, &floor, None, Bumper::Player), BumpAction::Move);
+        assert_eq!(resolve_bump(7, &Building.get_wall(), None, Bumper::Player), BumpAction::Blocked);
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::Monster { faction: None })), Bumper::Player), BumpAction::Attack(someone));
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::Barricade)), Bumper::Player), BumpAction::Attack(someone));
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::PartyMember)), Bumper::Player), BumpAction::SwapPlaces(someone));
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::Interactable)), Bumper::Player), BumpAction::Interact(someone));
+        assert_eq!(resolve_bump(7, &door(DoorState::Locked), None, Bumper::Player), BumpAction::OpenDoor(7));
+        // someone standing in an open doorway gets hit, the door is already open
+        assert_eq!(resolve_bump(7, &door(DoorState::Open), Some((someone, Occupant::Monster { faction: None })), Bumper::Player), BumpAction::Attack(someone));
+    }
+
+    #[test]
+    fn monsters_only_fight_the_party_and_rival_factions() {
+        let mut ecs = World::new();
+        let someone = ecs.create_entity().build();
+        let floor = Building.get_floor();
+        let orc = Bumper::Monster { doors: Some(DoorTier::Opens), faction: Some(Faction::Greenskins) };
+        let rat = Bumper::Monster { doors: None, faction: None };
+        let ogre = Bumper::Monster { doors: Some(DoorTier::Breaks), faction: Some(Faction::Greenskins) };
+
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::PartyMember)), rat), BumpAction::Attack(someone));
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::Monster { faction: Some(Faction::Undead) })), orc), BumpAction::Attack(someone));
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::Monster { faction: Some(Faction::Greenskins) })), orc), BumpAction::Blocked);
+        assert_eq!(resolve_bump(7, &floor, Some((someone, Occupant::Monster { faction: Some(Faction::Undead) })), rat), BumpAction::Blocked);
+        assert_eq!(resolve_bump(7, &door(DoorState::Closed), None, orc), BumpAction::OpenDoor(7));
+        assert_eq!(resolve_bump(7, &door(DoorState::Closed), None, rat), BumpAction::Blocked);
+        assert_eq!(resolve_bump(7, &door(DoorState::Locked), None, orc), BumpAction::Blocked);
+        assert_eq!(resolve_bump(7, &door(DoorState::Locked), None, ogre), BumpAction::OpenDoor(7));
+    }
+
+    #[test]
+    fn only_bumping_into_a_wall_is_free() {
+        let mut ecs = World::new();
+        let someone = ecs.create_entity().build();
+        assert_eq!(BumpAction::Attack(someone).cost(), ActionCost::Turn);
+        assert_eq!(BumpAction::OpenDoor(7).cost(), ActionCost::Turn);
+        assert_eq!(BumpAction::Move.cost(), ActionCost::Turn);
+        assert_eq!(BumpAction::Blocked.cost(), ActionCost::Free);
+    }
+}
@@ -0,0 +1,82 @@
+use specs::prelude::*;
+use crate::gamelog::GameLog;
+use crate::spawner;
+
+// CLOCK ----------------------------------------------------------------------
+// Contatore globale dei turni del giocatore, con una coda di eventi programmati
+// ("al turno N fai questo"): per ora i rinforzi che un allarme chiama qualche turno dopo
+// (vedi trap_system::spring_trap), con il messaggio che li annuncia.
+
+/// Something that will happen at a given turn.
+#[derive(Debug, Clone)]
+pub enum ScheduledEvent {
+    SpawnMonster { x: i32, y: i32 },
+    Message(String),
+}
+
+/// Turns the player has played so far, and the events waiting for their turn.
+#[derive(Default)]
+pub struct Clock {
+    pub turn: u64,
+    scheduled: Vec<(u64, ScheduledEvent)>,
+}
+
+impl Clock {
+    /// Queues `event` to fire at the end of turn `turn` (right away if that turn is already past).
+    pub fn schedule(&mut self, turn: u64, event: ScheduledEvent) {
+        self.scheduled.push((turn, event));
+    }
+
+    /// Removes and returns the events due by the current turn, in the order they were scheduled.
+    fn take_due(&mut self) -> Vec<ScheduledEvent> {
+        let turn = self.turn;
+        let (due, later) : (Vec<_>, Vec<_>) = self.scheduled.drain(..).partition(|(at, _event)| *at <= turn);
+        self.scheduled = later;
+        due.into_iter().map(|(_at, event)| event).collect()
+    }
+}
+
+/// Ends the player's turn: advances the clock and fires the events that are now due.
+pub fn advance_turn(ecs: &mut World) {
+    let due = {
+        let mut clock = ecs.write_resource::<Clock>();
+        clock.turn += 1;
+        clock.take_due()
+    };
+
+    for event in due {
+        match event {
+            ScheduledEvent::SpawnMonster { x, y } => {
+                if let Some((x, y)) = spawner::find_valid_spawn(ecs, x, y, 2) {
+                    spawner::random_monster(ecs, x, y);
+                }
+            }
+            ScheduledEvent::Message(message) => {
+                ecs.write_resource::<GameLog>().push(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_scheduled_event_fires_on_its_turn() {
+        let mut ecs = World::new();
+        ecs.insert(Clock::default());
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 100 });
+        ecs.write_resource::<Clock>().schedule(3, ScheduledEvent::Message("The portcullis rises.".to_string()));
+
+        advance_turn(&mut ecs);
+        advance_turn(&mut ecs);
+        assert!(ecs.fetch::<GameLog>().entries.is_empty(), "fired before turn 3");
+
+        advance_turn(&mut ecs);
+        assert_eq!(ecs.fetch::<GameLog>().entries.iter().cloned().collect::<Vec<_>>(), vec!["The portcullis rises.".to_string()]);
+
+        advance_turn(&mut ecs);
+        assert_eq!(ecs.fetch::<GameLog>().entries.len(), 1, "fired twice");
+    }
+}
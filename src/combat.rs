@@ -0,0 +1,239 @@
+use rltk::{LineAlg, Point, RGB};
+use specs::prelude::*;
+use super::{Barricade, CombatStats, Position, Stance};
+use crate::map::{Map, Tile};
+
+// COMBAT ---------------------------------------------------------------------
+// Le regole del combattimento in un posto solo, così gli attacchi veri
+// e le previsioni mostrate nella GUI non possono andare fuori sincrono.
+
+/// Damage of one hit: the attacker's power minus the defender's defense, never negative.
+pub fn damage(attacker: &CombatStats, defender: &CombatStats) -> i32 {
+    (attacker.power - defender.defense).max(0)
+}
+
+/// How much power an aggressive stance trades for defense (and a defensive one the other way round).
+pub const STANCE_SHIFT : i32 = 2;
+
+/// `stats` as they count in a fight with `stance`: aggressive moves STANCE_SHIFT points from
+/// defense to power, defensive from power to defense, balanced (or no stance) changes nothing.
+/// Neither goes below 0.
+pub fn with_stance(stats: &CombatStats, stance: Option<&Stance>) -> CombatStats {
+    let shift = match stance {
+        Some(Stance::Aggressive) => STANCE_SHIFT,
+        Some(Stance::Defensive) => -STANCE_SHIFT,
+        Some(Stance::Balanced) | None => 0,
+    };
+    CombatStats { power: (stats.power + shift).max(0), defense: (stats.defense - shift).max(0), ..*stats }
+}
+
+/// Hits `attacker` needs to take `defender` from its current hp to 0, None if it can't hurt it at all.
+pub fn hits_to_kill(attacker: &CombatStats, defender: &CombatStats) -> Option<i32> {
+    let damage = damage(attacker, defender);
+    if damage == 0 {
+        return None;
+    }
+    Some((defender.hp.max(1) + damage - 1) / damage)
+}
+
+/// How dangerous a monster is for the player as they are now.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreatRating {
+    Easy,   // goes down well before it can kill you
+    Tough,  // you win, but it takes more than half of what you can take
+    Deadly, // it kills you first (or together with you)
+}
+
+impl ThreatRating {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThreatRating::Easy => "Easy",
+            ThreatRating::Tough => "Tough",
+            ThreatRating::Deadly => "Deadly",
+        }
+    }
+
+    pub fn color(&self) -> RGB {
+        match self {
+            ThreatRating::Easy => RGB::named(rltk::GREEN),
+            ThreatRating::Tough => RGB::named(rltk::YELLOW),
+            ThreatRating::Deadly => RGB::named(rltk::RED),
+        }
+    }
+}
+
+/// Rates `monster` against `player` by trading blows: how many hits each one needs to finish
+/// the other, with the current hp of both. What can't hurt the player is Easy, what the player
+/// can't hurt (and can hurt back) is Deadly.
+pub fn threat_rating(player: &CombatStats, monster: &CombatStats) -> ThreatRating {
+    let to_kill_it = hits_to_kill(player, monster);
+    let to_kill_you = match hits_to_kill(monster, player) {
+        Some(hits) => hits,
+        None => return ThreatRating::Easy,
+    };
+    match to_kill_it {
+        None => ThreatRating::Deadly,
+        Some(hits) if to_kill_you <= hits => ThreatRating::Deadly,
+        Some(hits) if to_kill_you <= hits * 2 => ThreatRating::Tough,
+        Some(_) => ThreatRating::Easy,
+    }
+}
+
+/// True if a melee attack with reach `range` from `from` can hit `to`: the two must be on one
+/// straight line (only orthogonal ones on an orthogonal_only map), no more than `range` steps
+/// apart, with walkable tiles and nobody (`blocked`) in between. With range 1 this is plain adjacency.
+pub fn within_reach<F>(map: &Map, blocked: F, from: Point, to: Point, range: i32) -> bool
+    where F: Fn(Point) -> bool
+{
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    let distance = dx.abs().max(dy.abs());
+    let straight = dx == 0 || dy == 0 || (!map.orthogonal_only && dx.abs() == dy.abs());
+    if distance == 0 || distance > range || !straight {
+        return false;
+    }
+    (1..distance).all(|step| {
+        let p = Point::new(from.x + dx.signum() * step, from.y + dy.signum() * step);
+        map.tiles[map.xy_idx(p.x, p.y)].walkable && !blocked(p)
+    })
+}
+
+/// Chance (in %) that a ranged attack from `from` hits a target at `to`. Shots always hit,
+/// unless the last tile the shot crosses before the target provides cover.
+pub fn ranged_hit_chance(map: &Map, from: Point, to: Point) -> i32 {
+    const COVER_HIT_CHANCE: i32 = 50;
+
+    let line = rltk::line2d(LineAlg::Bresenham, from, to);
+    let before_target = line.iter().rev().find(|p| **p != to && **p != from);
+    match before_target {
+        Some(p) if map.tiles[map.xy_idx(p.x, p.y)].provides_cover => COVER_HIT_CHANCE,
+        _ => 100,
+    }
+}
+
+/// How many tiles closer a sleeping monster has to be to notice someone hugging cover.
+pub const COVER_STEALTH_BONUS : i32 = 2;
+
+/// True if one of the eight tiles around `at` provides cover: whoever stands there is
+/// harder to notice (see COVER_STEALTH_BONUS).
+pub fn hugs_cover(map: &Map, at: Point) -> bool {
+    (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+        .filter(|delta| *delta != (0, 0))
+        .map(|(dx, dy)| (at.x + dx, at.y + dy))
+        .any(|(x, y)| x >= 0 && y >= 0 && x < map.width && y < map.height && map.tiles[map.xy_idx(x, y)].provides_cover)
+}
+
+/// What a shot from `from` to `to` runs into first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShotResult {
+    Clear,                      // nothing in the way: the shot reaches `to`
+    BlockedByTile(Point),       // a wall, a closed door or a pane of glass stops it
+    Intercepted(Entity, Point), // a creature stands in the line of fire and takes the shot
+    Barricaded(Entity, Point),  // a barricade stops the shot, unharmed: only melee breaks it
+}
+
+/// True if a projectile can't fly through the tile. Glass lets the eye through, not the arrow.
+fn stops_projectiles(tile: &Tile) -> bool {
+    !tile.transparent || (!tile.walkable && tile.provides_cover)
+}
+
+/// Walks the line of fire from `from` to `to` (both ends excluded) and reports the first blocker,
+/// tile, barricade or creature. `creatures` are the tiles occupied by something that can be hit,
+/// `barricades` the ones holding a barricade.
+pub fn has_clear_shot(map: &Map, creatures: &[(Entity, Point)], barricades: &[(Entity, Point)], from: Point, to: Point) -> ShotResult {
+    for p in rltk::line2d(LineAlg::Bresenham, from, to).iter().filter(|p| **p != from && **p != to) {
+        if stops_projectiles(&map.tiles[map.xy_idx(p.x, p.y)]) {
+            return ShotResult::BlockedByTile(*p);
+        }
+        if let Some((entity, _pos)) = barricades.iter().find(|(_entity, pos)| pos == p) {
+            return ShotResult::Barricaded(*entity, *p);
+        }
+        if let Some((entity, _pos)) = creatures.iter().find(|(_entity, pos)| pos == p) {
+            return ShotResult::Intercepted(*entity, *p);
+        }
+    }
+    ShotResult::Clear
+}
+
+/// Every creature (anything with combat stats that isn't a barricade) and where it stands, to feed `has_clear_shot`.
+pub fn creature_positions(ecs: &World) -> Vec<(Entity, Point)> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let barricades = ecs.read_storage::<Barricade>();
+    (&entities, &positions, &combat_stats, !&barricades).join()
+        .map(|(entity, pos, _stats, _not_barricade)| (entity, Point::new(pos.x, pos.y)))
+        .collect()
+}
+
+/// Every barricade and where it stands, the other half of what `has_clear_shot` needs.
+pub fn barricade_positions(ecs: &World) -> Vec<(Entity, Point)> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let barricades = ecs.read_storage::<Barricade>();
+    (&entities, &positions, &barricades).join()
+        .map(|(entity, pos, _barricade)| (entity, Point::new(pos.x, pos.y)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Biome, Building, xy_idx};
+
+    #[test]
+    fn a_spear_reaches_two_tiles_down_a_clear_line() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        map.tiles[xy_idx(11, 12, width)] = Building.get_wall();
+        let nobody = |_p: Point| false;
+        let from = Point::new(10, 10);
+
+        assert!(within_reach(&map, nobody, from, Point::new(12, 10), 2));
+        assert!(within_reach(&map, nobody, from, Point::new(12, 12), 2));
+        assert!(!within_reach(&map, nobody, from, Point::new(12, 10), 1));
+        assert!(!within_reach(&map, nobody, from, Point::new(12, 11), 2)); // not on a straight line
+        assert!(!within_reach(&map, nobody, from, Point::new(10, 13), 2)); // too far
+        assert!(!within_reach(&map, |p| p == Point::new(11, 10), from, Point::new(12, 10), 2)); // someone in between
+        assert!(!within_reach(&map, nobody, Point::new(11, 11), Point::new(11, 13), 2)); // a wall in between
+    }
+
+    #[test]
+    fn standing_next_to_a_wall_is_hugging_cover() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        map.tiles[xy_idx(11, 11, width)] = Building.get_wall();
+
+        assert!(hugs_cover(&map, Point::new(10, 10)));
+        assert!(hugs_cover(&map, Point::new(12, 11)));
+        assert!(!hugs_cover(&map, Point::new(14, 14)));
+        assert!(!hugs_cover(&map, Point::new(0, 0))); // the map edge isn't cover
+    }
+
+    #[test]
+    fn a_defensive_stance_hurts_less_and_takes_less() {
+        let player = CombatStats { max_hp: 30, hp: 30, defense: 2, power: 5 };
+        let orc = CombatStats { max_hp: 16, hp: 16, defense: 1, power: 7 };
+        let defensive = with_stance(&player, Some(&Stance::Defensive));
+        let aggressive = with_stance(&player, Some(&Stance::Aggressive));
+
+        assert_eq!(with_stance(&player, Some(&Stance::Balanced)), player);
+        assert_eq!(damage(&orc, &defensive), 3);
+        assert_eq!(damage(&defensive, &orc), 2);
+        assert_eq!(damage(&orc, &aggressive), 7);
+        assert_eq!(damage(&aggressive, &orc), 6);
+    }
+
+    #[test]
+    fn the_threat_depends_on_who_falls_first() {
+        let player = CombatStats { max_hp: 30, hp: 30, defense: 2, power: 5 };
+        let rat = CombatStats { max_hp: 4, hp: 4, defense: 0, power: 3 };
+        let orc = CombatStats { max_hp: 16, hp: 16, defense: 1, power: 7 };
+        let ogre = CombatStats { max_hp: 24, hp: 24, defense: 2, power: 12 };
+
+        assert_eq!(threat_rating(&player, &rat), ThreatRating::Easy);   // 1 hit against 30
+        assert_eq!(threat_rating(&player, &orc), ThreatRating::Tough);  // 4 hits against 6
+        assert_eq!(threat_rating(&player, &ogre), ThreatRating::Deadly); // 8 hits against 3
+        // the same orc is deadly for a player on their last legs
+        assert_eq!(threat_rating(&CombatStats { hp: 5, ..player }, &orc), ThreatRating::Deadly);
+    }
+}
@@ -1,16 +1,48 @@
 use rltk::{RGB, FontCharType};
 use specs::prelude::*;
 use specs_derive::Component;
+use serde::{Deserialize, Serialize};
+use crate::map::{BiomeKind, StatusEffect};
 
 
 // COMPONENTS ----------------------------------------------------------------
 
+/// Calls the macro `$action` with the list of every component of the game. The list is written
+/// only here: the registration below and the level snapshots (see level_store.rs) are built from it,
+/// so a new component is added to this list and nowhere else.
+/// The components after the `;` hold an Entity: the snapshots save the entity they point to
+/// as an index, so they are listed apart from the others.
+macro_rules! for_each_component {
+    ($action:ident) => {
+        $action! {
+            Position, Renderable, LeftMover, Player, CanMove, CharacterSize, Viewshed, Monster, Name,
+            CombatStats, Energy, Gold, Animation, StatusEffects, Morale, DoorOpener, SmashingDoor,
+            LastSeen, Item, DropsLoot, Pickaxe, Key, Digging, AggroRange, Hunger, Food, LeavesCorpse,
+            Investigating, Perception, Hidden, SeeInvisible, GrantsSeeInvisible, Barricade, AutoPickup,
+            DeathEffect, Faction, Reach, DeployableTrap, DiggingBeam, Charges, Summoned, SummonsAlly,
+            ThrowableLight, LightSource, Immunities, Stance, Ammo, RangedWeapon, SpecialAttack, Armed,
+            Peeking;
+            InBackpack, PackMember
+        }
+    };
+}
+pub(crate) use for_each_component;
+
+/// Registers every component of the game in `ecs`. The game calls it at startup and the test
+/// worlds do the same.
+pub fn register_components(ecs: &mut World) {
+    macro_rules! register {
+        ($($component:ident),* ; $($linked:ident),*) => { $(ecs.register::<$component>();)* $(ecs.register::<$linked>();)* };
+    }
+    for_each_component!(register);
+}
+
 
 // tag component per la taglia del personaggio
 // implementiamo PartialEq e PartialOrd per permettere la comparazione tra i componenti
 // in particolare PartialOrd permette di usare il < e >, perchè li ritiene ordinati dal più piccolo al più grande
 #[allow(dead_code)]
-#[derive(Component, Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[derive(Component, Debug, PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
 pub enum CharacterSize {
     Tiny,
     Small,
@@ -18,29 +50,511 @@ pub enum CharacterSize {
     Large,
     Huge,
 }
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct Position { pub x: i32, pub y: i32 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct Renderable {
+    #[serde(with = "crate::save_system::ser_rgb")]
     pub fg: RGB,
+    #[serde(with = "crate::save_system::ser_rgb")]
     pub bg: RGB,
+    #[serde(with = "crate::save_system::ser_glyph")]
     pub glyph: FontCharType,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct LeftMover{}
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct CanMove{}
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct Player {}
 
+// risorsa con l'Entity del giocatore, per accedervi in O(1) senza join sul tag Player
+#[derive(Clone, Copy)]
+pub struct PlayerEntity(pub Entity);
+
+// forma del campo visivo: cerchio (default di rltk) o ellisse con semiassi diversi
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FovShape {
+    Circle,
+    Ellipse { radius_x : i32, radius_y : i32 }, // e.g. a wide but shallow "headlight"
+}
+
 // componente per il Field of View
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct Viewshed {
+    #[serde(skip)]
     pub visible_tiles : Vec<rltk::Point>,
     pub range : i32,
-    pub dirty : bool // Flag to indicate if the viewshed needs to be recalculated.
-}
\ No newline at end of file
+    pub dirty : bool, // Flag to indicate if the viewshed needs to be recalculated.
+    pub fov_shape : FovShape
+}
+// il giocatore sta sbirciando dietro l'angolo: vede anche da (x, y), la casella accanto,
+// finché non fa qualcos'altro
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Peeking {
+    pub x : i32,
+    pub y : i32
+}
+// tag component per i mostri
+#[derive(Component, Clone, Serialize, Deserialize)]
+pub struct Monster {}
+
+// nome leggibile dell'entità (usato dal bestiario e, in futuro, dal log)
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Name {
+    pub name : String
+}
+
+// statistiche di combattimento base
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CombatStats {
+    pub max_hp : i32,
+    pub hp : i32,
+    pub defense : i32,
+    pub power : i32
+}
+
+// percezione: si somma al d20 per scoprire le trappole nascoste (trap_dc)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Perception {
+    pub bonus : i32
+}
+
+// energia per il movimento: ogni turno ne aggiunge 1, entrare in una casella costa il suo movement_cost
+// così attraversare neve o fango richiede più turni per casella
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Energy {
+    pub current : i32
+}
+
+// quanto un mostro se la cava con le porte; chi non ha DoorOpener (le bestie, i più stupidi)
+// le tratta come muri
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DoorTier {
+    Opens,  // abbastanza sveglio da aprire le porte chiuse, non quelle a chiave
+    Breaks, // abbastanza forte da sfondare anche quelle a chiave, in qualche turno
+}
+
+// il mostro sa passare dalle porte per inseguire il giocatore (vedi DoorTier)
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct DoorOpener {
+    pub tier : DoorTier
+}
+
+// un mostro che sta sfondando una porta a chiave: quale casella e quanti colpi ha già dato
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct SmashingDoor {
+    pub idx : usize,
+    pub blows : i32
+}
+
+// il mostro dorme finché il giocatore non entra nel raggio (e in linea di vista), poi si sveglia e insegue
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AggroRange {
+    pub radius : i32,
+    pub awake : bool
+}
+
+// il mostro ha sentito un rumore e va a controllare da dove veniva (vedi noise.rs)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Investigating {
+    pub x : i32,
+    pub y : i32
+}
+
+// morale dei mostri: sotto flee_threshold (frazione dei pv) il mostro scappa invece di combattere
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Morale {
+    pub flee_threshold : f32,
+    pub fleeing : bool
+}
+
+// la chiave: nello zaino, apre una porta chiusa a chiave al posto della spallata (e resta nella serratura)
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Key {}
+
+// il piccone: permette di scavare i muri con dig_turns (per ora il giocatore lo ha fin dall'inizio)
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Pickaxe {}
+
+// scavo in corso: quale casella e quanti turni di lavoro ci sono già stati spesi
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Digging {
+    pub idx : usize,
+    pub progress : i32
+}
+
+// tag component: entità invisibile o nascosta, non si disegna e non si può mirare
+// finché il giocatore (o qualcuno del gruppo) non ha SeeInvisible
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Hidden {}
+
+// tag component: chi lo ha vede le entità Hidden
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct SeeInvisible {}
+
+// oggetto che, raccolto, dà SeeInvisible a chi lo prende
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct GrantsSeeInvisible {}
+
+// portata in mischia: si colpisce fino a `range` caselle di distanza, in linea retta e senza
+// niente in mezzo (1 = solo le caselle accanto). Su un oggetto (la lancia), chi lo raccoglie la ottiene
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Reach {
+    pub range : i32
+}
+
+// attacco speciale di un mostro, a distanza: uno sputo che ferisce (e avvelena per `poison_turns`
+// turni, 0 = niente veleno), una ragnatela che intrappola
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SpecialAttackKind {
+    Spit { damage : i32, poison_turns : i32 },
+    Web { turns : i32 },
+}
+
+// il mostro lo usa quando vede il giocatore entro `range` caselle e timer è a zero,
+// poi deve aspettare `cooldown` turni (timer scende di uno a ogni turno)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SpecialAttack {
+    pub kind : SpecialAttackKind,
+    pub range : i32,
+    pub cooldown : i32,
+    pub timer : i32
+}
+
+impl SpecialAttack {
+    /// Counts the cooldown down by one turn; true if the attack can be used now.
+    pub fn tick(&mut self) -> bool {
+        self.timer = (self.timer - 1).max(0);
+        self.timer == 0
+    }
+}
+
+// munizioni: che cosa si tira con un'arma a distanza
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AmmoKind {
+    Arrows,
+}
+
+impl AmmoKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            AmmoKind::Arrows => "arrows",
+        }
+    }
+}
+
+// una scorta di munizioni: sul giocatore è la faretra, su un oggetto è un fascio da raccogliere
+// (finisce nella faretra, se è dello stesso tipo)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Ammo {
+    pub kind : AmmoKind,
+    pub count : i32
+}
+
+// arma a distanza (tasto F): ogni tiro consuma una munizione del tipo giusto dalla faretra
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RangedWeapon {
+    pub ammo : AmmoKind
+}
+
+// bacchetta dello scavo: un raggio in linea retta che trasforma in pavimento i muri scavabili
+// per `range` caselle, una carica a colpo (vedi Charges). Su un oggetto, chi lo raccoglie ne prende le cariche
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DiggingBeam {
+    pub range : i32
+}
+
+// cariche di una bacchetta: ogni uso ne spende una, a zero la bacchetta non funziona più.
+// Se recharge_turns > 0 ne torna una ogni recharge_turns turni, fino a max (timer conta i turni)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Charges {
+    pub current : i32,
+    pub max : i32,
+    pub recharge_turns : i32,
+    pub timer : i32
+}
+
+impl Charges {
+    /// Uses up one charge. False (and nothing spent) when there is none left.
+    pub fn spend(&mut self) -> bool {
+        if self.current <= 0 {
+            return false;
+        }
+        self.current -= 1;
+        true
+    }
+
+    /// One turn of slow recharge; true when it brings a charge back.
+    pub fn tick(&mut self) -> bool {
+        if self.recharge_turns <= 0 || self.current >= self.max {
+            self.timer = 0;
+            return false;
+        }
+        self.timer += 1;
+        if self.timer < self.recharge_turns {
+            return false;
+        }
+        self.timer = 0;
+        self.current += 1;
+        true
+    }
+}
+
+// bastoncino luminoso da lanciare (tasto K): nello zaino finché non viene lanciato, poi
+// diventa una LightSource che illumina `radius` caselle per `turns` turni
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ThrowableLight {
+    pub radius : i32,
+    pub turns : i32
+}
+
+// fonte di luce ferma a terra: quello che illumina (il suo Viewshed) lo vede anche il giocatore,
+// finché non si spegne dopo turns_left turni (None: non si spegne mai, come i bracieri)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LightSource {
+    pub turns_left : Option<i32>
+}
+
+// alleato evocato: combatte i mostri per il giocatore e gli sta vicino, poi svanisce
+// quando turns_left arriva a zero (vedi ally_ai_system.rs)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Summoned {
+    pub turns_left : i32
+}
+
+// pergamena di evocazione: letta appena raccolta, chiama un alleato che resta per `turns` turni
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SummonsAlly {
+    pub turns : i32
+}
+
+// trappola da piazzare (tasto V): a terra è un oggetto da raccogliere, piazzata (Armed)
+// scatta sotto il primo mostro che ci passa sopra; il giocatore ci cammina sopra senza problemi
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DeployableTrap {
+    pub damage : i32
+}
+
+// trappola piazzata, pronta a scattare
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Armed {}
+
+// oggetto nello zaino di `owner`: non ha Position finché non viene usato
+#[derive(Component, Debug, Clone, Copy)]
+pub struct InBackpack {
+    pub owner : Entity
+}
+
+// barricata: ferma frecce e proiettili, ma si può abbattere a colpi in mischia (ha i suoi pv)
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Barricade {}
+
+// l'oggetto si raccoglie da solo camminandoci sopra (se auto_pickup è attivo in GameConfig),
+// gli altri aspettano il tasto per raccogliere
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct AutoPickup {}
+
+// membro di un branco: resta vicino al capobranco, sia quando caccia sia quando gironzola
+// (il capobranco stesso non ha il componente)
+#[derive(Component, Debug, Clone, Copy)]
+pub struct PackMember {
+    pub leader : Entity
+}
+
+// fazione di un mostro: le fazioni diverse si odiano e si azzuffano appena si vedono
+// (chi non ha il componente se la prende solo con il giocatore)
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Faction {
+    Greenskins, // orcs and goblins
+    Undead,
+    Beasts,
+    Friendly,   // allies summoned by the player (see Summoned)
+}
+
+impl Faction {
+    /// Whether a member of this faction attacks a member of `other` on sight.
+    pub fn hostile_to(self, other: Faction) -> bool {
+        self != other
+    }
+}
+
+// cosa fa un mostro quando muore (meccaniche da boss): esplode, chiama rinforzi o apre le porte vicine
+#[allow(dead_code)]
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DeathEffect {
+    Explode { radius : i32, damage : i32 }, // hurts everyone within radius, the player too
+    SpawnMinions { count : i32 },
+    OpenDoors { radius : i32 },
+}
+
+// tag component per gli oggetti a terra (pozioni, ...)
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Item {}
+
+// il mostro, morendo, può lasciare cadere un oggetto dalla tabella indicata (vedi loot.rs)
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct DropsLoot {
+    pub table : String
+}
+
+// l'animale, morendo, lascia a terra la sua carne: cibo che vale nutrition (vedi spawner::corpse)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LeavesCorpse {
+    pub nutrition : i32
+}
+
+// quanto è sazia l'entità: lo stato segue satiation (vedi hunger_system.rs)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum HungerState {
+    WellFed,
+    Normal,
+    Hungry,
+    Starving,
+}
+
+// fame del giocatore: satiation cala di 1 a turno, a zero si comincia a perdere pv
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Hunger {
+    pub satiation : i32,
+    pub state : HungerState
+}
+
+// oggetto commestibile: camminandoci sopra il giocatore lo mangia e recupera nutrition
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Food {
+    pub nutrition : i32
+}
+
+// oggetto che si raccoglie automaticamente camminandoci sopra
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Gold {
+    pub amount : i32
+}
+
+// il livello in cui si trova il giocatore (risorsa): profondità e bioma con cui è stato generato
+// resta valida per tutta la partita (descend la aggiorna), quindi i sistemi che vogliono
+// sapere "in che bioma sono?" (messaggi, luce, passi) la leggono con ReadExpect<CurrentLevel>
+// seed è il seme da cui è stata generata la mappa (None per i livelli disegnati a mano)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CurrentLevel {
+    pub depth : i32,
+    pub biome : BiomeKind,
+    pub seed : Option<u64>
+}
+
+// quanta parte del livello il gruppo ha già scoperto (risorsa), aggiornata dal VisibilitySystem
+#[derive(Default)]
+pub struct Exploration {
+    pub percent : i32
+}
+
+// se la scia di briciole (Map::walked_tiles) è visibile (risorsa), si accende e spegne col tasto I
+#[derive(Default)]
+pub struct BreadcrumbView {
+    pub shown : bool
+}
+
+// portafoglio del giocatore (risorsa), concorre al punteggio finale
+#[derive(Default)]
+pub struct Wallet {
+    pub gold : i32
+}
+
+// animazione del glifo: cicla tra i frame, ognuno dura period_ms millisecondi
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub struct Animation {
+    #[serde(with = "crate::save_system::ser_frames")]
+    pub frames : Vec<(FontCharType, RGB)>,
+    pub period_ms : f32
+}
+
+impl Animation {
+    /// The frame to show after `elapsed_ms` milliseconds, None if there are no frames.
+    pub fn frame_at(&self, elapsed_ms: f32) -> Option<(FontCharType, RGB)> {
+        if self.frames.is_empty() || self.period_ms <= 0.0 {
+            return None;
+        }
+        let index = (elapsed_ms / self.period_ms) as usize % self.frames.len();
+        Some(self.frames[index])
+    }
+}
+
+// dove il giocatore ha visto l'entità l'ultima volta (il "fantasma" disegnato nella nebbia)
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LastSeen {
+    pub x : i32,
+    pub y : i32
+}
+
+// effetti di stato attivi su un'entità, ognuno con i turni che gli restano
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatusEffects {
+    pub effects : Vec<(StatusEffect, i32)>
+}
+
+impl StatusEffects {
+    pub fn has(&self, effect: StatusEffect) -> bool {
+        self.effects.iter().any(|(active, turns)| *active == effect && *turns > 0)
+    }
+}
+
+// postura di combattimento (tasto S): aggressiva picchia più forte e para peggio, difensiva il contrario
+// (vedi combat::with_stance)
+#[derive(Component, Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Stance {
+    Aggressive,
+    #[default]
+    Balanced,
+    Defensive,
+}
+
+impl Stance {
+    /// The stance the toggle key switches to after this one.
+    pub fn next(self) -> Stance {
+        match self {
+            Stance::Balanced => Stance::Defensive,
+            Stance::Defensive => Stance::Aggressive,
+            Stance::Aggressive => Stance::Balanced,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stance::Aggressive => "aggressive",
+            Stance::Balanced => "balanced",
+            Stance::Defensive => "defensive",
+        }
+    }
+
+    /// Three letters for the HUD.
+    pub fn short(self) -> &'static str {
+        match self {
+            Stance::Aggressive => "Agg",
+            Stance::Balanced => "Bal",
+            Stance::Defensive => "Def",
+        }
+    }
+}
+
+// stati che all'entità non fanno nulla (un elementale del fuoco non brucia): l'EffectsSystem li scarta
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Immunities {
+    pub effects : Vec<StatusEffect>
+}
+
+impl Immunities {
+    pub fn covers(&self, effect: StatusEffect) -> bool {
+        self.effects.contains(&effect)
+    }
+}
+
+// orologio dei frame (risorsa), accumula ctx.frame_time_ms per le animazioni
+#[derive(Default)]
+pub struct FrameClock {
+    pub elapsed_ms : f32
+}
@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use crate::keybindings::InputProfile;
+
+// GAME CONFIG ----------------------------------------------------------------
+// Impostazioni lette all'avvio per costruire la finestra di rltk.
+// Cambiando questa struct cambiano titolo e dimensioni della console (e il layout della UI).
+
+pub struct GameConfig {
+    pub title: String,
+    pub width: i32,   // console size in tiles
+    pub height: i32,
+    pub tileset: String,      // font image, terminal8x8.png is embedded in rltk
+    pub tile_width: i32,      // pixel size of one glyph of the tileset
+    pub tile_height: i32,
+    pub auto_close_doors: bool, // doors close by themselves after the player walks through
+    pub autosave: bool,         // save every time the player changes level
+    pub save_path: String,
+    pub settings_path: String,  // where the choices of the settings menu are kept between games
+    pub log_history: usize,     // how many messages the log keeps for the history screen
+    pub orthogonal_only: bool,  // 4-directional movement: no diagonal steps for the player or the monsters
+    pub auto_pickup: bool,      // stepping on an item flagged AutoPickup (gold) picks it up, the rest needs the pickup key
+    pub telemetry_path: Option<String>, // where the balancing events are exported (JSON) when the game ends
+    pub seed: Option<u64>,      // seed of the game's random stream, None = a different game every time
+    pub reveal_all: bool,       // development: every generated map starts fully revealed (screenshots)
+    pub ascii_level: Option<String>, // a hand-drawn first level (see map_builders::map_from_ascii), None = generated
+    pub clear_to_descend: bool, // the way down stays sealed until every monster of the level is dead
+    pub low_hp_flash: bool,     // red pulsing vignette at the map edges while hp is critical (off for photosensitive players)
+    pub show_unexplored: bool,  // unrevealed tiles of the level are drawn as a dim dot, so the map edge shows (see UiLayout)
+    pub confirm: ConfirmableActions, // actions that ask "are you sure?" first, none by default
+    pub stance_change_takes_turn: bool, // switching combat stance (S) spends the turn instead of being free
+    pub debug_commands: bool,   // development: the debug keys work (F2 teleports the player to typed coordinates)
+    pub setup_path: String,     // where F4 writes the seeds, biome and builder of the current level, to share or attach to a bug report
+    pub corridor_width: i32,    // tiles across the corridors of BuilderChain::dungeon (the building levels)
+}
+
+impl Default for GameConfig {
+    fn default() -> GameConfig {
+        GameConfig {
+            title: "Roguelike Tutorial".to_string(),
+            width: 80,
+            height: 50,
+            tileset: "terminal8x8.png".to_string(),
+            tile_width: 8,
+            tile_height: 8,
+            auto_close_doors: false,
+            autosave: true,
+            save_path: "savegame.json".to_string(),
+            settings_path: "settings.txt".to_string(),
+            log_history: 500,
+            orthogonal_only: false,
+            auto_pickup: true,
+            telemetry_path: Some("telemetry.json".to_string()),
+            seed: None,
+            reveal_all: false,
+            ascii_level: None,
+            clear_to_descend: false,
+            low_hp_flash: true,
+            show_unexplored: false,
+            confirm: ConfirmableActions::default(),
+            stance_change_takes_turn: false,
+            setup_path: "level_setup.txt".to_string(),
+            debug_commands: false,
+            corridor_width: 1,
+        }
+    }
+}
+
+/// The actions that can't be taken back, which a misclick shouldn't trigger.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum ConfirmableAction {
+    Descend, // stepping into a portal, on to the next level
+    Quit,    // closing the game from the death screen
+    EnterHazard, // auto-travel about to step onto a known hazard: always asked, whatever the config says
+    LoadGame,    // a save was found at startup: resume it instead of starting over? Always asked
+}
+
+impl ConfirmableAction {
+    /// The question asked before going ahead.
+    pub fn prompt(&self) -> &'static str {
+        match self {
+            ConfirmableAction::Descend => "Descend? Y/N",
+            ConfirmableAction::Quit => "Quit the game? Y/N",
+            ConfirmableAction::EnterHazard => "The way leads through danger. Step in? Y/N",
+            ConfirmableAction::LoadGame => "Continue the saved game? Y/N",
+        }
+    }
+}
+
+/// Which of the ConfirmableAction ask for a confirmation (see RunState::Confirm).
+#[derive(Default, Clone, Debug)]
+pub struct ConfirmableActions {
+    pub actions: HashSet<ConfirmableAction>,
+}
+
+impl ConfirmableActions {
+    pub fn requires(&self, action: ConfirmableAction) -> bool {
+        self.actions.contains(&action)
+    }
+
+    pub fn set(&mut self, action: ConfirmableAction, required: bool) {
+        if required {
+            self.actions.insert(action);
+        } else {
+            self.actions.remove(&action);
+        }
+    }
+}
+
+/// The on/off switches of GameConfig that can be flipped from the settings menu while playing.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Toggle {
+    LowHpFlash,
+    ShowUnexplored,
+    OrthogonalOnly,
+    AutoPickup,
+    AutoCloseDoors,
+    ConfirmDescend,
+    ConfirmQuit,
+}
+
+impl Toggle {
+    /// Every toggle, in the order the settings menu lists them.
+    pub const ALL : [Toggle; 7] = [Toggle::LowHpFlash, Toggle::ShowUnexplored, Toggle::OrthogonalOnly, Toggle::AutoPickup, Toggle::AutoCloseDoors, Toggle::ConfirmDescend, Toggle::ConfirmQuit];
+
+    /// The key of the toggle in the settings file.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Toggle::LowHpFlash => "low_hp_flash",
+            Toggle::ShowUnexplored => "show_unexplored",
+            Toggle::OrthogonalOnly => "orthogonal_only",
+            Toggle::AutoPickup => "auto_pickup",
+            Toggle::AutoCloseDoors => "auto_close_doors",
+            Toggle::ConfirmDescend => "confirm_descend",
+            Toggle::ConfirmQuit => "confirm_quit",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Toggle::LowHpFlash => "Low hp flash",
+            Toggle::ShowUnexplored => "Show the unexplored map",
+            Toggle::OrthogonalOnly => "Orthogonal movement only",
+            Toggle::AutoPickup => "Auto pickup",
+            Toggle::AutoCloseDoors => "Doors close behind you",
+            Toggle::ConfirmDescend => "Confirm descending",
+            Toggle::ConfirmQuit => "Confirm quitting",
+        }
+    }
+
+    pub fn is_on(&self, config: &GameConfig) -> bool {
+        match self {
+            Toggle::LowHpFlash => config.low_hp_flash,
+            Toggle::ShowUnexplored => config.show_unexplored,
+            Toggle::OrthogonalOnly => config.orthogonal_only,
+            Toggle::AutoPickup => config.auto_pickup,
+            Toggle::AutoCloseDoors => config.auto_close_doors,
+            Toggle::ConfirmDescend => config.confirm.requires(ConfirmableAction::Descend),
+            Toggle::ConfirmQuit => config.confirm.requires(ConfirmableAction::Quit),
+        }
+    }
+
+    /// Switches the toggle in `config`. Returns its new state.
+    pub fn flip(&self, config: &mut GameConfig) -> bool {
+        let on = !self.is_on(config);
+        match self {
+            Toggle::LowHpFlash => config.low_hp_flash = on,
+            Toggle::ShowUnexplored => config.show_unexplored = on,
+            Toggle::OrthogonalOnly => config.orthogonal_only = on,
+            Toggle::AutoPickup => config.auto_pickup = on,
+            Toggle::AutoCloseDoors => config.auto_close_doors = on,
+            Toggle::ConfirmDescend => config.confirm.set(ConfirmableAction::Descend, on),
+            Toggle::ConfirmQuit => config.confirm.set(ConfirmableAction::Quit, on),
+        }
+        on
+    }
+}
+
+impl GameConfig {
+    /// The rltk context described by this config: one simple console covering the whole window.
+    pub fn build_context(&self) -> rltk::BResult<rltk::Rltk> {
+        rltk::RltkBuilder::new()
+            .with_title(&self.title)
+            .with_dimensions(self.width, self.height)
+            .with_tile_dimensions(self.tile_width, self.tile_height)
+            .with_font(&self.tileset, self.tile_width, self.tile_height)
+            .with_simple_console(self.width, self.height, &self.tileset)
+            .build()
+    }
+}
+
+// SETTINGS FILE --------------------------------------------------------------
+// Le scelte del menu delle impostazioni (profilo dei tasti e interruttori) vengono scritte
+// in settings_path a ogni modifica e rilette all'avvio, nello stesso formato chiave=valore
+// del salvataggio. Un file mancante o illeggibile lascia semplicemente i valori di default.
+
+/// Writes the movement `profile` and every Toggle of `config` to `config.settings_path`.
+pub fn save_settings(config: &GameConfig, profile: InputProfile) -> io::Result<()> {
+    let mut lines = vec![format!("keybindings={}", profile.name())];
+    for toggle in Toggle::ALL {
+        lines.push(format!("{}={}", toggle.key(), toggle.is_on(config)));
+    }
+    fs::write(&config.settings_path, lines.join("\n") + "\n")
+}
+
+/// Applies the toggles saved in `config.settings_path` to `config` and returns the saved
+/// movement profile. Missing keys and values that don't parse keep their defaults.
+pub fn load_settings(config: &mut GameConfig) -> Option<InputProfile> {
+    let text = fs::read_to_string(&config.settings_path).ok()?;
+    let value = |key: &str| text.lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(k, _v)| *k == key)
+        .map(|(_k, v)| v.trim().to_string());
+    for toggle in Toggle::ALL {
+        let saved = value(toggle.key()).and_then(|v| v.parse::<bool>().ok());
+        if saved.is_some_and(|on| toggle.is_on(config) != on) {
+            toggle.flip(config);
+        }
+    }
+    value("keybindings").and_then(|name| InputProfile::ALL.into_iter().find(|profile| profile.name() == name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_at(file: &str) -> GameConfig {
+        let path = std::env::temp_dir().join(file);
+        GameConfig { settings_path: path.to_str().unwrap().to_string(), ..GameConfig::default() }
+    }
+
+    #[test]
+    fn saved_settings_come_back_at_the_next_start() {
+        let mut config = config_at("rogue_settings_test.txt");
+        Toggle::LowHpFlash.flip(&mut config);
+        Toggle::ConfirmQuit.flip(&mut config);
+        save_settings(&config, InputProfile::ViKeys).unwrap();
+
+        let mut restarted = config_at("rogue_settings_test.txt");
+        let profile = load_settings(&mut restarted);
+        fs::remove_file(&config.settings_path).ok();
+
+        assert_eq!(profile, Some(InputProfile::ViKeys));
+        assert!(!restarted.low_hp_flash);
+        assert!(restarted.confirm.requires(ConfirmableAction::Quit));
+        assert!(restarted.auto_pickup);
+    }
+
+    #[test]
+    fn a_missing_or_garbled_file_keeps_the_defaults() {
+        let mut config = config_at("rogue_settings_missing_test.txt");
+        assert_eq!(load_settings(&mut config), None);
+
+        let mut garbled = config_at("rogue_settings_garbled_test.txt");
+        fs::write(&garbled.settings_path, "keybindings=Dvorak\nlow_hp_flash=maybe\n%%%\n").unwrap();
+        let profile = load_settings(&mut garbled);
+        fs::remove_file(&garbled.settings_path).ok();
+
+        assert_eq!(profile, None);
+        assert!(garbled.low_hp_flash);
+    }
+}
@@ -0,0 +1,322 @@
+use specs::prelude::*;
+use super::{Barricade, CombatStats, DeathEffect, DropsLoot, LeavesCorpse, Monster, Name, PackMember, PlayerEntity, Position, Renderable, RunState, Summoned, Viewshed};
+use crate::bestiary::Bestiary;
+use crate::effects::{self, EffectQueue, EffectTarget, EffectType};
+use crate::gamelog::GameLog;
+use crate::map::{DoorState, Map, xy_idx};
+use crate::party::{self, Party};
+use crate::rng::GameRng;
+use crate::targeting::Targeting;
+use crate::telemetry::{self, TelemetryEvent};
+use crate::{loot, spawner};
+
+/// Removes every monster whose hp dropped to zero, recording the kill in the bestiary
+/// before the entity disappears (dopo non avremmo più accesso ai suoi componenti).
+/// Monsters with a loot table may leave an item where they fell, and a monster with a
+/// DeathEffect sets it off before it is deleted (see `trigger_death_effect`); an animal
+/// with LeavesCorpse leaves its meat on the tile.
+/// If the player is the one at zero hp, the game switches to the game over screen;
+/// the other members of the party just leave it. Barricades at zero hp are smashed, and summoned allies fall.
+/// Afterwards nothing may still point at the deleted entities (see `clear_dangling_references`).
+pub fn delete_the_dead(ecs : &mut World) {
+    let mut dead : Vec<Entity> = Vec::new();
+    let mut killed : Vec<String> = Vec::new();
+    let mut drops : Vec<(String, i32, i32)> = Vec::new();
+    let mut corpses : Vec<(String, i32, i32, i32)> = Vec::new();
+    let mut last_acts : Vec<(DeathEffect, String, i32, i32)> = Vec::new();
+    {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        if combat_stats.get(player).is_some_and(|stats| stats.hp < 1) {
+            *ecs.write_resource::<RunState>() = RunState::GameOver;
+        }
+    }
+    {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        let fallen : Vec<Entity> = {
+            let combat_stats = ecs.read_storage::<CombatStats>();
+            party::party_members(ecs).into_iter()
+                .filter(|member| *member != player && combat_stats.get(*member).is_some_and(|stats| stats.hp < 1))
+                .collect()
+        };
+        for member in fallen {
+            let name = ecs.read_storage::<Name>().get(member).map(|name| name.name.clone()).unwrap_or_default();
+            ecs.write_resource::<GameLog>().push(format!("{} dies.", name));
+            ecs.write_resource::<Party>().remove(member);
+            dead.push(member);
+        }
+    }
+    {
+        let entities = ecs.entities();
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        let barricades = ecs.read_storage::<Barricade>();
+        let mut log = ecs.write_resource::<GameLog>();
+        for (entity, stats, _barricade) in (&entities, &combat_stats, &barricades).join() {
+            if stats.hp < 1 {
+                log.push("The barricade is smashed to pieces.".to_string());
+                dead.push(entity);
+            }
+        }
+    }
+    {
+        let entities = ecs.entities();
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        let summoned = ecs.read_storage::<Summoned>();
+        let names = ecs.read_storage::<Name>();
+        let mut log = ecs.write_resource::<GameLog>();
+        for (entity, stats, _summoned, name) in (&entities, &combat_stats, &summoned, &names).join() {
+            if stats.hp < 1 {
+                log.push(format!("The {} is struck down.", name.name));
+                dead.push(entity);
+            }
+        }
+    }
+    {
+        let entities = ecs.entities();
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        let monsters = ecs.read_storage::<Monster>();
+        let names = ecs.read_storage::<Name>();
+        let renderables = ecs.read_storage::<Renderable>();
+        let positions = ecs.read_storage::<Position>();
+        let loot_tables = ecs.read_storage::<DropsLoot>();
+        let death_effects = ecs.read_storage::<DeathEffect>();
+        let leaves_corpse = ecs.read_storage::<LeavesCorpse>();
+        let mut bestiary = ecs.write_resource::<Bestiary>();
+
+        for (entity, stats, _monster, name, render) in (&entities, &combat_stats, &monsters, &names, &renderables).join() {
+            if stats.hp < 1 {
+                bestiary.record_kill(&name.name, render.glyph, render.fg, stats);
+                killed.push(name.name.clone());
+                if let (Some(loot), Some(pos)) = (loot_tables.get(entity), positions.get(entity)) {
+                    drops.push((loot.table.clone(), pos.x, pos.y));
+                }
+                if let (Some(corpse), Some(pos)) = (leaves_corpse.get(entity), positions.get(entity)) {
+                    corpses.push((name.name.clone(), corpse.nutrition, pos.x, pos.y));
+                }
+                if let (Some(effect), Some(pos)) = (death_effects.get(entity), positions.get(entity)) {
+                    last_acts.push((*effect, name.name.clone(), pos.x, pos.y));
+                }
+                dead.push(entity);
+            }
+        }
+    }
+
+    for name in killed {
+        telemetry::record(ecs, TelemetryEvent::MonsterKilled { name });
+    }
+    for (effect, name, x, y) in last_acts {
+        trigger_death_effect(ecs, effect, &name, x, y);
+    }
+    for victim in dead {
+        ecs.delete_entity(victim).expect("Unable to delete");
+    }
+    ecs.maintain();
+    clear_dangling_references(ecs);
+
+    for (table, x, y) in drops {
+        let item = loot::roll_loot(&table, &mut ecs.write_resource::<GameRng>());
+        if let Some(item) = item {
+            spawner::spawn_named_item(ecs, item, x, y);
+        }
+    }
+    for (name, nutrition, x, y) in corpses {
+        spawner::corpse(ecs, x, y, &name, nutrition);
+    }
+}
+
+/// The last act of a monster dying at (x, y). The blast of an explosion is queued as
+/// environment damage on every tile in range, so it lands (on the player too) the next time
+/// the EffectsSystem runs, and may well kill another bomber standing nearby.
+fn trigger_death_effect(ecs : &mut World, effect : DeathEffect, name : &str, x : i32, y : i32) {
+    match effect {
+        DeathEffect::Explode { radius, damage } => {
+            ecs.write_resource::<GameLog>().push(format!("The {} explodes!", name));
+            let (width, height) = {
+                let map = ecs.fetch::<Map>();
+                (map.width, map.height)
+            };
+            for ty in (y - radius).max(0)..=(y + radius).min(height - 1) {
+                for tx in (x - radius).max(0)..=(x + radius).min(width - 1) {
+                    effects::add_effect(ecs, None, EffectType::Damage { amount: damage }, EffectTarget::Tile(xy_idx(tx, ty, width)));
+                }
+            }
+        }
+        DeathEffect::SpawnMinions { count } => {
+            ecs.write_resource::<GameLog>().push(format!("The {} calls for help with its last breath!", name));
+            for _ in 0..count {
+                if let Some((sx, sy)) = spawner::find_valid_spawn(ecs, x, y, 3) {
+                    spawner::random_monster(ecs, sx, sy);
+                }
+            }
+        }
+        DeathEffect::OpenDoors { radius } => {
+            let opened = {
+                let mut map = ecs.write_resource::<Map>();
+                let mut opened = 0;
+                for ty in (y - radius).max(0)..=(y + radius).min(map.height - 1) {
+                    for tx in (x - radius).max(0)..=(x + radius).min(map.width - 1) {
+                        let idx = map.xy_idx(tx, ty);
+                        if matches!(map.tiles[idx].door_state, Some(DoorState::Closed | DoorState::Locked)) {
+                            map.unlock_door(idx);
+                            map.open_door(idx);
+                            opened += 1;
+                        }
+                    }
+                }
+                opened
+            };
+            if opened > 0 {
+                crate::path_cache::invalidate(ecs);
+                ecs.write_resource::<GameLog>().push("Somewhere nearby, doors swing open.".to_string());
+                for viewshed in (&mut ecs.write_storage::<Viewshed>()).join() {
+                    viewshed.dirty = true;
+                }
+            }
+        }
+    }
+}
+
+/// Forgets every Entity handle kept outside the storages whose entity no longer exists:
+/// the ranged target, party members, queued effects aimed at (or coming from) the dead,
+/// the pack of a dead leader (its members go on alone).
+/// A stale handle isn't just useless, its id may be reused by a new entity.
+/// Resources the world doesn't have are skipped.
+pub fn clear_dangling_references(ecs : &mut World) {
+    let entities = ecs.entities();
+
+    if let Some(mut targeting) = ecs.try_fetch_mut::<Targeting>() {
+        targeting.current_target = targeting.current_target.filter(|target| entities.is_alive(*target));
+    }
+    if let Some(mut party) = ecs.try_fetch_mut::<Party>() {
+        let gone : Vec<Entity> = party.members.iter().copied().filter(|member| !entities.is_alive(*member)).collect();
+        for member in gone {
+            party.remove(member);
+        }
+    }
+    if let Some(mut effects) = ecs.try_fetch_mut::<EffectQueue>() {
+        effects.queue.retain(|effect| match effect.target {
+            EffectTarget::Single(target) => entities.is_alive(target),
+            EffectTarget::Tile(_) => true,
+        });
+        for effect in effects.queue.iter_mut() {
+            if effect.creator.is_some_and(|creator| !entities.is_alive(creator)) {
+                effect.creator = None;
+            }
+        }
+    }
+    if ecs.has_value::<specs::storage::MaskedStorage<PackMember>>() {
+        let mut packs = ecs.write_storage::<PackMember>();
+        let leaderless : Vec<Entity> = (&entities, &packs).join()
+            .filter(|(_member, pack)| !entities.is_alive(pack.leader))
+            .map(|(member, _pack)| member)
+            .collect();
+        for member in leaderless {
+            packs.remove(member);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::register_components;
+
+    #[test]
+    fn killing_the_target_clears_it() {
+        let mut ecs = World::new();
+        ecs.insert(Targeting::default());
+        let monster = ecs.create_entity().build();
+        ecs.write_resource::<Targeting>().current_target = Some(monster);
+
+        ecs.delete_entity(monster).expect("Unable to delete");
+        ecs.maintain();
+        // the freed id comes back with the next entity: the old handle must not find it
+        let _newcomer = ecs.create_entity().build();
+        clear_dangling_references(&mut ecs);
+
+        assert_eq!(ecs.fetch::<Targeting>().current_target, None);
+    }
+
+    #[test]
+    fn a_dying_bomber_blasts_whoever_is_too_close() {
+        use crate::clock::Clock;
+        use crate::effects::EffectsSystem;
+        use crate::map::{Biome, Building, Map};
+        use crate::telemetry::Telemetry;
+
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        let (width, height) = (80, 50);
+        ecs.insert(Map::new(width, height, Building.get_floor()));
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 100 });
+        ecs.insert(EffectQueue::default());
+        ecs.insert(Bestiary::default());
+        ecs.insert(Targeting::default());
+        ecs.insert(Clock::default());
+        ecs.insert(Telemetry::default());
+        ecs.insert(RunState::Running);
+
+        let player = ecs.create_entity()
+            .with(Position { x: 10, y: 5 })
+            .with(CombatStats { max_hp: 30, hp: 30, defense: 0, power: 5 })
+            .build();
+        ecs.insert(PlayerEntity(player));
+        let bystander = ecs.create_entity()
+            .with(Position { x: 20, y: 5 })
+            .with(CombatStats { max_hp: 30, hp: 30, defense: 0, power: 5 })
+            .build();
+        ecs.create_entity()
+            .with(Position { x: 11, y: 5 })
+            .with(Monster {})
+            .with(Name { name: "Bomber".to_string() })
+            .with(Renderable { glyph: rltk::to_cp437('b'), fg: rltk::RGB::named(rltk::RED), bg: rltk::RGB::named(rltk::BLACK) })
+            .with(CombatStats { max_hp: 6, hp: 0, defense: 0, power: 2 })
+            .with(DeathEffect::Explode { radius: 1, damage: 8 })
+            .build();
+
+        delete_the_dead(&mut ecs);
+        EffectsSystem {}.run_now(&ecs);
+
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        assert_eq!(combat_stats.get(player).unwrap().hp, 22);
+        assert_eq!(combat_stats.get(bystander).unwrap().hp, 30);
+    }
+
+    #[test]
+    fn a_dead_wolf_leaves_meat_where_it_fell() {
+        use crate::clock::Clock;
+        use crate::components::Food;
+        use crate::telemetry::Telemetry;
+
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 100 });
+        ecs.insert(Bestiary::default());
+        ecs.insert(Telemetry::default());
+        ecs.insert(Clock::default());
+        ecs.insert(RunState::Running);
+
+        let player = ecs.create_entity().with(CombatStats { max_hp: 30, hp: 30, defense: 0, power: 5 }).build();
+        ecs.insert(PlayerEntity(player));
+        ecs.create_entity()
+            .with(Position { x: 6, y: 2 })
+            .with(Monster {})
+            .with(Name { name: "Wolf".to_string() })
+            .with(Renderable { glyph: rltk::to_cp437('w'), fg: rltk::RGB::named(rltk::RED), bg: rltk::RGB::named(rltk::BLACK) })
+            .with(CombatStats { max_hp: 7, hp: 0, defense: 0, power: 3 })
+            .with(LeavesCorpse { nutrition: 400 })
+            .build();
+
+        delete_the_dead(&mut ecs);
+
+        let names = ecs.read_storage::<Name>();
+        let positions = ecs.read_storage::<Position>();
+        let foods = ecs.read_storage::<Food>();
+        let meat : Vec<_> = (&names, &positions, &foods).join().collect();
+        assert_eq!(meat.len(), 1);
+        let (name, pos, food) = meat[0];
+        assert_eq!(name.name, "Wolf Meat");
+        assert_eq!((pos.x, pos.y, food.nutrition), (6, 2, 400));
+        assert_eq!((&ecs.read_storage::<Monster>()).join().count(), 0);
+    }
+}
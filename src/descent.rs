@@ -0,0 +1,95 @@
+use rltk::RGB;
+use specs::prelude::*;
+use crate::components::Monster;
+use crate::config::GameConfig;
+use crate::gamelog::GameLog;
+use crate::map::{BiomeKind, Map};
+
+// DESCENT --------------------------------------------------------------------
+// Modalità "ripulisci per scendere" (GameConfig::clear_to_descend): la via verso il livello
+// successivo resta chiusa finché sul livello c'è un mostro vivo. La via è il portale o la scala
+// (vedi Tile::leads_down), che da chiusi si disegnano spenti.
+
+/// Whether the way down of the current level is sealed (risorsa).
+#[derive(Default)]
+pub struct DescentLock {
+    pub locked : bool,
+}
+
+/// Which biome each depth of the dungeon is made of (risorsa): a list of (first depth, biome),
+/// sorted by depth, each stage lasting until the next one starts. The portal of a level leads
+/// one level down, into the biome of that depth.
+/// With no stage for a depth, portals just lead to the next biome of the rotation (see BiomeKind::portal_target).
+pub struct BiomeProgression {
+    pub stages : Vec<(i32, BiomeKind)>,
+}
+
+impl Default for BiomeProgression {
+    fn default() -> BiomeProgression {
+        BiomeProgression { stages: vec![(1, BiomeKind::Building), (4, BiomeKind::Forest), (7, BiomeKind::SnowyMountains), (10, BiomeKind::Volcano)] }
+    }
+}
+
+impl BiomeProgression {
+    /// The biome of the last stage starting at or above `depth`, None if the table doesn't cover it.
+    pub fn biome_at(&self, depth: i32) -> Option<BiomeKind> {
+        self.stages.iter().rev().find(|(first_depth, _biome)| *first_depth <= depth).map(|(_first_depth, biome)| *biome)
+    }
+
+    /// Where the portal of a `current` level at `depth` leads.
+    pub fn next_biome(&self, depth: i32, current: BiomeKind) -> BiomeKind {
+        self.biome_at(depth + 1).unwrap_or(current.portal_target())
+    }
+}
+
+/// The portal the player said no to (see ConfirmableAction::Descend), until they step off it (risorsa).
+#[derive(Default)]
+pub struct DeclinedPortal {
+    pub idx : Option<usize>,
+}
+
+/// Seals or opens the portals (and stairs) of the level to match the monsters still alive, recolouring them
+/// and logging the change. Does nothing unless clear_to_descend is on.
+/// Called once the level is populated and after every death.
+pub fn update_descent_lock(ecs: &mut World) {
+    if !ecs.fetch::<GameConfig>().clear_to_descend {
+        return;
+    }
+    let monsters_left = ecs.read_storage::<Monster>().join().count();
+    let locked = monsters_left > 0;
+    if ecs.fetch::<DescentLock>().locked == locked {
+        return;
+    }
+    ecs.write_resource::<DescentLock>().locked = locked;
+
+    let colour = if locked { RGB::named(rltk::DIM_GRAY) } else { RGB::named(rltk::MAGENTA) };
+    for tile in ecs.write_resource::<Map>().tiles.iter_mut().filter(|tile| tile.leads_down()) {
+        tile.fg = colour;
+    }
+    let message = if locked {
+        "The portal is sealed: defeat every enemy on this level to open it."
+    } else {
+        "The last enemy falls, and the portal flickers to life!"
+    };
+    ecs.write_resource::<GameLog>().push(message.to_string());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_depth_range_has_its_biome() {
+        let progression = BiomeProgression { stages: vec![(1, BiomeKind::Building), (4, BiomeKind::Forest), (7, BiomeKind::Volcano)] };
+        assert_eq!(progression.biome_at(1), Some(BiomeKind::Building));
+        assert_eq!(progression.biome_at(3), Some(BiomeKind::Building));
+        assert_eq!(progression.next_biome(3, BiomeKind::Building), BiomeKind::Forest);
+        assert_eq!(progression.biome_at(6), Some(BiomeKind::Forest));
+        assert_eq!(progression.biome_at(25), Some(BiomeKind::Volcano));
+
+        // an empty table falls back to the rotation of the portals
+        let rotation = BiomeProgression { stages: Vec::new() };
+        assert_eq!(rotation.biome_at(1), None);
+        assert_eq!(rotation.next_biome(1, BiomeKind::Forest), BiomeKind::Volcano);
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+// DIFFICULTY -----------------------------------------------------------------
+// Risorsa scelta nel menu principale; spawner e combattimento la leggono
+// invece di avere numeri diversi sparsi per ogni entità.
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum DifficultyLevel {
+    Easy,
+    Normal,
+    Hard,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct Difficulty {
+    pub monster_hp_mult: f32,
+    pub monster_count_mult: f32,
+    pub spawn_depth_scale: f32, // extra monsters per level of depth (0.1 = +10% per level)
+}
+
+impl Difficulty {
+    pub fn from_level(level: DifficultyLevel) -> Difficulty {
+        match level {
+            DifficultyLevel::Easy => Difficulty { monster_hp_mult: 0.75, monster_count_mult: 0.6, spawn_depth_scale: 0.05 },
+            DifficultyLevel::Normal => Difficulty { monster_hp_mult: 1.0, monster_count_mult: 1.0, spawn_depth_scale: 0.1 },
+            DifficultyLevel::Hard => Difficulty { monster_hp_mult: 1.5, monster_count_mult: 1.5, spawn_depth_scale: 0.2 },
+        }
+    }
+
+    /// How many monsters to spawn on a level at `depth` (1 = first level).
+    pub fn monster_count(&self, base: i32, depth: i32) -> i32 {
+        let depth_factor = 1.0 + self.spawn_depth_scale * (depth - 1).max(0) as f32;
+        (base as f32 * self.monster_count_mult * depth_factor).round().max(1.0) as i32
+    }
+
+    /// Scales a monster's base hit points, never below 1.
+    pub fn monster_hp(&self, base_hp: i32) -> i32 {
+        ((base_hp as f32 * self.monster_hp_mult).round() as i32).max(1)
+    }
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty::from_level(DifficultyLevel::Normal)
+    }
+}
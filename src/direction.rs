@@ -0,0 +1,99 @@
+// DIRECTION ------------------------------------------------------------------
+// Le otto direzioni in cui ci si può muovere. Movimento, scavo e pathfinding usano questo tipo
+// invece di coppie (dx, dy) sparse per il codice.
+
+/// One of the eight neighbouring tiles, as seen from the current one (y grows downwards).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    South,
+    West,
+    East,
+    NorthWest,
+    NorthEast,
+    SouthWest,
+    SouthEast,
+}
+
+impl Direction {
+    /// Every direction, the four cardinal ones first.
+    pub const ALL : [Direction; 8] = [
+        Direction::West, Direction::East, Direction::North, Direction::South,
+        Direction::NorthWest, Direction::NorthEast, Direction::SouthWest, Direction::SouthEast,
+    ];
+
+    /// The direction of a single step by (dx, dy): the signs count, not the length.
+    /// None for (0, 0).
+    pub fn from_delta(dx: i32, dy: i32) -> Option<Direction> {
+        match (dx.signum(), dy.signum()) {
+            (0, -1) => Some(Direction::North),
+            (0, 1) => Some(Direction::South),
+            (-1, 0) => Some(Direction::West),
+            (1, 0) => Some(Direction::East),
+            (-1, -1) => Some(Direction::NorthWest),
+            (1, -1) => Some(Direction::NorthEast),
+            (-1, 1) => Some(Direction::SouthWest),
+            (1, 1) => Some(Direction::SouthEast),
+            _ => None,
+        }
+    }
+
+    pub fn to_delta(self) -> (i32, i32) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::South => (0, 1),
+            Direction::West => (-1, 0),
+            Direction::East => (1, 0),
+            Direction::NorthWest => (-1, -1),
+            Direction::NorthEast => (1, -1),
+            Direction::SouthWest => (-1, 1),
+            Direction::SouthEast => (1, 1),
+        }
+    }
+
+    /// The direction closest to the heading of (dx, dy), however far away: a point mostly
+    /// east and a little south is East, not SouthEast. None for (0, 0).
+    pub fn toward(dx: i32, dy: i32) -> Option<Direction> {
+        // within about 22.5 degrees of an axis the heading is a cardinal one (tan 22.5 ~ 0.41 ~ 5/12)
+        let (x, y) = if dy.abs() * 12 < dx.abs() * 5 {
+            (dx, 0)
+        } else if dx.abs() * 12 < dy.abs() * 5 {
+            (0, dy)
+        } else {
+            (dx, dy)
+        };
+        Direction::from_delta(x, y)
+    }
+
+    /// The compass abbreviation, for the HUD.
+    pub fn compass(self) -> &'static str {
+        match self {
+            Direction::North => "N",
+            Direction::South => "S",
+            Direction::West => "W",
+            Direction::East => "E",
+            Direction::NorthWest => "NW",
+            Direction::NorthEast => "NE",
+            Direction::SouthWest => "SW",
+            Direction::SouthEast => "SE",
+        }
+    }
+
+    /// An arrow pointing this way: cp437 has them for the four cardinal directions only,
+    /// the diagonals make do with slashes.
+    pub fn arrow(self) -> char {
+        match self {
+            Direction::North => '↑',
+            Direction::South => '↓',
+            Direction::West => '←',
+            Direction::East => '→',
+            Direction::NorthEast | Direction::SouthWest => '/',
+            Direction::NorthWest | Direction::SouthEast => '\\',
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        let (dx, dy) = self.to_delta();
+        dx != 0 && dy != 0
+    }
+}
@@ -0,0 +1,107 @@
+use specs::prelude::*;
+use super::{CombatStats, PlayerEntity, Position, Viewshed};
+use crate::config::GameConfig;
+use crate::gamelog::GameLog;
+use crate::map::{DoorState, Map};
+
+// DOORS ----------------------------------------------------------------------
+// Comodità per le porte: aprire tutte quelle adiacenti con un tasto e,
+// se attivato in GameConfig, porte che si richiudono da sole dopo il passaggio del giocatore.
+
+/// Doors the player has stood in and that will close as soon as their doorway is free.
+#[derive(Default)]
+pub struct PassedDoors {
+    pub tiles: Vec<usize>,
+}
+
+/// Every tile with a creature on it (the player included): doors never close on them.
+fn occupied_tiles(ecs: &World) -> Vec<usize> {
+    let positions = ecs.read_storage::<Position>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let map = ecs.fetch::<Map>();
+    (&positions, &combat_stats).join().map(|(pos, _stats)| map.xy_idx(pos.x, pos.y)).collect()
+}
+
+/// A door changed: what everybody sees changes with it.
+fn refresh_viewsheds(ecs: &mut World) {
+    for viewshed in (&mut ecs.write_storage::<Viewshed>()).join() {
+        viewshed.dirty = true;
+    }
+}
+
+/// Opens every closed (not locked) door around the player. Returns how many were opened.
+pub fn open_adjacent_doors(ecs: &mut World) -> i32 {
+    let (player_x, player_y) = {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        match ecs.read_storage::<Position>().get(player) {
+            Some(pos) => (pos.x, pos.y),
+            None => return 0,
+        }
+    };
+
+    let mut opened = 0;
+    {
+        let mut map = ecs.write_resource::<Map>();
+        for y in player_y - 1..=player_y + 1 {
+            for x in player_x - 1..=player_x + 1 {
+                if x < 0 || x >= map.width || y < 0 || y >= map.height {
+                    continue;
+                }
+                let idx = map.xy_idx(x, y);
+                if map.open_door(idx) {
+                    opened += 1;
+                }
+            }
+        }
+    }
+
+    let message = match opened {
+        0 => "There is no door to open here.".to_string(),
+        1 => "You open the door.".to_string(),
+        n => format!("You open {} doors.", n),
+    };
+    ecs.write_resource::<GameLog>().push(message);
+    if opened > 0 {
+        crate::path_cache::invalidate(ecs);
+        refresh_viewsheds(ecs);
+        crate::audio::play(ecs, crate::audio::SoundEvent::DoorOpen);
+    }
+    opened
+}
+
+/// Called once per player turn. With `auto_close_doors` on, a door closes the turn after
+/// the player walked through it, unless someone (player or monster) is standing in it.
+pub fn auto_close_doors(ecs: &mut World) {
+    if !ecs.fetch::<GameConfig>().auto_close_doors {
+        return;
+    }
+
+    let occupied = occupied_tiles(ecs);
+    let player_idx = {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        ecs.read_storage::<Position>().get(player).map(|pos| ecs.fetch::<Map>().xy_idx(pos.x, pos.y))
+    };
+
+    let mut closed_any = false;
+    {
+        let mut map = ecs.write_resource::<Map>();
+        let mut passed = ecs.write_resource::<PassedDoors>();
+
+        if let Some(idx) = player_idx.filter(|idx| map.tiles[*idx].door_state == Some(DoorState::Open) && !passed.tiles.contains(idx)) {
+            passed.tiles.push(idx);
+        }
+
+        passed.tiles.retain(|idx| {
+            if occupied.contains(idx) {
+                return true; // still in the doorway, try again next turn
+            }
+            closed_any |= map.close_door(*idx);
+            false
+        });
+    }
+
+    if closed_any {
+        crate::path_cache::invalidate(ecs);
+        refresh_viewsheds(ecs);
+    }
+}
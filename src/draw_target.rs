@@ -0,0 +1,239 @@
+use rltk::{FontCharType, Rltk, RGB, to_cp437};
+use specs::prelude::*;
+use crate::gui::{self, UiLayout};
+use crate::map::draw_map;
+
+// DRAW TARGET ----------------------------------------------------------------
+// Le funzioni di disegno scrivono su un DrawTarget invece che direttamente sul ctx di rltk:
+// in gioco il target è la console vera, nei test una griglia di caratteri da controllare.
+
+/// The few console operations the renderers need.
+pub trait DrawTarget {
+    fn set(&mut self, x: i32, y: i32, fg: RGB, bg: RGB, glyph: FontCharType);
+    fn print_color(&mut self, x: i32, y: i32, fg: RGB, bg: RGB, text: &str);
+    /// Changes only the background of a cell, keeping whatever glyph is already there.
+    fn set_bg(&mut self, x: i32, y: i32, bg: RGB);
+
+    fn print(&mut self, x: i32, y: i32, text: &str) {
+        self.print_color(x, y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), text);
+    }
+
+    /// Single-line box from (x, y) to (x + width, y + height) included, with the inside cleared.
+    fn draw_box(&mut self, x: i32, y: i32, width: i32, height: i32, fg: RGB, bg: RGB) {
+        for box_y in y..=y + height {
+            for box_x in x..=x + width {
+                let glyph = match (box_x == x, box_x == x + width, box_y == y, box_y == y + height) {
+                    (true, _, true, _) => '┌',
+                    (_, true, true, _) => '┐',
+                    (true, _, _, true) => '└',
+                    (_, true, _, true) => '┘',
+                    (_, _, true, _) | (_, _, _, true) => '─',
+                    (true, _, _, _) | (_, true, _, _) => '│',
+                    _ => ' ',
+                };
+                self.set(box_x, box_y, fg, bg, to_cp437(glyph));
+            }
+        }
+    }
+}
+
+impl DrawTarget for Rltk {
+    fn set(&mut self, x: i32, y: i32, fg: RGB, bg: RGB, glyph: FontCharType) {
+        Rltk::set(self, x, y, fg, bg, glyph);
+    }
+
+    fn print_color(&mut self, x: i32, y: i32, fg: RGB, bg: RGB, text: &str) {
+        Rltk::print_color(self, x, y, fg, bg, text);
+    }
+
+    fn print(&mut self, x: i32, y: i32, text: &str) {
+        Rltk::print(self, x, y, text);
+    }
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: RGB) {
+        Rltk::set_bg(self, x, y, bg);
+    }
+
+    fn draw_box(&mut self, x: i32, y: i32, width: i32, height: i32, fg: RGB, bg: RGB) {
+        Rltk::draw_box(self, x, y, width, height, fg, bg);
+    }
+}
+
+/// A screen made of plain characters: colours are dropped, glyphs outside the grid are ignored.
+pub struct CharGrid {
+    pub cells: Vec<Vec<char>>,
+}
+
+impl CharGrid {
+    pub fn new(width: i32, height: i32) -> CharGrid {
+        CharGrid { cells: vec![vec![' '; width.max(0) as usize]; height.max(0) as usize] }
+    }
+}
+
+impl DrawTarget for CharGrid {
+    fn set(&mut self, x: i32, y: i32, _fg: RGB, _bg: RGB, glyph: FontCharType) {
+        if x < 0 || y < 0 {
+            return;
+        }
+        if let Some(cell) = self.cells.get_mut(y as usize).and_then(|row| row.get_mut(x as usize)) {
+            *cell = rltk::to_char(glyph as u8);
+        }
+    }
+
+    fn print_color(&mut self, x: i32, y: i32, fg: RGB, bg: RGB, text: &str) {
+        for (offset, character) in text.chars().enumerate() {
+            self.set(x + offset as i32, y, fg, bg, to_cp437(character));
+        }
+    }
+
+    fn set_bg(&mut self, _x: i32, _y: i32, _bg: RGB) {}
+}
+
+/// What the game screen would show right now (map, entities, HUD and log), without a window.
+/// The mouse-driven overlays (path preview, combat prediction) are left out.
+#[allow(dead_code)]
+pub fn render_to_grid(ecs: &World) -> Vec<Vec<char>> {
+    let log_rect = ecs.fetch::<UiLayout>().log_rect;
+    let mut grid = CharGrid::new(log_rect.x2 + 1, log_rect.y2 + 1);
+    draw_map(ecs, &mut grid);
+    gui::draw_entities(ecs, &mut grid);
+    gui::draw_ui(ecs, &mut grid);
+    grid.cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::*;
+    use crate::gamelog::GameLog;
+    use crate::map::{Biome, Building, Map, Tile};
+    use crate::rng::GameRng;
+    use crate::trap_system::SearchProgress;
+
+    /// An 80x50 level of plain floor, all revealed, with the player at (10, 5) seeing everything around.
+    fn world_with_player() -> World {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        map.revealed_tiles = vec![true; (width * height) as usize];
+        map.visible_tiles = vec![true; (width * height) as usize];
+        ecs.insert(map);
+        ecs.insert(UiLayout::default());
+        ecs.insert(FrameClock::default());
+        ecs.insert(Exploration { percent: 62 });
+        ecs.insert(GameRng::seeded(1));
+        ecs.insert(Wallet { gold: 12 });
+        ecs.insert(GameLog { entries: vec!["Welcome to the dungeon!".to_string(), "You hear a lock click.".to_string()].into(), max_entries: 100 });
+
+        let player = ecs.create_entity()
+            .with(Position { x: 10, y: 5 })
+            .with(Renderable { glyph: to_cp437('@'), fg: RGB::named(rltk::YELLOW), bg: RGB::named(rltk::BLACK) })
+            .with(Viewshed {
+                visible_tiles: (0..20).flat_map(|x| (0..10).map(move |y| rltk::Point::new(x, y))).collect(),
+                range: 8, dirty: false, fov_shape: FovShape::Circle,
+            })
+            .build();
+        ecs.insert(PlayerEntity(player));
+        ecs
+    }
+
+    #[test]
+    fn shows_the_player_the_hud_and_the_log() {
+        let ecs = world_with_player();
+        let screen = render_to_grid(&ecs);
+        let layout = UiLayout::default();
+
+        assert_eq!(screen[5][10], '@');
+        assert_eq!(screen[5][11], '.');
+
+        let hud : String = screen[layout.hud_rect.y1 as usize].iter().collect();
+        assert!(hud.contains("Gold: 12"), "HUD row: {:?}", hud);
+        assert!(hud.contains("Explored: 62%"), "HUD row: {:?}", hud);
+
+        // newest message first, right under the top border of the log box
+        let first_line : String = screen[layout.log_rect.y1 as usize + 1].iter().collect();
+        assert!(first_line.contains("You hear a lock click."), "log row: {:?}", first_line);
+        let second_line : String = screen[layout.log_rect.y1 as usize + 2].iter().collect();
+        assert!(second_line.contains("Welcome to the dungeon!"), "log row: {:?}", second_line);
+    }
+
+    #[test]
+    fn hides_monsters_out_of_sight() {
+        let mut ecs = world_with_player();
+        ecs.create_entity()
+            .with(Position { x: 40, y: 20 })
+            .with(Renderable { glyph: to_cp437('o'), fg: RGB::named(rltk::RED), bg: RGB::named(rltk::BLACK) })
+            .build();
+
+        let screen = render_to_grid(&ecs);
+        assert_eq!(screen[20][40], '.');
+    }
+
+    #[test]
+    fn a_monster_stays_on_top_of_the_item_it_stands_on() {
+        let mut ecs = world_with_player();
+        ecs.create_entity()
+            .with(Position { x: 12, y: 5 })
+            .with(Renderable { glyph: to_cp437('o'), fg: RGB::named(rltk::RED), bg: RGB::named(rltk::BLACK) })
+            .build();
+        ecs.create_entity()
+            .with(Position { x: 12, y: 5 })
+            .with(Renderable { glyph: to_cp437('!'), fg: RGB::named(rltk::MAGENTA), bg: RGB::named(rltk::BLACK) })
+            .with(Item {})
+            .build();
+
+        for _frame in 0..3 {
+            assert_eq!(render_to_grid(&ecs)[5][12], 'o');
+        }
+    }
+
+    #[test]
+    fn draws_invisible_monsters_only_with_see_invisible() {
+        let mut ecs = world_with_player();
+        ecs.create_entity()
+            .with(Position { x: 12, y: 5 })
+            .with(Renderable { glyph: to_cp437('G'), fg: RGB::named(rltk::RED), bg: RGB::named(rltk::BLACK) })
+            .with(Hidden {})
+            .build();
+        assert_eq!(render_to_grid(&ecs)[5][12], '.');
+
+        let player = ecs.fetch::<PlayerEntity>().0;
+        ecs.write_storage::<SeeInvisible>().insert(player, SeeInvisible {}).unwrap();
+        assert_eq!(render_to_grid(&ecs)[5][12], 'G');
+    }
+
+    #[test]
+    fn the_camera_brings_the_bottom_rows_of_the_map_into_view() {
+        let ecs = world_with_player();
+        let player = ecs.fetch::<PlayerEntity>().0;
+        ecs.write_storage::<Position>().get_mut(player).unwrap().y = 48; // below the map region (rows 0-41)
+        ecs.write_storage::<Viewshed>().get_mut(player).unwrap().visible_tiles = vec![rltk::Point::new(10, 48)];
+
+        let map_rect = ecs.fetch::<UiLayout>().map_rect;
+        assert!(render_to_grid(&ecs).iter().all(|row| !row.contains(&'@')), "off camera, the player is not drawn over the HUD or the log");
+
+        ecs.write_resource::<UiLayout>().follow(10, 48, 80, 50);
+        let camera = ecs.fetch::<UiLayout>().camera;
+        assert_eq!(camera, (0, 50 - (map_rect.y2 + 1)), "scrolled down to the bottom edge of the map, no further");
+        let screen = render_to_grid(&ecs);
+        assert_eq!(screen[(48 - camera.1) as usize][10], '@');
+        assert_eq!(ecs.fetch::<UiLayout>().to_map(10, 48 - camera.1), Some((10, 48)));
+    }
+
+    #[test]
+    fn a_hidden_trap_shows_only_when_glimpsed_and_drawing_rolls_nothing() {
+        let mut ecs = world_with_player();
+        let idx = ecs.fetch::<Map>().xy_idx(12, 5);
+        ecs.write_resource::<Map>().tiles[idx] = Tile { glyph: to_cp437('^'), trap_dc: Some(30), ..Building.get_floor() };
+        ecs.insert(SearchProgress::default());
+
+        for _frame in 0..3 {
+            assert_eq!(render_to_grid(&ecs)[5][12], '.');
+        }
+        assert_eq!(ecs.fetch::<GameRng>().draws(), 0, "the frames don't use the game's random stream");
+        ecs.write_resource::<SearchProgress>().glimpsed.insert(idx);
+        assert_eq!(render_to_grid(&ecs)[5][12], '^');
+    }
+}
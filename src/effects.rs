@@ -0,0 +1,331 @@
+use std::collections::VecDeque;
+use specs::prelude::*;
+use crate::components::{CombatStats, Immunities, Name, PlayerEntity, Position, StatusEffects, Viewshed};
+use crate::audio::{Audio, SoundEvent};
+use crate::clock::Clock;
+use crate::gamelog::GameLog;
+use crate::map::{Map, StatusEffect};
+use crate::telemetry::{Telemetry, TelemetryEvent};
+
+// EFFECTS --------------------------------------------------------------------
+// Tutto ciò che cambia i pv, gli stati o la posizione di un'entità passa da qui: chi causa
+// l'effetto (un attacco, la lava, la fame, ...) lo mette in coda, e l'EffectsSystem lo applica.
+// Così c'è un solo punto da controllare quando un danno "sparisce" o arriva due volte.
+
+/// What an effect does to whatever it hits.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EffectType {
+    Damage { amount : i32 },
+    Heal { amount : i32 },
+    AddStatus { effect : StatusEffect, turns : i32 },
+    Teleport { x : i32, y : i32 },
+    Cleanse { effect : StatusEffect },
+}
+
+/// Who the effect hits: one entity, or every creature standing on a tile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EffectTarget {
+    Single(Entity),
+    Tile(usize),
+}
+
+/// One queued effect; `creator` is whoever (or whatever) caused it, None for the environment.
+#[derive(Debug, Clone, Copy)]
+pub struct Effect {
+    pub creator : Option<Entity>,
+    pub effect_type : EffectType,
+    pub target : EffectTarget,
+}
+
+/// How long a status caught from the terrain (lava, vines, ...) lasts.
+pub const HAZARD_STATUS_TURNS : i32 = 3;
+
+/// Damage dealt every turn by a status that hurts (Burning, Poisoned).
+pub const STATUS_DAMAGE_PER_TURN : i32 = 1;
+
+/// The effects waiting to be applied (risorsa).
+#[derive(Default)]
+pub struct EffectQueue {
+    pub queue : VecDeque<Effect>,
+}
+
+impl EffectQueue {
+    pub fn push(&mut self, creator: Option<Entity>, effect_type: EffectType, target: EffectTarget) {
+        self.queue.push_back(Effect { creator, effect_type, target });
+    }
+}
+
+pub fn status_name(status: StatusEffect) -> &'static str {
+    match status {
+        StatusEffect::Burning => "burning",
+        StatusEffect::Entangled => "entangled",
+        StatusEffect::Poisoned => "poisoned",
+    }
+}
+
+/// What the log says when a status is washed off the player (see EffectType::Cleanse).
+pub fn cleanse_message(status: StatusEffect) -> &'static str {
+    match status {
+        StatusEffect::Burning => "The flames are extinguished.",
+        StatusEffect::Entangled => "You slip free of the vines.",
+        StatusEffect::Poisoned => "The poison washes out of you.",
+    }
+}
+
+/// Queues an effect; it takes place the next time the EffectsSystem runs.
+pub fn add_effect(ecs: &World, creator: Option<Entity>, effect_type: EffectType, target: EffectTarget) {
+    ecs.write_resource::<EffectQueue>().push(creator, effect_type, target);
+}
+
+/// Applies every queued effect, in the order they were queued.
+/// Healing never goes past max_hp; a status that is already active keeps the longer
+/// of the two durations, and a status the target has Immunities to is shrugged off. Teleports onto a tile that can't be walked on fizzle.
+/// A cleanse ends the status at once (water on the flames, ...); on someone without it, it does nothing.
+/// Damage is also recorded in the Telemetry and played on the Audio sink, when the world has them.
+pub struct EffectsSystem {}
+
+impl<'a> System<'a> for EffectsSystem {
+    type SystemData = ( WriteExpect<'a, EffectQueue>,
+                        ReadExpect<'a, Map>,
+                        ReadExpect<'a, PlayerEntity>,
+                        WriteExpect<'a, GameLog>,
+                        Entities<'a>,
+                        ReadStorage<'a, Name>,
+                        WriteStorage<'a, CombatStats>,
+                        WriteStorage<'a, StatusEffects>,
+                        ReadStorage<'a, Immunities>,
+                        WriteStorage<'a, Position>,
+                        WriteStorage<'a, Viewshed>,
+                        Option<Write<'a, Telemetry>>,
+                        Option<Read<'a, Clock>>,
+                        Option<Write<'a, Audio>>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut effects, map, player, mut log, entities, names, mut combat_stats, mut status_effects, immunities, mut positions, mut viewsheds, mut telemetry, clock, mut audio) = data;
+        let turn = clock.map_or(0, |clock| clock.turn);
+
+        while let Some(effect) = effects.queue.pop_front() {
+            let targets : Vec<Entity> = match effect.target {
+                EffectTarget::Single(entity) => vec![entity],
+                EffectTarget::Tile(idx) => (&entities, &positions, &combat_stats).join()
+                    .filter(|(_entity, pos, _stats)| map.xy_idx(pos.x, pos.y) == idx)
+                    .map(|(entity, _pos, _stats)| entity)
+                    .collect(),
+            };
+
+            for target in targets {
+                match effect.effect_type {
+                    EffectType::Damage { amount } => {
+                        if let Some(stats) = combat_stats.get_mut(target) {
+                            stats.hp -= amount;
+                            if let Some(audio) = audio.as_mut() {
+                                audio.sink.play(SoundEvent::Hit { amount });
+                            }
+                            if let Some(telemetry) = telemetry.as_mut() {
+                                let name_of = |entity: Entity| names.get(entity).map_or("something".to_string(), |name| name.name.clone());
+                                telemetry.record(turn, TelemetryEvent::DamageDealt { source: effect.creator.map(name_of), target: name_of(target), amount });
+                            }
+                        }
+                    }
+                    EffectType::Heal { amount } => {
+                        if let Some(stats) = combat_stats.get_mut(target) {
+                            stats.hp = (stats.hp + amount).min(stats.max_hp);
+                        }
+                    }
+                    EffectType::AddStatus { effect: status, turns } => {
+                        if immunities.get(target).is_some_and(|immune| immune.covers(status)) {
+                            if target == player.0 {
+                                log.log_once(&format!("You can't be {}.", status_name(status)));
+                            } else if let Some(name) = names.get(target) {
+                                log.log_once(&format!("The {} is immune to being {}.", name.name, status_name(status)));
+                            }
+                            continue;
+                        }
+                        let active = status_effects.entry(target).ok().map(|entry| entry.or_insert_with(StatusEffects::default));
+                        if let Some(active) = active {
+                            match active.effects.iter_mut().find(|(existing, _turns)| *existing == status) {
+                                Some((_existing, remaining)) => *remaining = (*remaining).max(turns),
+                                None => active.effects.push((status, turns)),
+                            }
+                            if target == player.0 {
+                                log.push(format!("You are {}!", status_name(status)));
+                            } else if let Some(name) = names.get(target) {
+                                log.push(format!("The {} is {}.", name.name, status_name(status)));
+                            }
+                        }
+                    }
+                    EffectType::Cleanse { effect: status } => {
+                        let cleansed = status_effects.get_mut(target).is_some_and(|active| {
+                            let before = active.effects.len();
+                            active.effects.retain(|(existing, _turns)| *existing != status);
+                            active.effects.len() < before
+                        });
+                        if !cleansed {
+                            continue;
+                        }
+                        if target == player.0 {
+                            log.push(cleanse_message(status).to_string());
+                        } else if let Some(name) = names.get(target) {
+                            log.push(format!("The {} is no longer {}.", name.name, status_name(status)));
+                        }
+                    }
+                    EffectType::Teleport { x, y } => {
+                        let idx = map.xy_idx(x, y);
+                        if idx >= map.tiles.len() || !map.tiles[idx].walkable {
+                            continue;
+                        }
+                        if let Some(pos) = positions.get_mut(target) {
+                            pos.x = x;
+                            pos.y = y;
+                        }
+                        if let Some(viewshed) = viewsheds.get_mut(target) {
+                            viewshed.dirty = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One turn goes by for every active status: the ones that hurt queue their damage, all of them
+/// lose a turn and those at zero wear off (the player is told). Run it once per turn, before the
+/// EffectsSystem, so the damage lands in the same turn; death at 0 hp is then delete_the_dead's job.
+pub fn tick_statuses(ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let mut hurt : Vec<Entity> = Vec::new();
+    let mut worn_off : Vec<StatusEffect> = Vec::new();
+    {
+        let entities = ecs.entities();
+        let mut status_effects = ecs.write_storage::<StatusEffects>();
+        for (entity, active) in (&entities, &mut status_effects).join() {
+            for (status, turns) in active.effects.iter_mut().filter(|(_status, turns)| *turns > 0) {
+                if matches!(status, StatusEffect::Burning | StatusEffect::Poisoned) {
+                    hurt.push(entity);
+                }
+                *turns -= 1;
+                if *turns == 0 && entity == player {
+                    worn_off.push(*status);
+                }
+            }
+            active.effects.retain(|(_status, turns)| *turns > 0);
+        }
+    }
+    for entity in hurt {
+        add_effect(ecs, None, EffectType::Damage { amount: STATUS_DAMAGE_PER_TURN }, EffectTarget::Single(entity));
+    }
+    let mut log = ecs.write_resource::<GameLog>();
+    for status in worn_off {
+        log.push(format!("You are no longer {}.", status_name(status)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::register_components;
+    use crate::map::{Biome, Building, xy_idx};
+
+    fn world_with_player(hp: i32) -> (World, Entity) {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+
+        let (width, height) = (80, 50);
+        ecs.insert(Map::new(width, height, Building.get_floor()));
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 100 });
+        ecs.insert(EffectQueue::default());
+
+        let player = ecs.create_entity()
+            .with(Position { x: 10, y: 5 })
+            .with(CombatStats { max_hp: 30, hp, defense: 0, power: 5 })
+            .build();
+        ecs.insert(PlayerEntity(player));
+        (ecs, player)
+    }
+
+    fn hp(ecs: &World, entity: Entity) -> i32 {
+        ecs.read_storage::<CombatStats>().get(entity).unwrap().hp
+    }
+
+    #[test]
+    fn tile_effects_hit_whoever_stands_there() {
+        let (mut ecs, player) = world_with_player(30);
+        let width = ecs.fetch::<Map>().width;
+        add_effect(&ecs, None, EffectType::Damage { amount: 10 }, EffectTarget::Tile(xy_idx(10, 5, width)));
+        add_effect(&ecs, None, EffectType::Damage { amount: 10 }, EffectTarget::Tile(xy_idx(11, 5, width)));
+        add_effect(&ecs, None, EffectType::AddStatus { effect: StatusEffect::Burning, turns: 3 }, EffectTarget::Tile(xy_idx(10, 5, width)));
+        EffectsSystem {}.run_now(&ecs);
+        ecs.maintain();
+
+        assert_eq!(hp(&ecs, player), 20);
+        assert!(ecs.read_storage::<StatusEffects>().get(player).unwrap().has(StatusEffect::Burning));
+        assert!(ecs.fetch::<EffectQueue>().queue.is_empty());
+    }
+
+    #[test]
+    fn immune_creatures_shrug_off_the_status() {
+        let (ecs, player) = world_with_player(30);
+        ecs.write_storage::<Immunities>().insert(player, Immunities { effects: vec![StatusEffect::Burning] }).unwrap();
+        add_effect(&ecs, None, EffectType::AddStatus { effect: StatusEffect::Burning, turns: 3 }, EffectTarget::Single(player));
+        add_effect(&ecs, None, EffectType::AddStatus { effect: StatusEffect::Poisoned, turns: 3 }, EffectTarget::Single(player));
+        EffectsSystem {}.run_now(&ecs);
+
+        let status = ecs.read_storage::<StatusEffects>().get(player).cloned().unwrap();
+        assert!(!status.has(StatusEffect::Burning));
+        assert!(status.has(StatusEffect::Poisoned));
+        assert!(ecs.fetch::<GameLog>().entries.contains(&"You can't be burning.".to_string()));
+    }
+
+    #[test]
+    fn wading_in_puts_out_the_flames_and_nothing_else() {
+        let (ecs, player) = world_with_player(30);
+        add_effect(&ecs, None, EffectType::AddStatus { effect: StatusEffect::Burning, turns: 3 }, EffectTarget::Single(player));
+        add_effect(&ecs, None, EffectType::AddStatus { effect: StatusEffect::Poisoned, turns: 3 }, EffectTarget::Single(player));
+        EffectsSystem {}.run_now(&ecs);
+
+        let idx = ecs.fetch::<Map>().xy_idx(10, 5);
+        add_effect(&ecs, None, EffectType::Cleanse { effect: StatusEffect::Burning }, EffectTarget::Tile(idx));
+        EffectsSystem {}.run_now(&ecs);
+        let status = ecs.read_storage::<StatusEffects>().get(player).cloned().unwrap();
+        assert!(!status.has(StatusEffect::Burning));
+        assert!(status.has(StatusEffect::Poisoned));
+        assert_eq!(ecs.fetch::<GameLog>().entries.back(), Some(&"The flames are extinguished.".to_string()));
+
+        // already out: no second message
+        let logged = ecs.fetch::<GameLog>().entries.len();
+        add_effect(&ecs, None, EffectType::Cleanse { effect: StatusEffect::Burning }, EffectTarget::Single(player));
+        EffectsSystem {}.run_now(&ecs);
+        assert_eq!(ecs.fetch::<GameLog>().entries.len(), logged);
+    }
+
+    #[test]
+    fn healing_stops_at_max_hp() {
+        let (ecs, player) = world_with_player(29);
+        add_effect(&ecs, Some(player), EffectType::Heal { amount: 5 }, EffectTarget::Single(player));
+        EffectsSystem {}.run_now(&ecs);
+        assert_eq!(hp(&ecs, player), 30);
+    }
+
+    #[test]
+    fn burning_hurts_every_turn_until_it_wears_off() {
+        let (mut ecs, player) = world_with_player(30);
+        add_effect(&ecs, None, EffectType::AddStatus { effect: StatusEffect::Burning, turns: 2 }, EffectTarget::Single(player));
+        add_effect(&ecs, None, EffectType::AddStatus { effect: StatusEffect::Entangled, turns: 1 }, EffectTarget::Single(player));
+        EffectsSystem {}.run_now(&ecs);
+        let turn = |ecs: &mut World| {
+            tick_statuses(ecs);
+            EffectsSystem {}.run_now(ecs);
+        };
+
+        turn(&mut ecs);
+        assert_eq!(hp(&ecs, player), 30 - STATUS_DAMAGE_PER_TURN);
+        assert!(!ecs.read_storage::<StatusEffects>().get(player).unwrap().has(StatusEffect::Entangled));
+        turn(&mut ecs);
+        assert_eq!(hp(&ecs, player), 30 - 2 * STATUS_DAMAGE_PER_TURN);
+        assert!(ecs.read_storage::<StatusEffects>().get(player).unwrap().effects.is_empty());
+        assert_eq!(ecs.fetch::<GameLog>().entries.back(), Some(&"You are no longer burning.".to_string()));
+        turn(&mut ecs);
+        assert_eq!(hp(&ecs, player), 30 - 2 * STATUS_DAMAGE_PER_TURN);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::direction::Direction;
+use crate::map::Map;
+use crate::map_builders::distances_from;
+
+// FRONTIER -------------------------------------------------------------------
+// Il confine dell'esplorazione: le caselle già viste e percorribili che toccano qualcosa di
+// mai visto. È da qui che un'esplorazione automatica deve ripartire, e la vista di debug
+// della heatmap (vedi heatmap.rs) le colora per regolarla.
+
+/// True if the tile is revealed, walkable and next to (8 directions) a tile not revealed yet.
+pub fn is_frontier(map: &Map, idx: usize) -> bool {
+    if !map.revealed_tiles[idx] || !map.tiles[idx].walkable {
+        return false;
+    }
+    let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+    Direction::ALL.iter()
+        .map(|direction| direction.to_delta())
+        .map(|(dx, dy)| (x + dx, y + dy))
+        .any(|(nx, ny)| nx >= 0 && nx < map.width && ny >= 0 && ny < map.height && !map.revealed_tiles[map.xy_idx(nx, ny)])
+}
+
+/// Every frontier tile of the map, in index order.
+pub fn frontier_tiles(map: &Map) -> Vec<usize> {
+    (0..map.tiles.len()).filter(|idx| is_frontier(map, *idx)).collect()
+}
+
+/// The frontier tile the fewest steps away from `from` (ties go to the lowest index),
+/// None when the reachable part of the map is fully explored.
+pub fn nearest_frontier(map: &Map, from: (i32, i32)) -> Option<usize> {
+    let distances = distances_from(map, from);
+    frontier_tiles(map).into_iter()
+        .filter_map(|idx| distances[idx].map(|distance| (distance, idx)))
+        .min()
+        .map(|(_distance, idx)| idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Biome, Building, xy_idx};
+
+    #[test]
+    fn the_nearest_frontier_is_the_closest_edge_of_the_known_area() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        // a known strip from x 10 to 20 on row 10, with a wall at its east end
+        for x in 10..=20 {
+            map.revealed_tiles[xy_idx(x, 10, width)] = true;
+        }
+        map.tiles[xy_idx(20, 10, width)] = Building.get_wall();
+
+        assert!(is_frontier(&map, xy_idx(12, 10, width)), "the rows above and below are unknown");
+        assert!(!is_frontier(&map, xy_idx(20, 10, width)), "walls aren't frontier");
+        assert!(!is_frontier(&map, xy_idx(30, 10, width)), "unknown tiles aren't frontier");
+        assert_eq!(nearest_frontier(&map, (15, 10)), Some(xy_idx(15, 10, width)));
+
+        map.revealed_tiles = vec![true; (width * height) as usize];
+        assert!(frontier_tiles(&map).is_empty());
+        assert_eq!(nearest_frontier(&map, (15, 10)), None);
+    }
+}
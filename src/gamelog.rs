@@ -0,0 +1,49 @@
+use std::collections::VecDeque;
+
+// Log dei messaggi mostrati al giocatore, il più recente è l'ultimo della coda.
+// È un buffer circolare: pieno, ogni messaggio nuovo fa cadere il più vecchio
+pub struct GameLog {
+    pub entries : VecDeque<String>,
+    pub max_entries : usize // storia conservata: oltre questo i messaggi più vecchi si perdono
+}
+
+impl GameLog {
+    /// Appends a message, dropping the oldest ones once the log holds `max_entries`,
+    /// so the history never grows without bound however long the run.
+    pub fn push(&mut self, message: String) {
+        while self.entries.len() >= self.max_entries.max(1) {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(message);
+    }
+
+    /// Adds the message only if it isn't already the latest entry,
+    /// so repeating the same action (e.g. holding a key against a wall) doesn't spam the log.
+    pub fn log_once(&mut self, message: &str) {
+        if self.entries.back().map(|last| last.as_str()) != Some(message) {
+            self.push(message.to_string());
+        }
+    }
+}
+
+// Conta i passi dall'ultimo messaggio di terreno, per non riempire il log a ogni passo
+#[derive(Default)]
+pub struct FootstepThrottle {
+    pub steps_since_message : i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_log_drops_the_oldest_messages() {
+        let mut log = GameLog { entries: VecDeque::new(), max_entries: 3 };
+        for n in 1..=5 {
+            log.push(format!("message {}", n));
+        }
+        assert_eq!(log.entries, ["message 3", "message 4", "message 5"]);
+        log.log_once("message 5");
+        assert_eq!(log.entries.len(), 3);
+    }
+}
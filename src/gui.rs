@@ -0,0 +1,883 @@
+use rltk::{Rltk, VirtualKeyCode, RGB};
+use specs::prelude::*;
+use crate::bestiary::Bestiary;
+use crate::gamelog::GameLog;
+use crate::components::{AggroRange, Animation, Charges, CombatStats, Exploration, FrameClock, Hidden, Hunger, HungerState, Item, LastSeen, LightSource, Monster, Name, PlayerEntity, Position, Renderable, Stance, StatusEffects, Viewshed, Wallet};
+use crate::draw_target::DrawTarget;
+use crate::targeting::{self, Targeting};
+use crate::direction::Direction;
+use crate::combat;
+use crate::config::{GameConfig, Toggle};
+use crate::map::{Map, StatusEffect, Tile};
+use crate::difficulty::DifficultyLevel;
+use crate::keybindings::InputProfile;
+use crate::party::Party;
+use crate::rect::Rect;
+
+// GUI -----------------------------------------------------------------------
+// Schermate e menu che si disegnano sopra la mappa.
+
+/// Where each part of the screen goes. Every renderer reads its region from here,
+/// so the layout is decided in one place: a taller log automatically leaves fewer rows to the map.
+/// Rects are inclusive on both ends, like the arguments of `ctx.draw_box`.
+/// The map region is a window on the map: `camera` is the map tile drawn in its top left corner,
+/// and every map position goes through to_screen (the mouse through to_map) to find its cell.
+pub struct UiLayout {
+    pub map_rect: Rect,
+    pub hud_rect: Rect,
+    pub log_rect: Rect,
+    pub show_unexplored: bool, // draw unrevealed map tiles as a dim placeholder, so the map edge is visible (GameConfig)
+    pub camera: (i32, i32),
+}
+
+impl UiLayout {
+    /// Stacks, from the bottom of the screen: the log box (`log_height` rows), one HUD row, then the map.
+    /// The unexplored placeholder starts off, GameConfig::show_unexplored turns it on.
+    pub fn new(screen_width: i32, screen_height: i32, log_height: i32) -> UiLayout {
+        let log_rect = Rect::new(0, screen_height - log_height, screen_width - 1, log_height - 1);
+        let hud_rect = Rect::new(0, log_rect.y1 - 1, screen_width - 1, 0);
+        let map_rect = Rect::new(0, 0, screen_width - 1, hud_rect.y1 - 1);
+        UiLayout { map_rect, hud_rect, log_rect, show_unexplored: false, camera: (0, 0) }
+    }
+
+    /// The screen cell of map tile (x, y), None if the camera doesn't show it.
+    pub fn to_screen(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let (screen_x, screen_y) = (x - self.camera.0 + self.map_rect.x1, y - self.camera.1 + self.map_rect.y1);
+        if self.map_rect.contains(screen_x, screen_y) { Some((screen_x, screen_y)) } else { None }
+    }
+
+    /// The map tile shown in screen cell (x, y) (where the mouse is), None outside the map region.
+    pub fn to_map(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        if !self.map_rect.contains(x, y) {
+            return None;
+        }
+        Some((x - self.map_rect.x1 + self.camera.0, y - self.map_rect.y1 + self.camera.1))
+    }
+
+    /// Moves the camera so that (x, y) is in the middle of the map region, without scrolling
+    /// past the edges of a `width` x `height` map. A map that fits the region isn't scrolled at all.
+    pub fn follow(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        let (view_width, view_height) = (self.map_rect.x2 - self.map_rect.x1 + 1, self.map_rect.y2 - self.map_rect.y1 + 1);
+        let scroll = |at: i32, view: i32, size: i32| (at - view / 2).clamp(0, (size - view).max(0));
+        self.camera = (scroll(x, view_width, width), scroll(y, view_height, height));
+    }
+}
+
+impl Default for UiLayout {
+    fn default() -> UiLayout {
+        UiLayout::new(80, 50, 7)
+    }
+}
+
+/// The background that tells what is under an entity: the tile's own background if it has one,
+/// otherwise a dim wash of the tile's colour for the tiles worth remembering
+/// (stairs, portals, traps already spotted). None for plain ground.
+fn backdrop(tile: &Tile) -> Option<RGB> {
+    if tile.bg != RGB::named(rltk::BLACK) {
+        return Some(tile.bg);
+    }
+    let spotted_trap = tile.trap_kind.is_some() && tile.trap_dc.is_none();
+    if tile.stairs.is_some() || spotted_trap || tile.portal.is_some() {
+        Some(RGB::from_f32(tile.fg.r * 0.35, tile.fg.g * 0.35, tile.fg.b * 0.35))
+    } else {
+        None
+    }
+}
+
+/// Draws the entities on the map.
+/// Fog of war: entities in sight are drawn where they are, entities out of sight are drawn
+/// greyed where the player last saw them (LastSeen), and never-seen entities aren't drawn.
+/// An entity without a background of its own keeps the one of what it stands on (see `backdrop`),
+/// or a dim wash of the item lying under it.
+/// Entities sharing a tile are drawn in a fixed order, items first and then by entity id,
+/// so the same one ends up on top every frame.
+pub fn draw_entities(ecs: &World, ctx: &mut dyn DrawTarget) {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let renderables = ecs.read_storage::<Renderable>();
+    let animations = ecs.read_storage::<Animation>();
+    let status_effects = ecs.read_storage::<StatusEffects>();
+    let last_seen = ecs.read_storage::<LastSeen>();
+    let aggro_ranges = ecs.read_storage::<AggroRange>();
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let party = crate::party::party_members(ecs);
+    let hiddens = ecs.read_storage::<Hidden>();
+    let detection = crate::targeting::detects_hidden(ecs);
+    let elapsed_ms = ecs.fetch::<FrameClock>().elapsed_ms;
+    let layout = ecs.fetch::<UiLayout>();
+    let map = ecs.fetch::<Map>();
+    let items = ecs.read_storage::<Item>();
+    let item_under = |entity: Entity, x: i32, y: i32| (&entities, &positions, &renderables, &items).join()
+        .find(|(other, pos, _render, _item)| *other != entity && pos.x == x && pos.y == y)
+        .map(|(_other, _pos, render, _item)| RGB::from_f32(render.fg.r * 0.35, render.fg.g * 0.35, render.fg.b * 0.35));
+
+    // what any member of the party sees is on screen, and so is what a light in their line of sight lights up
+    let lights = ecs.read_storage::<LightSource>();
+    let watchers : Vec<rltk::Point> = party.iter().filter_map(|member| positions.get(*member)).map(|pos| rltk::Point::new(pos.x, pos.y)).collect();
+    let lit : Vec<Entity> = (&entities, &lights, &positions).join()
+        .filter(|(_entity, _light, pos)| crate::visibility_system::light_in_view(&map, &watchers, rltk::Point::new(pos.x, pos.y)))
+        .map(|(entity, _light, _pos)| entity)
+        .collect();
+    let in_sight = |x: i32, y: i32| party.iter().chain(lit.iter()).any(|member| viewsheds.get(*member)
+        .is_some_and(|viewshed| viewshed.visible_tiles.contains(&rltk::Point::new(x, y))));
+
+    // ghosts first, so a visible entity on the same tile is drawn over them
+    for (entity, render, memory) in (&entities, &renderables, &last_seen).join() {
+        if hiddens.get(entity).is_some() && !detection {
+            continue;
+        }
+        let visible_now = positions.get(entity).is_some_and(|pos| in_sight(pos.x, pos.y));
+        if visible_now || in_sight(memory.x, memory.y) {
+            continue;
+        }
+        if let Some((x, y)) = layout.to_screen(memory.x, memory.y) {
+            ctx.set(x, y, render.fg.to_greyscale(), render.bg, render.glyph);
+        }
+    }
+
+    let mut drawn : Vec<_> = (&entities, &positions, &renderables, animations.maybe(), status_effects.maybe()).join().collect();
+    drawn.sort_by_key(|(entity, ..)| (items.get(*entity).is_none(), entity.id()));
+    for (entity, pos, render, animation, effects) in drawn {
+        let (screen_x, screen_y) = match layout.to_screen(pos.x, pos.y) {
+            Some(cell) => cell,
+            None => continue, // off camera, or it would land under the HUD or the log
+        };
+        if !party.contains(&entity) && !in_sight(pos.x, pos.y) {
+            continue; // out of sight: at most its ghost was drawn above
+        }
+        if hiddens.get(entity).is_some() && !detection {
+            continue; // invisible, and nobody can see invisible things
+        }
+        let (glyph, mut fg) = animation
+            .and_then(|a| a.frame_at(elapsed_ms))
+            .unwrap_or((render.glyph, render.fg));
+        let mut bg = render.bg;
+        if bg == RGB::named(rltk::BLACK) && items.get(entity).is_none() {
+            bg = backdrop(&map.tiles[map.xy_idx(pos.x, pos.y)]).or_else(|| item_under(entity, pos.x, pos.y)).unwrap_or(bg);
+        }
+        // sleeping monsters are dimmed: you may sneak past them
+        if aggro_ranges.get(entity).is_some_and(|aggro| !aggro.awake) {
+            fg = RGB::from_f32(fg.r * 0.5, fg.g * 0.5, fg.b * 0.5);
+        }
+        // afflicted entities glow with the color of their status (orange = burning, green = poison)
+        if let Some(tint) = effects.and_then(status_tint) {
+            fg = fg.lerp(tint, 0.6);
+            bg = RGB::from_f32(tint.r * 0.3, tint.g * 0.3, tint.b * 0.3);
+        }
+        ctx.set(screen_x, screen_y, fg, bg, glyph);
+    }
+}
+
+/// How many cells deep the low hp vignette reaches into the map.
+const VIGNETTE_DEPTH : i32 = 3;
+
+/// While the player is at or below a quarter of their hp, tints the edges of the map red,
+/// strongest on the border and fading inwards, pulsing slowly. Off when GameConfig::low_hp_flash is.
+pub fn draw_low_hp_vignette(ecs: &World, ctx: &mut dyn DrawTarget) {
+    if ecs.try_fetch::<GameConfig>().is_some_and(|config| !config.low_hp_flash) {
+        return;
+    }
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let critical = ecs.read_storage::<CombatStats>().get(player).is_some_and(|stats| stats.hp * 4 <= stats.max_hp);
+    if !critical {
+        return;
+    }
+    let elapsed_ms = ecs.fetch::<FrameClock>().elapsed_ms;
+    let pulse = 0.75 + 0.25 * (elapsed_ms / 1000.0 * std::f32::consts::TAU).sin();
+    let map_rect = ecs.fetch::<UiLayout>().map_rect;
+    for y in map_rect.y1..=map_rect.y2 {
+        for x in map_rect.x1..=map_rect.x2 {
+            let depth = (x - map_rect.x1).min(map_rect.x2 - x).min(y - map_rect.y1).min(map_rect.y2 - y);
+            if depth < VIGNETTE_DEPTH {
+                let strength = (VIGNETTE_DEPTH - depth) as f32 / VIGNETTE_DEPTH as f32 * pulse;
+                ctx.set_bg(x, y, RGB::from_f32(0.6 * strength, 0.0, 0.0));
+            }
+        }
+    }
+}
+
+/// Draws the HUD row (gold, hunger) and the message log box (ultimi messaggi in basso).
+pub fn draw_ui(ecs: &World, ctx: &mut dyn DrawTarget) {
+    let layout = ecs.fetch::<UiLayout>();
+
+    let hud = layout.hud_rect;
+    let wallet = ecs.fetch::<Wallet>();
+    ctx.print_color(hud.x1 + 2, hud.y1, RGB::named(rltk::GOLD), RGB::named(rltk::BLACK), &format!("Gold: {}", wallet.gold));
+
+    // stato della fame, solo quando c'è qualcosa da dire
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let hunger = ecs.read_storage::<Hunger>().get(player).map(|hunger| hunger.state);
+    let hunger_label = match hunger {
+        Some(HungerState::WellFed) => Some(("Well Fed", RGB::named(rltk::GREEN))),
+        Some(HungerState::Hungry) => Some(("Hungry", RGB::named(rltk::ORANGE))),
+        Some(HungerState::Starving) => Some(("Starving", RGB::named(rltk::RED))),
+        Some(HungerState::Normal) | None => None,
+    };
+    if let Some((label, color)) = hunger_label {
+        ctx.print_color(hud.x1 + 16, hud.y1, color, RGB::named(rltk::BLACK), label);
+    }
+
+    let explored = ecs.fetch::<Exploration>().percent;
+    ctx.print_color(hud.x1 + 28, hud.y1, RGB::named(rltk::LIGHT_BLUE), RGB::named(rltk::BLACK), &format!("Explored: {}%", explored));
+
+    // dov'è il nemico più vicino tra quelli in vista, anche se è sul bordo del campo visivo
+    if let Some((direction, distance)) = nearest_threat(ecs) {
+        ctx.print_color(hud.x1 + 46, hud.y1, RGB::named(rltk::RED), RGB::named(rltk::BLACK),
+            &format!("Threat: {} {} {}", direction.arrow(), direction.compass(), distance));
+    }
+
+    // la postura si vede solo quando non è quella normale
+    if let Some(stance) = ecs.read_storage::<Stance>().get(player).filter(|stance| **stance != Stance::Balanced) {
+        ctx.print_color(hud.x1 + 64, hud.y1, RGB::named(rltk::CYAN), RGB::named(rltk::BLACK), &format!("Stance: {}", stance.short()));
+    }
+
+    let log_rect = layout.log_rect;
+    ctx.draw_box(log_rect.x1, log_rect.y1, log_rect.x2 - log_rect.x1, log_rect.y2 - log_rect.y1,
+        RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    // le cariche della bacchetta, sul bordo del riquadro dei messaggi
+    if let Some(charges) = ecs.read_storage::<Charges>().get(player) {
+        let color = if charges.current > 0 { RGB::named(rltk::ORANGE) } else { RGB::named(rltk::GRAY) };
+        ctx.print_color(log_rect.x2 - 14, log_rect.y1, color, RGB::named(rltk::BLACK), &format!("Wand: {}/{}", charges.current, charges.max));
+    }
+    // in combattimento, chi agisce nei prossimi turni (vedi initiative.rs), sul bordo a sinistra
+    let order = crate::initiative::upcoming_turns(ecs, crate::initiative::PREVIEW_LENGTH);
+    if !order.is_empty() {
+        let room = (log_rect.x2 - log_rect.x1 - 20).max(0) as usize;
+        let line : String = format!("Next: {}", order.join(", ")).chars().take(room).collect();
+        ctx.print_color(log_rect.x1 + 2, log_rect.y1, RGB::named(rltk::LIGHT_BLUE), RGB::named(rltk::BLACK), &line);
+    }
+    // con più personaggi nel gruppo, a chi passa i comandi il tasto P, sul bordo in basso
+    let next_member = ecs.try_fetch::<Party>().and_then(|party| party.next_in_line());
+    if let Some(name) = next_member.and_then(|member| ecs.read_storage::<Name>().get(member).map(|name| name.name.clone())) {
+        ctx.print_color(log_rect.x1 + 2, log_rect.y2, RGB::named(rltk::LIGHT_BLUE), RGB::named(rltk::BLACK), &format!("P: control {}", name));
+    }
+    let log = ecs.fetch::<GameLog>();
+    for (y, message) in (log_rect.y1 + 1..log_rect.y2).zip(log.entries.iter().rev()) {
+        ctx.print(log_rect.x1 + 2, y, message);
+    }
+}
+
+/// Heading and distance (in tiles, rounded) of the nearest monster in the player's view
+/// (see targeting::visible_monsters), None when there is none or it stands on the player's tile.
+pub fn nearest_threat(ecs: &World) -> Option<(Direction, i32)> {
+    let nearest = targeting::visible_monsters(ecs).first().copied()?;
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let positions = ecs.read_storage::<Position>();
+    let (from, to) = (positions.get(player)?, positions.get(nearest)?);
+    let direction = Direction::toward(to.x - from.x, to.y - from.y)?;
+    let distance = rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(from.x, from.y), rltk::Point::new(to.x, to.y));
+    Some((direction, distance.round() as i32))
+}
+
+/// Highlights the path the player would walk to reach the revealed tile under the mouse.
+/// Nothing is drawn for unrevealed, blocked or unreachable tiles.
+pub fn draw_path_preview(ecs: &World, ctx: &mut Rltk) {
+    let map = ecs.fetch::<Map>();
+    let layout = ecs.fetch::<UiLayout>();
+    let (mouse_x, mouse_y) = ctx.mouse_pos();
+    let target_idx = match layout.to_map(mouse_x, mouse_y).and_then(|(x, y)| crate::map::try_xy_idx(x, y, &map)) {
+        Some(idx) => idx,
+        None => return,
+    };
+    if !map.revealed_tiles[target_idx] || !map.tiles[target_idx].walkable {
+        return;
+    }
+
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let positions = ecs.read_storage::<Position>();
+    let pos = match positions.get(player) {
+        Some(pos) => pos,
+        None => return,
+    };
+    let path = rltk::a_star_search(map.xy_idx(pos.x, pos.y), target_idx, &*map);
+    if !path.success {
+        return;
+    }
+    // the first step is the player's own tile
+    for idx in path.steps.iter().skip(1) {
+        if let Some((x, y)) = layout.to_screen(*idx as i32 % map.width, *idx as i32 / map.width) {
+            ctx.set_bg(x, y, RGB::named(rltk::DARKSLATEGRAY));
+        }
+    }
+}
+
+/// Predicted outcome of a fight with the monster under the mouse (or, failing that, the current
+/// ranged target): damage both ways, the chance to hit it from here and how dangerous it is
+/// (see combat::threat_rating), drawn in the top right corner.
+pub fn draw_combat_prediction(ecs: &World, ctx: &mut Rltk) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let monsters = ecs.read_storage::<Monster>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let names = ecs.read_storage::<Name>();
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let map = ecs.fetch::<Map>();
+    let map_rect = ecs.fetch::<UiLayout>().map_rect;
+
+    let stance = ecs.read_storage::<Stance>().get(player).copied();
+    let (player_pos, player_stats, viewshed) = match (positions.get(player), combat_stats.get(player), viewsheds.get(player)) {
+        (Some(pos), Some(stats), Some(viewshed)) => (rltk::Point::new(pos.x, pos.y), combat::with_stance(stats, stance.as_ref()), viewshed),
+        _ => return,
+    };
+    let (mouse_x, mouse_y) = ctx.mouse_pos();
+    let under_mouse = ecs.fetch::<UiLayout>().to_map(mouse_x, mouse_y);
+    let hovered = (&entities, &monsters, &positions).join()
+        .find(|(entity, _monster, pos)| under_mouse == Some((pos.x, pos.y)) && crate::targeting::is_perceived(ecs, *entity))
+        .map(|(entity, _monster, _pos)| entity);
+    let target = match hovered.or(ecs.fetch::<Targeting>().current_target) {
+        Some(target) => target,
+        None => return,
+    };
+    let (target_pos, target_stats) = match (positions.get(target), combat_stats.get(target)) {
+        (Some(pos), Some(stats)) => (rltk::Point::new(pos.x, pos.y), stats),
+        _ => return,
+    };
+    if !viewshed.visible_tiles.contains(&target_pos) {
+        return; // no predictions about what we can't see
+    }
+
+    let adjacent = rltk::DistanceAlg::Pythagoras.distance2d(player_pos, target_pos) < 1.5;
+    // melee always connects, from a distance the shot may be blocked or stopped by the target's cover
+    let creatures = combat::creature_positions(ecs);
+    let barricades = combat::barricade_positions(ecs);
+    let shot = if adjacent { combat::ShotResult::Clear } else { combat::has_clear_shot(&map, &creatures, &barricades, player_pos, target_pos) };
+    let hit_chance = if adjacent { 100 } else { combat::ranged_hit_chance(&map, player_pos, target_pos) };
+    let name = names.get(target).map(|n| n.name.clone()).unwrap_or_default();
+    let you = match shot {
+        combat::ShotResult::Clear => format!("You: ~{} dmg, {}% hit", combat::damage(&player_stats, target_stats), hit_chance),
+        combat::ShotResult::BlockedByTile(_) => "You: no clear shot".to_string(),
+        combat::ShotResult::Intercepted(..) => "You: something in the way".to_string(),
+        combat::ShotResult::Barricaded(..) => "You: barricade in the way".to_string(),
+    };
+    let threat = combat::threat_rating(&player_stats, target_stats);
+    let lines = [
+        name,
+        you,
+        format!("It: ~{} dmg", combat::damage(target_stats, &player_stats)),
+        format!("Threat: {}", threat.name()),
+    ];
+
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as i32 + 3;
+    let x = map_rect.x2 - width;
+    let y = map_rect.y1 + 1;
+    ctx.draw_box(x, y, width, lines.len() as i32 + 1, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    for (i, line) in lines.iter().enumerate() {
+        let fg = match i {
+            0 => RGB::named(rltk::YELLOW),
+            3 => threat.color(),
+            _ => RGB::named(rltk::WHITE),
+        };
+        ctx.print_color(x + 2, y + 1 + i as i32, fg, RGB::named(rltk::BLACK), line);
+    }
+}
+
+/// The color an afflicted entity is tinted with, so you can tell at a glance who's burning or poisoned.
+/// Burning wins over poison when both are active. Entangled has no tint (the vines already show it).
+pub fn status_tint(effects: &StatusEffects) -> Option<RGB> {
+    if effects.has(StatusEffect::Burning) {
+        Some(RGB::named(rltk::ORANGE))
+    } else if effects.has(StatusEffect::Poisoned) {
+        Some(RGB::named(rltk::GREEN))
+    } else {
+        None
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum ExamineResult {
+    NoResponse,
+    MoveCursor { x: i32, y: i32 },
+    Cancel,
+}
+
+/// The detail panel of look mode for `entity`: name, hp bar, active statuses with the turns
+/// they have left and, for monsters, the threat rating. It goes on the half of the screen away
+/// from the reticle at screen column `x`, so it never hides what is being examined.
+fn examine_panel(ecs: &World, ctx: &mut Rltk, entity: Entity, x: i32) {
+    const WIDTH : i32 = 26;
+    let names = ecs.read_storage::<Name>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let status_effects = ecs.read_storage::<StatusEffects>();
+    let stats = match combat_stats.get(entity) {
+        Some(stats) => *stats,
+        None => return,
+    };
+    let threat = match (ecs.read_storage::<Monster>().get(entity), combat_stats.get(ecs.fetch::<PlayerEntity>().0)) {
+        (Some(_monster), Some(player_stats)) => Some(combat::threat_rating(player_stats, &stats)),
+        _ => None,
+    };
+    let statuses : Vec<String> = status_effects.get(entity).map_or(Vec::new(), |active| active.effects.iter()
+        .filter(|(_effect, turns)| *turns > 0)
+        .map(|(effect, turns)| format!("{} ({})", crate::effects::status_name(*effect), turns))
+        .collect());
+
+    let map_rect = ecs.fetch::<UiLayout>().map_rect;
+    let left = if x < map_rect.x2 / 2 { map_rect.x2 - WIDTH - 1 } else { map_rect.x1 + 1 };
+    let top = map_rect.y1 + 2;
+    let height = 4 + statuses.len().max(1) as i32 + threat.map_or(0, |_| 1);
+    let (white, black) = (RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.draw_box(left, top, WIDTH, height, white, black);
+
+    let name = names.get(entity).map_or("Something".to_string(), |name| name.name.clone());
+    ctx.print_color(left + 2, top + 1, RGB::named(rltk::YELLOW), black, &name);
+    ctx.print_color(left + 2, top + 2, white, black, format!("HP {}/{}", stats.hp.max(0), stats.max_hp));
+    ctx.draw_bar_horizontal(left + 12, top + 2, WIDTH - 13, stats.hp.max(0), stats.max_hp, RGB::named(rltk::RED), black);
+    let mut row = top + 3;
+    if statuses.is_empty() {
+        ctx.print_color(left + 2, row, RGB::named(rltk::GRAY), black, "no status effects");
+        row += 1;
+    }
+    for status in statuses.iter() {
+        ctx.print_color(left + 2, row, RGB::named(rltk::ORANGE), black, status);
+        row += 1;
+    }
+    if let Some(threat) = threat {
+        ctx.print_color(left + 2, row, threat.color(), black, format!("Threat: {}", threat.name()));
+    }
+}
+
+/// Look mode: a reticle over (x, y) and the names of what's there, monsters tagged with
+/// how dangerous they are (see combat::threat_rating). A creature the player can see there
+/// also gets a detail panel (see examine_panel).
+/// Arrows move the reticle, L snaps it to the next visible entity, Escape goes back to the game.
+pub fn examine(ecs: &World, ctx: &mut Rltk, x: i32, y: i32) -> ExamineResult {
+    if let Some((screen_x, screen_y)) = ecs.fetch::<UiLayout>().to_screen(x, y) {
+        ctx.set_bg(screen_x, screen_y, RGB::named(rltk::CYAN));
+    }
+
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<Name>();
+    let positions = ecs.read_storage::<Position>();
+    let monsters = ecs.read_storage::<Monster>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let player_stats = combat_stats.get(ecs.fetch::<PlayerEntity>().0);
+    let map = ecs.fetch::<Map>();
+    let in_bounds = x >= 0 && x < map.width && y >= 0 && y < map.height;
+    let mut seen : Vec<(String, Option<combat::ThreatRating>)> = Vec::new();
+    if in_bounds && map.revealed_tiles[map.xy_idx(x, y)] {
+        for (entity, name, pos) in (&entities, &names, &positions).join() {
+            if pos.x == x && pos.y == y {
+                let threat = match (monsters.get(entity), player_stats, combat_stats.get(entity)) {
+                    (Some(_monster), Some(player_stats), Some(stats)) => Some(combat::threat_rating(player_stats, stats)),
+                    _ => None,
+                };
+                seen.push((name.name.clone(), threat));
+            }
+        }
+    }
+    let white = RGB::named(rltk::WHITE);
+    let black = RGB::named(rltk::BLACK);
+    let mut column = 1;
+    let mut print = |ctx: &mut Rltk, text: &str, fg: RGB| {
+        ctx.print_color(column, 0, fg, black, text);
+        column += text.len() as i32;
+    };
+    print(ctx, "You see: ", white);
+    if seen.is_empty() {
+        print(ctx, "nothing of interest", white);
+    }
+    for (i, (name, threat)) in seen.iter().enumerate() {
+        if i > 0 {
+            print(ctx, ", ", white);
+        }
+        print(ctx, name, white);
+        if let Some(threat) = threat {
+            print(ctx, &format!(" ({})", threat.name()), threat.color());
+        }
+    }
+    print(ctx, "   (L: next, arrows: move, ESC: back)", white);
+
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let in_view = ecs.read_storage::<Viewshed>().get(player).is_some_and(|viewshed| viewshed.visible_tiles.contains(&rltk::Point::new(x, y)));
+    let creature = (&entities, &positions, &combat_stats).join()
+        .find(|(entity, pos, _stats)| pos.x == x && pos.y == y && *entity != player && crate::targeting::is_perceived(ecs, *entity))
+        .map(|(entity, _pos, _stats)| entity);
+    if let Some(creature) = creature.filter(|_| in_view) {
+        let screen_x = ecs.fetch::<UiLayout>().to_screen(x, y).map_or(x, |(screen_x, _screen_y)| screen_x);
+        examine_panel(ecs, ctx, creature, screen_x);
+    }
+
+    let clamp = |nx: i32, ny: i32| ExamineResult::MoveCursor { x: nx.clamp(0, map.width - 1), y: ny.clamp(0, map.height - 1) };
+    match ctx.key {
+        None => ExamineResult::NoResponse,
+        Some(key) => match key {
+            VirtualKeyCode::Escape => ExamineResult::Cancel,
+            VirtualKeyCode::Left | VirtualKeyCode::Numpad4 => clamp(x - 1, y),
+            VirtualKeyCode::Right | VirtualKeyCode::Numpad6 => clamp(x + 1, y),
+            VirtualKeyCode::Up | VirtualKeyCode::Numpad8 => clamp(x, y - 1),
+            VirtualKeyCode::Down | VirtualKeyCode::Numpad2 => clamp(x, y + 1),
+            VirtualKeyCode::L => {
+                let (nx, ny) = crate::targeting::next_look_position(ecs, Some((x, y)));
+                ExamineResult::MoveCursor { x: nx, y: ny }
+            }
+            _ => ExamineResult::NoResponse,
+        },
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum ThrowResult {
+    NoResponse,
+    MoveCursor { x: i32, y: i32 },
+    Throw { x: i32, y: i32 },
+    Cancel,
+}
+
+/// Aiming a throw: a reticle over (x, y), green while it's within `range` of the player and red
+/// beyond. Arrows move it, Enter throws at it (only within range), Escape gives up.
+pub fn throw_target(ecs: &World, ctx: &mut Rltk, x: i32, y: i32, range: i32) -> ThrowResult {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let in_range = ecs.read_storage::<Position>().get(player).is_some_and(|pos| {
+        rltk::DistanceAlg::Pythagoras.distance2d(rltk::Point::new(pos.x, pos.y), rltk::Point::new(x, y)) <= range as f32
+    });
+    if let Some((screen_x, screen_y)) = ecs.fetch::<UiLayout>().to_screen(x, y) {
+        ctx.set_bg(screen_x, screen_y, if in_range { RGB::named(rltk::GREEN) } else { RGB::named(rltk::RED) });
+    }
+    ctx.print_color(1, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Throw where? (arrows: move, ENTER: throw, ESC: back)");
+
+    let map = ecs.fetch::<Map>();
+    let clamp = |nx: i32, ny: i32| ThrowResult::MoveCursor { x: nx.clamp(0, map.width - 1), y: ny.clamp(0, map.height - 1) };
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => ThrowResult::Cancel,
+        Some(VirtualKeyCode::Left | VirtualKeyCode::Numpad4) => clamp(x - 1, y),
+        Some(VirtualKeyCode::Right | VirtualKeyCode::Numpad6) => clamp(x + 1, y),
+        Some(VirtualKeyCode::Up | VirtualKeyCode::Numpad8) => clamp(x, y - 1),
+        Some(VirtualKeyCode::Down | VirtualKeyCode::Numpad2) => clamp(x, y + 1),
+        Some(VirtualKeyCode::Return) if in_range => ThrowResult::Throw { x, y },
+        _ => ThrowResult::NoResponse,
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum TeleportResult {
+    NoResponse,
+    Edit { x: i32, y: i32, editing_y: bool },
+    Teleport { x: i32, y: i32 },
+    Cancel,
+}
+
+/// The value of a digit key, top row or numpad.
+fn digit(key: VirtualKeyCode) -> Option<i32> {
+    use VirtualKeyCode::*;
+    [(Key0, Numpad0), (Key1, Numpad1), (Key2, Numpad2), (Key3, Numpad3), (Key4, Numpad4),
+     (Key5, Numpad5), (Key6, Numpad6), (Key7, Numpad7), (Key8, Numpad8), (Key9, Numpad9)]
+        .iter()
+        .position(|(top, pad)| key == *top || key == *pad)
+        .map(|value| value as i32)
+}
+
+/// Debug prompt for where to teleport the player: digits type the coordinate being edited
+/// (x first), Backspace deletes one, Comma or Tab moves on to y, Enter jumps, Escape gives up.
+pub fn teleport_prompt(ctx: &mut Rltk, x: i32, y: i32, editing_y: bool) -> TeleportResult {
+    let (shown_x, shown_y) = (x.to_string(), if editing_y { y.to_string() } else { "_".to_string() });
+    ctx.print_color(1, 0, RGB::named(rltk::MAGENTA), RGB::named(rltk::BLACK), format!("Teleport to x,y: {},{}  (ENTER: go, ESC: back)", shown_x, shown_y));
+
+    let edit = |value: i32| if editing_y { TeleportResult::Edit { x, y: value, editing_y } } else { TeleportResult::Edit { x: value, y, editing_y } };
+    let current = if editing_y { y } else { x };
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => TeleportResult::Cancel,
+        Some(VirtualKeyCode::Return) if editing_y => TeleportResult::Teleport { x, y },
+        Some(VirtualKeyCode::Return | VirtualKeyCode::Comma | VirtualKeyCode::Tab) if !editing_y => TeleportResult::Edit { x, y: 0, editing_y: true },
+        Some(VirtualKeyCode::Back) => edit(current / 10),
+        Some(key) => match digit(key) {
+            Some(value) => edit((current * 10 + value).min(9999)),
+            None => TeleportResult::NoResponse,
+        },
+        None => TeleportResult::NoResponse,
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum DropResult {
+    NoResponse { selection: usize },
+    Drop { category: Option<crate::backpack::ItemCategory> },
+    Cancel,
+}
+
+/// The drop screen: "everything" first, then each category the player's backpack holds, with
+/// how many items of it there are. Up/Down move the cursor, Enter drops the whole lot, Escape goes back.
+pub fn drop_menu(ecs: &World, ctx: &mut Rltk, selection: usize) -> DropResult {
+    use crate::backpack::{self, ItemCategory};
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let carried = backpack::carried(ecs, player);
+    let mut choices : Vec<(Option<ItemCategory>, usize)> = vec![(None, carried.len())];
+    for category in ItemCategory::ALL {
+        let count = carried.iter().filter(|(_entity, kind)| *kind == category).count();
+        if count > 0 {
+            choices.push((Some(category), count));
+        }
+    }
+    let selection = selection.min(choices.len() - 1);
+
+    let (x, y) = (20, 12);
+    let height = choices.len() as i32 + 3;
+    ctx.draw_box(x, y, 39, height, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(x + 3, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Drop");
+    ctx.print_color(x + 3, y + height, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ENTER to drop, ESCAPE to go back");
+    for (i, (category, count)) in choices.iter().enumerate() {
+        let fg = if i == selection { RGB::named(rltk::MAGENTA) } else { RGB::named(rltk::WHITE) };
+        let label = category.map_or("Everything", |category| category.name());
+        ctx.print_color(x + 2, y + 2 + i as i32, fg, RGB::named(rltk::BLACK), format!("{} ({})", label, count));
+    }
+
+    match ctx.key {
+        Some(VirtualKeyCode::Up | VirtualKeyCode::Numpad8) => DropResult::NoResponse { selection: (selection + choices.len() - 1) % choices.len() },
+        Some(VirtualKeyCode::Down | VirtualKeyCode::Numpad2) => DropResult::NoResponse { selection: (selection + 1) % choices.len() },
+        Some(VirtualKeyCode::Return) => DropResult::Drop { category: choices[selection].0 },
+        Some(VirtualKeyCode::Escape) => DropResult::Cancel,
+        _ => DropResult::NoResponse { selection },
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum MenuResult {
+    NoResponse,
+    Cancel,
+}
+
+/// Number of bestiary lines that fit in the menu box.
+const BESTIARY_ROWS: usize = 20;
+
+/// Draws the "known monsters" screen. Up/Down scroll the list, Escape or B close it.
+pub fn show_bestiary(ecs: &mut World, ctx: &mut Rltk) -> MenuResult {
+    let mut bestiary = ecs.write_resource::<Bestiary>();
+
+    let max_scroll = bestiary.entries.len().saturating_sub(BESTIARY_ROWS);
+    bestiary.scroll = bestiary.scroll.min(max_scroll);
+
+    let x = 10;
+    let y = 10;
+    let height = BESTIARY_ROWS as i32 + 3;
+    ctx.draw_box(x, y, 59, height, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(x + 3, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Bestiary");
+    ctx.print_color(x + 3, y + height, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Up/Down to scroll, ESCAPE to close");
+
+    if bestiary.entries.is_empty() {
+        ctx.print(x + 2, y + 2, "You haven't met any monster yet.");
+    }
+
+    for (row, (name, entry)) in (y + 2..).zip(bestiary.entries.iter().skip(bestiary.scroll).take(BESTIARY_ROWS)) {
+        ctx.set(x + 2, row, entry.fg, RGB::named(rltk::BLACK), entry.glyph);
+        ctx.print(x + 4, row, name);
+        match &entry.stats {
+            Some(stats) => ctx.print(x + 20, row, format!("HP:{} DEF:{} POW:{}  Killed:{}", stats.max_hp, stats.defense, stats.power, entry.kills)),
+            None => ctx.print(x + 20, row, "HP:??? DEF:??? POW:???"),
+        }
+    }
+
+    match ctx.key {
+        None => MenuResult::NoResponse,
+        Some(key) => match key {
+            VirtualKeyCode::Escape | VirtualKeyCode::B => MenuResult::Cancel,
+            VirtualKeyCode::Up | VirtualKeyCode::Numpad8 => {
+                bestiary.scroll = bestiary.scroll.saturating_sub(1);
+                MenuResult::NoResponse
+            }
+            VirtualKeyCode::Down | VirtualKeyCode::Numpad2 => {
+                bestiary.scroll = (bestiary.scroll + 1).min(max_scroll);
+                MenuResult::NoResponse
+            }
+            _ => MenuResult::NoResponse,
+        },
+    }
+}
+
+/// How many lines of the legend fit in its box; the rest is cut off.
+const LEGEND_ROWS: usize = 30;
+
+/// The map legend (see legend.rs): every glyph of the current level next to its meaning.
+pub fn show_legend(ecs: &World, ctx: &mut Rltk) -> MenuResult {
+    let entries = crate::legend::legend(ecs);
+    let (x, y) = (20, 5);
+    let height = entries.len().clamp(1, LEGEND_ROWS) as i32 + 3;
+    ctx.draw_box(x, y, 39, height, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(x + 3, y, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Legend");
+    ctx.print_color(x + 3, y + height, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "ESCAPE to close");
+
+    for (row, entry) in entries.iter().take(LEGEND_ROWS).enumerate() {
+        let row = y + 2 + row as i32;
+        ctx.set(x + 2, row, entry.fg, RGB::named(rltk::BLACK), entry.glyph);
+        ctx.print(x + 4, row, &entry.meaning);
+    }
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape | VirtualKeyCode::Slash) => MenuResult::Cancel,
+        _ => MenuResult::NoResponse,
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum LogResult {
+    NoResponse,
+    Scroll { scroll: usize },
+    Cancel,
+}
+
+/// The message history, full screen, newest message at the bottom. `scroll` is how many
+/// messages we are looking back from the newest: PageUp/PageDown move a page, Up/Down one line,
+/// Escape or M close it.
+pub fn show_log(ecs: &World, ctx: &mut Rltk, scroll: usize) -> LogResult {
+    let log = ecs.fetch::<GameLog>();
+    let screen = ecs.fetch::<UiLayout>().log_rect; // the log box spans the whole width and ends on the last row
+    let (width, height) = (screen.x2 + 1, screen.y2 + 1);
+    let rows = (height - 4).max(1) as usize;
+
+    let max_scroll = log.entries.len().saturating_sub(rows);
+    let scroll = scroll.min(max_scroll);
+
+    ctx.draw_box(0, 0, width - 1, height - 1, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(3, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Message log");
+    ctx.print_color(3, height - 1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "PgUp/PgDn to scroll, ESCAPE to close");
+
+    // the page ends `scroll` messages before the newest one
+    let end = log.entries.len() - scroll;
+    let start = end.saturating_sub(rows);
+    let first_row = height - 2 - (end - start) as i32;
+    for (y, message) in (first_row..).zip(log.entries.range(start..end)) {
+        ctx.print(2, y, message);
+    }
+    if scroll > 0 {
+        ctx.print_color(width - 16, height - 1, RGB::named(rltk::GRAY), RGB::named(rltk::BLACK), format!("({} newer)", scroll));
+    }
+
+    match ctx.key {
+        None => LogResult::NoResponse,
+        Some(key) => match key {
+            VirtualKeyCode::Escape | VirtualKeyCode::M => LogResult::Cancel,
+            VirtualKeyCode::PageUp => LogResult::Scroll { scroll: (scroll + rows).min(max_scroll) },
+            VirtualKeyCode::PageDown => LogResult::Scroll { scroll: scroll.saturating_sub(rows) },
+            VirtualKeyCode::Up | VirtualKeyCode::Numpad8 => LogResult::Scroll { scroll: (scroll + 1).min(max_scroll) },
+            VirtualKeyCode::Down | VirtualKeyCode::Numpad2 => LogResult::Scroll { scroll: scroll.saturating_sub(1) },
+            _ => LogResult::NoResponse,
+        },
+    }
+}
+
+/// Final score: collected gold plus 10 points for every monster killed.
+pub fn final_score(ecs: &World) -> i32 {
+    let wallet = ecs.fetch::<Wallet>();
+    let bestiary = ecs.fetch::<Bestiary>();
+    let kills : i32 = bestiary.entries.values().map(|entry| entry.kills).sum();
+    wallet.gold + kills * 10
+}
+
+/// The death screen, with the final score. Escape quits the game.
+pub fn game_over(ecs: &World, ctx: &mut Rltk) -> MenuResult {
+    let gold = ecs.fetch::<Wallet>().gold;
+    ctx.print_color_centered(15, RGB::named(rltk::RED), RGB::named(rltk::BLACK), "You are dead!");
+    ctx.print_color_centered(18, RGB::named(rltk::GOLD), RGB::named(rltk::BLACK), format!("Gold collected: {}", gold));
+    ctx.print_color_centered(19, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), format!("Final score: {}", final_score(ecs)));
+    ctx.print_color_centered(22, RGB::named(rltk::MAGENTA), RGB::named(rltk::BLACK), "Press ESCAPE to quit.");
+
+    match ctx.key {
+        Some(VirtualKeyCode::Escape) => MenuResult::Cancel,
+        _ => MenuResult::NoResponse,
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum ConfirmResult {
+    NoResponse,
+    Yes,
+    No,
+}
+
+/// A yes/no box over whatever is on screen: Y or Enter confirm, N or Escape cancel.
+pub fn confirm(question: &str, ctx: &mut Rltk) -> ConfirmResult {
+    let width = question.len() as i32 + 4;
+    let x = (ctx.get_char_size().0 as i32 - width) / 2;
+    ctx.draw_box(x, 20, width, 2, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color(x + 2, 21, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), question);
+
+    match ctx.key {
+        Some(VirtualKeyCode::Y) | Some(VirtualKeyCode::Return) => ConfirmResult::Yes,
+        Some(VirtualKeyCode::N) | Some(VirtualKeyCode::Escape) => ConfirmResult::No,
+        _ => ConfirmResult::NoResponse,
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum MainMenuResult {
+    NoSelection { selected: DifficultyLevel },
+    Selected { selected: DifficultyLevel },
+    Settings,
+}
+
+/// The main menu: pick a difficulty with Up/Down and confirm with Enter. S opens the settings.
+pub fn main_menu(selection: DifficultyLevel, ctx: &mut Rltk) -> MainMenuResult {
+    ctx.print_color_centered(15, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Roguelike Tutorial");
+    ctx.print_color_centered(17, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "Choose your difficulty:");
+
+    let options = [
+        (DifficultyLevel::Easy, "Easy"),
+        (DifficultyLevel::Normal, "Normal"),
+        (DifficultyLevel::Hard, "Hard"),
+    ];
+    for (i, (level, label)) in options.iter().enumerate() {
+        let fg = if *level == selection { RGB::named(rltk::MAGENTA) } else { RGB::named(rltk::WHITE) };
+        ctx.print_color_centered(19 + i as i32, fg, RGB::named(rltk::BLACK), label);
+    }
+    ctx.print_color_centered(19 + options.len() as i32 + 1, RGB::named(rltk::GRAY), RGB::named(rltk::BLACK), "S: settings");
+
+    let index = options.iter().position(|(level, _)| *level == selection).unwrap_or(1);
+    match ctx.key {
+        Some(VirtualKeyCode::Up) | Some(VirtualKeyCode::Numpad8) => {
+            MainMenuResult::NoSelection { selected: options[(index + options.len() - 1) % options.len()].0 }
+        }
+        Some(VirtualKeyCode::Down) | Some(VirtualKeyCode::Numpad2) => {
+            MainMenuResult::NoSelection { selected: options[(index + 1) % options.len()].0 }
+        }
+        Some(VirtualKeyCode::Return) => MainMenuResult::Selected { selected: selection },
+        Some(VirtualKeyCode::S) => MainMenuResult::Settings,
+        _ => MainMenuResult::NoSelection { selected: selection },
+    }
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum SettingsResult {
+    NoResponse { selection: usize },
+    Selected { profile: InputProfile },
+    Toggled { toggle: Toggle },
+    Cancel,
+}
+
+/// The settings screen: the movement key profiles, the `active` one marked, then the on/off
+/// switches of `config` (see config::Toggle) with their current state.
+/// Up/Down move the cursor (`selection`) across both lists, Enter switches to the highlighted
+/// profile or flips the highlighted toggle, Escape goes back.
+pub fn settings_menu(active: InputProfile, config: &GameConfig, selection: usize, ctx: &mut Rltk) -> SettingsResult {
+    ctx.print_color_centered(11, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Settings");
+    ctx.print_color_centered(13, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "Movement keys:");
+
+    let profiles = InputProfile::ALL;
+    let toggles = Toggle::ALL;
+    let entries = profiles.len() + toggles.len();
+    let selection = selection.min(entries - 1);
+    for (i, profile) in profiles.iter().enumerate() {
+        let fg = if i == selection { RGB::named(rltk::MAGENTA) } else { RGB::named(rltk::WHITE) };
+        let marker = if *profile == active { "* " } else { "  " };
+        ctx.print_color_centered(15 + i as i32, fg, RGB::named(rltk::BLACK), format!("{}{}", marker, profile.name()));
+    }
+    let toggles_y = 15 + profiles.len() as i32 + 1;
+    ctx.print_color_centered(toggles_y, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK), "Options:");
+    for (i, toggle) in toggles.iter().enumerate() {
+        let fg = if profiles.len() + i == selection { RGB::named(rltk::MAGENTA) } else { RGB::named(rltk::WHITE) };
+        let state = if toggle.is_on(config) { "on" } else { "off" };
+        ctx.print_color_centered(toggles_y + 2 + i as i32, fg, RGB::named(rltk::BLACK), format!("{}: {}", toggle.name(), state));
+    }
+    ctx.print_color_centered(toggles_y + 2 + toggles.len() as i32 + 1, RGB::named(rltk::GRAY), RGB::named(rltk::BLACK), "ENTER to choose, ESCAPE to go back");
+
+    match ctx.key {
+        Some(VirtualKeyCode::Up) | Some(VirtualKeyCode::Numpad8) => {
+            SettingsResult::NoResponse { selection: (selection + entries - 1) % entries }
+        }
+        Some(VirtualKeyCode::Down) | Some(VirtualKeyCode::Numpad2) => {
+            SettingsResult::NoResponse { selection: (selection + 1) % entries }
+        }
+        Some(VirtualKeyCode::Return) if selection < profiles.len() => SettingsResult::Selected { profile: profiles[selection] },
+        Some(VirtualKeyCode::Return) => SettingsResult::Toggled { toggle: toggles[selection - profiles.len()] },
+        Some(VirtualKeyCode::Escape) => SettingsResult::Cancel,
+        _ => SettingsResult::NoResponse { selection },
+    }
+}
@@ -0,0 +1,163 @@
+use rltk::RGB;
+use specs::prelude::*;
+use crate::components::{PlayerEntity, Position};
+use crate::direction::Direction;
+use crate::frontier;
+use crate::draw_target::DrawTarget;
+use crate::gui::UiLayout;
+use crate::map::{Map, Tile};
+use crate::map_builders::{distances_from, start_point};
+
+// HEATMAP --------------------------------------------------------------------
+// Vista di debug per bilanciare la generazione: colora ogni casella secondo una metrica
+// (distanza dall'inizio, apertura, densità di pericoli), dal blu (poco) al rosso (tanto).
+// Il campo si ricalcola a ogni frame dalla mappa corrente, niente cache da tenere allineata.
+
+/// What the overlay measures.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HeatmapMetric {
+    #[default]
+    Off,
+    DistanceFromStart, // walking distance from the start point, unreachable tiles stay uncoloured
+    Openness,          // walkable neighbours of each walkable tile
+    HazardDensity,     // damaging tiles, bad statuses and traps within HAZARD_RADIUS
+    Frontier,          // explored tiles bordering the unexplored (see frontier.rs), the nearest one in yellow
+}
+
+impl HeatmapMetric {
+    /// The metric the toggle key switches to after this one.
+    pub fn next(self) -> HeatmapMetric {
+        match self {
+            HeatmapMetric::Off => HeatmapMetric::DistanceFromStart,
+            HeatmapMetric::DistanceFromStart => HeatmapMetric::Openness,
+            HeatmapMetric::Openness => HeatmapMetric::HazardDensity,
+            HeatmapMetric::HazardDensity => HeatmapMetric::Frontier,
+            HeatmapMetric::Frontier => HeatmapMetric::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HeatmapMetric::Off => "off",
+            HeatmapMetric::DistanceFromStart => "distance from start",
+            HeatmapMetric::Openness => "openness",
+            HeatmapMetric::HazardDensity => "hazard density",
+            HeatmapMetric::Frontier => "exploration frontier",
+        }
+    }
+}
+
+/// The metric currently shown (risorsa).
+#[derive(Default)]
+pub struct HeatmapView {
+    pub metric : HeatmapMetric,
+}
+
+/// How far (in tiles, square) hazards are counted around each tile.
+const HAZARD_RADIUS : i32 = 3;
+
+fn is_hazard(tile: &Tile) -> bool {
+    tile.direct_damage > 0 || tile.status_effect.is_some() || tile.trap_kind.is_some()
+}
+
+/// The value of `metric` for every tile, scaled to 0.0..=1.0; None where it means nothing
+/// (walls for openness, unreachable tiles for distance, everything when the view is off).
+pub fn field(map: &Map, metric: HeatmapMetric) -> Vec<Option<f32>> {
+    let raw : Vec<Option<f32>> = match metric {
+        HeatmapMetric::Off => vec![None; map.tiles.len()],
+        HeatmapMetric::DistanceFromStart => distances_from(map, start_point(map)).into_iter()
+            .map(|distance| distance.map(|d| d as f32))
+            .collect(),
+        HeatmapMetric::Openness => (0..map.tiles.len()).map(|idx| {
+            if !map.tiles[idx].walkable {
+                return None;
+            }
+            let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+            let open = Direction::ALL.iter()
+                .map(|direction| direction.to_delta())
+                .filter(|(dx, dy)| {
+                    let (nx, ny) = (x + dx, y + dy);
+                    nx >= 0 && nx < map.width && ny >= 0 && ny < map.height && map.tiles[map.xy_idx(nx, ny)].walkable
+                })
+                .count();
+            Some(open as f32)
+        }).collect(),
+        HeatmapMetric::HazardDensity => (0..map.tiles.len()).map(|idx| {
+            let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+            let mut hazards = 0;
+            for ny in (y - HAZARD_RADIUS).max(0)..=(y + HAZARD_RADIUS).min(map.height - 1) {
+                for nx in (x - HAZARD_RADIUS).max(0)..=(x + HAZARD_RADIUS).min(map.width - 1) {
+                    if is_hazard(&map.tiles[map.xy_idx(nx, ny)]) {
+                        hazards += 1;
+                    }
+                }
+            }
+            Some(hazards as f32)
+        }).collect(),
+        HeatmapMetric::Frontier => (0..map.tiles.len())
+            .map(|idx| frontier::is_frontier(map, idx).then_some(1.0))
+            .collect(),
+    };
+
+    let max = raw.iter().flatten().fold(0.0_f32, |max, value| max.max(*value));
+    if max <= 0.0 {
+        return raw;
+    }
+    raw.into_iter().map(|value| value.map(|v| v / max)).collect()
+}
+
+/// Paints the background of every map tile with the active metric (blue = low, red = high).
+/// Nothing to do when the view is off.
+pub fn draw_heatmap(ecs: &World, ctx: &mut dyn DrawTarget) {
+    let metric = ecs.fetch::<HeatmapView>().metric;
+    if metric == HeatmapMetric::Off {
+        return;
+    }
+    let map = ecs.fetch::<Map>();
+    let layout = ecs.fetch::<UiLayout>();
+    let map_rect = layout.map_rect;
+    for (idx, value) in field(&map, metric).into_iter().enumerate() {
+        let on_screen = layout.to_screen(idx as i32 % map.width, idx as i32 / map.width);
+        if let (Some(value), Some((x, y))) = (value, on_screen) {
+            let tile = &map.tiles[idx];
+            let heat = RGB::from_f32(value * 0.8, 0.1, (1.0 - value) * 0.8);
+            ctx.set(x, y, tile.fg, heat, tile.glyph);
+        }
+    }
+    let mut title = format!("Heatmap: {}", metric.name());
+    if metric == HeatmapMetric::Frontier {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        let from = ecs.read_storage::<Position>().get(player).map(|pos| (pos.x, pos.y));
+        match from.and_then(|from| frontier::nearest_frontier(&map, from)) {
+            Some(idx) => {
+                let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+                if let Some((screen_x, screen_y)) = layout.to_screen(x, y) {
+                    ctx.set(screen_x, screen_y, map.tiles[idx].fg, RGB::named(rltk::YELLOW), map.tiles[idx].glyph);
+                }
+                title = format!("{} (nearest at {}, {})", title, x, y);
+            }
+            None => title = format!("{} (all explored)", title),
+        }
+    }
+    ctx.print_color(map_rect.x1 + 1, map_rect.y1, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), &title);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Biome, Building, xy_idx};
+
+    #[test]
+    fn openness_is_lower_along_the_walls() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        for x in 0..width {
+            map.tiles[xy_idx(x, 0, width)] = Building.get_wall();
+        }
+
+        let openness = field(&map, HeatmapMetric::Openness);
+        assert_eq!(openness[xy_idx(10, 0, width)], None);
+        assert_eq!(openness[xy_idx(10, 10, width)], Some(1.0));
+        assert_eq!(openness[xy_idx(10, 1, width)], Some(5.0 / 8.0));
+    }
+}
@@ -0,0 +1,60 @@
+use specs::prelude::*;
+use super::{CombatStats, Hunger, HungerState, Name};
+use crate::effects::{EffectQueue, EffectTarget, EffectType};
+use crate::gamelog::GameLog;
+
+/// Satiation the player starts with (and the most a meal can bring them to).
+pub const MAX_SATIATION : i32 = 1500;
+/// Above this the player is well fed.
+const WELL_FED_ABOVE : i32 = 1200;
+/// Below this the player is hungry; at zero they are starving.
+const HUNGRY_BELOW : i32 = 300;
+
+/// The hunger state for a given satiation.
+pub fn hunger_state(satiation: i32) -> HungerState {
+    if satiation > WELL_FED_ABOVE {
+        HungerState::WellFed
+    } else if satiation >= HUNGRY_BELOW {
+        HungerState::Normal
+    } else if satiation > 0 {
+        HungerState::Hungry
+    } else {
+        HungerState::Starving
+    }
+}
+
+/// Hunger clock, once per player turn: satiation goes down by one, the log warns when the
+/// state gets worse, and a starving entity loses 1 hp a turn (as a queued effect) until it eats something.
+pub struct HungerSystem {}
+
+impl<'a> System<'a> for HungerSystem {
+    type SystemData = ( WriteExpect<'a, GameLog>,
+                        WriteExpect<'a, EffectQueue>,
+                        Entities<'a>,
+                        ReadStorage<'a, Name>,
+                        WriteStorage<'a, Hunger>,
+                        ReadStorage<'a, CombatStats>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut log, mut effects, entities, names, mut hungers, combat_stats) = data;
+
+        for (entity, name, hunger, stats) in (&entities, &names, &mut hungers, &combat_stats).join() {
+            hunger.satiation = (hunger.satiation - 1).max(0);
+            let state = hunger_state(hunger.satiation);
+            if state != hunger.state {
+                match state {
+                    HungerState::Hungry => log.push("You are hungry.".to_string()),
+                    HungerState::Starving => log.push("You are starving!".to_string()),
+                    _ => {}
+                }
+                hunger.state = state;
+            }
+            if hunger.state == HungerState::Starving {
+                effects.push(None, EffectType::Damage { amount: 1 }, EffectTarget::Single(entity));
+                if stats.hp <= 1 {
+                    log.push(format!("{} starves to death.", name.name));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use specs::prelude::*;
+use crate::components::{AggroRange, Monster, Name, PlayerEntity, Position, Summoned, Viewshed};
+use crate::targeting;
+
+// INITIATIVE -----------------------------------------------------------------
+// Chi agisce dopo chi: l'ordine dei turni è fisso (vedi State::end_turn), prima il giocatore,
+// poi i mostri nell'ordine in cui MonsterAI li scorre (per id), infine gli alleati evocati.
+// Qui lo si ricostruisce per mostrarlo a schermo, limitato a quello che il giocatore vede:
+// i mostri fuori vista o ancora addormentati non compaiono.
+
+/// How many upcoming actors the turn order widget lists.
+pub const PREVIEW_LENGTH : usize = 6;
+
+/// The next `count` creatures due to act, by name, starting from the player: the visible awake
+/// monsters, then the summoned allies in sight, then round again to the player if there's room.
+/// Empty when no monster is in view (outside a fight the order is of no interest).
+pub fn upcoming_turns(ecs: &World, count: usize) -> Vec<String> {
+    let mut monsters = targeting::visible_monsters(ecs);
+    if monsters.is_empty() {
+        return Vec::new();
+    }
+    // the same order as the join in MonsterAI
+    monsters.sort_by_key(|monster| monster.id());
+
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let entities = ecs.entities();
+    let names = ecs.read_storage::<Name>();
+    let aggro_ranges = ecs.read_storage::<AggroRange>();
+    let summoned = ecs.read_storage::<Summoned>();
+    let monster_tags = ecs.read_storage::<Monster>();
+    let positions = ecs.read_storage::<Position>();
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let in_sight = |entity: Entity| match (viewsheds.get(player), positions.get(entity)) {
+        (Some(viewshed), Some(pos)) => viewshed.visible_tiles.iter().any(|p| p.x == pos.x && p.y == pos.y),
+        _ => false,
+    };
+    let name = |entity: Entity| names.get(entity).map_or("Something".to_string(), |name| name.name.clone());
+
+    let mut round = vec!["You".to_string()];
+    round.extend(monsters.into_iter()
+        .filter(|monster| aggro_ranges.get(*monster).is_none_or(|aggro| aggro.awake))
+        .map(name));
+    round.extend((&entities, &summoned, !&monster_tags).join()
+        .filter(|(ally, _summoned, _not_monster)| in_sight(*ally))
+        .map(|(ally, _summoned, _not_monster)| name(ally)));
+    round.iter().cycle().take(count).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{register_components, FovShape};
+    use rltk::Point;
+
+    #[test]
+    fn the_player_goes_first_then_the_awake_monsters_in_sight() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+
+        let visible : Vec<Point> = (0..10).map(|x| Point::new(x, 0)).collect();
+        let player = ecs.create_entity()
+            .with(Position { x: 0, y: 0 })
+            .with(Viewshed { visible_tiles: visible, range: 8, dirty: false, fov_shape: FovShape::Circle })
+            .build();
+        ecs.insert(PlayerEntity(player));
+        assert!(upcoming_turns(&ecs, PREVIEW_LENGTH).is_empty());
+
+        let mut monster = |name: &str, x: i32| ecs.create_entity()
+            .with(Position { x, y: 0 })
+            .with(Monster {})
+            .with(Name { name: name.to_string() })
+            .build();
+        monster("Orc", 5);
+        monster("Orc", 2);
+        let sleeper = monster("Goblin", 3);
+        monster("Bat", 9);
+        monster("Ogre", 20); // out of sight
+        ecs.write_storage::<AggroRange>().insert(sleeper, AggroRange { radius: 4, awake: false }).unwrap();
+
+        assert_eq!(upcoming_turns(&ecs, 4), vec!["You", "Orc", "Orc", "Bat"]);
+        assert_eq!(upcoming_turns(&ecs, 6), vec!["You", "Orc", "Orc", "Bat", "You", "Orc"]);
+    }
+}
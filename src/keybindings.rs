@@ -0,0 +1,90 @@
+use rltk::VirtualKeyCode;
+use crate::direction::Direction;
+
+// KEYBINDINGS ----------------------------------------------------------------
+// Profili di tasti per il movimento, scelti dal menu delle impostazioni.
+// Il profilo attivo è una risorsa: player_input la legge a ogni tasto, quindi cambiarlo
+// ha effetto subito. Le altre azioni (B, F, L, ...) restano uguali in tutti i profili,
+// ma il movimento ha la precedenza: con i tasti vi B e L muovono, e bestiario e osserva
+// restano su \ e ; (come in NetHack).
+
+/// The built-in movement presets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputProfile {
+    Classic,         // WASD + QEZC, plus arrows and numpad
+    Numpad,          // numpad only
+    ArrowsDiagonals, // arrows, with Home/PgUp/End/PgDn for the diagonals
+    ViKeys,          // hjkl + yubn
+}
+
+impl InputProfile {
+    /// Every profile, in the order the settings menu lists them.
+    pub const ALL : [InputProfile; 4] = [InputProfile::Classic, InputProfile::Numpad, InputProfile::ArrowsDiagonals, InputProfile::ViKeys];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputProfile::Classic => "Classic (WASD)",
+            InputProfile::Numpad => "Numpad",
+            InputProfile::ArrowsDiagonals => "Arrows + Diagonals",
+            InputProfile::ViKeys => "Vi-keys",
+        }
+    }
+}
+
+/// The movement keys of the active profile (risorsa).
+pub struct Keybindings {
+    pub profile : InputProfile,
+    moves : Vec<(VirtualKeyCode, Direction)>,
+}
+
+impl Keybindings {
+    /// The named preset for `profile`.
+    pub fn preset(profile: InputProfile) -> Keybindings {
+        use VirtualKeyCode::*;
+        let numpad = [
+            (Numpad4, Direction::West), (Numpad6, Direction::East), (Numpad8, Direction::North), (Numpad2, Direction::South),
+            (Numpad7, Direction::NorthWest), (Numpad9, Direction::NorthEast), (Numpad1, Direction::SouthWest), (Numpad3, Direction::SouthEast),
+        ];
+        let arrows = [(Left, Direction::West), (Right, Direction::East), (Up, Direction::North), (Down, Direction::South)];
+        let moves : Vec<(VirtualKeyCode, Direction)> = match profile {
+            InputProfile::Classic => [
+                (A, Direction::West), (D, Direction::East), (W, Direction::North), (X, Direction::South),
+                (Q, Direction::NorthWest), (E, Direction::NorthEast), (Z, Direction::SouthWest), (C, Direction::SouthEast),
+            ].into_iter().chain(arrows).chain(numpad).collect(),
+            InputProfile::Numpad => numpad.to_vec(),
+            InputProfile::ArrowsDiagonals => arrows.into_iter().chain([
+                (Home, Direction::NorthWest), (PageUp, Direction::NorthEast), (End, Direction::SouthWest), (PageDown, Direction::SouthEast),
+            ]).collect(),
+            InputProfile::ViKeys => vec![
+                (H, Direction::West), (L, Direction::East), (K, Direction::North), (J, Direction::South),
+                (Y, Direction::NorthWest), (U, Direction::NorthEast), (B, Direction::SouthWest), (N, Direction::SouthEast),
+            ],
+        };
+        Keybindings { profile, moves }
+    }
+
+    /// The direction `key` moves in with this profile, if any.
+    pub fn direction(&self, key: VirtualKeyCode) -> Option<Direction> {
+        self.moves.iter().find(|(bound, _direction)| *bound == key).map(|(_bound, direction)| *direction)
+    }
+}
+
+impl Default for Keybindings {
+    fn default() -> Keybindings {
+        Keybindings::preset(InputProfile::Classic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vi_keys_move_with_hjkl_and_yubn() {
+        let vi = Keybindings::preset(InputProfile::ViKeys);
+        assert_eq!(vi.direction(VirtualKeyCode::H), Some(Direction::West));
+        assert_eq!(vi.direction(VirtualKeyCode::N), Some(Direction::SouthEast));
+        assert_eq!(vi.direction(VirtualKeyCode::A), None);
+        assert_eq!(Keybindings::default().direction(VirtualKeyCode::A), Some(Direction::West));
+    }
+}
@@ -0,0 +1,144 @@
+use rltk::{FontCharType, RGB};
+use specs::prelude::*;
+use crate::components::{Hidden, Name, Position, Renderable, Viewshed};
+use crate::map::{DoorState, Map, Stairs, Tile};
+
+// LEGEND ---------------------------------------------------------------------
+// La legenda dei simboli (tasto ?): non è una lista fissa, la si ricava dal livello corrente,
+// cioè dalle caselle già scoperte e da quello che il gruppo ha sotto gli occhi.
+// Così elenca solo i simboli che il giocatore può effettivamente incontrare in questo bioma.
+
+/// One line of the legend: the glyph as drawn and what it stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegendEntry {
+    pub glyph : FontCharType,
+    pub fg : RGB,
+    pub meaning : String,
+}
+
+/// What a tile is, told from its properties rather than from the biome that made it
+/// (so lava and water, or the many glyphs of connected walls, come out right by themselves).
+pub fn describe_tile(tile: &Tile) -> String {
+    if let Some(stairs) = tile.stairs {
+        return match stairs {
+            Stairs::Down => "stairs down",
+            Stairs::Up => "stairs up",
+        }.to_string();
+    }
+    if let Some(target) = tile.portal {
+        return format!("portal to the {:?}", target);
+    }
+    if let Some(state) = tile.door_state {
+        return match state {
+            DoorState::Open => "open door",
+            DoorState::Closed => "closed door",
+            DoorState::Locked => "locked door",
+            DoorState::Broken => "broken door",
+        }.to_string();
+    }
+    if tile.trap_kind.is_some() {
+        return "trap".to_string();
+    }
+    if let Some(status) = tile.cleanses {
+        return format!("shallow water (washes off {})", crate::effects::status_name(status));
+    }
+    if tile.direct_damage > 0 || tile.status_effect.is_some() {
+        return match tile.status_effect {
+            Some(status) => format!("hazard ({})", crate::effects::status_name(status)),
+            None => "hazard".to_string(),
+        };
+    }
+    let name = match (tile.walkable, tile.transparent) {
+        (false, true) => "glass",
+        (false, false) => "wall",
+        _ if tile.slipperiness > 0 => "slippery ground",
+        _ if tile.movement_cost > 1 => "rough ground",
+        _ => "floor",
+    };
+    name.to_string()
+}
+
+/// The legend of the current level: a line for every kind of tile revealed so far (hidden
+/// traps stay hidden, they look like floor), then one for every kind of creature or object
+/// the party can see right now, the party itself included. Each meaning is listed once.
+pub fn legend(ecs: &World) -> Vec<LegendEntry> {
+    let mut entries : Vec<LegendEntry> = Vec::new();
+    let mut add = |glyph: FontCharType, fg: RGB, meaning: String| {
+        if !entries.iter().any(|entry| entry.meaning == meaning) {
+            entries.push(LegendEntry { glyph, fg, meaning });
+        }
+    };
+
+    let map = ecs.fetch::<Map>();
+    for (idx, tile) in map.tiles.iter().enumerate() {
+        if map.revealed_tiles[idx] && !(tile.trap_kind.is_some() && tile.trap_dc.is_some()) {
+            add(tile.glyph, tile.fg, describe_tile(tile));
+        }
+    }
+
+    let party = crate::party::party_members(ecs);
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let renderables = ecs.read_storage::<Renderable>();
+    let names = ecs.read_storage::<Name>();
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let hiddens = ecs.read_storage::<Hidden>();
+    let detection = crate::targeting::detects_hidden(ecs);
+    let in_sight = |x: i32, y: i32| party.iter().any(|member| viewsheds.get(*member)
+        .is_some_and(|viewshed| viewshed.visible_tiles.contains(&rltk::Point::new(x, y))));
+    let mut seen : Vec<_> = (&entities, &positions, &renderables, &names).join()
+        .filter(|(entity, pos, _render, _name)| party.contains(entity) || in_sight(pos.x, pos.y))
+        .filter(|(entity, ..)| detection || hiddens.get(*entity).is_none())
+        .collect();
+    seen.sort_by_key(|(entity, ..)| entity.id());
+    for (_entity, _pos, render, name) in seen {
+        add(render.glyph, render.fg, name.name.clone());
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{register_components, PlayerEntity};
+    use crate::map::{Biome, Building, Forest, Volcano, xy_idx};
+
+    #[test]
+    fn lists_each_kind_of_revealed_tile_and_visible_creature_once() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        map.tiles[xy_idx(3, 3, width)] = Volcano.get_water().unwrap();
+        map.tiles[xy_idx(4, 3, width)] = Forest.get_trap().unwrap();
+        map.tiles[xy_idx(5, 3, width)] = Building.get_wall();
+        map.tiles[xy_idx(6, 3, width)] = Building.get_wall();
+        for x in 2..=6 {
+            map.revealed_tiles[xy_idx(x, 3, width)] = true;
+        }
+        ecs.insert(map);
+
+        let player = ecs.create_entity()
+            .with(Position { x: 2, y: 3 })
+            .with(Renderable { glyph: rltk::to_cp437('@'), fg: RGB::named(rltk::YELLOW), bg: RGB::named(rltk::BLACK) })
+            .with(Name { name: "Player".to_string() })
+            .with(Viewshed { visible_tiles: vec![rltk::Point::new(2, 3), rltk::Point::new(3, 4)], range: 8, dirty: false, fov_shape: crate::components::FovShape::Circle })
+            .build();
+        ecs.insert(PlayerEntity(player));
+        ecs.create_entity()
+            .with(Position { x: 3, y: 4 })
+            .with(Renderable { glyph: rltk::to_cp437('o'), fg: RGB::named(rltk::RED), bg: RGB::named(rltk::BLACK) })
+            .with(Name { name: "Orc".to_string() })
+            .build();
+        ecs.create_entity()
+            .with(Position { x: 40, y: 40 })
+            .with(Renderable { glyph: rltk::to_cp437('O'), fg: RGB::named(rltk::RED), bg: RGB::named(rltk::BLACK) })
+            .with(Name { name: "Ogre".to_string() })
+            .build();
+
+        let meanings : Vec<String> = legend(&ecs).into_iter().map(|entry| entry.meaning).collect();
+        assert_eq!(meanings, vec!["floor", "hazard (burning)", "wall", "Player", "Orc"]);
+        assert_eq!(describe_tile(&Building.get_stairs().unwrap()), "stairs down");
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use crate::components::*;
+use crate::gamelog::GameLog;
+use crate::map::Map;
+
+// LEVEL STORE ----------------------------------------------------------------
+// Quando il giocatore lascia un livello, le entità (mostri, oggetti) vengono "congelate"
+// in una fotografia indicizzata per profondità, e ricreate quando ci ritorna.
+// Anche la mappa viene messa da parte, con quello che il giocatore ne aveva già esplorato.
+// Così tornare su un livello non rigenera né duplica le sue creature.
+// La fotografia copia tutti i componenti della lista di components.rs (for_each_component),
+// quindi un componente nuovo viene salvato senza toccare questo file.
+// Le fotografie sono dati semplici (serde): il salvataggio (save_system.rs) le scrive su file
+// insieme ai livelli congelati, e fotografa allo stesso modo anche il livello in corso.
+
+macro_rules! saved_components {
+    ($($component:ident),* ; $($linked:ident),*) => {
+        /// One component of a frozen entity. The components holding an Entity aren't here:
+        /// EntitySnapshot keeps the entity they point to as an index.
+        #[derive(Clone, Serialize, Deserialize)]
+        enum SavedComponent {
+            $($component($component)),*
+        }
+
+        /// Copies every component `entity` has (see components::for_each_component).
+        fn copy_components(ecs: &World, entity: Entity) -> Vec<SavedComponent> {
+            let mut components = Vec::new();
+            $(if let Some(component) = ecs.read_storage::<$component>().get(entity).cloned() {
+                components.push(SavedComponent::$component(component));
+            })*
+            components
+        }
+
+        /// Puts the copied components back, on the entity that replaces the frozen one.
+        fn paste_components(ecs: &World, entity: Entity, components: Vec<SavedComponent>) {
+            for component in components {
+                match component {
+                    $(SavedComponent::$component(component) => {
+                        ecs.write_storage::<$component>().insert(entity, component).expect("Unable to insert component");
+                    })*
+                }
+            }
+        }
+    };
+}
+for_each_component!(saved_components);
+
+/// Copy of every component of an entity.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EntitySnapshot {
+    components: Vec<SavedComponent>,
+    pack_leader: Option<usize>,    // index of the leader's snapshot in the same list
+    backpack_owner: Option<usize>, // index of the owner's snapshot, for an item in a backpack
+}
+
+/// A level the party has left: its map, what was on it, and the tile the party left it from.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FrozenLevel {
+    pub map: Map,
+    pub level: CurrentLevel,
+    pub exit: (i32, i32),
+    entities: Vec<EntitySnapshot>,
+}
+
+/// Every level the party has left, keyed by depth.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct LevelStore {
+    pub levels: HashMap<i32, FrozenLevel>,
+}
+
+/// Copies `entities`, in order. The entities don't survive the copy, so a pack leader or a
+/// backpack owner is remembered by its index in the list (and forgotten if it isn't in the list).
+pub fn snapshot_entities(ecs: &World, entities: &[Entity]) -> Vec<EntitySnapshot> {
+    let packs = ecs.read_storage::<PackMember>();
+    let backpacks = ecs.read_storage::<InBackpack>();
+    let index_of = |target: Entity| entities.iter().position(|entity| *entity == target);
+    entities.iter().map(|entity| EntitySnapshot {
+        components: copy_components(ecs, *entity),
+        pack_leader: packs.get(*entity).and_then(|pack| index_of(pack.leader)),
+        backpack_owner: backpacks.get(*entity).and_then(|item| index_of(item.owner)),
+    }).collect()
+}
+
+/// Creates one entity per snapshot and returns them in the same order, with packs and
+/// backpacks pointing at the new entities.
+pub fn restore_entities(ecs: &mut World, snapshots: Vec<EntitySnapshot>) -> Vec<Entity> {
+    let created : Vec<Entity> = snapshots.iter().map(|_snapshot| ecs.create_entity().build()).collect();
+    let mut links = Vec::new(); // (entity, leader, owner), resolved once every entity exists
+    for (entity, snapshot) in created.iter().zip(snapshots) {
+        paste_components(ecs, *entity, snapshot.components);
+        links.push((*entity, snapshot.pack_leader, snapshot.backpack_owner));
+    }
+
+    let mut pack_members = ecs.write_storage::<PackMember>();
+    let mut backpacks = ecs.write_storage::<InBackpack>();
+    for (entity, leader, owner) in links {
+        if let Some(leader) = leader.and_then(|leader| created.get(leader)) {
+            pack_members.insert(entity, PackMember { leader: *leader }).expect("Unable to insert pack member");
+        }
+        if let Some(owner) = owner.and_then(|owner| created.get(owner)) {
+            backpacks.insert(entity, InBackpack { owner: *owner }).expect("Unable to insert backpack item");
+        }
+    }
+    // what they saw is recomputed, from where they stand now
+    let mut viewsheds = ecs.write_storage::<Viewshed>();
+    for entity in created.iter() {
+        if let Some(viewshed) = viewsheds.get_mut(*entity) {
+            viewshed.visible_tiles.clear();
+            viewshed.dirty = true;
+        }
+    }
+    created
+}
+
+/// Saves the current level (map, CurrentLevel and every non-player entity) under its depth,
+/// with the tile the active player is leaving from, then removes the entities from the world.
+/// Dead monsters were already deleted, so they simply aren't part of the snapshot.
+pub fn freeze_level(ecs: &mut World) {
+    let to_freeze : Vec<Entity> = {
+        let all_entities = ecs.entities();
+        let players = ecs.read_storage::<Player>();
+        let positions = ecs.read_storage::<Position>();
+        (&all_entities, &positions, !&players).join().map(|(entity, _pos, _not_player)| entity).collect()
+    };
+    let entities = snapshot_entities(ecs, &to_freeze);
+
+    for entity in to_freeze {
+        ecs.delete_entity(entity).expect("Unable to delete");
+    }
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let exit = ecs.read_storage::<Position>().get(player).map_or((0, 0), |pos| (pos.x, pos.y));
+    let level = *ecs.fetch::<CurrentLevel>();
+    let map = (*ecs.fetch::<Map>()).clone();
+    ecs.write_resource::<LevelStore>().levels.insert(level.depth, FrozenLevel { map, level, exit, entities });
+}
+
+/// Puts back the map and the CurrentLevel saved for `depth` and recreates its entities.
+/// Returns the tile the party left the level from, or None if the level was never visited
+/// (or can't be restored), so the caller knows it has to generate a fresh level instead.
+pub fn thaw_level(ecs: &mut World, depth: i32) -> Option<(i32, i32)> {
+    let frozen = ecs.write_resource::<LevelStore>().levels.remove(&depth)?;
+    // a stored map that doesn't hold together is dropped: the caller generates a fresh level
+    if let Err(e) = frozen.map.validate() {
+        ecs.write_resource::<GameLog>().push(format!("The level could not be restored ({}).", e));
+        return None;
+    }
+    ecs.insert(frozen.map);
+    ecs.insert(frozen.level);
+    crate::path_cache::invalidate(ecs);
+    restore_entities(ecs, frozen.entities);
+    Some(frozen.exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::map::{Biome, BiomeKind, Building, StatusEffect};
+    use rltk::RGB;
+
+    #[test]
+    fn a_thawed_monster_keeps_every_component_and_its_pack() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(GameConfig { autosave: false, ..GameConfig::default() });
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 100 });
+        ecs.insert(LevelStore::default());
+        ecs.insert(Map::new(20, 10, Building.get_floor()));
+        ecs.insert(CurrentLevel { depth: 1, biome: BiomeKind::Building, seed: Some(3) });
+        let player = ecs.create_entity().with(Position { x: 1, y: 1 }).with(Player {}).build();
+        ecs.insert(PlayerEntity(player));
+
+        let leader = ecs.create_entity()
+            .with(Position { x: 5, y: 5 })
+            .with(Renderable { glyph: rltk::to_cp437('w'), fg: RGB::named(rltk::GRAY), bg: RGB::named(rltk::BLACK) })
+            .with(Monster {})
+            .build();
+        ecs.create_entity()
+            .with(Position { x: 6, y: 5 })
+            .with(Renderable { glyph: rltk::to_cp437('w'), fg: RGB::named(rltk::GRAY), bg: RGB::named(rltk::BLACK) })
+            .with(Monster {})
+            .with(PackMember { leader })
+            .with(StatusEffects { effects: vec![(StatusEffect::Poisoned, 3)] })
+            .with(Hunger { satiation: 40, state: HungerState::Normal })
+            .with(Summoned { turns_left: 7 })
+            .with(Viewshed { visible_tiles: Vec::new(), range: 6, dirty: false, fov_shape: FovShape::Circle })
+            .build();
+
+        freeze_level(&mut ecs);
+        ecs.maintain();
+        assert_eq!(ecs.read_storage::<Monster>().join().count(), 0);
+        assert_eq!(thaw_level(&mut ecs, 1), Some((1, 1)), "the party comes back where it left");
+        assert_eq!(thaw_level(&mut ecs, 1), None, "a level is thawed only once");
+
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let packs = ecs.read_storage::<PackMember>();
+        let (member, pack) = (&entities, &packs).join().map(|(entity, pack)| (entity, pack.leader)).next().expect("the pack survived");
+        assert_eq!(positions.get(pack).map(|pos| pos.x), Some(5));
+        assert_eq!(ecs.read_storage::<StatusEffects>().get(member).map(|status| status.effects.clone()), Some(vec![(StatusEffect::Poisoned, 3)]));
+        assert_eq!(ecs.read_storage::<Hunger>().get(member).map(|hunger| hunger.satiation), Some(40));
+        assert_eq!(ecs.read_storage::<Summoned>().get(member).map(|summoned| summoned.turns_left), Some(7));
+        assert!(ecs.read_storage::<Viewshed>().get(member).is_some_and(|viewshed| viewshed.dirty));
+    }
+}
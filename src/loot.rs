@@ -0,0 +1,40 @@
+use crate::rng::GameRng;
+
+// LOOT TABLES ----------------------------------------------------------------
+// Cosa lasciano cadere i mostri quando muoiono. Ogni tabella è una lista di (oggetto, peso):
+// la voce "None" è la probabilità di non lasciare nulla.
+// Per ora le tabelle stanno qui nel codice, finché non avremo dei file di dati (raws).
+
+/// The weighted entries of a named loot table (see `DropsLoot`).
+pub fn loot_table(name: &str) -> Option<&'static [(&'static str, i32)]> {
+    match name {
+        "orc" => Some(&[("None", 6), ("Health Potion", 3), ("Gold", 1), ("Ration", 2), ("Spear", 1)]),
+        "goblin" => Some(&[("None", 8), ("Health Potion", 1), ("Gold", 1), ("Bear Trap", 1), ("Arrows", 2)]),
+        "ghost" => Some(&[("None", 3), ("Amulet of Sight", 1)]),
+        "bomber" => Some(&[("None", 4), ("Health Potion", 1)]),
+        "wolf" => Some(&[("None", 5), ("Ration", 1)]),
+        "ogre" => Some(&[("None", 3), ("Gold", 2), ("Ration", 1)]),
+        "fire elemental" => Some(&[("None", 4), ("Gold", 1)]),
+        "spider" => Some(&[("None", 5), ("Health Potion", 1)]),
+        "cobra" => Some(&[("None", 5), ("Health Potion", 1)]),
+        _ => None,
+    }
+}
+
+/// Picks one entry of the table, proportionally to its weight. None means "no drop".
+pub fn roll_loot(table: &str, rng: &mut GameRng) -> Option<&'static str> {
+    let entries = loot_table(table)?;
+    let total : i32 = entries.iter().map(|(_name, weight)| *weight).sum();
+    if total <= 0 {
+        return None;
+    }
+
+    let mut roll = rng.roll_dice(1, total);
+    for (name, weight) in entries.iter() {
+        if roll <= *weight {
+            return if *name == "None" { None } else { Some(*name) };
+        }
+        roll -= weight;
+    }
+    None
+}
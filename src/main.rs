@@ -3,15 +3,84 @@ use rltk::{GameState, Rltk, VirtualKeyCode, RGB};
 mod components;
 mod map;
 mod rect;
-use map::{draw_map, dungeon_map, wilderness_map, Building, Forest, SnowyMountains, Tile, Volcano, xy_idx};
+mod map_builders;
+mod map_bench;
+use map_builders::{BorderShape, BuilderChain, DrunkardsWalkBuilder, DungeonBuilder, LinkTraps, MapBorder, MapBuilder, PlacePortal, RevealAll, WildernessBuilder};
+use map::{draw_map, dungeon_map, wilderness_map, BiomeKind, Building, Forest, SnowyMountains, Stairs, Tile, Volcano, try_xy_idx};
 mod player;
-use player::{PlayerAction, PlayerIntent};
+use player::{ActionCost, InputQueue, PlayerAction};
 use specs::prelude::*;
 use crate::components::*;
 use specs_derive::Component;
 use std::cmp::{max, min};
 mod visibility_system;
 use visibility_system::VisibilitySystem;
+mod bestiary;
+use bestiary::{Bestiary, BestiarySystem};
+mod damage_system;
+mod gui;
+mod gamelog;
+use gamelog::{FootstepThrottle, GameLog};
+mod spawner;
+mod targeting;
+mod level_store;
+use level_store::LevelStore;
+mod difficulty;
+mod animation_system;
+use animation_system::LowHpAnimationSystem;
+mod monster_ai_system;
+use monster_ai_system::MonsterAI;
+mod ally_ai_system;
+use ally_ai_system::AllyAI;
+mod config;
+use config::{ConfirmableAction, GameConfig, Toggle};
+mod door_system;
+use door_system::PassedDoors;
+mod memory_system;
+use memory_system::MemorySystem;
+mod loot;
+mod save_system;
+mod combat;
+mod clock;
+use clock::Clock;
+mod hunger_system;
+mod draw_target;
+mod party;
+use party::Party;
+mod noise;
+use noise::NoiseEvents;
+mod direction;
+use direction::Direction;
+mod effects;
+mod trap_system;
+use trap_system::SearchProgress;
+mod telemetry;
+mod audio;
+mod rng;
+mod keybindings;
+mod heatmap;
+mod frontier;
+mod legend;
+mod backpack;
+mod tile_timer;
+mod tile_effects;
+mod bump;
+mod descent;
+mod path_cache;
+mod initiative;
+use descent::{BiomeProgression, DeclinedPortal, DescentLock};
+use bump::{BumpAction, Bumper, Occupant};
+use heatmap::HeatmapView;
+use tile_timer::{TileTimer, Transformation};
+use path_cache::PathCache;
+use keybindings::{InputProfile, Keybindings};
+use rng::GameRng;
+use audio::{Audio, SoundEvent};
+use telemetry::{Telemetry, TelemetryEvent};
+use effects::{EffectQueue, EffectTarget, EffectType, EffectsSystem};
+use hunger_system::HungerSystem;
+use difficulty::{Difficulty, DifficultyLevel};
+use targeting::{TargetIntent, Targeting};
 
 
 // Lo State contiene il mondo ECS, poi lo implementiamo per i sistemi
@@ -19,6 +88,25 @@ struct State {
     ecs: World,
 }
 
+// RunState indica in quale schermata si trova il gioco; è salvato come risorsa nel mondo ECS
+#[derive(PartialEq, Copy, Clone)]
+pub enum RunState {
+    MainMenu { selection: DifficultyLevel },
+    Running,
+    Examine { x: i32, y: i32 },
+    ChooseDigDirection,
+    ChooseZapDirection,
+    ThrowTarget { x: i32, y: i32 },
+    DebugTeleport { x: i32, y: i32, editing_y: bool },
+    ShowBestiary,
+    ShowLegend,
+    DropItems { selection: usize },
+    ShowLog { scroll: usize },
+    Settings { selection: usize, in_game: bool },
+    GameOver,
+    Confirm { action: ConfirmableAction },
+}
+
 
 
 
@@ -26,95 +114,1114 @@ struct State {
 // La funzione di movimento non cambia. per altre azioni aggiungeremo in seguito le relative funzioni
 
 
-fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
-    // --- Phase 1: Read-only checks --- //
-    let intent = {
-        let players = ecs.read_storage::<Player>();
-        let positions = ecs.read_storage::<Position>();
-        let sizes = ecs.read_storage::<CharacterSize>();
-        // We fetch the whole Map resource. Previously, this was incorrectly fetching `Vec<Tile>`,
-        // which caused a panic because the resource did not exist.
-        let map = ecs.fetch::<map::Map>();
-
-        let mut intent = PlayerIntent::DoNothing;
+/// Phase 1 of a move: read-only checks deciding what a step in `direction` means
+/// (see bump::resolve_bump). Also returns the energy needed to enter the destination tile
+/// (see Tile::movement_cost).
+fn player_intent(direction: Direction, ecs: &World) -> (BumpAction, i32) {
+    let (delta_x, delta_y) = direction.to_delta();
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let positions = ecs.read_storage::<Position>();
+    let sizes = ecs.read_storage::<CharacterSize>();
+    // We fetch the whole Map resource. Previously, this was incorrectly fetching `Vec<Tile>`,
+    // which caused a panic because the resource did not exist.
+    let map = ecs.fetch::<map::Map>();
 
-        for (_player, pos, size) in (&players, &positions, &sizes).join() {
-            let dest_x = pos.x + delta_x;
-            let dest_y = pos.y + delta_y;
+    let (pos, size) = match (positions.get(player), sizes.get(player)) {
+        (Some(pos), Some(size)) => (pos, size),
+        _ => return (BumpAction::Blocked, 1),
+    };
+    let dest_x = pos.x + delta_x;
+    let dest_y = pos.y + delta_y;
 
-            // Boundary check
-            if dest_x < 0 || dest_x > 79 || dest_y < 0 || dest_y > 49 {
-                intent = PlayerIntent::DoNothing;
-                break; // Don't try to move out of bounds
-            }
-            let dest_idx = xy_idx(dest_x, dest_y);
+    // Boundary check
+    let dest_idx = match try_xy_idx(dest_x, dest_y, &map) {
+        Some(idx) => idx,
+        None => return (BumpAction::Blocked, 1), // Don't try to move out of bounds
+    };
 
-            // Access the `tiles` field of the `map` resource to check the door state.
-            if let Some(door_state) = map.tiles[dest_idx].door_state {
-                if door_state == map::DoorState::Closed || door_state == map::DoorState::Locked {
-                    intent = PlayerIntent::OpenDoor(dest_idx);
-                    break;
-                }
+    // who is standing there: monsters and barricades get attacked, the rest of the party
+    // (summoned allies included) swaps places
+    let entities = ecs.entities();
+    let monsters = ecs.read_storage::<Monster>();
+    let barricades = ecs.read_storage::<Barricade>();
+    let players = ecs.read_storage::<Player>();
+    let summoned = ecs.read_storage::<Summoned>();
+    let factions = ecs.read_storage::<Faction>();
+    let occupant = (&entities, &positions).join()
+        .filter(|(_entity, p)| p.x == dest_x && p.y == dest_y)
+        .find_map(|(entity, _p)| {
+            if monsters.contains(entity) {
+                Some((entity, Occupant::Monster { faction: factions.get(entity).copied() }))
+            } else if barricades.contains(entity) {
+                Some((entity, Occupant::Barricade))
+            } else if players.contains(entity) || summoned.contains(entity) {
+                Some((entity, Occupant::PartyMember))
+            } else {
+                None
             }
+        });
 
-            let mut can_move_to_dest = true;
-            if delta_x != 0 && delta_y != 0 && size >= &CharacterSize::Medium {
-                let adjacent_x_idx = xy_idx(pos.x + delta_x, pos.y);
-                let adjacent_y_idx = xy_idx(pos.x, pos.y + delta_y);
-                if !map.tiles[adjacent_x_idx].walkable && !map.tiles[adjacent_y_idx].walkable {
-                    can_move_to_dest = false;
-                }
-            }
+    // with a reach weapon, a monster farther down the same line is hit from here
+    if let Some(reach) = ecs.read_storage::<Reach>().get(player).filter(|_| occupant.is_none()) {
+        let creatures = combat::creature_positions(ecs);
+        let from = rltk::Point::new(pos.x, pos.y);
+        let reached = (2..=reach.range)
+            .map(|step| rltk::Point::new(pos.x + delta_x * step, pos.y + delta_y * step))
+            .filter(|p| try_xy_idx(p.x, p.y, &map).is_some())
+            .find_map(|p| creatures.iter().find(|(_entity, at)| *at == p).copied())
+            .filter(|(entity, at)| monsters.contains(*entity) && targeting::is_perceived(ecs, *entity)
+                && combat::within_reach(&map, |p| creatures.iter().any(|(_other, c)| *c == p), from, *at, reach.range));
+        if let Some((target, _at)) = reached {
+            return (BumpAction::Attack(target), 1);
+        }
+    }
 
-            if map.tiles[dest_idx].walkable && can_move_to_dest {
-                intent = PlayerIntent::Move;
-            }
+    // a medium or bigger character can't squeeze diagonally between two walls
+    let mut tile = map.tiles[dest_idx];
+    if direction.is_diagonal() && size >= &CharacterSize::Medium {
+        // off the map counts as wall
+        let walkable = |x: i32, y: i32| try_xy_idx(x, y, &map).is_some_and(|idx| map.tiles[idx].walkable);
+        if !walkable(pos.x + delta_x, pos.y) && !walkable(pos.x, pos.y + delta_y) {
+            tile.walkable = false;
         }
-        intent
-    };
+    }
+
+    let action = bump::resolve_bump(dest_idx, &tile, occupant, Bumper::Player);
+    let cost = if action == BumpAction::Move { tile.movement_cost } else { 1 };
+    (action, cost)
+}
+
+/// Returns true if the move used up the player's turn (see BumpAction::cost: bumping into a wall doesn't).
+fn try_move_player(direction: Direction, ecs: &mut World) -> bool {
+    // --- Phase 1: Read-only checks --- //
+    let (intent, move_cost) = player_intent(direction, ecs);
+    let (delta_x, delta_y) = direction.to_delta();
     // All read-only borrows are dropped here.
 
     // --- Phase 2: Write actions --- //
     match intent {
-        PlayerIntent::OpenDoor(idx) => {
+        BumpAction::OpenDoor(idx) => {
             try_open_door(idx, ecs);
         }
-        PlayerIntent::Move => {
-            let mut positions = ecs.write_storage::<Position>();
-            let mut players = ecs.write_storage::<Player>();
-            let mut viewsheds = ecs.write_storage::<Viewshed>();
-            for (_player, pos, viewshed) in (&mut players, &mut positions, &mut viewsheds).join() {
-                pos.x = min(79, max(0, pos.x + delta_x));
-                pos.y = min(49, max(0, pos.y + delta_y));
+        BumpAction::Attack(target) => {
+            attack(target, ecs);
+        }
+        BumpAction::SwapPlaces(other) => {
+            swap_places(other, ecs);
+        }
+        BumpAction::Interact(target) => {
+            let name = ecs.read_storage::<Name>().get(target).map_or("It".to_string(), |name| name.name.clone());
+            ecs.write_resource::<GameLog>().push(format!("{} has nothing to say.", name));
+        }
+        BumpAction::Move => {
+            // tangled in the vines: first we have to break free (attacking and opening doors still work)
+            if !break_free(ecs) {
+                return true;
+            }
+            // Rough terrain: if we can't afford the tile yet, this turn is spent trudging
+            if !spend_energy(move_cost, ecs) {
+                return true;
+            }
+            step_player(delta_x, delta_y, ecs);
+            slide(direction, ecs);
+        }
+        BumpAction::Blocked => {
+            // the move was blocked by a wall or the map edge
+            ecs.write_resource::<GameLog>().log_once("You can't go that way.");
+        }
+    }
+    intent.cost() == ActionCost::Turn
+}
 
-                // When the player moves, we mark their viewshed as 'dirty' to trigger a recalculation.
-                viewshed.dirty = true;
+/// Moves the player one tile by (delta_x, delta_y) and deals with what they step on:
+/// items to pick up, footsteps, traps and hurtful terrain.
+fn step_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
+    {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        let mut positions = ecs.write_storage::<Position>();
+        let mut viewsheds = ecs.write_storage::<Viewshed>();
+        if let Some(pos) = positions.get_mut(player) {
+            // the tile we leave joins the breadcrumb trail
+            let mut map = ecs.write_resource::<map::Map>();
+            if let Some(idx) = try_xy_idx(pos.x, pos.y, &map) {
+                map.walked_tiles[idx] = true;
             }
+            pos.x = min(map.width - 1, max(0, pos.x + delta_x));
+            pos.y = min(map.height - 1, max(0, pos.y + delta_y));
+        }
+        // When the player moves, we mark their viewshed as 'dirty' to trigger a recalculation.
+        if let Some(viewshed) = viewsheds.get_mut(player) {
+            viewshed.dirty = true;
+        }
+    }
+    pick_up_items(ecs, false);
+    footstep_feedback(ecs);
+    trigger_trap(ecs);
+    tile_effects::hurt_by_terrain(ecs);
+}
+
+/// After a step onto slippery ground the player keeps sliding in `direction`, one more tile
+/// per point of the tile's slipperiness. Every tile of the slide is bumped like a normal step
+/// (see player_intent): a monster in the way gets slammed into (an attack) and stops the slide,
+/// anything else that isn't open ground (walls, doors, companions) stops it just short.
+/// A closed door is never opened by a slide, and a diagonal slide halts at a corner the
+/// player couldn't squeeze through on foot (the CharacterSize rule in player_intent).
+/// Each tile slid onto goes through step_player, so the viewshed, traps and hurtful terrain
+/// are handled there, including on the tile where the slide ends.
+/// Sliding costs no energy and no extra turn.
+fn slide(direction: Direction, ecs: &mut World) {
+    let mut momentum = match player_position(ecs) {
+        Some((x, y)) => {
+            let map = ecs.fetch::<map::Map>();
+            map.tiles[map.xy_idx(x, y)].slipperiness
+        }
+        None => return,
+    };
+    let (delta_x, delta_y) = direction.to_delta();
+    while momentum > 0 {
+        match player_intent(direction, ecs).0 {
+            BumpAction::Move => step_player(delta_x, delta_y, ecs),
+            BumpAction::Attack(target) => {
+                let name = ecs.read_storage::<Name>().get(target).map_or("something".to_string(), |name| name.name.clone());
+                ecs.write_resource::<GameLog>().push(format!("You slide into the {}!", name));
+                attack(target, ecs);
+                return;
+            }
+            // the door stays shut: we just stop in front of it (as in front of anything else)
+            _ => return,
+        }
+        momentum -= 1;
+    }
+}
+
+/// The player and the party member `other` trade places (bumping into a companion).
+fn swap_places(other: Entity, ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let mut positions = ecs.write_storage::<Position>();
+    let (player_xy, other_xy) = match (positions.get(player), positions.get(other)) {
+        (Some(player_pos), Some(other_pos)) => ((player_pos.x, player_pos.y), (other_pos.x, other_pos.y)),
+        _ => return,
+    };
+    positions.insert(player, Position { x: other_xy.0, y: other_xy.1 }).expect("Unable to move player");
+    positions.insert(other, Position { x: player_xy.0, y: player_xy.1 }).expect("Unable to move party member");
+    let mut viewsheds = ecs.write_storage::<Viewshed>();
+    for entity in [player, other] {
+        if let Some(viewshed) = viewsheds.get_mut(entity) {
+            viewshed.dirty = true;
+        }
+    }
+}
+
+/// d20 needed to tear free of the vines.
+const ENTANGLED_ESCAPE_DC : i32 = 12;
+
+/// Called when the player tries to move: if they're Entangled they roll a d20 against
+/// ENTANGLED_ESCAPE_DC. On a success the status goes away and the move goes on, otherwise
+/// the turn is spent struggling. Returns whether the player is free to move.
+fn break_free(ecs: &mut World) -> bool {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let entangled = ecs.read_storage::<StatusEffects>().get(player).is_some_and(|status| status.has(map::StatusEffect::Entangled));
+    if !entangled {
+        return true;
+    }
+    let roll = ecs.write_resource::<GameRng>().roll_dice(1, 20);
+    if roll < ENTANGLED_ESCAPE_DC {
+        ecs.write_resource::<GameLog>().push("You struggle against the vines.".to_string());
+        return false;
+    }
+    if let Some(status) = ecs.write_storage::<StatusEffects>().get_mut(player) {
+        status.effects.retain(|(effect, _turns)| *effect != map::StatusEffect::Entangled);
+    }
+    ecs.write_resource::<GameLog>().push("You tear free of the vines.".to_string());
+    true
+}
+
+/// Springs the alarm trap under the player, if any (see trap_system::spring_trap).
+/// Hazard traps need no springing: stepping on them is enough to get hurt (see tile_effects::hurt_by_terrain).
+fn trigger_trap(ecs: &mut World) {
+    let (player_x, player_y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return,
+    };
+    let idx = ecs.fetch::<map::Map>().xy_idx(player_x, player_y);
+
+    let trap_kind = ecs.fetch::<map::Map>().tiles[idx].trap_kind;
+    if trap_kind == Some(map::TrapKind::Summon) {
+        trap_system::spring_trap(ecs, idx, None);
+    }
+}
+
+/// The biome the portal under the player leads to, if they are standing on one,
+/// it isn't sealed (see descent.rs) and they haven't just refused to go through it
+/// (or arrived on it, see State::change_level). Down stairs lead to the biome of the next depth (see BiomeProgression).
+fn portal_under_player(ecs: &World) -> Option<BiomeKind> {
+    let (x, y) = player_position(ecs)?;
+    let idx = ecs.fetch::<map::Map>().xy_idx(x, y);
+    {
+        let mut declined = ecs.write_resource::<DeclinedPortal>();
+        if declined.idx == Some(idx) {
+            return None;
+        }
+        declined.idx = None;
+    }
+    let tile = ecs.fetch::<map::Map>().tiles[idx];
+    let target = match (tile.portal, tile.stairs) {
+        (Some(target), _) => target,
+        (None, Some(Stairs::Down)) => {
+            let level = *ecs.fetch::<CurrentLevel>();
+            ecs.fetch::<BiomeProgression>().next_biome(level.depth, level.biome)
+        }
+        (None, Some(Stairs::Up)) | (None, None) => return None,
+    };
+    if ecs.fetch::<DescentLock>().locked {
+        ecs.write_resource::<GameLog>().log_once("The portal is dark. Enemies still roam this level.");
+        return None;
+    }
+    Some(target)
+}
+
+/// True if the player is standing on up stairs, unless they have just arrived on them
+/// (checked after portal_under_player, which forgets the declined tile once the player steps off).
+fn stairs_up_under_player(ecs: &World) -> bool {
+    let (x, y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return false,
+    };
+    let map = ecs.fetch::<map::Map>();
+    let idx = map.xy_idx(x, y);
+    ecs.fetch::<DeclinedPortal>().idx != Some(idx) && map.tiles[idx].stairs == Some(Stairs::Up)
+}
+
+/// The generator used for every level of `kind`. Buildings are dungeons of rooms and corridors
+/// `corridor_width` tiles wide, left through the stairs in their last room (see BuilderChain::dungeon).
+/// The other biomes are open maps with their nearby traps wired together and a portal to the `next` biome:
+/// they get a thick, ragged border, and forests open a few clearings for the camps (see spawner::spawn_camps).
+/// With `reveal_all` the whole map starts out revealed.
+fn level_builder(kind: BiomeKind, next: BiomeKind, corridor_width: i32, reveal_all: bool) -> Box<dyn MapBuilder> {
+    // we can choose between WildernessBuilder, DrunkardsWalkBuilder and BuilderChain::dungeon(&biome, corridor_width),
+    // or assemble a custom BuilderChain of post-processing steps
+    let chain = match kind {
+        BiomeKind::Building => BuilderChain::dungeon(&*kind.biome(), corridor_width),
+        BiomeKind::Forest | BiomeKind::Volcano | BiomeKind::SnowyMountains => {
+            let border = MapBorder { thickness: 2, shape: BorderShape::Ragged };
+            let clearings = if kind == BiomeKind::Forest { 5 } else { 0 };
+            BuilderChain::new(Box::new(WildernessBuilder { border, clearings }))
+                .with(LinkTraps::new(12.0))
+                .with(PlacePortal::new(next))
         }
-        PlayerIntent::DoNothing => {}
+    };
+    Box::new(if reveal_all { chain.with(RevealAll) } else { chain })
+}
+
+/// How many seeds generate_map tries before giving up on a level.
+const MAP_GENERATION_ATTEMPTS : u32 = 20;
+
+/// The builder the levels of `kind` come from (see level_builder), as the setup line names it.
+fn level_builder_name(kind: BiomeKind) -> &'static str {
+    match kind {
+        BiomeKind::Building => "dungeon",
+        BiomeKind::Forest | BiomeKind::Volcano | BiomeKind::SnowyMountains => "wilderness",
+    }
+}
+
+/// Builds a fresh map of `kind` for `depth` and returns it with the player's starting point
+/// and the seed that produced it.
+/// Its portal (or stairs) leads to the biome of the next depth (see BiomeProgression).
+/// The first seed is drawn from the game's random stream; unplayable maps are thrown away and
+/// regenerated from the following seeds (see map_builders::generate_with_retries).
+fn generate_map(ecs: &World, kind: BiomeKind, depth: i32, orthogonal_only: bool, reveal_all: bool) -> (map::Map, (i32, i32), u64) {
+    let base_seed = ecs.write_resource::<GameRng>().range(0, i32::MAX) as u64;
+    let next = ecs.fetch::<BiomeProgression>().next_biome(depth, kind);
+    let corridor_width = ecs.fetch::<GameConfig>().corridor_width;
+    let (mut map, _rooms, seed) = map_builders::generate_with_retries(&*level_builder(kind, next, corridor_width, reveal_all), &*kind.biome(), base_seed, MAP_GENERATION_ATTEMPTS)
+        .unwrap_or_else(|error| panic!("Unable to generate a {:?} level: {}", kind, error));
+    map.orthogonal_only = orthogonal_only;
+    // the first room, or a tile the player can stand on when there are none (see start_point)
+    let start = map_builders::start_point(&map);
+    // below the first level the party arrives on the stairs that lead back up
+    if depth > 1 {
+        let idx = map.xy_idx(start.0, start.1);
+        map.tiles[idx] = kind.biome().get_up_stairs();
     }
+    map.tint_for_depth(depth);
+    map.ensure_spottable_traps(depth, &mut ecs.write_resource::<GameRng>());
+    (map, start, seed)
 }
 
-/// Opens a door and updates its properties on the map.
+/// One line telling exactly what produced the current level: the game seed, the level's own seed,
+/// biome, depth and builder. Shown in play, and written to GameConfig::setup_path by F4.
+fn setup_line(ecs: &World) -> String {
+    let level = *ecs.fetch::<CurrentLevel>();
+    let game_seed = ecs.fetch::<GameRng>().seed();
+    let level_seed = level.seed.map_or("hand-drawn".to_string(), |seed| seed.to_string());
+    format!("game {} level {} {:?} d{} {}", game_seed, level_seed, level.biome, level.depth, level_builder_name(level.biome))
+}
+
+/// Writes the setup line to GameConfig::setup_path, so it can be pasted into a bug report
+/// (there's no clipboard access in rltk, a file works everywhere). The file also gets the
+/// level's layout hash (see Map::layout_hash), to check that the seed rebuilds the same map.
+fn copy_setup(ecs: &mut World) {
+    let path = ecs.fetch::<GameConfig>().setup_path.clone();
+    let line = format!("{} layout {:016x}\n", setup_line(ecs), ecs.fetch::<map::Map>().layout_hash());
+    let message = match std::fs::write(&path, line) {
+        Ok(()) => format!("Level setup written to {}.", path),
+        Err(e) => format!("Could not write {}: {}", path, e),
+    };
+    ecs.write_resource::<GameLog>().push(message);
+}
+
+/// Logs the terrain under the player's feet, at most once every few steps.
+fn footstep_feedback(ecs: &mut World) {
+    const FOOTSTEP_INTERVAL: i32 = 4;
+
+    let (x, y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return,
+    };
+    let map = ecs.fetch::<map::Map>();
+    let mut throttle = ecs.write_resource::<FootstepThrottle>();
+
+    throttle.steps_since_message += 1;
+    if throttle.steps_since_message < FOOTSTEP_INTERVAL {
+        return;
+    }
+    let biome = ecs.fetch::<CurrentLevel>().biome;
+    if let Some(message) = map::footstep_message(&map.tiles[map.xy_idx(x, y)], biome) {
+        ecs.write_resource::<GameLog>().push(message.to_string());
+        throttle.steps_since_message = 0;
+    }
+}
+
+/// Every player turn grants 1 energy, entering a tile costs its `movement_cost`.
+/// Returns false while the player is still accumulating energy for an expensive tile.
+fn spend_energy(cost: i32, ecs: &mut World) -> bool {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let mut energies = ecs.write_storage::<Energy>();
+    if let Some(energy) = energies.get_mut(player) {
+        // the accumulated energy is capped to the cost, so cheap tiles don't bank free moves
+        energy.current = min(energy.current + 1, cost);
+        if energy.current < cost {
+            return false;
+        }
+        energy.current -= cost;
+    }
+    true
+}
+
+/// Opens the closed door at `idx`. A locked door opens quietly with a key from the backpack
+/// (the key stays in the lock); without one it gets bashed instead: it gives way one time in three,
+/// and every blow can be heard from afar (see noise.rs).
 fn try_open_door(idx: usize, ecs: &mut World) {
-    // Fetch the whole Map resource to modify its tiles.
+    let (locked, width) = {
+        let map = ecs.fetch::<map::Map>();
+        (map.tiles[idx].door_state == Some(map::DoorState::Locked), map.width)
+    };
+    if !locked {
+        if ecs.write_resource::<map::Map>().open_door(idx) {
+            path_cache::invalidate(ecs);
+            audio::play(ecs, SoundEvent::DoorOpen);
+        }
+        return;
+    }
+
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let key = carried_keys(ecs, player).first().copied();
+    if let Some(key) = key {
+        ecs.delete_entity(key).expect("Unable to delete");
+        ecs.write_resource::<GameLog>().push("You unlock the door. The key stays in the lock.".to_string());
+    } else {
+        let (x, y) = (idx as i32 % width, idx as i32 / width);
+        noise::make_noise(ecs, x, y, noise::SMASH_LOUDNESS);
+        let roll = ecs.write_resource::<GameRng>().roll_dice(1, 3);
+        if roll < 3 {
+            ecs.write_resource::<GameLog>().push("You bash the door, but it holds.".to_string());
+            return;
+        }
+        ecs.write_resource::<GameLog>().push("You smash the door open!".to_string());
+    }
+    {
+        let mut map = ecs.write_resource::<map::Map>();
+        map.unlock_door(idx);
+        map.open_door(idx);
+    }
+    path_cache::invalidate(ecs);
+    audio::play(ecs, if key.is_some() { SoundEvent::DoorOpen } else { SoundEvent::DoorSmash });
+    for viewshed in (&mut ecs.write_storage::<Viewshed>()).join() {
+        viewshed.dirty = true;
+    }
+}
+
+/// Leans around the corner: the player keeps standing where they are but also sees what is in
+/// sight from the tile next to them, in `direction`, until they do something else.
+/// Only works towards a tile one could see from (no peeking into walls). Costs no turn.
+fn peek(direction: Direction, ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let (player_x, player_y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return,
+    };
+    let (delta_x, delta_y) = direction.to_delta();
+    let (x, y) = (player_x + delta_x, player_y + delta_y);
+    let can_lean = {
+        let map = ecs.fetch::<map::Map>();
+        try_xy_idx(x, y, &map).is_some_and(|idx| map.tiles[idx].transparent)
+    };
+    if !can_lean {
+        ecs.write_resource::<GameLog>().log_once("You can't peek that way.");
+        return;
+    }
+    ecs.write_storage::<Peeking>().insert(player, Peeking { x, y }).expect("Unable to insert peeking");
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player) {
+        viewshed.dirty = true;
+    }
+    ecs.write_resource::<GameLog>().push("You peek around the corner.".to_string());
+}
+
+/// Back to seeing only from where the player stands, if they were peeking.
+fn stop_peeking(ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    if ecs.write_storage::<Peeking>().remove(player).is_none() {
+        return;
+    }
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player) {
+        viewshed.dirty = true;
+    }
+}
+
+/// One turn of digging into the wall next to the player, in `direction`.
+/// Walls need `dig_turns` turns of work, tracked by the Digging component, and become floor when done.
+/// Returns true if the turn was spent digging.
+fn try_dig(direction: Direction, ecs: &mut World) -> bool {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    if ecs.read_storage::<Pickaxe>().get(player).is_none() {
+        ecs.write_resource::<GameLog>().log_once("You have nothing to dig with.");
+        return false;
+    }
+    let (player_x, player_y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return false,
+    };
+    let (delta_x, delta_y) = direction.to_delta();
+    let (x, y) = (player_x + delta_x, player_y + delta_y);
+
+    let needed_turns = {
+        let map = ecs.fetch::<map::Map>();
+        // the border of the map is the edge of the world, not a wall to dig through
+        let on_border = x <= 0 || x >= map.width - 1 || y <= 0 || y >= map.height - 1;
+        let tile = match try_xy_idx(x, y, &map) {
+            Some(idx) => map.tiles[idx],
+            None => {
+                ecs.write_resource::<GameLog>().log_once("This is too hard to dig.");
+                return false;
+            }
+        };
+        match tile.dig_turns {
+            Some(turns) if !on_border => turns,
+            _ => {
+                let message = if tile.walkable { "There is nothing to dig there." } else { "This is too hard to dig." };
+                ecs.write_resource::<GameLog>().log_once(message);
+                return false;
+            }
+        }
+    };
+
+    let idx = ecs.fetch::<map::Map>().xy_idx(x, y);
+    let progress = {
+        let mut diggings = ecs.write_storage::<Digging>();
+        match diggings.get_mut(player) {
+            Some(digging) if digging.idx == idx => {
+                digging.progress += 1;
+                digging.progress
+            }
+            // a new wall: the work on the previous one is lost
+            _ => {
+                diggings.insert(player, Digging { idx, progress: 1 }).expect("Unable to insert digging");
+                1
+            }
+        }
+    };
+
+    noise::make_noise(ecs, x, y, noise::DIG_LOUDNESS);
+    if progress < needed_turns {
+        ecs.write_resource::<GameLog>().push("You dig into the wall...".to_string());
+        return true;
+    }
+
+    let dug = ecs.fetch::<map::Map>().tiles[idx];
+    ecs.write_resource::<map::Map>().dig(idx);
+    path_cache::invalidate(ecs);
+    reconnect_walls(ecs, &[idx]);
+    // in montagna il ghiaccio si richiude dietro di noi
+    if ecs.fetch::<CurrentLevel>().biome == BiomeKind::SnowyMountains {
+        ecs.write_resource::<TileTimer>().set(idx, tile_timer::REFREEZE_TURNS, Transformation::Become(dug));
+    }
+    ecs.write_storage::<Digging>().remove(player);
+    ecs.write_resource::<GameLog>().push("You break through!".to_string());
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player) {
+        viewshed.dirty = true;
+    }
+    true
+}
+
+/// True if the player holds a digging beam with charges left; otherwise says why not.
+fn can_zap(ecs: &World) -> bool {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let message = match (ecs.read_storage::<DiggingBeam>().get(player), ecs.read_storage::<Charges>().get(player)) {
+        (Some(_beam), Some(charges)) if charges.current > 0 => return true,
+        (Some(_beam), _) => "Your wand is out of charges.",
+        (None, _) => "You have nothing to zap.",
+    };
+    ecs.write_resource::<GameLog>().log_once(message);
+    false
+}
+
+/// Fires the player's digging beam in `direction`: the soft walls in its path become floor at once
+/// (see Map::dig_beam), at the cost of one charge. Returns true if the turn was spent zapping.
+fn zap_digging_beam(direction: Direction, ecs: &mut World) -> bool {
+    if !can_zap(ecs) {
+        return false;
+    }
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let (x, y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return false,
+    };
+    let range = ecs.read_storage::<DiggingBeam>().get(player).map_or(0, |beam| beam.range);
+    let carved = ecs.write_resource::<map::Map>().dig_beam(x, y, direction.to_delta(), range);
+    path_cache::invalidate(ecs);
+    reconnect_walls(ecs, &carved.iter().map(|(idx, _dug)| *idx).collect::<Vec<_>>());
+    let charges = {
+        let mut charges = ecs.write_storage::<Charges>();
+        let charges = charges.get_mut(player).expect("Checked by can_zap");
+        charges.spend();
+        charges.current
+    };
+
+    // in montagna il ghiaccio si richiude anche dietro al raggio
+    if ecs.fetch::<CurrentLevel>().biome == BiomeKind::SnowyMountains {
+        let mut timer = ecs.write_resource::<TileTimer>();
+        for (idx, dug) in carved.iter() {
+            timer.set(*idx, tile_timer::REFREEZE_TURNS, Transformation::Become(*dug));
+        }
+    }
+    noise::make_noise(ecs, x, y, noise::DIG_LOUDNESS);
+    let message = if carved.is_empty() { "The beam fizzles against the rock." } else { "The beam bores a tunnel through the rock!" };
+    ecs.write_resource::<GameLog>().push(format!("{} ({} charges left)", message, charges));
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player) {
+        viewshed.dirty = true;
+    }
+    true
+}
+
+/// One turn of slow recharge for the player's wand (see Charges::tick).
+fn recharge_wands(ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let recharged = ecs.write_storage::<Charges>().get_mut(player).is_some_and(|charges| charges.tick());
+    if recharged {
+        ecs.write_resource::<GameLog>().push("Your wand hums: a charge is back.".to_string());
+    }
+}
+
+/// Keeps the connected walls (see map::apply_wall_glyphs) right after the tiles in `changed`
+/// were dug out, on the levels whose biome draws them.
+fn reconnect_walls(ecs: &mut World, changed: &[usize]) {
+    if !ecs.fetch::<CurrentLevel>().biome.biome().connected_walls() {
+        return;
+    }
     let mut map = ecs.write_resource::<map::Map>();
-    if let Some(door_state) = map.tiles[idx].door_state {
-        match door_state {
-            map::DoorState::Closed => {
-                // Change the tile's properties to represent an open door.
-                map.tiles[idx].door_state = Some(map::DoorState::Open);
-                map.tiles[idx].glyph = rltk::to_cp437('/'); // Open door glyph
-                map.tiles[idx].walkable = true;
-                map.tiles[idx].transparent = true;
-                map.tiles[idx].provides_cover = false;
+    for idx in changed {
+        map::refresh_wall_glyphs(&mut map, *idx);
+    }
+}
+
+/// Picks up what lies on the player's tile. Stepping there (`manual` false) only grabs the items
+/// flagged AutoPickup, and only if auto_pickup is on in GameConfig; the pickup key (`manual` true)
+/// grabs everything. The items left behind are named in the log. Returns how many were picked up.
+fn pick_up_items(ecs: &mut World, manual: bool) -> usize {
+    let picked = collect_gold(ecs, manual) + eat_food(ecs, manual) + pick_up_see_invisible(ecs, manual) + pick_up_reach_weapon(ecs, manual) + pick_up_traps(ecs, manual) + pick_up_keys(ecs, manual) + pick_up_wands(ecs, manual) + read_summoning_scrolls(ecs, manual) + pick_up_lightsticks(ecs, manual) + pick_up_ammo(ecs, manual);
+    let (player_x, player_y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return picked,
+    };
+    let left : Vec<String> = {
+        let positions = ecs.read_storage::<Position>();
+        let names = ecs.read_storage::<Name>();
+        let items = ecs.read_storage::<Item>();
+        let golds = ecs.read_storage::<Gold>();
+        (&positions, &names, items.maybe(), golds.maybe()).join()
+            .filter(|(pos, _name, item, gold)| pos.x == player_x && pos.y == player_y && (item.is_some() || gold.is_some()))
+            .map(|(_pos, name, _item, _gold)| name.name.clone())
+            .collect()
+    };
+    if !left.is_empty() {
+        ecs.write_resource::<GameLog>().push(format!("You see here: {}.", left.join(", ")));
+    } else if manual && picked == 0 {
+        ecs.write_resource::<GameLog>().push("There is nothing here to pick up.".to_string());
+    }
+    picked
+}
+
+/// True if the item is to be picked up: always with the pickup key, on a plain step only if auto-pickup allows it.
+fn grabs(ecs: &World, entity: Entity, manual: bool) -> bool {
+    manual || (ecs.fetch::<GameConfig>().auto_pickup && ecs.read_storage::<AutoPickup>().get(entity).is_some())
+}
+
+/// What becomes of an item once `pick_up` has taken it.
+#[derive(Clone, Copy, PartialEq)]
+enum Pickup {
+    Use,  // used on the spot: the item is gone afterwards
+    Pack, // into the player's backpack, before `on_pickup` runs
+}
+
+/// Picks up, one at a time, the items with a `C` component lying on the player's tile that
+/// `grabs` allows. `on_pickup` gets the player, the item, its `C` and its name, does what the
+/// item is for and returns the line to log; None leaves an item to `Use` where it lies.
+/// Returns how many items were picked up.
+fn pick_up<C: Component + Clone>(ecs: &mut World, manual: bool, pickup: Pickup, mut on_pickup: impl FnMut(&mut World, Entity, Entity, C, &str) -> Option<String>) -> usize {
+    let (player_x, player_y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return 0,
+    };
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let lying : Vec<(Entity, C, String)> = {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let components = ecs.read_storage::<C>();
+        let names = ecs.read_storage::<Name>();
+        // the player stands on the tile too, and may carry a C of their own (a Reach, a quiver...)
+        (&entities, &components, &names, &positions).join()
+            .filter(|(entity, _component, _name, pos)| *entity != player && pos.x == player_x && pos.y == player_y && grabs(ecs, *entity, manual))
+            .map(|(entity, component, name, _pos)| (entity, component.clone(), name.name.clone()))
+            .collect()
+    };
+    let mut picked = 0;
+    for (item, component, name) in lying {
+        if pickup == Pickup::Pack {
+            ecs.write_storage::<Position>().remove(item);
+            ecs.write_storage::<InBackpack>().insert(item, InBackpack { owner: player }).expect("Unable to insert in backpack");
+        }
+        let message = match on_pickup(ecs, player, item, component, &name) {
+            Some(message) => message,
+            None => continue,
+        };
+        if pickup == Pickup::Use {
+            ecs.delete_entity(item).expect("Unable to delete");
+        }
+        ecs.write_resource::<GameLog>().push(message);
+        picked += 1;
+    }
+    picked
+}
+
+/// Every pile of gold picked up on the player's tile goes into the Wallet.
+fn collect_gold(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Use, |ecs, _player, _item, gold: Gold, _name| {
+        let mut wallet = ecs.write_resource::<Wallet>();
+        wallet.gold += gold.amount;
+        Some(format!("You pick up {} gold. You now have {} gold.", gold.amount, wallet.gold))
+    })
+}
+
+/// Food picked up on the player's tile is eaten on the spot, and refills their Hunger.
+fn eat_food(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Use, |ecs, player, _item, food: Food, name| {
+        {
+            let mut hungers = ecs.write_storage::<Hunger>();
+            let hunger = hungers.get_mut(player)?;
+            hunger.satiation = min(hunger_system::MAX_SATIATION, hunger.satiation + food.nutrition);
+            hunger.state = hunger_system::hunger_state(hunger.satiation);
+        }
+        telemetry::record(ecs, TelemetryEvent::ItemUsed { name: name.to_string() });
+        Some(format!("You eat the {}.", name))
+    })
+}
+
+/// An item granting see invisible picked up on the player's tile is worn at once.
+fn pick_up_see_invisible(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Use, |ecs, player, _item, _grant: GrantsSeeInvisible, name| {
+        ecs.write_storage::<SeeInvisible>().insert(player, SeeInvisible {}).expect("Unable to insert see invisible");
+        telemetry::record(ecs, TelemetryEvent::ItemUsed { name: name.to_string() });
+        Some(format!("You put on the {}. Your eyes tingle.", name))
+    })
+}
+
+/// Takes up the reach weapons (spears) lying under the player: from now on their melee attacks
+/// hit from farther away (see combat::within_reach). A longer reach replaces a shorter one.
+fn pick_up_reach_weapon(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Use, |ecs, player, _item, reach: Reach, name| {
+        {
+            let mut reaches = ecs.write_storage::<Reach>();
+            if reaches.get(player).is_none_or(|current| current.range < reach.range) {
+                reaches.insert(player, reach).expect("Unable to insert reach");
+            }
+        }
+        telemetry::record(ecs, TelemetryEvent::ItemUsed { name: name.to_string() });
+        Some(format!("You take up the {}. You can strike from {} tiles away.", name, reach.range))
+    })
+}
+
+/// Packs the traps lying under the player into their backpack, ready to be set with `set_trap`.
+fn pick_up_traps(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Pack, |ecs, player, _item, _trap: DeployableTrap, name| {
+        let carried = carried_traps(ecs, player).len();
+        Some(format!("You pack the {} ({} carried, V to set one).", name, carried))
+    })
+}
+
+/// Packs the keys lying under the player into their backpack: each one opens a locked door (see try_open_door).
+fn pick_up_keys(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Pack, |_ecs, _player, _item, _key: Key, name| {
+        Some(format!("You pack the {}. It should fit a locked door.", name))
+    })
+}
+
+/// The keys in `owner`'s backpack.
+fn carried_keys(ecs: &World, owner: Entity) -> Vec<Entity> {
+    let entities = ecs.entities();
+    let backpacks = ecs.read_storage::<InBackpack>();
+    let keys = ecs.read_storage::<Key>();
+    (&entities, &backpacks, &keys).join()
+        .filter(|(_entity, backpack, _key)| backpack.owner == owner)
+        .map(|(entity, _backpack, _key)| entity)
+        .collect()
+}
+
+/// Takes up the wands of digging lying under the player: their Charges go to the player's
+/// own DiggingBeam, zapped with `/`, and raise its maximum too. A longer range replaces a shorter one.
+fn pick_up_wands(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Use, |ecs, player, item, beam: DiggingBeam, name| {
+        let mut beams = ecs.write_storage::<DiggingBeam>();
+        let mut charges = ecs.write_storage::<Charges>();
+        let held = match beams.get(player) {
+            Some(current) => DiggingBeam { range: current.range.max(beam.range) },
+            None => beam,
+        };
+        beams.insert(player, held).expect("Unable to insert digging beam");
+        let wand_charges = charges.get(item).copied().unwrap_or(Charges { current: 0, max: 0, recharge_turns: 0, timer: 0 });
+        let total = match charges.get(player) {
+            Some(current) => Charges { current: current.current + wand_charges.current, max: current.max + wand_charges.max, ..*current },
+            None => wand_charges,
+        };
+        charges.insert(player, total).expect("Unable to insert charges");
+        Some(format!("You take the {} ({}/{} charges, / to zap).", name, total.current, total.max))
+    })
+}
+
+/// Puts the ammo lying on the player's tile into their quiver, when it's the kind it holds
+/// (an empty quiver takes any kind). Other ammo is left where it is.
+fn pick_up_ammo(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Use, |ecs, player, _item, bundle: Ammo, name| {
+        let mut ammo = ecs.write_storage::<Ammo>();
+        let quiver = match ammo.get(player) {
+            Some(current) if current.kind == bundle.kind => Ammo { kind: bundle.kind, count: current.count + bundle.count },
+            Some(current) if current.count > 0 => return None,
+            _ => bundle,
+        };
+        ammo.insert(player, quiver).expect("Unable to insert ammo");
+        Some(format!("You add the {} to your quiver ({} {}).", name, quiver.count, quiver.kind.name()))
+    })
+}
+
+/// A scroll of summoning picked up on the player's tile is read at once: a guardian appears
+/// next to the player and fights at their side for a while (see ally_ai_system.rs).
+fn read_summoning_scrolls(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Use, |ecs, _player, _item, scroll: SummonsAlly, name| {
+        let (player_x, player_y) = player_position(ecs)?;
+        let message = match spawner::find_valid_spawn(ecs, player_x, player_y, 2) {
+            Some((x, y)) => {
+                spawner::guardian(ecs, x, y, scroll.turns);
+                format!("You read the {}. A guardian appears at your side!", name)
             }
-            map::DoorState::Locked => {
-                // For now, you can't open locked doors.
-                // We could add a message to the player here later.
+            None => format!("You read the {}, but there is no room for anything to appear.", name),
+        };
+        telemetry::record(ecs, TelemetryEvent::ItemUsed { name: name.to_string() });
+        Some(message)
+    })
+}
+
+/// Packs the lightsticks lying under the player into their backpack, ready to be thrown with `throw_lightstick`.
+fn pick_up_lightsticks(ecs: &mut World, manual: bool) -> usize {
+    pick_up(ecs, manual, Pickup::Pack, |ecs, player, _item, _light: ThrowableLight, name| {
+        let carried = carried_lightsticks(ecs, player).len();
+        Some(format!("You pack the {} ({} carried, K to throw one).", name, carried))
+    })
+}
+
+/// The lightsticks in `owner`'s backpack.
+fn carried_lightsticks(ecs: &World, owner: Entity) -> Vec<Entity> {
+    let entities = ecs.entities();
+    let backpacks = ecs.read_storage::<InBackpack>();
+    let lights = ecs.read_storage::<ThrowableLight>();
+    (&entities, &backpacks, &lights).join()
+        .filter(|(_entity, backpack, _light)| backpack.owner == owner)
+        .map(|(entity, _backpack, _light)| entity)
+        .collect()
+}
+
+/// How far (in tiles) the player can throw.
+const THROW_RANGE : i32 = 8;
+
+/// Debug: puts the player straight on (x, y), as long as it is on the map and walkable.
+/// Otherwise says why not. Doesn't take a turn.
+fn debug_teleport(ecs: &mut World, x: i32, y: i32) -> Result<(), String> {
+    {
+        let map = ecs.fetch::<map::Map>();
+        let idx = try_xy_idx(x, y, &map).ok_or_else(|| format!("({}, {}) is outside the map.", x, y))?;
+        if !map.tiles[idx].walkable {
+            return Err(format!("You can't stand at ({}, {}).", x, y));
+        }
+    }
+    let player = ecs.fetch::<PlayerEntity>().0;
+    if let Some(pos) = ecs.write_storage::<Position>().get_mut(player) {
+        pos.x = x;
+        pos.y = y;
+    }
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player) {
+        viewshed.dirty = true;
+    }
+    Ok(())
+}
+
+/// Throws a lightstick from the backpack toward (x, y): it flies along a straight line and drops
+/// on the last open tile before a wall, where it lights up the area around it (see LightSource)
+/// even out of the player's sight. Returns true if something was thrown (and the turn spent).
+fn throw_lightstick(ecs: &mut World, x: i32, y: i32) -> bool {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let (player_x, player_y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return false,
+    };
+    let stick = match carried_lightsticks(ecs, player).first().copied() {
+        Some(stick) => stick,
+        None => {
+            ecs.write_resource::<GameLog>().log_once("You have nothing to throw.");
+            return false;
+        }
+    };
+    let landing = {
+        let map = ecs.fetch::<map::Map>();
+        rltk::line2d(rltk::LineAlg::Bresenham, rltk::Point::new(player_x, player_y), rltk::Point::new(x, y)).into_iter()
+            .take_while(|p| try_xy_idx(p.x, p.y, &map).is_some_and(|idx| map.tiles[idx].walkable))
+            .last()
+            .map_or((player_x, player_y), |p| (p.x, p.y))
+    };
+    let light = ecs.read_storage::<ThrowableLight>().get(stick).copied().expect("Checked by carried_lightsticks");
+
+    ecs.write_storage::<InBackpack>().remove(stick);
+    ecs.write_storage::<Item>().remove(stick);
+    ecs.write_storage::<Position>().insert(stick, Position { x: landing.0, y: landing.1 }).expect("Unable to insert position");
+    ecs.write_storage::<LightSource>().insert(stick, LightSource { turns_left: Some(light.turns) }).expect("Unable to insert light source");
+    ecs.write_storage::<Viewshed>().insert(stick, Viewshed { visible_tiles: Vec::new(), range: light.radius, dirty: true, fov_shape: FovShape::Circle }).expect("Unable to insert viewshed");
+    ecs.write_resource::<GameLog>().push("You throw the lightstick. It lands with a soft glow.".to_string());
+    true
+}
+
+/// The traps in `owner`'s backpack.
+fn carried_traps(ecs: &World, owner: Entity) -> Vec<Entity> {
+    let entities = ecs.entities();
+    let backpacks = ecs.read_storage::<InBackpack>();
+    let traps = ecs.read_storage::<DeployableTrap>();
+    (&entities, &backpacks, &traps).join()
+        .filter(|(_entity, backpack, _trap)| backpack.owner == owner)
+        .map(|(entity, _backpack, _trap)| entity)
+        .collect()
+}
+
+/// Sets a trap from the backpack on the player's tile: it stays there, armed, until a monster
+/// steps on it (see trap_system::spring_player_traps). Not on doors, portals or another trap.
+/// Returns true if a trap was set (and the turn spent).
+fn set_trap(ecs: &mut World) -> bool {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let (x, y) = match player_position(ecs) {
+        Some(pos) => pos,
+        None => return false,
+    };
+    let trap = match carried_traps(ecs, player).first().copied() {
+        Some(trap) => trap,
+        None => {
+            ecs.write_resource::<GameLog>().log_once("You have no traps to set.");
+            return false;
+        }
+    };
+    let unsuitable = {
+        let map = ecs.fetch::<map::Map>();
+        let tile = &map.tiles[map.xy_idx(x, y)];
+        tile.door_state.is_some() || tile.leads_down() || tile.trap_kind.is_some()
+    };
+    let already_set = (&ecs.read_storage::<Armed>(), &ecs.read_storage::<Position>()).join().any(|(_armed, pos)| pos.x == x && pos.y == y);
+    if unsuitable || already_set {
+        ecs.write_resource::<GameLog>().log_once("You can't set a trap here.");
+        return false;
+    }
+
+    ecs.write_storage::<InBackpack>().remove(trap);
+    ecs.write_storage::<Item>().remove(trap);
+    ecs.write_storage::<Position>().insert(trap, Position { x, y }).expect("Unable to insert position");
+    ecs.write_storage::<Armed>().insert(trap, Armed {}).expect("Unable to insert armed");
+    let name = ecs.read_storage::<Name>().get(trap).map_or("trap".to_string(), |name| name.name.clone());
+    ecs.write_resource::<GameLog>().push(format!("You set the {}. Now lure something onto it.", name));
+    true
+}
+
+/// Ranged attack against the monster selected with Tab, if it's still in sight.
+/// The shot stops at the first creature or barricade in the line of fire (see combat::has_clear_shot),
+/// and a target behind cover may be missed (see combat::ranged_hit_chance).
+/// The player never shoots themselves or the party: such a shot is refused (see targeting::validate_target).
+/// Every shot takes one piece of the weapon's ammo from the quiver (see Ammo): with an empty
+/// quiver, or no ranged weapon at all, nothing is fired.
+/// Returns true if a shot was fired (and the turn spent).
+fn fire_at_target(ecs: &mut World) -> bool {
+    if let Err(message) = ready_ammo(ecs) {
+        ecs.write_resource::<GameLog>().log_once(&message);
+        return false;
+    }
+    let target = ecs.fetch::<Targeting>().current_target;
+    let visible = targeting::visible_monsters(ecs);
+    match target {
+        Some(target) if visible.contains(&target) => {
+            if let Err(message) = targeting::validate_target(ecs, target, TargetIntent::Harmful) {
+                ecs.write_resource::<GameLog>().push(message);
+                return false;
+            }
+            let name = ecs.read_storage::<Name>().get(target).map(|n| n.name.clone()).unwrap_or_default();
+            let (from, to) = {
+                let positions = ecs.read_storage::<Position>();
+                match (player_position(ecs), positions.get(target)) {
+                    (Some((px, py)), Some(pos)) => (rltk::Point::new(px, py), rltk::Point::new(pos.x, pos.y)),
+                    _ => return false,
+                }
+            };
+            let creatures = combat::creature_positions(ecs);
+            let barricades = combat::barricade_positions(ecs);
+            let shot = combat::has_clear_shot(&ecs.fetch::<map::Map>(), &creatures, &barricades, from, to);
+            match shot {
+                combat::ShotResult::Clear => {}
+                combat::ShotResult::BlockedByTile(_) => {
+                    ecs.write_resource::<GameLog>().push(format!("You have no clear shot at the {}.", name));
+                    return false;
+                }
+                combat::ShotResult::Barricaded(..) => {
+                    ecs.write_resource::<GameLog>().push(format!("A barricade stands between you and the {}.", name));
+                    return false;
+                }
+                combat::ShotResult::Intercepted(other, _) => {
+                    if targeting::validate_target(ecs, other, TargetIntent::Harmful).is_err() {
+                        let other_name = ecs.read_storage::<Name>().get(other).map(|n| n.name.clone()).unwrap_or_default();
+                        ecs.write_resource::<GameLog>().push(format!("{} is in the line of fire.", other_name));
+                        return false;
+                    }
+                    let message = if targeting::is_perceived(ecs, other) {
+                        let other_name = ecs.read_storage::<Name>().get(other).map(|n| n.name.clone()).unwrap_or_default();
+                        format!("You fire at the {}, but the {} is in the way.", name, other_name)
+                    } else {
+                        format!("You fire at the {}, but something unseen is in the way!", name)
+                    };
+                    ecs.write_resource::<GameLog>().push(message);
+                    spend_ammo(ecs);
+                    attack(other, ecs);
+                    return true;
+                }
+            }
+            spend_ammo(ecs);
+            let hit_chance = combat::ranged_hit_chance(&ecs.fetch::<map::Map>(), from, to);
+            let roll = ecs.write_resource::<GameRng>().roll_dice(1, 100);
+            if roll > hit_chance {
+                ecs.write_resource::<GameLog>().push(format!("You fire at the {}, but the shot hits its cover.", name));
+                return true;
+            }
+            ecs.write_resource::<GameLog>().push(format!("You fire at the {}.", name));
+            attack(target, ecs);
+            true
+        }
+        _ => {
+            ecs.write_resource::<GameLog>().push("You have no target in sight.".to_string());
+            false
+        }
+    }
+}
+
+/// Checks that the player can shoot: they need a ranged weapon and at least one piece of its ammo.
+fn ready_ammo(ecs: &World) -> Result<(), String> {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let kind = match ecs.read_storage::<RangedWeapon>().get(player) {
+        Some(weapon) => weapon.ammo,
+        None => return Err("You have nothing to shoot with.".to_string()),
+    };
+    match ecs.read_storage::<Ammo>().get(player) {
+        Some(quiver) if quiver.kind == kind && quiver.count > 0 => Ok(()),
+        _ => Err(format!("You are out of {}.", kind.name())),
+    }
+}
+
+/// Takes one piece of ammo from the player's quiver, warning when the last one is gone.
+fn spend_ammo(ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let left = ecs.write_storage::<Ammo>().get_mut(player).map(|quiver| {
+        quiver.count = (quiver.count - 1).max(0);
+        (quiver.kind, quiver.count)
+    });
+    if let Some((kind, 0)) = left {
+        ecs.write_resource::<GameLog>().push(format!("That was the last of your {}.", kind.name()));
+    }
+}
+
+/// Melee attack from the player against `target`: damage is power minus defense.
+/// The fight is loud enough to be heard around (see noise.rs).
+fn attack(target: Entity, ecs: &mut World) {
+    {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        let damage = {
+            let combat_stats = ecs.read_storage::<CombatStats>();
+            let stance = ecs.read_storage::<Stance>().get(player).copied();
+            match (combat_stats.get(player), combat_stats.get(target)) {
+                (Some(attacker), Some(defender)) => combat::damage(&combat::with_stance(attacker, stance.as_ref()), defender),
+                _ => return,
             }
-            map::DoorState::Open => {}
+        };
+        effects::add_effect(ecs, Some(player), EffectType::Damage { amount: damage }, EffectTarget::Single(target));
+    }
+    if let Some((x, y)) = player_position(ecs) {
+        noise::make_noise(ecs, x, y, noise::FIGHT_LOUDNESS);
+    }
+}
+
+/// Queues a 1 hp heal for the player. Returns the hp they will have once it is applied,
+/// or None if the player was already at full health.
+fn regenerate_player(ecs: &mut World) -> Option<i32> {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let hp = {
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        let stats = combat_stats.get(player)?;
+        if stats.hp >= stats.max_hp {
+            return None;
         }
+        stats.hp + 1
+    };
+    effects::add_effect(ecs, Some(player), EffectType::Heal { amount: 1 }, EffectTarget::Single(player));
+    Some(hp)
+}
+
+fn player_hp(ecs: &World) -> i32 {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    ecs.read_storage::<CombatStats>().get(player).map(|stats| stats.hp).unwrap_or(0)
+}
+
+fn player_position(ecs: &World) -> Option<(i32, i32)> {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    ecs.read_storage::<Position>().get(player).map(|pos| (pos.x, pos.y))
+}
+
+/// True if any monster stands on a tile of the player's viewshed.
+fn monster_in_view(ecs: &World) -> bool {
+    !targeting::visible_monsters(ecs).is_empty()
+}
+
+/// Writes the settings to disk after a change in the settings menu (see config::save_settings).
+/// Like the autosave, a failed write is only logged.
+fn persist_settings(ecs: &mut World) {
+    let profile = ecs.fetch::<Keybindings>().profile;
+    if let Err(e) = config::save_settings(&ecs.fetch::<GameConfig>(), profile) {
+        ecs.write_resource::<GameLog>().push(format!("Could not save the settings: {}", e));
     }
 }
 
@@ -123,21 +1230,48 @@ fn try_open_door(idx: usize, ecs: &mut World) {
 // in base al tasto passato al ctx.key (contesto key di Rltk)
 // se trova l'azione restituisce Some altrimenti None
 // Some() e None sono varianti di Option
-fn player_input(ctx: &mut Rltk) -> Option<PlayerAction> {
+// il tasto arriva dall'InputQueue e non direttamente da ctx.key, per non perdere le pressioni veloci
+// i tasti del movimento dipendono dal profilo scelto nelle impostazioni (Keybindings) e vincono sulle azioni
+// con orthogonal_only i tasti delle diagonali (Q/E/Z/C e gli angoli del tastierino) non fanno nulla
+// tenendo premuto shift un tasto di movimento sbircia in quella direzione invece di muoversi
+fn player_input(key: Option<VirtualKeyCode>, shift: bool, keybindings: &Keybindings, orthogonal_only: bool) -> Option<PlayerAction> {
     // Player movement
-    match ctx.key {
+    let action = match key {
         None => None, // Nothing happened
-        Some(key) => match key {
-            VirtualKeyCode::A | VirtualKeyCode::Left | VirtualKeyCode::Numpad4=> Some(PlayerAction::Move { dx: -1, dy: 0 }),
-            VirtualKeyCode::D | VirtualKeyCode::Right | VirtualKeyCode::Numpad6 => Some(PlayerAction::Move { dx: 1, dy: 0 }),
-            VirtualKeyCode::W | VirtualKeyCode::Up | VirtualKeyCode::Numpad8 => Some(PlayerAction::Move { dx: 0, dy: -1 }),
-            VirtualKeyCode::X | VirtualKeyCode::Down | VirtualKeyCode::Numpad2 => Some(PlayerAction::Move { dx: 0, dy: 1 }),
-            VirtualKeyCode::Q | VirtualKeyCode::Numpad7 => Some(PlayerAction::Move { dx: -1, dy: -1 }),
-            VirtualKeyCode::E | VirtualKeyCode::Numpad9 => Some(PlayerAction::Move { dx: 1, dy: -1 }),
-            VirtualKeyCode::Z | VirtualKeyCode::Numpad1 => Some(PlayerAction::Move { dx: -1, dy: 1 }),
-            VirtualKeyCode::C | VirtualKeyCode::Numpad3 => Some(PlayerAction::Move { dx: 1, dy: 1 }),
-            _ => None, // se non trova nulla restituisce None
+        Some(key) => match keybindings.direction(key) {
+            Some(direction) if shift => Some(PlayerAction::Peek(direction)),
+            Some(direction) => Some(PlayerAction::Move(direction)),
+            None => match key {
+                VirtualKeyCode::B | VirtualKeyCode::Backslash => Some(PlayerAction::OpenBestiary),
+                VirtualKeyCode::R => Some(PlayerAction::Rest),
+                VirtualKeyCode::Tab => Some(PlayerAction::CycleTarget),
+                VirtualKeyCode::F => Some(PlayerAction::Fire),
+                VirtualKeyCode::L | VirtualKeyCode::Semicolon => Some(PlayerAction::Look),
+                VirtualKeyCode::O => Some(PlayerAction::OpenDoors),
+                VirtualKeyCode::G => Some(PlayerAction::Dig),
+                VirtualKeyCode::P => Some(PlayerAction::SwitchMember),
+                VirtualKeyCode::M => Some(PlayerAction::ShowLog),
+                VirtualKeyCode::T => Some(PlayerAction::Disarm),
+                VirtualKeyCode::Comma if shift => Some(PlayerAction::DropItems), // <
+                VirtualKeyCode::Comma => Some(PlayerAction::PickUp),
+                VirtualKeyCode::Escape => Some(PlayerAction::Settings),
+                VirtualKeyCode::V => Some(PlayerAction::SetTrap),
+                VirtualKeyCode::Slash if shift => Some(PlayerAction::ShowLegend), // ?
+                VirtualKeyCode::Slash => Some(PlayerAction::Zap),
+                VirtualKeyCode::Period => Some(PlayerAction::TravelToPortal),
+                VirtualKeyCode::K | VirtualKeyCode::Apostrophe => Some(PlayerAction::Throw),
+                VirtualKeyCode::S => Some(PlayerAction::CycleStance),
+                VirtualKeyCode::I => Some(PlayerAction::ToggleBreadcrumbs),
+                VirtualKeyCode::F3 => Some(PlayerAction::CycleHeatmap), // debug
+                VirtualKeyCode::F2 => Some(PlayerAction::DebugTeleport), // debug, only with GameConfig::debug_commands
+                VirtualKeyCode::F4 => Some(PlayerAction::CopySetup),
+                _ => None, // se non trova nulla restituisce None
+            },
         },
+    };
+    match action {
+        Some(PlayerAction::Move(direction) | PlayerAction::Peek(direction)) if orthogonal_only && direction.is_diagonal() => None,
+        _ => action,
     }
 }
 
@@ -146,35 +1280,413 @@ impl GameState for State {
     fn tick(&mut self, ctx : &mut Rltk) {
         // ora con il movimento ha senso pulire il buffer della console
         ctx.cls();
-        // disegniamo la mappa in un blocco separato per rilasciare il borrow di ecs
-        // che avviene a causa di self.ecs.fetch() (ovvero durante l'accesso al world come risorsa)
-        // in questo caso non c'è bisogno di usare il borrow perché non si modifica il mondo
-        // ma solo si accede ad esso
-        {
-            draw_map(&self.ecs, ctx);
+        self.ecs.write_resource::<FrameClock>().elapsed_ms += ctx.frame_time_ms;
+        // the camera keeps the active character in the middle of the map region
+        if let (Some((x, y)), Some(map)) = (player_position(&self.ecs), self.ecs.try_fetch::<map::Map>()) {
+            self.ecs.write_resource::<gui::UiLayout>().follow(x, y, map.width, map.height);
         }
 
-        // INPUTS -------------------------------------------
-        let player_action = player_input(ctx);
-        if let Some(action) = player_action {
-            match action {
-                // se trova l'azione Move esegue try_move_player
-                PlayerAction::Move { dx, dy } => {
-                    // passiamo gli spostamenti assegnati al tasto e il mondo
-                    try_move_player(dx, dy, &mut self.ecs);
+        let runstate = *self.ecs.fetch::<RunState>();
+        // during play every key press is queued, menus keep reading ctx.key directly
+        if let Some(key) = ctx.key.filter(|_| runstate == RunState::Running) {
+            self.ecs.write_resource::<InputQueue>().push(key, ctx.shift);
+        }
+
+        match runstate {
+            RunState::Running => {
+                // disegniamo la mappa in un blocco separato per rilasciare il borrow di ecs
+                // che avviene a causa di self.ecs.fetch() (ovvero durante l'accesso al world come risorsa)
+                // in questo caso non c'è bisogno di usare il borrow perché non si modifica il mondo
+                // ma solo si accede ad esso
+                {
+                    draw_map(&self.ecs, ctx);
+                    heatmap::draw_heatmap(&self.ecs, ctx);
                 }
-                // aggiungeremo altre azioni qui in futuro
-            }
-        } // se trova None non fa nulla
 
-        // run ECS systems
-        self.run_systems();
-        // ECS Entities rendering pipeline
-        let positions = self.ecs.read_storage::<Position>();
-        let renderables = self.ecs.read_storage::<Renderable>();
+                // INPUTS -------------------------------------------
+                // one queued key, so one action, per turn
+                let (queued_key, shift) = match self.ecs.write_resource::<InputQueue>().pop() {
+                    Some((key, shift)) => (Some(key), shift),
+                    None => (None, false),
+                };
+                let orthogonal_only = self.ecs.fetch::<GameConfig>().orthogonal_only;
+                let player_action = player_input(queued_key, shift, &self.ecs.fetch::<Keybindings>(), orthogonal_only);
+                // i mostri agiscono solo quando il giocatore ha speso il suo turno: l'azione
+                // deve essere andata a segno e costare un turno (vedi PlayerAction::cost)
+                let mut turn_taken = false;
+                if let Some(action) = player_action {
+                    let mut performed = true;
+                    // qualsiasi altra azione rimette il giocatore dritto
+                    if !matches!(action, PlayerAction::Peek(_)) {
+                        stop_peeking(&mut self.ecs);
+                    }
+                    match action {
+                        // se trova l'azione Move esegue try_move_player
+                        PlayerAction::Move(direction) => {
+                            // passiamo la direzione assegnata al tasto e il mondo
+                            performed = try_move_player(direction, &mut self.ecs);
+                        }
+                        PlayerAction::Peek(direction) => {
+                            peek(direction, &mut self.ecs);
+                        }
+                        PlayerAction::OpenBestiary => {
+                            self.ecs.write_resource::<InputQueue>().clear();
+                            *self.ecs.write_resource::<RunState>() = RunState::ShowBestiary;
+                        }
+                        PlayerAction::ShowLegend => {
+                            self.ecs.write_resource::<InputQueue>().clear();
+                            *self.ecs.write_resource::<RunState>() = RunState::ShowLegend;
+                        }
+                        PlayerAction::ShowLog => {
+                            self.ecs.write_resource::<InputQueue>().clear();
+                            *self.ecs.write_resource::<RunState>() = RunState::ShowLog { scroll: 0 };
+                        }
+                        PlayerAction::ToggleBreadcrumbs => {
+                            let shown = {
+                                let mut view = self.ecs.write_resource::<BreadcrumbView>();
+                                view.shown = !view.shown;
+                                view.shown
+                            };
+                            let message = if shown { "Breadcrumbs: on." } else { "Breadcrumbs: off." };
+                            self.ecs.write_resource::<GameLog>().push(message.to_string());
+                        }
+                        PlayerAction::CycleStance => {
+                            let player = self.ecs.fetch::<PlayerEntity>().0;
+                            let stance = {
+                                let mut stances = self.ecs.write_storage::<Stance>();
+                                let stance = stances.get(player).copied().unwrap_or_default().next();
+                                stances.insert(player, stance).expect("Unable to insert stance");
+                                stance
+                            };
+                            self.ecs.write_resource::<GameLog>().push(format!("You take a {} stance.", stance.name()));
+                        }
+                        PlayerAction::CycleHeatmap => {
+                            let metric = {
+                                let mut view = self.ecs.write_resource::<HeatmapView>();
+                                view.metric = view.metric.next();
+                                view.metric
+                            };
+                            self.ecs.write_resource::<GameLog>().push(format!("Heatmap: {}.", metric.name()));
+                        }
+                        PlayerAction::Settings => {
+                            self.ecs.write_resource::<InputQueue>().clear();
+                            let current = InputProfile::ALL.iter().position(|p| *p == self.ecs.fetch::<Keybindings>().profile).unwrap_or(0);
+                            *self.ecs.write_resource::<RunState>() = RunState::Settings { selection: current, in_game: true };
+                        }
+                        PlayerAction::Rest => {
+                            self.rest_until_interrupted();
+                        }
+                        PlayerAction::TravelToPortal => {
+                            self.travel_to_portal(false);
+                        }
+                        PlayerAction::CycleTarget => {
+                            targeting::cycle_target(&mut self.ecs);
+                        }
+                        PlayerAction::SwitchMember => {
+                            party::switch_active_member(&mut self.ecs);
+                        }
+                        PlayerAction::Fire => {
+                            performed = fire_at_target(&mut self.ecs);
+                        }
+                        PlayerAction::Dig => {
+                            self.ecs.write_resource::<InputQueue>().clear();
+                            *self.ecs.write_resource::<RunState>() = RunState::ChooseDigDirection;
+                        }
+                        PlayerAction::Zap => {
+                            if can_zap(&self.ecs) {
+                                self.ecs.write_resource::<InputQueue>().clear();
+                                *self.ecs.write_resource::<RunState>() = RunState::ChooseZapDirection;
+                            }
+                        }
+                        PlayerAction::Throw => {
+                            let player = self.ecs.fetch::<PlayerEntity>().0;
+                            if carried_lightsticks(&self.ecs, player).is_empty() {
+                                self.ecs.write_resource::<GameLog>().log_once("You have nothing to throw.");
+                            } else if let Some((x, y)) = player_position(&self.ecs) {
+                                self.ecs.write_resource::<InputQueue>().clear();
+                                *self.ecs.write_resource::<RunState>() = RunState::ThrowTarget { x, y };
+                            }
+                        }
+                        PlayerAction::DropItems => {
+                            let player = self.ecs.fetch::<PlayerEntity>().0;
+                            if backpack::carried(&self.ecs, player).is_empty() {
+                                self.ecs.write_resource::<GameLog>().log_once("Your backpack is empty.");
+                            } else {
+                                self.ecs.write_resource::<InputQueue>().clear();
+                                *self.ecs.write_resource::<RunState>() = RunState::DropItems { selection: 0 };
+                            }
+                        }
+                        PlayerAction::PickUp => {
+                            performed = pick_up_items(&mut self.ecs, true) > 0;
+                        }
+                        PlayerAction::SetTrap => {
+                            performed = set_trap(&mut self.ecs);
+                        }
+                        PlayerAction::Disarm => {
+                            performed = trap_system::disarm_adjacent_trap(&mut self.ecs);
+                        }
+                        PlayerAction::OpenDoors => {
+                            performed = door_system::open_adjacent_doors(&mut self.ecs) > 0;
+                        }
+                        PlayerAction::CopySetup => {
+                            copy_setup(&mut self.ecs);
+                        }
+                        PlayerAction::DebugTeleport => {
+                            if self.ecs.fetch::<GameConfig>().debug_commands {
+                                self.ecs.write_resource::<InputQueue>().clear();
+                                *self.ecs.write_resource::<RunState>() = RunState::DebugTeleport { x: 0, y: 0, editing_y: false };
+                            }
+                        }
+                        PlayerAction::Look => {
+                            let (x, y) = targeting::next_look_position(&self.ecs, None);
+                            self.ecs.write_resource::<InputQueue>().clear();
+                            *self.ecs.write_resource::<RunState>() = RunState::Examine { x, y };
+                        }
+                    }
+                    turn_taken = performed && action.cost(&self.ecs.fetch::<GameConfig>()) == ActionCost::Turn;
+                } // se trova None non fa nulla
 
-        for (pos, render) in (&positions, &renderables).join() {
-            ctx.set(pos.x, pos.y, render.fg, render.bg, render.glyph);
+                // run ECS systems
+                self.run_systems();
+                if turn_taken {
+                    self.end_turn();
+                }
+                damage_system::delete_the_dead(&mut self.ecs);
+                descent::update_descent_lock(&mut self.ecs);
+                self.take_stairs();
+                self.render_entities(ctx);
+            }
+            RunState::Confirm { action } => {
+                if action != ConfirmableAction::Quit && action != ConfirmableAction::LoadGame {
+                    draw_map(&self.ecs, ctx);
+                    self.render_entities(ctx);
+                }
+                match (gui::confirm(action.prompt(), ctx), action) {
+                    (gui::ConfirmResult::NoResponse, _) => {}
+                    (gui::ConfirmResult::Yes, ConfirmableAction::Descend) => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                        if let Some(target) = portal_under_player(&self.ecs) {
+                            self.descend(target);
+                        }
+                    }
+                    (gui::ConfirmResult::No, ConfirmableAction::Descend) => {
+                        // stays put on the portal: it asks again only once the player steps off and back on
+                        let idx = player_position(&self.ecs).map(|(x, y)| self.ecs.fetch::<map::Map>().xy_idx(x, y));
+                        self.ecs.write_resource::<DeclinedPortal>().idx = idx;
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                    (gui::ConfirmResult::Yes, ConfirmableAction::EnterHazard) => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                        self.travel_to_portal(true);
+                    }
+                    (gui::ConfirmResult::No, ConfirmableAction::EnterHazard) => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                    (gui::ConfirmResult::Yes, ConfirmableAction::Quit) => self.quit(ctx),
+                    (gui::ConfirmResult::No, ConfirmableAction::Quit) => {
+                        *self.ecs.write_resource::<RunState>() = RunState::GameOver;
+                    }
+                    (gui::ConfirmResult::Yes, ConfirmableAction::LoadGame) => {
+                        *self.ecs.write_resource::<RunState>() = if self.resume_saved_game() {
+                            RunState::Running
+                        } else {
+                            RunState::MainMenu { selection: DifficultyLevel::Normal }
+                        };
+                    }
+                    (gui::ConfirmResult::No, ConfirmableAction::LoadGame) => {
+                        *self.ecs.write_resource::<RunState>() = RunState::MainMenu { selection: DifficultyLevel::Normal };
+                    }
+                }
+            }
+            RunState::Examine { x, y } => {
+                draw_map(&self.ecs, ctx);
+                self.render_entities(ctx);
+                match gui::examine(&self.ecs, ctx, x, y) {
+                    gui::ExamineResult::NoResponse => {}
+                    gui::ExamineResult::MoveCursor { x, y } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Examine { x, y };
+                    }
+                    gui::ExamineResult::Cancel => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                }
+            }
+            RunState::ChooseDigDirection => {
+                draw_map(&self.ecs, ctx);
+                self.render_entities(ctx);
+                ctx.print_color(1, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Dig in which direction? (ESC to cancel)");
+                let orthogonal_only = self.ecs.fetch::<GameConfig>().orthogonal_only;
+                let chosen = player_input(ctx.key, false, &self.ecs.fetch::<Keybindings>(), orthogonal_only);
+                if ctx.key == Some(VirtualKeyCode::Escape) {
+                    *self.ecs.write_resource::<RunState>() = RunState::Running;
+                } else if let Some(PlayerAction::Move(direction)) = chosen {
+                    *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    if try_dig(direction, &mut self.ecs) {
+                        self.run_systems();
+                        self.end_turn();
+                        damage_system::delete_the_dead(&mut self.ecs);
+                    }
+                }
+            }
+            RunState::ChooseZapDirection => {
+                draw_map(&self.ecs, ctx);
+                self.render_entities(ctx);
+                ctx.print_color(1, 0, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Zap in which direction? (ESC to cancel)");
+                let orthogonal_only = self.ecs.fetch::<GameConfig>().orthogonal_only;
+                let chosen = player_input(ctx.key, false, &self.ecs.fetch::<Keybindings>(), orthogonal_only);
+                if ctx.key == Some(VirtualKeyCode::Escape) {
+                    *self.ecs.write_resource::<RunState>() = RunState::Running;
+                } else if let Some(PlayerAction::Move(direction)) = chosen {
+                    *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    if zap_digging_beam(direction, &mut self.ecs) {
+                        self.run_systems();
+                        self.end_turn();
+                        damage_system::delete_the_dead(&mut self.ecs);
+                    }
+                }
+            }
+            RunState::ThrowTarget { x, y } => {
+                draw_map(&self.ecs, ctx);
+                self.render_entities(ctx);
+                match gui::throw_target(&self.ecs, ctx, x, y, THROW_RANGE) {
+                    gui::ThrowResult::NoResponse => {}
+                    gui::ThrowResult::MoveCursor { x, y } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::ThrowTarget { x, y };
+                    }
+                    gui::ThrowResult::Throw { x, y } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                        if throw_lightstick(&mut self.ecs, x, y) {
+                            self.run_systems();
+                            self.end_turn();
+                            damage_system::delete_the_dead(&mut self.ecs);
+                        }
+                    }
+                    gui::ThrowResult::Cancel => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                }
+            }
+            RunState::DebugTeleport { x, y, editing_y } => {
+                draw_map(&self.ecs, ctx);
+                self.render_entities(ctx);
+                match gui::teleport_prompt(ctx, x, y, editing_y) {
+                    gui::TeleportResult::NoResponse => {}
+                    gui::TeleportResult::Edit { x, y, editing_y } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::DebugTeleport { x, y, editing_y };
+                    }
+                    gui::TeleportResult::Teleport { x, y } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                        let message = match debug_teleport(&mut self.ecs, x, y) {
+                            Ok(()) => format!("Teleported to ({}, {}).", x, y),
+                            Err(reason) => reason,
+                        };
+                        self.ecs.write_resource::<GameLog>().push(message);
+                        self.run_systems();
+                    }
+                    gui::TeleportResult::Cancel => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                }
+            }
+            RunState::DropItems { selection } => {
+                draw_map(&self.ecs, ctx);
+                self.render_entities(ctx);
+                match gui::drop_menu(&self.ecs, ctx, selection) {
+                    gui::DropResult::NoResponse { selection } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::DropItems { selection };
+                    }
+                    gui::DropResult::Drop { category } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                        let player = self.ecs.fetch::<PlayerEntity>().0;
+                        // the whole batch goes down in one turn
+                        if backpack::drop_all(&mut self.ecs, player, category) > 0 {
+                            self.run_systems();
+                            self.end_turn();
+                            damage_system::delete_the_dead(&mut self.ecs);
+                        }
+                    }
+                    gui::DropResult::Cancel => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                }
+            }
+            RunState::ShowBestiary => {
+                if gui::show_bestiary(&mut self.ecs, ctx) == gui::MenuResult::Cancel {
+                    *self.ecs.write_resource::<RunState>() = RunState::Running;
+                }
+            }
+            RunState::ShowLegend => {
+                draw_map(&self.ecs, ctx);
+                self.render_entities(ctx);
+                if gui::show_legend(&self.ecs, ctx) == gui::MenuResult::Cancel {
+                    *self.ecs.write_resource::<RunState>() = RunState::Running;
+                }
+            }
+            RunState::ShowLog { scroll } => {
+                match gui::show_log(&self.ecs, ctx, scroll) {
+                    gui::LogResult::NoResponse => {}
+                    gui::LogResult::Scroll { scroll } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::ShowLog { scroll };
+                    }
+                    gui::LogResult::Cancel => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                }
+            }
+            RunState::Settings { selection, in_game } => {
+                let back = if in_game { RunState::Running } else { RunState::MainMenu { selection: DifficultyLevel::Normal } };
+                let active = self.ecs.fetch::<Keybindings>().profile;
+                let result = gui::settings_menu(active, &self.ecs.fetch::<GameConfig>(), selection, ctx);
+                match result {
+                    gui::SettingsResult::NoResponse { selection: next } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::Settings { selection: next, in_game };
+                    }
+                    gui::SettingsResult::Selected { profile } => {
+                        self.ecs.insert(Keybindings::preset(profile));
+                        persist_settings(&mut self.ecs);
+                        *self.ecs.write_resource::<RunState>() = back;
+                    }
+                    gui::SettingsResult::Toggled { toggle } => {
+                        let on = toggle.flip(&mut self.ecs.write_resource::<GameConfig>());
+                        // the map in play follows the new movement rule at once, the next ones get it from GameConfig
+                        if let Some(mut map) = self.ecs.try_fetch_mut::<map::Map>().filter(|_| toggle == Toggle::OrthogonalOnly) {
+                            map.orthogonal_only = on;
+                        }
+                        if toggle == Toggle::ShowUnexplored {
+                            self.ecs.write_resource::<gui::UiLayout>().show_unexplored = on;
+                        }
+                        persist_settings(&mut self.ecs);
+                    }
+                    gui::SettingsResult::Cancel => {
+                        *self.ecs.write_resource::<RunState>() = back;
+                    }
+                }
+            }
+            RunState::MainMenu { selection } => {
+                match gui::main_menu(selection, ctx) {
+                    gui::MainMenuResult::NoSelection { selected } => {
+                        *self.ecs.write_resource::<RunState>() = RunState::MainMenu { selection: selected };
+                    }
+                    gui::MainMenuResult::Settings => {
+                        let current = InputProfile::ALL.iter().position(|p| *p == self.ecs.fetch::<Keybindings>().profile).unwrap_or(0);
+                        *self.ecs.write_resource::<RunState>() = RunState::Settings { selection: current, in_game: false };
+                    }
+                    gui::MainMenuResult::Selected { selected } => {
+                        self.ecs.insert(Difficulty::from_level(selected));
+                        self.populate_level(1);
+                        *self.ecs.write_resource::<RunState>() = RunState::Running;
+                    }
+                }
+            }
+            RunState::GameOver => {
+                if gui::game_over(&self.ecs, ctx) == gui::MenuResult::Cancel {
+                    if self.ecs.fetch::<GameConfig>().confirm.requires(ConfirmableAction::Quit) {
+                        *self.ecs.write_resource::<RunState>() = RunState::Confirm { action: ConfirmableAction::Quit };
+                    } else {
+                        self.quit(ctx);
+                    }
+                }
+            }
         }
     }
 }
@@ -183,78 +1695,464 @@ impl GameState for State {
 struct LeftWalker {}
 impl<'a> System<'a> for LeftWalker {
     type SystemData = (ReadStorage<'a, LeftMover>, 
-                        WriteStorage<'a, Position>);
+                        WriteStorage<'a, Position>,
+                        ReadExpect<'a, map::Map>);
 // lefty è l'alias di riferimento alla readstorage su LeftMover
 // pos è l'alias di riferimento alla writestorage (per questo è mut) su Position
-    fn run(&mut self, (lefty, mut pos) : Self::SystemData) {
+    fn run(&mut self, (lefty, mut pos, map) : Self::SystemData) {
         //join per verificare che l'entità abbia entrambi i componenti
         for (_lefty,pos) in (&lefty, &mut pos).join() {
             // purtroppo il % in rust non è smart come in python e non permette overflow
-            // per questo motivo dobbiamo usare la formula (pos.x - 1 + 80) % 80
+            // per questo motivo usiamo rem_euclid sulla larghezza della mappa
             // per evitare overflow verso sinistra
-            pos.x = (pos.x - 1 + 80) % 80;
+            pos.x = (pos.x - 1).rem_euclid(map.width);
         }
     }
 }
 // ECS Systems execution pipeline
 impl State {
+    /// Spawns monsters, gold, food, an amulet and a few barricades for the level at `depth`, scaled by the chosen Difficulty.
+    /// In clear_to_descend mode the portal is then sealed until they are all dead.
+    fn populate_level(&mut self, depth: i32) {
+        const BASE_MONSTERS: i32 = 10;
+        const GOLD_PILES: i32 = 8;
+        const RATIONS: i32 = 3;
+        const BARRICADES: i32 = 2;
+        const CAMP_SIZE: i32 = 2;
+        const WOLF_PACKS: i32 = 1;
+        const LIGHTSTICKS: i32 = 2;
+        const ARROW_BUNDLES: i32 = 2;
+
+        let player_start = player_position(&self.ecs).unwrap_or((0, 0));
+        let monster_count = self.ecs.fetch::<Difficulty>().monster_count(BASE_MONSTERS, depth);
+        spawner::spawn_monsters(&mut self.ecs, monster_count, player_start);
+        spawner::spawn_gold(&mut self.ecs, GOLD_PILES, player_start);
+        spawner::spawn_food(&mut self.ecs, RATIONS, player_start);
+        spawner::spawn_amulet_of_sight(&mut self.ecs, player_start);
+        spawner::spawn_spear(&mut self.ecs, player_start);
+        spawner::spawn_key(&mut self.ecs, player_start);
+        spawner::spawn_bear_trap(&mut self.ecs, player_start);
+        spawner::spawn_wand_of_digging(&mut self.ecs, player_start);
+        spawner::spawn_scroll_of_summoning(&mut self.ecs, player_start);
+        spawner::spawn_lightsticks(&mut self.ecs, LIGHTSTICKS, player_start);
+        spawner::spawn_arrows(&mut self.ecs, ARROW_BUNDLES, player_start);
+        spawner::spawn_barricades(&mut self.ecs, BARRICADES, player_start);
+        let biome = self.ecs.fetch::<CurrentLevel>().biome;
+        // camps fill the clearings of the open maps, the rooms of a building are no camps
+        if biome != BiomeKind::Building {
+            spawner::spawn_camps(&mut self.ecs, CAMP_SIZE, player_start);
+        }
+        spawner::spawn_braziers(&mut self.ecs, biome.biome().decorations().brazier_chance);
+        // wolves only live outdoors, in the woods and on the mountains
+        if matches!(biome, BiomeKind::Forest | BiomeKind::SnowyMountains) {
+            spawner::spawn_packs(&mut self.ecs, WOLF_PACKS, player_start);
+        }
+        descent::update_descent_lock(&mut self.ecs);
+    }
+
+    /// The end of the game: exports the telemetry (if configured) and closes the window.
+    fn quit(&mut self, ctx: &mut Rltk) {
+        let path = self.ecs.fetch::<GameConfig>().telemetry_path.clone();
+        if let Some(path) = path {
+            telemetry::dump(&self.ecs, &path).unwrap_or_else(|e| eprintln!("Could not write the telemetry to {}: {}", path, e));
+        }
+        ctx.quit();
+    }
+
+    /// After the player has moved: goes through the portal or down the stairs under them (asking
+    /// first, if GameConfig.confirm wants it), or up the stairs.
+    fn take_stairs(&mut self) {
+        if let Some(target) = portal_under_player(&self.ecs) {
+            if self.ecs.fetch::<GameConfig>().confirm.requires(ConfirmableAction::Descend) {
+                self.ecs.write_resource::<InputQueue>().clear();
+                *self.ecs.write_resource::<RunState>() = RunState::Confirm { action: ConfirmableAction::Descend };
+            } else {
+                self.descend(target);
+            }
+        } else if stairs_up_under_player(&self.ecs) {
+            self.ascend();
+        }
+    }
+
+    /// Portal (or stairs, see Biome::get_stairs): takes the party one level down, into a new level
+    /// of the `target` biome, or back into the one they already visited there (see change_level).
+    fn descend(&mut self, target: BiomeKind) {
+        let depth = self.ecs.fetch::<CurrentLevel>().depth + 1;
+        self.change_level(depth, target);
+        telemetry::record(&self.ecs, TelemetryEvent::LevelDescended { depth, biome: format!("{:?}", self.ecs.fetch::<CurrentLevel>().biome) });
+        audio::play(&self.ecs, SoundEvent::Descend);
+        self.ecs.write_resource::<GameLog>().push("The world twists around you!".to_string());
+        save_system::autosave(&mut self.ecs);
+    }
+
+    /// Up stairs: takes the party back to the level above, as they left it.
+    fn ascend(&mut self) {
+        let level = *self.ecs.fetch::<CurrentLevel>();
+        let depth = level.depth - 1;
+        // a biome for the level above only matters if it can't be restored and has to be generated again
+        let biome = self.ecs.fetch::<BiomeProgression>().biome_at(depth).unwrap_or(level.biome);
+        self.change_level(depth, biome);
+        self.ecs.write_resource::<GameLog>().push("You climb back up the stairs.".to_string());
+        save_system::autosave(&mut self.ecs);
+    }
+
+    /// Moves the party to the level at `depth`. The level they leave is frozen (see level_store);
+    /// the one at `depth` is thawed if they have been there, and the party arrives on the tile they
+    /// left it from. Otherwise a fresh level of the `biome` kind is generated and populated, and
+    /// the party arrives on its start. Either way the tile they arrive on doesn't send them
+    /// straight back before they step off it (see DeclinedPortal).
+    fn change_level(&mut self, depth: i32, biome: BiomeKind) {
+        level_store::freeze_level(&mut self.ecs);
+        let (arrival, fresh) = match level_store::thaw_level(&mut self.ecs, depth) {
+            Some(exit) => (exit, false),
+            None => {
+                let (orthogonal_only, reveal_all) = {
+                    let config = self.ecs.fetch::<GameConfig>();
+                    (config.orthogonal_only, config.reveal_all)
+                };
+                let (map, start, seed) = generate_map(&self.ecs, biome, depth, orthogonal_only, reveal_all);
+                self.ecs.insert(map);
+                *self.ecs.write_resource::<CurrentLevel>() = CurrentLevel { depth, biome, seed: Some(seed) };
+                (start, true)
+            }
+        };
+        let (start_x, start_y) = arrival;
+        self.ecs.insert(PassedDoors::default());
+        self.ecs.insert(TileTimer::default());
+        self.ecs.insert(SearchProgress::default());
+        self.ecs.insert(PathCache::default());
+        self.ecs.write_resource::<Targeting>().current_target = None;
+        // the whole party goes through: the active member on the start tile, the others around it
+        let player = self.ecs.fetch::<PlayerEntity>().0;
+        let followers : Vec<Entity> = party::party_members(&self.ecs).into_iter().filter(|member| *member != player).collect();
+        for member in std::iter::once(player).chain(followers) {
+            let (x, y) = if member == player {
+                (start_x, start_y)
+            } else {
+                spawner::find_valid_spawn(&self.ecs, start_x, start_y, 3).unwrap_or((start_x, start_y))
+            };
+            if let Some(pos) = self.ecs.write_storage::<Position>().get_mut(member) {
+                pos.x = x;
+                pos.y = y;
+            }
+            if let Some(viewshed) = self.ecs.write_storage::<Viewshed>().get_mut(member) {
+                viewshed.dirty = true;
+            }
+        }
+
+        if fresh {
+            self.populate_level(depth);
+        } else {
+            descent::update_descent_lock(&mut self.ecs);
+        }
+        let arrival_idx = self.ecs.fetch::<map::Map>().xy_idx(start_x, start_y);
+        self.ecs.write_resource::<DeclinedPortal>().idx = Some(arrival_idx);
+        self.refresh_fov();
+    }
+
+    /// Replaces the game main has just set up with the one in the save file.
+    /// False, with the reason in the log, if the save can't be read.
+    fn resume_saved_game(&mut self) -> bool {
+        let path = self.ecs.fetch::<GameConfig>().save_path.clone();
+        if let Err(e) = save_system::load_game(&mut self.ecs, &path) {
+            self.ecs.write_resource::<GameLog>().push(format!("Could not load the game: {}", e));
+            return false;
+        }
+        descent::update_descent_lock(&mut self.ecs);
+        // saves are made on arrival, so the party is standing on the stairs it came through
+        if let Some((x, y)) = player_position(&self.ecs) {
+            let idx = self.ecs.fetch::<map::Map>().xy_idx(x, y);
+            self.ecs.write_resource::<DeclinedPortal>().idx = Some(idx);
+        }
+        self.refresh_fov();
+        true
+    }
+
+    /// Passes turns one after the other, regenerating 1 hp per turn, until the player is
+    /// fully healed, a monster comes into view or the player takes damage.
+    fn rest_until_interrupted(&mut self) {
+        const MAX_REST_TURNS: i32 = 200;
+        let mut message = "You rest for a while.";
+
+        for _turn in 0..MAX_REST_TURNS {
+            if monster_in_view(&self.ecs) {
+                message = "You can't rest with enemies in sight!";
+                break;
+            }
+            let hp_after_regen = match regenerate_player(&mut self.ecs) {
+                Some(hp) => hp,
+                None => {
+                    message = "You feel fully rested.";
+                    break;
+                }
+            };
+
+            self.run_systems();
+            self.end_turn();
+            damage_system::delete_the_dead(&mut self.ecs);
+
+            if player_hp(&self.ecs) < hp_after_regen {
+                message = "Your rest is interrupted!";
+                break;
+            }
+        }
+
+        self.ecs.write_resource::<GameLog>().push(message.to_string());
+    }
+
+    /// Walks the player, one turn per step, along the shortest path (a_star_search) to the
+    /// portal they have already seen on this level. Stops as soon as a monster comes into view,
+    /// the player takes damage or a step doesn't go through; the arrival is handled like any
+    /// other step onto the portal.
+    /// It also halts on the edge of a known hazard (see Map::known_hazard) and asks first:
+    /// with `into_hazard` the player already said yes, and the first step goes ahead anyway.
+    fn travel_to_portal(&mut self, into_hazard: bool) {
+        const MAX_TRAVEL_STEPS: usize = 300;
+        let (player_x, player_y) = match player_position(&self.ecs) {
+            Some(pos) => pos,
+            None => return,
+        };
+        let path = {
+            let map = self.ecs.fetch::<map::Map>();
+            match map.known_portal() {
+                Some(portal) => rltk::a_star_search(map.xy_idx(player_x, player_y), portal, &*map),
+                None => {
+                    self.ecs.write_resource::<GameLog>().log_once("You don't know where the way down is yet.");
+                    return;
+                }
+            }
+        };
+        if !path.success {
+            self.ecs.write_resource::<GameLog>().log_once("You know of no way there.");
+            return;
+        }
+
+        let width = self.ecs.fetch::<map::Map>().width;
+        let mut message = None;
+        for (count, step) in path.steps.iter().skip(1).take(MAX_TRAVEL_STEPS).enumerate() {
+            if monster_in_view(&self.ecs) {
+                message = Some("You stop: there are enemies in sight!");
+                break;
+            }
+            if self.ecs.fetch::<map::Map>().known_hazard(*step) && !(into_hazard && count == 0) {
+                self.ecs.write_resource::<InputQueue>().clear();
+                *self.ecs.write_resource::<RunState>() = RunState::Confirm { action: ConfirmableAction::EnterHazard };
+                break;
+            }
+            let (x, y) = match player_position(&self.ecs) {
+                Some(pos) => pos,
+                None => break,
+            };
+            let direction = match Direction::from_delta(*step as i32 % width - x, *step as i32 / width - y) {
+                Some(direction) => direction,
+                None => break,
+            };
+            let hp_before = player_hp(&self.ecs);
+            if !try_move_player(direction, &mut self.ecs) || player_position(&self.ecs) != Some((*step as i32 % width, *step as i32 / width)) {
+                message = Some("Something is in the way.");
+                break;
+            }
+            self.run_systems();
+            self.end_turn();
+            damage_system::delete_the_dead(&mut self.ecs);
+            if player_hp(&self.ecs) < hp_before {
+                message = Some("Your journey is interrupted!");
+                break;
+            }
+        }
+        if let Some(message) = message {
+            self.ecs.write_resource::<GameLog>().push(message.to_string());
+        }
+    }
+
+    /// ECS Entities rendering pipeline: entities, overlays and the UI on top of the map.
+    fn render_entities(&self, ctx: &mut Rltk) {
+        gui::draw_entities(&self.ecs, ctx);
+
+        gui::draw_path_preview(&self.ecs, ctx);
+        gui::draw_combat_prediction(&self.ecs, ctx);
+
+        // reticle on the current ranged target
+        if let Some(target) = self.ecs.fetch::<Targeting>().current_target {
+            let layout = self.ecs.fetch::<gui::UiLayout>();
+            if let Some((x, y)) = self.ecs.read_storage::<Position>().get(target).and_then(|pos| layout.to_screen(pos.x, pos.y)) {
+                ctx.set_bg(x, y, RGB::named(rltk::MAGENTA));
+            }
+        }
+        gui::draw_low_hp_vignette(&self.ecs, ctx);
+
+        gui::draw_ui(&self.ecs, ctx);
+        // in basso a sinistra, sul bordo dei messaggi: da dove viene questo livello (F4 lo scrive su file)
+        let log_rect = self.ecs.fetch::<gui::UiLayout>().log_rect;
+        ctx.print_color(log_rect.x1 + 2, log_rect.y2, RGB::named(rltk::GRAY), RGB::named(rltk::BLACK), setup_line(&self.ecs));
+    }
+
+    /// Computes what the party sees from where it stands, without anything else happening.
+    /// tick draws the map before running the systems, so a freshly placed party (new game,
+    /// new level) would otherwise see nothing until the first key press.
+    fn refresh_fov(&mut self) {
+        let mut vis = VisibilitySystem{};
+        vis.run_now(&self.ecs);
+        let mut memory = MemorySystem{};
+        memory.run_now(&self.ecs);
+        self.ecs.maintain();
+    }
+
     fn run_systems(&mut self) {
+        let mut effects = EffectsSystem{};
+        effects.run_now(&self.ecs);
         let mut vis = VisibilitySystem{};
         vis.run_now(&self.ecs);
+        let mut memory = MemorySystem{};
+        memory.run_now(&self.ecs);
+        let mut bestiary = BestiarySystem{};
+        bestiary.run_now(&self.ecs);
+        let mut low_hp = LowHpAnimationSystem{};
+        low_hp.run_now(&self.ecs);
         let mut lw = LeftWalker{};
         lw.run_now(&self.ecs);
         self.ecs.maintain();
     }
+
+    /// Everything that happens after the player has spent a turn: every monster acts once
+    /// (and may walk into a trap set by the player, or into one of the map's own), then the summoned allies,
+    /// the player gets a little hungrier, the damage and healing of the turn are applied,
+    /// doors close behind the player, a hidden trap next to them may give itself away
+    /// (and is spotted for good after a few turns spent beside it),
+    /// timed tiles (ice, vines) count down and the clock fires the events scheduled for this turn.
+    fn end_turn(&mut self) {
+        let before = trap_system::monster_positions(&self.ecs);
+        let mut ai = MonsterAI{};
+        ai.run_now(&self.ecs);
+        trap_system::spring_player_traps(&mut self.ecs);
+        trap_system::spring_traps_under_monsters(&mut self.ecs, &before);
+        let mut allies = AllyAI{};
+        allies.run_now(&self.ecs);
+        let mut hunger = HungerSystem{};
+        hunger.run_now(&self.ecs);
+        effects::tick_statuses(&mut self.ecs);
+        let mut effects = EffectsSystem{};
+        effects.run_now(&self.ecs);
+        self.ecs.maintain();
+        door_system::auto_close_doors(&mut self.ecs);
+        trap_system::sense_danger(&mut self.ecs);
+        trap_system::passive_search(&mut self.ecs);
+        trap_system::glimpse_traps(&mut self.ecs);
+        tile_timer::tick_tile_timers(&mut self.ecs);
+        visibility_system::burn_down_lights(&mut self.ecs);
+        recharge_wands(&mut self.ecs);
+        clock::advance_turn(&mut self.ecs);
+    }
+}
+
+/// Registers the components and inserts every resource of a new game in `ecs`, all but the
+/// map, the CurrentLevel and the party, which main creates next.
+fn insert_resources(ecs: &mut World, config: GameConfig, profile: InputProfile) {
+    // ECS Components registration
+    register_components(ecs);
+    ecs.insert(RunState::MainMenu { selection: DifficultyLevel::Normal });
+    ecs.insert(Difficulty::default());
+    ecs.insert(Bestiary::default());
+    ecs.insert(Wallet::default());
+    ecs.insert(Targeting::default());
+    ecs.insert(FootstepThrottle::default());
+    ecs.insert(InputQueue::default());
+    ecs.insert(PassedDoors::default());
+    ecs.insert(Clock::default());
+    ecs.insert(NoiseEvents::default());
+    ecs.insert(EffectQueue::default());
+    ecs.insert(Telemetry::default());
+    ecs.insert(Audio::default());
+    ecs.insert(Keybindings::preset(profile));
+    ecs.insert(HeatmapView::default());
+    ecs.insert(TileTimer::default());
+    ecs.insert(PathCache::default());
+    ecs.insert(SearchProgress::default());
+    ecs.insert(DescentLock::default());
+    ecs.insert(DeclinedPortal::default());
+    ecs.insert(Exploration::default());
+    ecs.insert(BreadcrumbView::default());
+    ecs.insert(config.seed.map_or_else(GameRng::new, GameRng::seeded));
+    ecs.insert(LevelStore::default());
+    ecs.insert(FrameClock::default());
+    ecs.insert(gui::UiLayout { show_unexplored: config.show_unexplored, ..gui::UiLayout::new(config.width, config.height, 7) });
+    ecs.insert(GameLog { entries: vec!["Welcome to the dungeon!".to_string()].into(), max_entries: config.log_history });
+    ecs.insert(config);
+    ecs.insert(BiomeProgression::default());
 }
 
 fn main() -> rltk::BError {
     // STARTUP ----------------------------------------------
-    use rltk::RltkBuilder;
-    let context = RltkBuilder::simple80x50()
-        .with_title("Roguelike Tutorial")
-        .build()?;
+    // titolo, dimensioni della finestra e tileset si cambiano in GameConfig
+    // (la mappa non dipende dalla console: se è più grande, la telecamera ne mostra solo una parte)
+    // flag nascosto: `--bench-maps N` misura i generatori di mappe e chiude, senza aprire la finestra
+    let args : Vec<String> = std::env::args().collect();
+    if let Some(at) = args.iter().position(|arg| arg == "--bench-maps") {
+        let maps = args.get(at + 1).and_then(|n| n.parse().ok()).unwrap_or(100);
+        map_bench::run(maps);
+        return Ok(());
+    }
+    let mut config = GameConfig::default();
+    let profile = config::load_settings(&mut config).unwrap_or(InputProfile::Classic);
+    let context = config.build_context()?;
     let mut gs = State {
         ecs: World::new()
     };
-    // ECS Components registration
-    gs.ecs.register::<Position>();
-    gs.ecs.register::<Renderable>();
-    gs.ecs.register::<LeftMover>(); // tag component è comunque da registrare
-    gs.ecs.register::<Player>();
-    gs.ecs.register::<CanMove>();
-    gs.ecs.register::<CharacterSize>();
-    gs.ecs.register::<Viewshed>();
+    insert_resources(&mut gs.ecs, config, profile);
     
     // inseriamo la mappa come risorsa, quindi globalmente accessibile nel mondo ecs
         // --- MAP CREATION ---
-    // Here, we decide which biome to generate.
-    // We can easily switch `Forest` to `Volcano`, `Building`, or `SnowyMountains`
-    // to completely change the generated world.
-    let biome = Building{};
-    // we can choose between wilderness_map and dungeon_map creators
-    let (map, rooms) = wilderness_map(&biome);
-    gs.ecs.insert(map);
-    let (player_x, player_y) = if rooms.is_empty() {
-        (40, 25) // Default position for wilderness maps
-    } else {
-        rooms[0].center() // Position for dungeon maps
+    // The biome of each depth comes from the BiomeProgression: editing its table (or emptying it,
+    // to go back to the portal rotation) changes the whole dungeon.
+    // (the generator itself is chosen in level_builder)
+    let biome = gs.ecs.fetch::<BiomeProgression>().biome_at(1).unwrap_or(BiomeKind::Building);
+    let (orthogonal_only, reveal_all) = {
+        let config = gs.ecs.fetch::<GameConfig>();
+        (config.orthogonal_only, config.reveal_all)
     };
+    let ascii_level = gs.ecs.fetch::<GameConfig>().ascii_level.clone();
+    let hand_drawn = ascii_level.and_then(|path| match map_builders::load_map_from_ascii(&path, &*biome.biome()) {
+        Ok(map) => Some(map),
+        Err(e) => {
+            eprintln!("Could not read the level {}: {}", path, e);
+            None
+        }
+    });
+    let (map, (player_x, player_y), seed) = match hand_drawn {
+        Some(mut map) => {
+            map.orthogonal_only = orthogonal_only;
+            if reveal_all {
+                map.revealed_tiles = vec![true; map.tiles.len()];
+            }
+            let start = map_builders::start_point(&map);
+            (map, start, None)
+        }
+        None => {
+            let (map, start, seed) = generate_map(&gs.ecs, biome, 1, orthogonal_only, reveal_all);
+            (map, start, Some(seed))
+        }
+    };
+    gs.ecs.insert(map);
+    gs.ecs.insert(CurrentLevel { depth: 1, biome, seed });
 
     // ECS Entities creation pipeline
-    gs.ecs
-        .create_entity()
-        .with(Position { x: player_x, y: player_y })
-        .with(Renderable {
-        glyph: rltk::to_cp437('@'),
-        fg: RGB::named(rltk::YELLOW),
-        bg: RGB::named(rltk::BLACK),
-    })
-    .with(Player{}) //identifica il player
-    .with(CanMove{}) // permette al player di muovere
-    .with(CharacterSize::Medium) // definisce la taglia del player
-    // The player's viewshed is initially dirty so it's calculated on the first turn.
-    .with(Viewshed { visible_tiles: Vec::new(), range: 8, dirty: true }) // definisce il campo visivo del player
-    .build();
+    // teniamo l'Entity del giocatore come risorsa, così i sistemi non devono cercarlo con un join
+    let player_entity = spawner::player(&mut gs.ecs, player_x, player_y);
+    gs.ecs.insert(PlayerEntity(player_entity));
+    // un compagno accanto al giocatore: P passa i comandi dall'uno all'altro
+    let mut party = vec![player_entity];
+    if let Some((x, y)) = spawner::find_valid_spawn(&gs.ecs, player_x, player_y, 2) {
+        party.push(spawner::companion(&mut gs.ecs, x, y));
+    }
+    gs.ecs.insert(Party::new(party));
+    // la zona di partenza è già visibile al primo frame
+    gs.refresh_fov();
+    // se c'è un salvataggio, prima del menu principale si chiede se riprenderlo
+    if std::path::Path::new(&gs.ecs.fetch::<GameConfig>().save_path).exists() {
+        *gs.ecs.write_resource::<RunState>() = RunState::Confirm { action: ConfirmableAction::LoadGame };
+    }
+
+    // mostri e oro vengono creati dopo la scelta della difficoltà nel menu principale (vedi populate_level)
 
     // Togliendo la creazione dei nemici, il sistema LeftWalker non ha più nulla da fare, 
     // quindi non fa nulla, anche senza cancellarlo
@@ -273,4 +2171,111 @@ fn main() -> rltk::BError {
 
     // GAMELOOP ---------------------------------------------
     rltk::main_loop(context, gs)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A game on its first level, as main starts it, saving to `save_path`.
+    fn new_game(save_path: &str) -> State {
+        let mut gs = State { ecs: World::new() };
+        let config = GameConfig { seed: Some(11), save_path: save_path.to_string(), ..GameConfig::default() };
+        insert_resources(&mut gs.ecs, config, InputProfile::Classic);
+        let biome = gs.ecs.fetch::<BiomeProgression>().biome_at(1).unwrap_or(BiomeKind::Building);
+        let (map, (x, y), seed) = generate_map(&gs.ecs, biome, 1, false, false);
+        gs.ecs.insert(map);
+        gs.ecs.insert(CurrentLevel { depth: 1, biome, seed: Some(seed) });
+        let player = spawner::player(&mut gs.ecs, x, y);
+        gs.ecs.insert(PlayerEntity(player));
+        gs.ecs.insert(Party::new(vec![player]));
+        gs.refresh_fov();
+        gs
+    }
+
+    #[test]
+    fn going_down_a_level_autosaves() {
+        let path = std::env::temp_dir().join("rogue_autosave_descend_test.json");
+        let path = path.to_str().unwrap();
+        std::fs::remove_file(path).ok();
+        let mut gs = new_game(path);
+
+        gs.descend(BiomeKind::Building);
+        let saved = std::fs::read_to_string(path);
+        std::fs::remove_file(path).ok();
+        assert_eq!(gs.ecs.fetch::<CurrentLevel>().depth, 2);
+        assert!(saved.is_ok_and(|text| text.contains("\"rng_seed\":11")), "the save file was written");
+        assert!(gs.ecs.fetch::<GameLog>().entries.iter().any(|entry| entry == "Game saved."));
+    }
+
+    #[test]
+    fn a_saved_game_resumes_where_it_was_saved() {
+        let path = std::env::temp_dir().join("rogue_resume_test.json");
+        let path = path.to_str().unwrap();
+        let mut gs = new_game(path);
+        gs.ecs.write_resource::<Wallet>().gold = 12;
+        gs.descend(BiomeKind::Building);
+        let position = player_position(&gs.ecs).unwrap();
+        let tiles = gs.ecs.fetch::<map::Map>().tiles.clone();
+
+        let mut resumed = new_game(path);
+        let resumed_ok = resumed.resume_saved_game();
+        std::fs::remove_file(path).ok();
+        assert!(resumed_ok, "the save could be read");
+        assert_eq!(resumed.ecs.fetch::<CurrentLevel>().depth, 2);
+        assert_eq!(player_position(&resumed.ecs), Some(position));
+        assert!(resumed.ecs.fetch::<map::Map>().tiles == tiles, "the map is the saved one, not a fresh level 1");
+        assert_eq!(resumed.ecs.fetch::<Wallet>().gold, 12);
+        assert!(resumed.ecs.fetch::<LevelStore>().levels.contains_key(&1), "the level above is still there to go back to");
+    }
+
+    #[test]
+    fn the_level_above_is_found_as_it_was_left() {
+        let path = std::env::temp_dir().join("rogue_level_stack_test.json");
+        let mut gs = new_game(path.to_str().unwrap());
+        let (tiles, revealed) = {
+            let map = gs.ecs.fetch::<map::Map>();
+            (map.tiles.clone(), map.revealed_tiles.clone())
+        };
+        let start = player_position(&gs.ecs).unwrap();
+
+        gs.descend(BiomeKind::Building);
+        // the party arrives on the up stairs, which wait for them to step off and back on
+        let arrival = player_position(&gs.ecs).unwrap();
+        assert_eq!(gs.ecs.fetch::<map::Map>().tiles[gs.ecs.fetch::<map::Map>().xy_idx(arrival.0, arrival.1)].stairs, Some(Stairs::Up));
+        gs.take_stairs();
+        assert_eq!(gs.ecs.fetch::<CurrentLevel>().depth, 2);
+        let step = Direction::ALL.into_iter()
+            .find(|direction| try_move_player(*direction, &mut gs.ecs) && player_position(&gs.ecs) != Some(arrival))
+            .expect("the up stairs have a free tile next to them");
+        gs.take_stairs();
+        let (dx, dy) = step.to_delta();
+        try_move_player(Direction::from_delta(-dx, -dy).unwrap(), &mut gs.ecs);
+        assert_eq!(player_position(&gs.ecs), Some(arrival));
+        gs.take_stairs();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(gs.ecs.fetch::<CurrentLevel>().depth, 1);
+        assert_eq!(player_position(&gs.ecs), Some(start), "the party comes back where it left");
+        let map = gs.ecs.fetch::<map::Map>();
+        assert!(map.tiles == tiles, "the tiles of level 1 were not regenerated");
+        assert_eq!(map.revealed_tiles, revealed);
+    }
+
+    #[test]
+    fn a_step_grabs_the_gold_and_the_pickup_key_the_spear() {
+        let path = std::env::temp_dir().join("rogue_pickup_test.json");
+        let mut gs = new_game(path.to_str().unwrap());
+        let (x, y) = player_position(&gs.ecs).unwrap();
+        gs.ecs.create_entity().with(Position { x, y }).with(Name { name: "Gold".to_string() }).with(Gold { amount: 5 }).with(AutoPickup {}).build();
+        gs.ecs.create_entity().with(Position { x, y }).with(Name { name: "Spear".to_string() }).with(Item {}).with(Reach { range: 2 }).build();
+        let player = gs.ecs.fetch::<PlayerEntity>().0;
+
+        assert_eq!(pick_up_items(&mut gs.ecs, false), 1);
+        assert_eq!(gs.ecs.fetch::<Wallet>().gold, 5);
+        assert!(gs.ecs.read_storage::<Reach>().get(player).is_none(), "the spear waits for the pickup key");
+        assert_eq!(pick_up_items(&mut gs.ecs, true), 1);
+        assert_eq!(gs.ecs.read_storage::<Reach>().get(player).map(|reach| reach.range), Some(2));
+        assert_eq!(pick_up_items(&mut gs.ecs, true), 0, "the player's own Reach is not picked up");
+    }
+}
@@ -1,17 +1,27 @@
-use rltk::{RandomNumberGenerator, RGB, to_cp437, Algorithm2D, BaseMap, Point, Rltk};
+use rltk::{RGB, to_cp437, Algorithm2D, BaseMap, Point};
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use std::cmp::{max, min};
 
+use crate::components::{BreadcrumbView, FrameClock};
+use crate::draw_target::DrawTarget;
+use crate::direction::Direction;
 use crate::rect::Rect;
+use crate::rng::GameRng;
+use crate::trap_system::SearchProgress;
 
 // Struttura che ci serve per la memoria della mappa (per il campo visivo: cosa ho già visto e cosa non ho ancora visto?)
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct Map {
     pub tiles : Vec<Tile>,
     pub rooms : Vec<Rect>,
     pub width : i32,
     pub height : i32,
-    pub revealed_tiles : Vec<bool>
+    pub revealed_tiles : Vec<bool>,
+    pub visible_tiles : Vec<bool>, // caselle nel campo visivo del gruppo in questo momento (le ricalcola VisibilitySystem)
+    pub orthogonal_only : bool, // niente passi in diagonale, né per il giocatore né per i mostri (GameConfig)
+    pub trap_groups : Vec<Vec<usize>>, // trappole collegate: se ne scatta una a catena, scattano tutte (vedi trap_system.rs)
+    pub walked_tiles : Vec<bool>, // caselle da cui il giocatore è già passato: la scia di briciole (vedi BreadcrumbView)
 }
 
 // RLTK traits per il bridge con le mappe costruite alla nostra maniera
@@ -20,11 +30,278 @@ impl Algorithm2D for Map {
         Point::new(self.width, self.height)
     }
 }
+impl Map {
+    /// The index of (x, y) in this map's per-tile layers. No bounds check, as for the free xy_idx.
+    pub fn xy_idx(&self, x: i32, y: i32) -> usize {
+        xy_idx(x, y, self.width)
+    }
+
+    /// A `width` x `height` map made only of `tile`, nothing revealed yet: the blank sheet of the generators.
+    pub fn new(width: i32, height: i32, tile: Tile) -> Map {
+        let size = (width.max(0) * height.max(0)) as usize;
+        Map{
+            tiles : vec![tile; size],
+            rooms : Vec::new(),
+            width,
+            height,
+            revealed_tiles : vec![false; size], // inizializza tutti i valori a false (non visti) quando crea la mappa
+            visible_tiles : vec![false; size],
+            orthogonal_only : false,
+            trap_groups : Vec::new(),
+            walked_tiles : vec![false; size],
+        }
+    }
+
+    /// Checks that every per-tile layer has exactly `width * height` entries, so a malformed map
+    /// is refused on loading instead of panicking on `tiles[idx]` in the middle of a game.
+    /// Err describes the first layer that doesn't fit.
+    pub fn validate(&self) -> Result<(), String> {
+        let expected = (self.width.max(0) * self.height.max(0)) as usize;
+        let layers = [("tiles", self.tiles.len()), ("revealed tiles", self.revealed_tiles.len()), ("visible tiles", self.visible_tiles.len()), ("walked tiles", self.walked_tiles.len())];
+        for (layer, found) in layers {
+            if found != expected {
+                return Err(format!("{}x{} map: expected {} {}, found {}", self.width, self.height, expected, layer, found));
+            }
+        }
+        Ok(())
+    }
+
+    /// Stable hash of the map structure: dimensions plus the gameplay properties of every tile
+    /// (walkability, sight, cover, doors, hazards, traps, portals). Colours, glyphs and volatile state
+    /// like trap spotting are ignored, so two maps with the same layout hash the same.
+    /// Uses FNV-1a rather than std's hasher, whose output may change between Rust versions.
+    pub fn layout_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let mut hash = FNV_OFFSET;
+        let mut feed = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+
+        feed(&self.width.to_le_bytes());
+        feed(&self.height.to_le_bytes());
+        for tile in self.tiles.iter() {
+            let flags = (tile.walkable as u8) | ((tile.transparent as u8) << 1) | ((tile.provides_cover as u8) << 2);
+            let door = match tile.door_state { None => 0, Some(DoorState::Open) => 1, Some(DoorState::Closed) => 2, Some(DoorState::Locked) => 3, Some(DoorState::Broken) => 4 };
+            let status = match tile.status_effect { None => 0, Some(StatusEffect::Burning) => 1, Some(StatusEffect::Entangled) => 2, Some(StatusEffect::Poisoned) => 3 };
+            let trap = match tile.trap_kind { None => 0, Some(TrapKind::Hazard) => 1, Some(TrapKind::Summon) => 2 };
+            let portal = match (tile.portal, tile.stairs) { (Some(kind), _) => kind as u8 + 1, (None, Some(Stairs::Down)) => 0x80, (None, Some(Stairs::Up)) => 0x81, (None, None) => 0 };
+            feed(&[flags, door, status, trap, portal]);
+            feed(&tile.direct_damage.to_le_bytes());
+            feed(&tile.slipperiness.to_le_bytes());
+            feed(&tile.movement_cost.to_le_bytes());
+            feed(&tile.dig_turns.unwrap_or(-1).to_le_bytes());
+        }
+        hash
+    }
+
+    /// True if (x, y) is inside the map and `passable` accepts its tile.
+    fn is_exit_valid(&self, x: i32, y: i32, passable: &dyn Fn(&Tile) -> bool) -> bool {
+        if x < 0 || x >= self.width || y < 0 || y >= self.height {
+            return false;
+        }
+        passable(&self.tiles[self.xy_idx(x, y)])
+    }
+
+    /// Exits of a tile for pathfinding, with the tiles an actor can get through decided by `passable`
+    /// (plain walkers use `walkable`, door-opening monsters also accept closed doors).
+    /// The cost is the destination's movement_cost, diagonals cost a bit more
+    /// (and aren't exits at all on an orthogonal_only map).
+    pub fn exits_where(&self, idx: usize, passable: &dyn Fn(&Tile) -> bool) -> rltk::SmallVec<[(usize, f32); 10]> {
+        let mut exits = rltk::SmallVec::new();
+        let x = idx as i32 % self.width;
+        let y = idx as i32 / self.width;
+
+        for direction in Direction::ALL {
+            let (dx, dy) = direction.to_delta();
+            let (nx, ny) = (x + dx, y + dy);
+            if !self.is_exit_valid(nx, ny, passable) {
+                continue;
+            }
+            let diagonal = direction.is_diagonal();
+            if diagonal && self.orthogonal_only {
+                continue;
+            }
+            // same corner rule as the player's movement: no squeezing between two blocked tiles
+            if diagonal && !self.is_exit_valid(x + dx, y, passable) && !self.is_exit_valid(x, y + dy, passable) {
+                continue;
+            }
+            let cost = self.tiles[self.xy_idx(nx, ny)].movement_cost as f32;
+            exits.push((self.xy_idx(nx, ny), if diagonal { cost * 1.45 } else { cost }));
+        }
+        exits
+    }
+
+    /// The other traps wired to the one at `idx` (empty if it isn't part of a group).
+    pub fn linked_traps(&self, idx: usize) -> Vec<usize> {
+        self.trap_groups.iter()
+            .find(|group| group.contains(&idx))
+            .map(|group| group.iter().copied().filter(|other| *other != idx && self.tiles[*other].trap_kind.is_some()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Opens the closed door at `idx`, making it walkable and see-through.
+    /// Returns false if there's no closed door there (locked doors stay shut).
+    pub fn open_door(&mut self, idx: usize) -> bool {
+        if self.tiles[idx].door_state != Some(DoorState::Closed) {
+            return false;
+        }
+        let tile = &mut self.tiles[idx];
+        tile.door_state = Some(DoorState::Open);
+        tile.glyph = to_cp437('/'); // Open door glyph
+        tile.walkable = true;
+        tile.transparent = true;
+        tile.provides_cover = false;
+        true
+    }
+
+    /// True if the player knows that stepping on the tile hurts or hinders: it burns, poisons
+    /// or entangles, or holds a trap already found. Hidden traps aren't known, so they don't count.
+    pub fn known_hazard(&self, idx: usize) -> bool {
+        let tile = &self.tiles[idx];
+        tile.trap_dc.is_none() && (tile.direct_damage > 0 || tile.status_effect.is_some() || tile.trap_kind.is_some())
+    }
+
+    /// The share (0-100) of the walkable tiles that have been revealed.
+    pub fn explored_percent(&self) -> i32 {
+        let walkable = self.tiles.iter().filter(|tile| tile.walkable).count();
+        if walkable == 0 {
+            return 100;
+        }
+        let revealed = self.tiles.iter().zip(self.revealed_tiles.iter())
+            .filter(|(tile, revealed)| tile.walkable && **revealed)
+            .count();
+        (revealed * 100 / walkable) as i32
+    }
+
+    /// Smashes the closed or locked door at `idx`: what is left of it can't be closed again.
+    /// Returns false if there's no shut door there.
+    pub fn break_door(&mut self, idx: usize) -> bool {
+        if !matches!(self.tiles[idx].door_state, Some(DoorState::Closed | DoorState::Locked)) {
+            return false;
+        }
+        let tile = &mut self.tiles[idx];
+        tile.door_state = Some(DoorState::Broken);
+        tile.glyph = to_cp437('\'');
+        tile.fg = tile.fg.to_greyscale();
+        tile.walkable = true;
+        tile.transparent = true;
+        tile.provides_cover = false;
+        true
+    }
+
+    /// Turns the diggable wall at `idx` into rubble floor. Returns false if it can't be dug.
+    pub fn dig(&mut self, idx: usize) -> bool {
+        if self.tiles[idx].dig_turns.is_none() {
+            return false;
+        }
+        let tile = &mut self.tiles[idx];
+        tile.dig_turns = None;
+        tile.glyph = to_cp437('.');
+        tile.fg = tile.fg.to_greyscale();
+        tile.walkable = true;
+        tile.transparent = true;
+        tile.provides_cover = false;
+        true
+    }
+
+    /// A digging beam from (x, y) travelling `range` tiles in the `delta` direction: every diggable
+    /// wall along the way becomes floor (see `dig`), open ground lets the beam through, and the first
+    /// tile that can't be dug (or the border of the map) stops it. Returns the carved tiles as they were.
+    pub fn dig_beam(&mut self, x: i32, y: i32, delta: (i32, i32), range: i32) -> Vec<(usize, Tile)> {
+        let mut carved = Vec::new();
+        for step in 1..=range {
+            let (tx, ty) = (x + delta.0 * step, y + delta.1 * step);
+            if tx <= 0 || tx >= self.width - 1 || ty <= 0 || ty >= self.height - 1 {
+                break;
+            }
+            let idx = self.xy_idx(tx, ty);
+            let tile = self.tiles[idx];
+            if self.dig(idx) {
+                carved.push((idx, tile));
+            } else if !tile.walkable {
+                break;
+            }
+        }
+        carved
+    }
+
+    /// The way down (portal or stairs) the player has already seen on this level (the nearest to
+    /// the top-left corner if there are more), None while it's still to be found.
+    pub fn known_portal(&self) -> Option<usize> {
+        (0..self.tiles.len()).find(|idx| self.tiles[*idx].leads_down() && self.revealed_tiles[*idx])
+    }
+
+    /// Tints every tile for `depth` (see tint_for_depth), so the same biome looks gloomier deeper down.
+    pub fn tint_for_depth(&mut self, depth: i32) {
+        for tile in self.tiles.iter_mut() {
+            tile.fg = tint_for_depth(tile.fg, depth);
+            tile.bg = tint_for_depth(tile.bg, depth);
+        }
+    }
+
+    /// Makes sure some of the hidden traps can really be spotted: one in SPOTTABLE_TRAP_SHARE
+    /// (at least one, if the level has any) gets its trap_dc lowered to spottable_trap_dc(depth),
+    /// the others keep the DC their biome gave them.
+    pub fn ensure_spottable_traps(&mut self, depth: i32, rng: &mut GameRng) {
+        let mut hidden : Vec<usize> = (0..self.tiles.len())
+            .filter(|idx| self.tiles[*idx].trap_kind.is_some() && self.tiles[*idx].trap_dc.is_some())
+            .collect();
+        let spottable = hidden.len().div_ceil(SPOTTABLE_TRAP_SHARE);
+        let cap = spottable_trap_dc(depth);
+        for _ in 0..spottable {
+            let idx = hidden.swap_remove(rng.range(0, hidden.len() as i32) as usize);
+            self.tiles[idx].trap_dc = self.tiles[idx].trap_dc.map(|dc| dc.min(cap));
+        }
+    }
+
+    /// Unlocks the locked door at `idx`, leaving it closed. Returns false if there's no locked door there.
+    pub fn unlock_door(&mut self, idx: usize) -> bool {
+        if self.tiles[idx].door_state != Some(DoorState::Locked) {
+            return false;
+        }
+        self.tiles[idx].door_state = Some(DoorState::Closed);
+        self.tiles[idx].fg = RGB::named(rltk::CHOCOLATE); // same colour as the plain doors
+        true
+    }
+
+    /// Closes the open door at `idx` again. Returns false if there's no open door there.
+    /// Whether something is standing in the doorway is the caller's business.
+    pub fn close_door(&mut self, idx: usize) -> bool {
+        if self.tiles[idx].door_state != Some(DoorState::Open) {
+            return false;
+        }
+        let tile = &mut self.tiles[idx];
+        tile.door_state = Some(DoorState::Closed);
+        tile.glyph = to_cp437('+'); // Closed door glyph
+        tile.walkable = false;
+        tile.transparent = false;
+        tile.provides_cover = true;
+        true
+    }
+}
+
 // RLTK traits per il bridge con le mappe costruite alla nostra maniera
 impl BaseMap for Map {
     fn is_opaque(&self, idx:usize) -> bool {
         // abbiamo già la proprietà transparent in Tile, quindi non dobbiamo fare altro che restituirla invertita per opaque
-        !self.tiles[idx as usize].transparent
+        !self.tiles[idx].transparent
+    }
+
+    // uscite percorribili da una casella, usate da a_star_search; il costo tiene conto del movement_cost
+    fn get_available_exits(&self, idx: usize) -> rltk::SmallVec<[(usize, f32); 10]> {
+        self.exits_where(idx, &|tile| tile.walkable)
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        let w = self.width as usize;
+        let p1 = Point::new(idx1 % w, idx1 / w);
+        let p2 = Point::new(idx2 % w, idx2 / w);
+        rltk::DistanceAlg::Pythagoras.distance2d(p1, p2)
     }
 }
 
@@ -37,46 +314,116 @@ impl BaseMap for Map {
 /// Represents a single tile on the map.
 /// Instead of a simple enum, this is a struct containing all the information
 /// needed to interact with and display the tile.
-#[derive(Clone, Copy, Debug)]
+/// Serializable: rltk's colours and glyphs go through the wrappers of save_system.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct Tile {
     // --- Visuals (Large Types) ---
+    #[serde(with = "crate::save_system::ser_rgb")]
     pub fg: RGB,                   // 12 bytes
+    #[serde(with = "crate::save_system::ser_rgb")]
     pub bg: RGB,                   // 12 bytes
 
     // --- Tile-specific State (Medium Types) ---
     pub trap_dc: Option<i32>,      // 8 bytes
+    pub dig_turns: Option<i32>,    // 8 bytes (turns of digging to turn the tile into floor, None = can't be dug)
 
     // --- Gameplay Effects (Medium Types) ---
     pub direct_damage: i32,        // 4 bytes
     pub slipperiness: i32,         // 4 bytes
+    pub movement_cost: i32,        // 4 bytes (energy spent to enter the tile, 1 = normal ground)
 
     // --- Visuals (Small Types) ---
+    #[serde(with = "crate::save_system::ser_glyph")]
     pub glyph: rltk::FontCharType, // 2 bytes
 
     // --- State and Properties (Smallest Types) ---
     pub door_state: Option<DoorState>, // 1 byte (with niche optimization)
+    pub trap_kind: Option<TrapKind>,   // 1 byte (with niche optimization)
     pub status_effect: Option<StatusEffect>, // 1 byte (with niche optimization)
+    pub portal: Option<BiomeKind>,     // 1 byte (with niche optimization), the biome the level turns into
     pub walkable: bool,                // 1 byte
     pub transparent: bool,             // 1 byte
     pub provides_cover: bool,          // 1 byte
+    #[serde(default)]
+    pub cleanses: Option<StatusEffect>, // 1 byte, the status that stepping in washes off (see effects.rs)
+    #[serde(default)]
+    pub stairs: Option<Stairs>,         // 1 byte, set on the stairs tiles (see Stairs)
+}
+
+impl Tile {
+    /// True for the tiles that take the party one level down: portals and down stairs.
+    pub fn leads_down(&self) -> bool {
+        self.portal.is_some() || self.stairs == Some(Stairs::Down)
+    }
 }
 
-/// Enum for status effects that a tile can apply.
-/// This can be expanded with more effects like Poisoned, Slowed, etc.
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Enum for status effects that a tile (or a monster's special attack) can apply.
+/// This can be expanded with more effects like Slowed, etc.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum StatusEffect {
     Burning,
     Entangled,
+    Poisoned,
+}
+
+/// Enum for what a trap does when something steps on it.
+/// `Hazard` traps hurt through the tile's own damage/status fields,
+/// `Summon` traps (alarms) call a monster to a nearby tile.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TrapKind {
+    Hazard,
+    Summon,
 }
 
 /// Enum for the state of a door. This allows us to handle doors that can be
 /// opened, closed, or even locked, requiring different interactions.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum DoorState {
     Open,
     Closed,
     Locked,
+    Broken, // sfondata: resta aperta per sempre
+}
+
+/// Marks a tile as stairs. Unlike a portal, the stairs don't know where they lead: going down
+/// takes the party to the next depth, in whatever biome the BiomeProgression has for it,
+/// going up back to the level above, as the party left it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Stairs {
+    Down,
+    Up,
+}
+
+/// The biomes as plain values, for the places that have to store or compare them
+/// (a portal tile remembers where it leads, the current level remembers what it is).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BiomeKind {
+    Building,
+    Forest,
+    Volcano,
+    SnowyMountains,
+}
+
+impl BiomeKind {
+    pub fn biome(&self) -> Box<dyn Biome> {
+        match self {
+            BiomeKind::Building => Box::new(Building),
+            BiomeKind::Forest => Box::new(Forest),
+            BiomeKind::Volcano => Box::new(Volcano),
+            BiomeKind::SnowyMountains => Box::new(SnowyMountains),
+        }
+    }
+
+    /// Where the portals of this biome lead: the biomes take turns, forest -> volcano -> snow -> building -> forest.
+    pub fn portal_target(&self) -> BiomeKind {
+        match self {
+            BiomeKind::Forest => BiomeKind::Volcano,
+            BiomeKind::Volcano => BiomeKind::SnowyMountains,
+            BiomeKind::SnowyMountains => BiomeKind::Building,
+            BiomeKind::Building => BiomeKind::Forest,
+        }
+    }
 }
 
 /// The Biome Trait: A contract for all biomes.
@@ -93,10 +440,35 @@ pub trait Biome {
     // These use `Option` to indicate that a biome might not have this tile type.
     // The map generator can then decide how to handle its absence.
     fn get_water(&self) -> Option<Tile> { None }
+    /// Water you can wade through, found along the banks of the deep water: it puts out the flames.
+    fn get_shallow_water(&self) -> Option<Tile> { None }
     fn get_trap(&self) -> Option<Tile> { None }
+    fn get_alarm_trap(&self) -> Option<Tile> { None }
     fn get_stairs(&self) -> Option<Tile> { None }
     fn get_door(&self) -> Option<Tile> { None }
     fn get_locked_door(&self) -> Option<Tile> { None }
+    /// A wall you can see through (glass, force fields): blocks movement but not sight.
+    fn get_glass(&self) -> Option<Tile> { None }
+    /// The tile the player lands on when the level starts: plain floor unless the biome
+    /// wants something safer (or just more recognisable).
+    fn get_start_tile(&self) -> Tile { self.get_floor() }
+    /// The stairs back up, put on the start of every level below the first.
+    fn get_up_stairs(&self) -> Tile {
+        Tile { glyph: to_cp437('<'), fg: RGB::named(rltk::YELLOW), stairs: Some(Stairs::Up), ..self.get_start_tile() }
+    }
+
+    // --- Generation Options ---
+    /// If true, walls are drawn with box-drawing lines (─ │ ┌ ...) instead of the wall glyph.
+    fn connected_walls(&self) -> bool { false }
+    /// What the biome dresses its levels with once they are generated (none by default).
+    fn decorations(&self) -> Decorations { Decorations::default() }
+}
+
+/// The set dressing of a biome's levels, placed with the rest of the level's entities.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Decorations {
+    /// Chance in 100 that a room gets a lit brazier in one of its corners.
+    pub brazier_chance : i32,
 }
 
 
@@ -113,7 +485,7 @@ impl Biome for Building {
             glyph: to_cp437('.'), // Ensuring this is a period for less noise
             fg: RGB::named(rltk::DARK_GRAY),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
         }
     }
 
@@ -123,10 +495,21 @@ impl Biome for Building {
             glyph: to_cp437('#'),
             fg: RGB::named(rltk::WHITE),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: Some(4), portal: None, cleanses: None, stairs: None,
         }
     }
 
+    fn get_stairs(&self) -> Option<Tile> {
+        Some(Tile {
+            walkable: true, transparent: true, provides_cover: false,
+            glyph: to_cp437('>'),
+            fg: RGB::named(rltk::YELLOW),
+            bg: RGB::named(rltk::BLACK),
+            // stepping on them takes the party one level down, into the biome of that depth
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: Some(Stairs::Down),
+        })
+    }
+
     fn get_door(&self) -> Option<Tile> {
         Some(Tile {
             walkable: false, transparent: false, // A closed door blocks movement and sight.
@@ -134,8 +517,8 @@ impl Biome for Building {
             glyph: to_cp437('+'), // Closed door glyph
             fg: RGB::named(rltk::CHOCOLATE),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0,
-            door_state: Some(DoorState::Closed), trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1,
+            door_state: Some(DoorState::Closed), trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
         })
     }
 
@@ -145,10 +528,36 @@ impl Biome for Building {
             glyph: to_cp437('+'), // Same glyph, but maybe a different color later
             fg: RGB::named(rltk::RED), // Locked doors are red
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0,
-            door_state: Some(DoorState::Locked), trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1,
+            door_state: Some(DoorState::Locked), trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
+        })
+    }
+
+    fn get_glass(&self) -> Option<Tile> { // A window pane
+        Some(Tile {
+            walkable: false, transparent: true, provides_cover: true,
+            glyph: to_cp437('▒'),
+            fg: RGB::named(rltk::LIGHT_CYAN),
+            bg: RGB::named(rltk::BLACK),
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1,
+            door_state: None, trap_dc: None, trap_kind: None, dig_turns: Some(1), portal: None, cleanses: None, stairs: None,
         })
     }
+
+    fn get_alarm_trap(&self) -> Option<Tile> { // A pressure plate wired to an alarm
+        Some(Tile {
+            walkable: true, transparent: true, provides_cover: false,
+            glyph: to_cp437('^'),
+            fg: RGB::named(rltk::YELLOW),
+            bg: RGB::named(rltk::BLACK),
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1,
+            door_state: None, trap_dc: Some(12), trap_kind: Some(TrapKind::Summon), dig_turns: None, portal: None, cleanses: None, stairs: None, // DC 12 to spot this trap
+        })
+    }
+
+    fn connected_walls(&self) -> bool { true }
+
+    fn decorations(&self) -> Decorations { Decorations { brazier_chance: 25 } }
 }
 
 // 2. The Forest Biome
@@ -161,7 +570,7 @@ impl Biome for Forest {
             glyph: to_cp437('.'), // Changed from a quote to a period for less noise
             fg: RGB::named(rltk::GREEN),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
         }
     }
 
@@ -171,7 +580,7 @@ impl Biome for Forest {
             glyph: to_cp437('♣'),
             fg: RGB::named(rltk::BROWN1),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: Some(2), portal: None, cleanses: None, stairs: None,
             // Later, we could add a component to trees to make them climbable.
         }
     }
@@ -183,7 +592,18 @@ impl Biome for Forest {
             fg: RGB::named(rltk::BLUE),
             bg: RGB::named(rltk::DARK_BLUE),
             direct_damage: 5, // Drowning damage
-            status_effect: None, slipperiness: 0, door_state: None, trap_dc: None,
+            status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
+        })
+    }
+
+    fn get_shallow_water(&self) -> Option<Tile> { // Ford
+        Some(Tile {
+            walkable: true, transparent: true, provides_cover: false,
+            glyph: to_cp437('≈'),
+            fg: RGB::named(rltk::LIGHT_BLUE),
+            bg: RGB::named(rltk::BLACK),
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 2, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None,
+            cleanses: Some(StatusEffect::Burning), stairs: None,
         })
     }
 
@@ -194,7 +614,7 @@ impl Biome for Forest {
             fg: RGB::named(rltk::DARK_GREEN),
             bg: RGB::named(rltk::BLACK),
             direct_damage: 1, // Spike damage
-            status_effect: Some(StatusEffect::Entangled), slipperiness: 0, door_state: None, trap_dc: Some(15), // DC 15 to spot this trap
+            status_effect: Some(StatusEffect::Entangled), slipperiness: 0, movement_cost: 2, door_state: None, trap_dc: Some(15), trap_kind: Some(TrapKind::Hazard), dig_turns: None, portal: None, cleanses: None, stairs: None, // DC 15 to spot this trap
         })
     }
 }
@@ -209,7 +629,7 @@ impl Biome for Volcano {
             glyph: to_cp437('▒'),
             fg: RGB::named(rltk::DARK_GRAY),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
         }
     }
 
@@ -219,7 +639,7 @@ impl Biome for Volcano {
             glyph: to_cp437('▲'),
             fg: RGB::named(rltk::PURPLE),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 0, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
         }
     }
 
@@ -230,9 +650,21 @@ impl Biome for Volcano {
             fg: RGB::named(rltk::ORANGE),
             bg: RGB::named(rltk::RED),
             direct_damage: 10,
-            status_effect: Some(StatusEffect::Burning), slipperiness: 0, door_state: None, trap_dc: None,
+            status_effect: Some(StatusEffect::Burning), slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
         })
     }
+
+    fn get_start_tile(&self) -> Tile { // Cooled basalt platform, whatever is flowing around it
+        Tile {
+            walkable: true, transparent: true, provides_cover: false,
+            glyph: to_cp437('■'),
+            fg: RGB::named(rltk::GRAY),
+            bg: RGB::named(rltk::BLACK),
+            direct_damage: 0, status_effect: None, slipperiness: 0, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
+        }
+    }
+
+    fn decorations(&self) -> Decorations { Decorations { brazier_chance: 40 } }
 }
 
 // 4. The Snowy Mountains Biome
@@ -245,7 +677,7 @@ impl Biome for SnowyMountains {
             glyph: to_cp437(' '),
             fg: RGB::named(rltk::WHITE),
             bg: RGB::named(rltk::LIGHT_GRAY),
-            direct_damage: 0, status_effect: None, slipperiness: 2, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 2, movement_cost: 2, door_state: None, trap_dc: None, trap_kind: None, dig_turns: None, portal: None, cleanses: None, stairs: None,
         }
     }
 
@@ -255,53 +687,120 @@ impl Biome for SnowyMountains {
             glyph: to_cp437('▲'),
             fg: RGB::named(rltk::LIGHT_CYAN),
             bg: RGB::named(rltk::BLACK),
-            direct_damage: 0, status_effect: None, slipperiness: 1, door_state: None, trap_dc: None,
+            direct_damage: 0, status_effect: None, slipperiness: 1, movement_cost: 1, door_state: None, trap_dc: None, trap_kind: None, dig_turns: Some(3), portal: None, cleanses: None, stairs: None,
         }
     }
 }
 
 
+/// How much of the murky tone a level takes on for each level below the first, and at most.
+const DEPTH_TINT_STEP : f32 = 0.06;
+const MAX_DEPTH_TINT : f32 = 0.5;
+
+/// `base` as it looks at `depth`: unchanged on the first level, then a little darker and
+/// dingier (blended toward a murky brown) on every level below, up to MAX_DEPTH_TINT.
+pub fn tint_for_depth(base: RGB, depth: i32) -> RGB {
+    let amount = ((depth - 1).max(0) as f32 * DEPTH_TINT_STEP).min(MAX_DEPTH_TINT);
+    base.lerp(RGB::from_f32(0.2, 0.16, 0.1), amount)
+}
+
+/// One hidden trap in this many is guaranteed to be spottable (see Map::ensure_spottable_traps).
+const SPOTTABLE_TRAP_SHARE : usize = 3;
+
+/// The highest spotting DC of the guaranteed traps at `depth`: an unskilled d20 finds them
+/// more often than not near the surface, a little less often deeper down.
+pub fn spottable_trap_dc(depth: i32) -> i32 {
+    (8 + depth / 2).min(12)
+}
+
+/// Short flavour text for stepping on a tile, derived from the properties the biome gave it
+/// and from the biome of the current level (see CurrentLevel).
+/// Plain ground returns None, so only notable terrain shows up in the log.
+pub fn footstep_message(tile: &Tile, biome: BiomeKind) -> Option<&'static str> {
+    if tile.door_state == Some(DoorState::Open) {
+        Some("The door creaks as you pass through.")
+    } else if tile.door_state == Some(DoorState::Broken) {
+        Some("Splinters crunch under your feet.")
+    } else if tile.status_effect == Some(StatusEffect::Entangled) {
+        Some("Leaves rustle around your ankles.")
+    } else if tile.slipperiness > 0 && biome == BiomeKind::SnowyMountains {
+        Some("The snow crunches under your feet.")
+    } else if tile.slipperiness > 0 {
+        Some("Your feet slide on the slick ground.")
+    } else if tile.movement_cost > 1 {
+        Some("You trudge through the rough ground.")
+    } else {
+        None
+    }
+}
+
 // --- Map Generation ---
 
-/// Calculates the array index from a 2D coordinate.
-pub fn xy_idx(x: i32, y: i32) -> usize {
-    (y as usize * 80) + x as usize
+/// Size of the generated levels: as large as the default console (see GameConfig), but nothing
+/// in the map code depends on it, every loop and bound reads `map.width` and `map.height`.
+pub const MAP_WIDTH : i32 = 80;
+pub const MAP_HEIGHT : i32 = 50;
+
+/// Calculates the array index from a 2D coordinate
+/// in a grid `width` tiles wide (Map::xy_idx passes the map's own).
+/// No bounds check: for coordinates that may fall off the map use try_xy_idx.
+pub fn xy_idx(x: i32, y: i32, width: i32) -> usize {
+    (y as usize * width as usize) + x as usize
+}
+
+/// The index of (x, y), or None if it falls outside `map`. For coordinates computed on the fly
+/// (one step further, a neighbour, a reach) that nobody has checked yet.
+pub fn try_xy_idx(x: i32, y: i32, map: &Map) -> Option<usize> {
+    if x < 0 || x >= map.width || y < 0 || y >= map.height {
+        return None;
+    }
+    Some(map.xy_idx(x, y)).filter(|idx| *idx < map.tiles.len())
 }
 
 /// Creates a map for a given biome.
 /// This function is now generic and works with any `&dyn Biome`.
 /// It doesn't know what a "Forest" or "Volcano" is; it just asks the biome
 /// for the appropriate tiles.
+/// With `clearings` > 0 it also opens that many empty clearings among the scattered walls and
+/// returns them as rooms (the first one around the player's start), so outdoor maps can host
+/// room-based spawns and prefabs. With 0 the rooms are empty, as before.
 // MAP BUILDER - Wilderness
 #[allow(dead_code)]
-pub fn wilderness_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
-    let mut map = Map{
-        tiles : vec![biome.get_floor(); 80*50],
-        rooms : Vec::new(),
-        width : 80,
-        height: 50,
-        revealed_tiles : vec![false; 80*50] // inizializza tutti i valori a false (non visti) quando crea la mappa
-    };
+pub fn wilderness_map(biome: &dyn Biome, rng: &mut GameRng, clearings: i32) -> (Map, Vec<Rect>) {
+    wilderness_map_sized(biome, rng, clearings, MAP_WIDTH, MAP_HEIGHT)
+}
+
+/// `wilderness_map` from a stream of its own started at `seed`: the same seed always gives the same map,
+/// so a layout can be reproduced, tested or shared.
+#[allow(dead_code)]
+pub fn wilderness_map_seeded(biome: &dyn Biome, seed: u64, clearings: i32) -> (Map, Vec<Rect>) {
+    wilderness_map(biome, &mut GameRng::seeded(seed), clearings)
+}
+
+/// `wilderness_map` on a `width` x `height` map instead of the standard size.
+#[allow(dead_code)]
+pub fn wilderness_map_sized(biome: &dyn Biome, rng: &mut GameRng, clearings: i32, width: i32, height: i32) -> (Map, Vec<Rect>) {
+    let mut map = Map::new(width, height, biome.get_floor());
+    let (start_x, start_y) = (map.width / 2, map.height / 2);
     
     let wall_tile = biome.get_wall();
 
     // Make the boundaries walls
-    for x in 0..80 {
-        map.tiles[xy_idx(x, 0)] = wall_tile;
-        map.tiles[xy_idx(x, 49)] = wall_tile;
+    for x in 0..map.width {
+        let (top, bottom) = (map.xy_idx(x, 0), map.xy_idx(x, map.height - 1));
+        map.tiles[top] = wall_tile;
+        map.tiles[bottom] = wall_tile;
     }
-    for y in 0..50 {
-        map.tiles[xy_idx(0, y)] = wall_tile;
-        map.tiles[xy_idx(79, y)] = wall_tile;
+    for y in 0..map.height {
+        let (left, right) = (map.xy_idx(0, y), map.xy_idx(map.width - 1, y));
+        map.tiles[left] = wall_tile;
+        map.tiles[right] = wall_tile;
     }
 
-    // Randomly place some walls
-    let mut rng = RandomNumberGenerator::new();
+    // Randomly place some walls (and everything scattered below), never on the border
     for _i in 0..400 {
-        let x = rng.roll_dice(1, 79);
-        let y = rng.roll_dice(1, 49);
-        let idx = xy_idx(x, y);
-        if idx != xy_idx(40, 25) { // Don't block the player's starting position
+        let idx = random_inner_tile(&map, rng);
+        if idx != map.xy_idx(start_x, start_y) { // Don't block the player's starting position
             // 20% chance of placing a door, if the biome supports it.
             let roll = rng.roll_dice(1, 100);
             if roll > 80 {
@@ -309,17 +808,17 @@ pub fn wilderness_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
                     if let Some(locked_door) = biome.get_locked_door() {
                         map.tiles[idx] = locked_door;
                     } else {
-                        map.tiles[idx] = wall_tile.clone();
+                        map.tiles[idx] = wall_tile;
                     }
                 } else {
                     if let Some(door) = biome.get_door() {
                         map.tiles[idx] = door;
                     } else {
-                        map.tiles[idx] = wall_tile.clone();
+                        map.tiles[idx] = wall_tile;
                     }
                 }
             } else {
-                map.tiles[idx] = wall_tile.clone();
+                map.tiles[idx] = wall_tile;
             }
         }
     }
@@ -327,47 +826,192 @@ pub fn wilderness_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
     // Optionally, place some biome-specific features like water or traps
     if let Some(water_tile) = biome.get_water() {
         for _i in 0..20 {
-            let x = rng.roll_dice(1, 79);
-            let y = rng.roll_dice(1, 49);
-            let idx = xy_idx(x, y);
+            let idx = random_inner_tile(&map, rng);
             map.tiles[idx] = water_tile;
         }
+        // the banks: open ground next to the water gets shallow (no draw from the rng, so the rest of the map stays the same)
+        if let Some(shallow_tile) = biome.get_shallow_water() {
+            let floor_glyph = biome.get_floor().glyph;
+            let banks : Vec<usize> = (1..map.height - 1).flat_map(|y| (1..map.width - 1).map(move |x| (x, y)))
+                .filter(|(x, y)| map.tiles[map.xy_idx(*x, *y)].glyph == floor_glyph)
+                .filter(|(x, y)| [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+                    .any(|(dx, dy)| map.tiles[map.xy_idx(x + dx, y + dy)].glyph == water_tile.glyph && !map.tiles[map.xy_idx(x + dx, y + dy)].walkable))
+                .map(|(x, y)| map.xy_idx(x, y))
+                .collect();
+            for idx in banks {
+                map.tiles[idx] = shallow_tile;
+            }
+        }
     }
 
     if let Some(trap_tile) = biome.get_trap() {
         for _i in 0..10 {
-            let x = rng.roll_dice(1, 79);
-            let y = rng.roll_dice(1, 49);
-            let idx = xy_idx(x, y);
+            let idx = random_inner_tile(&map, rng);
             map.tiles[idx] = trap_tile;
         }
     }
 
-    (map, Vec::new()) // Return map and an empty list of rooms
+    if let Some(alarm_tile) = biome.get_alarm_trap() {
+        for _i in 0..3 {
+            let idx = random_inner_tile(&map, rng);
+            map.tiles[idx] = alarm_tile;
+        }
+    }
+
+    if let Some(glass_tile) = biome.get_glass() {
+        for _i in 0..15 {
+            let idx = random_inner_tile(&map, rng);
+            map.tiles[idx] = glass_tile;
+        }
+    }
+
+    let rooms = carve_clearings(&mut map, clearings, biome, rng);
+
+    // The scatter above can box the player in: carve a safe landing zone around the start.
+    carve_safe_zone(&mut map, start_x, start_y, biome);
+
+    if biome.connected_walls() {
+        apply_wall_glyphs(&mut map);
+    }
+
+    (map, rooms)
+}
+
+/// A random tile off the border (x in 1..width-1, y in 1..height-1): what the wilderness scatters
+/// (walls, doors, water, traps and alarms, glass) must never replace the wall around the map.
+fn random_inner_tile(map: &Map, rng: &mut GameRng) -> usize {
+    let x = rng.roll_dice(1, map.width - 2);
+    let y = rng.roll_dice(1, map.height - 2);
+    map.xy_idx(x, y)
+}
+
+/// Opens up to `count` non-overlapping clearings of plain floor, the first one centred on the
+/// player's start (the centre of the map). They keep clear of the map edges, where the border goes.
+fn carve_clearings(map: &mut Map, count: i32, biome: &dyn Biome, rng: &mut GameRng) -> Vec<Rect> {
+    const MIN_SIZE: i32 = 5;
+    const MAX_SIZE: i32 = 9;
+    const MARGIN: i32 = 4;
+
+    if count <= 0 {
+        return Vec::new();
+    }
+    // a clearing never reaches the border walls, even on a map too small for it
+    let (width, height) = (map.width, map.height);
+    let inside = |rect: Rect| Rect { x1: rect.x1.max(1), y1: rect.y1.max(1), x2: rect.x2.min(width - 2), y2: rect.y2.min(height - 2) };
+    let mut clearings = vec![inside(Rect::new(map.width / 2 - 3, map.height / 2 - 3, 6, 6))];
+    let mut tries = 0;
+    while (clearings.len() as i32) < count && tries < 100 {
+        tries += 1;
+        let w = rng.range(MIN_SIZE, MAX_SIZE + 1);
+        let h = rng.range(MIN_SIZE, MAX_SIZE + 1);
+        let x = rng.range(MARGIN, map.width - MARGIN - w);
+        let y = rng.range(MARGIN, map.height - MARGIN - h);
+        let clearing = inside(Rect::new(x, y, w, h));
+        if clearings.iter().all(|other| !clearing.intersect(other)) {
+            clearings.push(clearing);
+        }
+    }
+    for clearing in clearings.iter() {
+        for y in clearing.y1..=clearing.y2 {
+            for x in clearing.x1..=clearing.x2 {
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = biome.get_floor();
+            }
+        }
+    }
+    clearings
+}
+
+/// Radius of the guaranteed walkable area around the player start (1 = a 3x3 square).
+const SAFE_ZONE_RADIUS: i32 = 1;
+
+/// Turns the square around `(cx, cy)` into plain floor and makes sure it has at least one exit.
+/// If the ring just outside the square is fully blocked, a corridor is tunnelled east
+/// until it meets a walkable tile (or the map border).
+fn carve_safe_zone(map: &mut Map, cx: i32, cy: i32, biome: &dyn Biome) {
+    let floor = biome.get_floor();
+    for y in cy - SAFE_ZONE_RADIUS..=cy + SAFE_ZONE_RADIUS {
+        for x in cx - SAFE_ZONE_RADIUS..=cx + SAFE_ZONE_RADIUS {
+            if x > 0 && x < map.width - 1 && y > 0 && y < map.height - 1 {
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = floor;
+            }
+        }
+    }
+
+    // A closed (not locked) door counts as an exit, the player can open it.
+    let is_exit = |tile: &Tile| tile.walkable || tile.door_state == Some(DoorState::Closed);
+    let ring = SAFE_ZONE_RADIUS + 1;
+    let mut has_exit = false;
+    for y in cy - ring..=cy + ring {
+        for x in cx - ring..=cx + ring {
+            let on_ring = (x - cx).abs() == ring || (y - cy).abs() == ring;
+            if on_ring && x > 0 && x < map.width - 1 && y > 0 && y < map.height - 1
+                && is_exit(&map.tiles[map.xy_idx(x, y)]) {
+                has_exit = true;
+            }
+        }
+    }
+
+    if !has_exit {
+        let mut x = cx + ring;
+        while x < map.width - 1 && !map.tiles[map.xy_idx(x, cy)].walkable {
+            let idx = map.xy_idx(x, cy);
+            map.tiles[idx] = floor;
+            x += 1;
+        }
+    }
+}
+
+/// How long each frame of the water/lava shimmer lasts, in milliseconds.
+const FLUID_FRAME_MS : f32 = 400.0;
+/// The glyphs a fluid tile cycles through (~ and ≈).
+const FLUID_GLYPHS : [u16; 3] = [126, 247, 126];
+
+/// Background of the tiles the player has already walked over, while the trail is shown.
+const BREADCRUMB_BG : (u8, u8, u8) = (45, 40, 20);
+
+/// Water and lava (the impassable ~ tiles) shimmer: glyph and brightness change over time,
+/// shifted by `idx` so neighbouring tiles don't all move together. Only the drawing changes,
+/// the tile itself stays the same. Anything else is drawn as it is.
+fn shimmer(idx: usize, tile: &Tile, fg: RGB, elapsed_ms: f32) -> (rltk::FontCharType, RGB) {
+    if tile.glyph != to_cp437('~') || tile.walkable {
+        return (tile.glyph, fg);
+    }
+    let phase = (idx + (elapsed_ms / FLUID_FRAME_MS) as usize) % FLUID_GLYPHS.len();
+    let brightness = [1.0, 0.85, 0.7][phase];
+    (FLUID_GLYPHS[phase], RGB::from_f32(fg.r * brightness, fg.g * brightness, fg.b * brightness))
 }
 
 // procedura di rendering della mappa
 /// This function is now much simpler. It doesn't need to know anything about
 /// different tile types. It just iterates through the map and uses the properties
 /// (glyph, color) stored in each Tile struct.
-pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
+pub fn draw_map(ecs: &World, ctx: &mut dyn DrawTarget) {
     let map = ecs.fetch::<Map>();
-    let mut rng = rltk::RandomNumberGenerator::new();
+    // la mappa si disegna solo nella sua regione dello schermo, senza coprire HUD e log,
+    // e di lei si vede la parte inquadrata dalla camera
+    let layout = ecs.fetch::<crate::gui::UiLayout>();
+    // le trappole nascoste intraviste in questo turno (il tiro si fa a fine turno, vedi trap_system::glimpse_traps)
+    let glimpsed = ecs.try_fetch::<SearchProgress>();
+    let elapsed_ms = ecs.fetch::<FrameClock>().elapsed_ms;
+    let breadcrumbs = ecs.try_fetch::<BreadcrumbView>().is_some_and(|view| view.shown);
 
     let mut y = 0;
     let mut x = 0;
     for (idx, tile) in map.tiles.iter().enumerate() {
-        // We only draw tiles that have been revealed
-        if map.revealed_tiles[idx] {
-            let glyph;
-            let fg;
+        // We only draw tiles that have been revealed, and that the camera shows (see UiLayout::to_screen)
+        let on_screen = layout.to_screen(x, y);
+        if let Some((screen_x, screen_y)) = on_screen.filter(|_| map.revealed_tiles[idx]) {
+            let mut glyph;
+            let mut fg;
 
             // Check if the tile is currently visible
-            if map.revealed_tiles[idx] {
+            if map.visible_tiles.get(idx).copied().unwrap_or(false) {
                 fg = tile.fg;
                 // Check for hidden traps only if visible (THIS IS FOOD FOR THE VISIBILITY SYSTEM)
-                if let Some(dc) = tile.trap_dc {
-                    if rng.roll_dice(1, 20) < dc {
+                if tile.trap_dc.is_some() {
+                    if !glimpsed.as_ref().is_some_and(|search| search.glimpsed.contains(&idx)) {
                         glyph = rltk::to_cp437('.'); // Failed to spot
                     } else {
                         glyph = tile.glyph; // Spotted
@@ -376,17 +1020,25 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
                     glyph = tile.glyph; // No trap
                 }
             } else {
-                // If not visible but revealed, draw in grayscale
+                // If not visible but revealed, draw in grayscale (an unspotted trap stays hidden out of sight)
                 fg = tile.fg.to_greyscale();
-                glyph = tile.glyph;
+                glyph = if tile.trap_dc.is_some() { rltk::to_cp437('.') } else { tile.glyph };
             }
-            ctx.set(x, y, fg, tile.bg, glyph);
+            if glyph == tile.glyph {
+                (glyph, fg) = shimmer(idx, tile, fg, elapsed_ms);
+            }
+            let walked = breadcrumbs && map.walked_tiles.get(idx).copied().unwrap_or(false);
+            let bg = if walked { RGB::from_u8(BREADCRUMB_BG.0, BREADCRUMB_BG.1, BREADCRUMB_BG.2) } else { tile.bg };
+            ctx.set(screen_x, screen_y, fg, bg, glyph);
+        } else if let Some((screen_x, screen_y)) = on_screen.filter(|_| layout.show_unexplored) {
+            // inside the level but never seen: a barely visible dot, so the map has an edge
+            ctx.set(screen_x, screen_y, RGB::from_u8(30, 30, 30), RGB::named(rltk::BLACK), to_cp437('·'));
         }
 
         // Move the coordinates
         //  The "move coordinates" block is responsible for converting the 1D index of the tile in the map.tiles vector into the correct 2D 
         // (x, y)
-        // position on the screen for rendering.
+        // position on the map (to_screen then finds the cell on the screen).
         x += 1;
         if x >= map.width {
             x = 0;
@@ -397,39 +1049,61 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
 
 // MAP BUILDER - Dungeon
 #[allow(dead_code)]
-fn apply_room_to_map(room: &Rect, map: &mut [Tile], biome: &dyn Biome) {
+fn apply_room_to_map(room: &Rect, map: &mut Map, biome: &dyn Biome) {
     let floor = biome.get_floor();
     for y in room.y1 + 1..=room.y2 {
         for x in room.x1 + 1..=room.x2 {
-            let idx = xy_idx(x, y);
-            map[idx] = floor.clone();
+            let idx = map.xy_idx(x, y);
+            map.tiles[idx] = floor;
         }
     }
 }
 
+/// The lanes of a corridor `width` tiles wide, as offsets from its centre line (0 is the centre).
+fn corridor_lanes(width: i32) -> std::ops::RangeInclusive<i32> {
+    let width = width.max(1);
+    -(width - 1) / 2..=width / 2
+}
+
+/// True if the extra lane of a wide corridor may be carved at (x, y): inside the outer wall
+/// of the map and clear of the rooms, whose walls stay whole (only the doors open them).
+fn can_widen(map: &Map, x: i32, y: i32, rooms: &[Rect]) -> bool {
+    x > 0 && x < map.width - 1 && y > 0 && y < map.height - 1
+        && !rooms.iter().any(|room| x >= room.x1 && x <= room.x2 + 1 && y >= room.y1 && y <= room.y2 + 1)
+}
+
 #[allow(dead_code)]
-fn apply_horizontal_tunnel(map: &mut [Tile], x1: i32, x2: i32, y: i32, biome: &dyn Biome) {
+fn apply_horizontal_tunnel(map: &mut Map, x1: i32, x2: i32, y: i32, width: i32, rooms: &[Rect], biome: &dyn Biome) {
     let floor = biome.get_floor();
     for x in min(x1, x2)..=max(x1, x2) {
-        let idx = xy_idx(x, y);
-        if idx > 0 && idx < (80 * 50) {
-            map[idx] = floor.clone();
+        for lane in corridor_lanes(width) {
+            if lane != 0 && !can_widen(map, x, y + lane, rooms) {
+                continue;
+            }
+            let idx = map.xy_idx(x, y + lane);
+            if idx > 0 && idx < map.tiles.len() {
+                map.tiles[idx] = floor;
+            }
         }
     }
 }
 
 #[allow(dead_code)]
-fn apply_vertical_tunnel(map: &mut [Tile], y1: i32, y2: i32, x: i32, biome: &dyn Biome) {
+fn apply_vertical_tunnel(map: &mut Map, y1: i32, y2: i32, x: i32, width: i32, rooms: &[Rect], biome: &dyn Biome) {
     let floor = biome.get_floor();
     for y in min(y1, y2)..=max(y1, y2) {
-        let idx = xy_idx(x, y);
-        if idx > 0 && idx < (80*50) {
-            map[idx] = floor.clone();
+        for lane in corridor_lanes(width) {
+            if lane != 0 && !can_widen(map, x + lane, y, rooms) {
+                continue;
+            }
+            let idx = map.xy_idx(x + lane, y);
+            if idx > 0 && idx < map.tiles.len() {
+                map.tiles[idx] = floor;
+            }
         }
     }
 }
 
-#[allow(dead_code)]
 /// Calculates the coordinate for a tunnel to start or end, just outside a room's door.
 /// This ensures that tunnels connect to the tile adjacent to the door,
 /// rather than starting on the door tile itself, which would overwrite it.
@@ -472,6 +1146,7 @@ fn find_door_candidate(center_x: i32, center_y: i32, room: &Rect) -> Option<(i32
     }
 
     // Find the candidate closest to the room center
+    // (sort_by is stable: on a tie the push order above decides, so the choice never changes between runs)
     candidates.sort_by(|a, b| {
         let dist_a = (a.0 - center_x).pow(2) + (a.1 - center_y).pow(2);
         let dist_b = (b.0 - center_x).pow(2) + (b.1 - center_y).pow(2);
@@ -489,31 +1164,37 @@ fn find_door_candidate(center_x: i32, center_y: i32, room: &Rect) -> Option<(i32
 /// 4. For each pair of consecutive rooms:
 ///    a. Find the best candidate point on the edge of each room for a door.
 ///    b. Calculate the "exit point" for the tunnel, which is the tile just outside the door.
-///    c. Carve L-shaped tunnels between the two exit points.
-/// 5. Place door tiles at all the candidate points stored in step 4a.
+///    c. Carve L-shaped tunnels between the two exit points, `corridor_width` tiles wide (the extra lanes never cut into the walls of a room).
+///    d. Open the candidate points, so each corridor really reaches its room.
+///
+/// Doors, stairs and wall glyphs are not placed here: they are post-processing steps
+/// (see `map_builders::MapModifier`) chained after this base layout.
 #[allow(dead_code)]
-pub fn dungeon_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
-    let wall = biome.get_wall();
-    let mut map = Map{
-        tiles : vec![wall; 80*50],
-        rooms : Vec::new(),
-        width : 80,
-        height: 50,
-        revealed_tiles : vec![false; 80*50] // inizializza tutti i valori a false (non visti) quando crea la mappa
-    };
+pub fn dungeon_map(biome: &dyn Biome, rng: &mut GameRng, corridor_width: i32) -> (Map, Vec<Rect>) {
+    dungeon_map_sized(biome, rng, corridor_width, MAP_WIDTH, MAP_HEIGHT)
+}
+
+/// `dungeon_map` from a stream of its own started at `seed` (see `wilderness_map_seeded`).
+#[allow(dead_code)]
+pub fn dungeon_map_seeded(biome: &dyn Biome, seed: u64, corridor_width: i32) -> (Map, Vec<Rect>) {
+    dungeon_map(biome, &mut GameRng::seeded(seed), corridor_width)
+}
+
+/// `dungeon_map` on a `width` x `height` map instead of the standard size.
+#[allow(dead_code)]
+pub fn dungeon_map_sized(biome: &dyn Biome, rng: &mut GameRng, corridor_width: i32, width: i32, height: i32) -> (Map, Vec<Rect>) {
+    let mut map = Map::new(width, height, biome.get_wall());
 
     let mut rooms: Vec<Rect> = Vec::new();
     const MAX_ROOMS: i32 = 30;
     const MIN_SIZE: i32 = 6;
     const MAX_SIZE: i32 = 10;
 
-    let mut rng = RandomNumberGenerator::new();
-
     for _ in 0..MAX_ROOMS {
         let w = rng.range(MIN_SIZE, MAX_SIZE);
         let h = rng.range(MIN_SIZE, MAX_SIZE);
-        let x = rng.roll_dice(1, 80 - w - 1) - 1;
-        let y = rng.roll_dice(1, 50 - h - 1) - 1;
+        let x = rng.roll_dice(1, map.width - w - 1) - 1;
+        let y = rng.roll_dice(1, map.height - h - 1) - 1;
         let new_room = Rect::new(x, y, w, h);
 
         // To prevent rooms from spilling over the edge of the map or overlapping, we perform checks.
@@ -523,7 +1204,7 @@ pub fn dungeon_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
             if new_room.intersect(other_room) { ok = false }
         }
         // Check if the room is within the map boundaries.
-        if new_room.x1 < 1 || new_room.x2 > 78 || new_room.y1 < 1 || new_room.y2 > 48 { 
+        if new_room.x1 < 1 || new_room.x2 > map.width - 2 || new_room.y1 < 1 || new_room.y2 > map.height - 2 { 
             ok = false;
         }
 
@@ -533,10 +1214,10 @@ pub fn dungeon_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
     }
 
     for room in rooms.iter() {
-        apply_room_to_map(room, &mut map.tiles, biome);
+        apply_room_to_map(room, &mut map, biome);
     }
 
-    let mut doors = Vec::new();
+    let floor = biome.get_floor();
     // Iterate through the rooms to create corridors connecting them.
     for i in 1..rooms.len() {
         // Get the center points of the current and previous rooms.
@@ -548,9 +1229,10 @@ pub fn dungeon_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
         let p2_door_candidate = find_door_candidate(new_x, new_y, &rooms[i]);
 
         if let (Some(p1), Some(p2)) = (p1_door_candidate, p2_door_candidate) {
-            // Store the door locations for later placement.
-            doors.push(p1);
-            doors.push(p2);
+            // Open the room walls where the corridor arrives; PlaceDoors turns these gaps into doors.
+            let (door1, door2) = (map.xy_idx(p1.0, p1.1), map.xy_idx(p2.0, p2.1));
+            map.tiles[door1] = floor;
+            map.tiles[door2] = floor;
 
             // Get the tunnel exit points, which are adjacent to the doors.
             let c1 = get_exit_point(p1, &rooms[i-1]);
@@ -558,22 +1240,325 @@ pub fn dungeon_map(biome: &dyn Biome) -> (Map, Vec<Rect>) {
 
             // Randomly decide whether to carve the horizontal or vertical tunnel first.
             if rng.range(0,2) == 1 {
-                apply_horizontal_tunnel(&mut map.tiles, c1.0, c2.0, c1.1, biome);
-                apply_vertical_tunnel(&mut map.tiles, c1.1, c2.1, c2.0, biome);
+                apply_horizontal_tunnel(&mut map, c1.0, c2.0, c1.1, corridor_width, &rooms, biome);
+                apply_vertical_tunnel(&mut map, c1.1, c2.1, c2.0, corridor_width, &rooms, biome);
             } else {
-                apply_vertical_tunnel(&mut map.tiles, c1.1, c2.1, c1.0, biome);
-                apply_horizontal_tunnel(&mut map.tiles, c1.0, c2.0, c2.1, biome);
+                apply_vertical_tunnel(&mut map, c1.1, c2.1, c1.0, corridor_width, &rooms, biome);
+                apply_horizontal_tunnel(&mut map, c1.0, c2.0, c2.1, corridor_width, &rooms, biome);
             }
         }
     }
 
-    // Finally, place the doors at all the candidate locations we stored.
-    if let Some(door_tile) = biome.get_door() {
-        for door_pos in doors {
-            let idx = xy_idx(door_pos.0, door_pos.1);
-            map.tiles[idx] = door_tile.clone();
+    map.rooms = rooms.clone();
+    (map, rooms)
+}
+
+// --- Wall Connectivity ---
+// Post-generation pass that replaces the wall glyph with box-drawing lines,
+// so rooms look outlined instead of filled with hash symbols.
+
+fn is_wall(tile: &Tile) -> bool {
+    !tile.walkable && !tile.transparent && tile.door_state.is_none()
+}
+
+/// A wall is "exposed" if at least one of its 8 neighbours is not a wall.
+/// Only exposed walls are ever seen, so solid rock keeps its glyph.
+fn is_exposed_wall(map: &Map, x: i32, y: i32) -> bool {
+    if !is_wall(&map.tiles[map.xy_idx(x, y)]) {
+        return false;
+    }
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx >= 0 && nx < map.width && ny >= 0 && ny < map.height && !is_wall(&map.tiles[map.xy_idx(nx, ny)]) {
+                return true;
+            }
         }
     }
+    false
+}
+
+/// True if the tile at (x, y) should connect to a neighbouring wall line (exposed walls and doors).
+fn connects(map: &Map, x: i32, y: i32) -> bool {
+    if x < 0 || x >= map.width || y < 0 || y >= map.height {
+        return false;
+    }
+    map.tiles[map.xy_idx(x, y)].door_state.is_some() || is_exposed_wall(map, x, y)
+}
 
-    (map, rooms)
+fn wall_glyph(map: &Map, x: i32, y: i32) -> rltk::FontCharType {
+    let mut mask = 0;
+    if connects(map, x, y - 1) { mask += 1; } // North
+    if connects(map, x, y + 1) { mask += 2; } // South
+    if connects(map, x - 1, y) { mask += 4; } // West
+    if connects(map, x + 1, y) { mask += 8; } // East
+
+    match mask {
+        0 => to_cp437('■'),                 // Isolated pillar
+        1..=3 => to_cp437('│'),             // North and/or South
+        4 | 8 | 12 => to_cp437('─'),        // West and/or East
+        5 => to_cp437('┘'),                 // North and West
+        6 => to_cp437('┐'),                 // South and West
+        7 => to_cp437('┤'),                 // North, South and West
+        9 => to_cp437('└'),                 // North and East
+        10 => to_cp437('┌'),                // South and East
+        11 => to_cp437('├'),                // North, South and East
+        13 => to_cp437('┴'),                // North, West and East
+        14 => to_cp437('┬'),                // South, West and East
+        _ => to_cp437('┼'),                 // All sides
+    }
+}
+
+/// Updates the glyph of every exposed wall according to its orthogonal wall neighbours.
+pub fn apply_wall_glyphs(map: &mut Map) {
+    let (width, height) = (map.width, map.height);
+    connect_walls_in(map, 0..=width - 1, 0..=height - 1);
+}
+
+/// Redraws the connected walls near `idx` after that tile changed (dug out, frozen back, ...),
+/// so the lines stay right without recomputing the whole map: a tile only affects whether
+/// its neighbours are exposed, and those the lines of the walls next to them, hence the 5x5 square.
+/// Doors always connect, open or shut, so opening one changes nothing. Only for biomes with
+/// connected_walls, like apply_wall_glyphs.
+pub fn refresh_wall_glyphs(map: &mut Map, idx: usize) {
+    let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+    let (width, height) = (map.width, map.height);
+    connect_walls_in(map, (x - 2).max(0)..=(x + 2).min(width - 1), (y - 2).max(0)..=(y + 2).min(height - 1));
+}
+
+/// The glyph pass shared by apply_wall_glyphs and refresh_wall_glyphs: every exposed wall in the
+/// rectangle gets its line from wall_glyph. The glyphs are collected first and written after,
+/// so each one is worked out on the map as it was.
+fn connect_walls_in(map: &mut Map, xs: std::ops::RangeInclusive<i32>, ys: std::ops::RangeInclusive<i32>) {
+    let mut glyphs = Vec::new();
+    for y in ys {
+        for x in xs.clone() {
+            if is_exposed_wall(map, x, y) {
+                glyphs.push((map.xy_idx(x, y), wall_glyph(map, x, y)));
+            }
+        }
+    }
+    for (idx, glyph) in glyphs {
+        map.tiles[idx].glyph = glyph;
+    }
+}
+
+// MAP BUILDER - Drunkard's Walk
+/// Creates a natural cavern by letting a "drunk" digger stumble around from the centre of the map,
+/// carving floor until `coverage` (0.0 - 1.0) of the inner area is open. Everything else stays wall,
+/// so the cavern is connected by construction. The start is returned as a single small room.
+#[allow(dead_code)]
+pub fn drunkards_walk_map(biome: &dyn Biome, rng: &mut GameRng, coverage: f32) -> (Map, Vec<Rect>) {
+    drunkards_walk_map_sized(biome, rng, coverage, MAP_WIDTH, MAP_HEIGHT)
+}
+
+/// `drunkards_walk_map` on a `width` x `height` map instead of the standard size.
+#[allow(dead_code)]
+pub fn drunkards_walk_map_sized(biome: &dyn Biome, rng: &mut GameRng, coverage: f32, width: i32, height: i32) -> (Map, Vec<Rect>) {
+    let wall = biome.get_wall();
+    let floor = biome.get_floor();
+    let mut map = Map::new(width, height, wall);
+
+    let coverage = coverage.clamp(0.05, 0.9);
+    let inner_area = ((map.width - 2) * (map.height - 2)) as f32;
+    let target_floor = (inner_area * coverage) as usize;
+    // safety net: a walker that keeps crossing old ground could take a very long time
+    let max_steps = target_floor * 200;
+
+    let (start_x, start_y) = (map.width / 2, map.height / 2);
+    let (mut x, mut y) = (start_x, start_y);
+    let idx = map.xy_idx(x, y);
+    map.tiles[idx] = floor;
+    let mut floor_count = 1;
+    let mut steps = 0;
+
+    while floor_count < target_floor && steps < max_steps {
+        steps += 1;
+        match rng.roll_dice(1, 4) {
+            1 => if x > 1 { x -= 1 },
+            2 => if x < map.width - 2 { x += 1 },
+            3 => if y > 1 { y -= 1 },
+            _ => if y < map.height - 2 { y += 1 },
+        }
+        let idx = map.xy_idx(x, y);
+        if !map.tiles[idx].walkable {
+            map.tiles[idx] = floor;
+            floor_count += 1;
+        }
+    }
+
+    if biome.connected_walls() {
+        apply_wall_glyphs(&mut map);
+    }
+
+    let start_room = Rect::new(start_x - 1, start_y - 1, 2, 2);
+    (map, vec![start_room])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_xy_idx_refuses_what_is_off_the_map() {
+        let (width, height) = (80, 50);
+        let map = Map::new(width, height, Building.get_floor());
+
+        assert_eq!(try_xy_idx(0, 0, &map), Some(0));
+        assert_eq!(try_xy_idx(79, 49, &map), Some(xy_idx(79, 49, width)));
+        assert_eq!(try_xy_idx(-1, 10, &map), None);
+        assert_eq!(try_xy_idx(80, 10, &map), None); // would wrap onto the next row with xy_idx
+        assert_eq!(try_xy_idx(10, 50, &map), None);
+    }
+
+    #[test]
+    fn the_same_seed_builds_the_same_map() {
+        let glyphs = |map: &Map| map.tiles.iter().map(|tile| tile.glyph).collect::<Vec<_>>();
+        let (first, first_rooms) = dungeon_map_seeded(&Building, 42, 1);
+        let (second, second_rooms) = dungeon_map_seeded(&Building, 42, 1);
+        assert_eq!(glyphs(&first), glyphs(&second));
+        assert_eq!(first_rooms.len(), second_rooms.len());
+        assert_eq!(glyphs(&wilderness_map_seeded(&Forest, 42, 3).0), glyphs(&wilderness_map_seeded(&Forest, 42, 3).0));
+
+        let (other, other_rooms) = dungeon_map_seeded(&Building, 43, 1);
+        assert!(other_rooms.len() != first_rooms.len() || glyphs(&other) != glyphs(&first), "seeds 42 and 43 built the same dungeon");
+    }
+
+    #[test]
+    fn the_alarms_are_scattered_inside_the_border() {
+        for seed in 0..20 {
+            let (map, _rooms) = wilderness_map_seeded(&Building, seed, 0);
+            let on_border = |idx: usize| {
+                let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+                x == 0 || y == 0 || x == map.width - 1 || y == map.height - 1
+            };
+            let alarms : Vec<usize> = (0..map.tiles.len()).filter(|idx| map.tiles[*idx].trap_kind == Some(TrapKind::Summon)).collect();
+            assert!(!alarms.is_empty());
+            assert!(alarms.iter().all(|idx| !on_border(*idx)), "seed {} put an alarm on the border", seed);
+            assert!((0..map.tiles.len()).filter(|idx| on_border(*idx)).all(|idx| !map.tiles[idx].walkable), "seed {} opened the border", seed);
+        }
+    }
+
+    #[test]
+    fn a_known_seed_keeps_its_layout() {
+        let (map, _rooms) = wilderness_map_seeded(&Building, 42, 0);
+        assert_eq!(map.layout_hash(), 0xf7bd_98bb_dc76_c5e7, "the wilderness generator builds a different map from seed 42");
+    }
+
+    #[test]
+    fn a_map_of_another_size_is_indexed_and_walled_by_its_own_dimensions() {
+        let (map, _rooms) = wilderness_map_sized(&Building, &mut GameRng::seeded(7), 0, 60, 60);
+        assert_eq!(map.tiles.len(), 60 * 60);
+        assert!(map.validate().is_ok());
+
+        for i in 0..60 {
+            for (x, y) in [(i, 0), (i, 59), (0, i), (59, i)] {
+                assert!(is_wall(&map.tiles[map.xy_idx(x, y)]), "no wall at ({}, {})", x, y);
+            }
+        }
+        let centre = map.xy_idx(30, 30);
+        assert_eq!(centre, 30 * 60 + 30);
+        assert_eq!((centre as i32 % map.width, centre as i32 / map.width), (30, 30));
+        assert!(map.tiles[centre].walkable, "the start is kept clear");
+    }
+
+    #[test]
+    fn small_maps_keep_caverns_and_clearings_inside_their_walls() {
+        let (cave, _rooms) = drunkards_walk_map_sized(&Building, &mut GameRng::seeded(7), 0.4, 30, 20);
+        assert_eq!((cave.width, cave.height), (30, 20));
+        assert!(cave.validate().is_ok());
+
+        // too small for even the first 6x6 clearing
+        let (map, clearings) = wilderness_map_sized(&Building, &mut GameRng::seeded(7), 3, 6, 6);
+        assert!(map.validate().is_ok());
+        for clearing in clearings.iter() {
+            assert!(clearing.x1 >= 1 && clearing.y1 >= 1 && clearing.x2 <= 4 && clearing.y2 <= 4, "clearing out of the map: {:?}", (clearing.x1, clearing.y1, clearing.x2, clearing.y2));
+        }
+    }
+
+    #[test]
+    fn only_the_hazards_in_plain_sight_are_known() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        map.tiles[1] = Volcano.get_water().unwrap();
+        map.tiles[2] = Forest.get_trap().unwrap();
+        map.tiles[3] = Tile { trap_dc: None, ..Forest.get_trap().unwrap() };
+
+        assert!(!map.known_hazard(0));
+        assert!(map.known_hazard(1));
+        assert!(!map.known_hazard(2), "a trap nobody has spotted yet");
+        assert!(map.known_hazard(3));
+    }
+
+    #[test]
+    fn deeper_levels_look_darker() {
+        let base = Building.get_floor().fg;
+        assert_eq!(tint_for_depth(base, 1), base);
+        let deep = tint_for_depth(base, 8);
+        assert_ne!(deep, base);
+        assert!(deep.r + deep.g + deep.b < base.r + base.g + base.b);
+        assert_eq!(tint_for_depth(base, 50), tint_for_depth(base, 100), "the tint stops getting stronger");
+    }
+
+    #[test]
+    fn some_traps_of_every_level_can_be_spotted() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        let trap = Tile { trap_kind: Some(TrapKind::Hazard), trap_dc: Some(18), ..Building.get_floor() };
+        for x in 10..17 {
+            map.tiles[xy_idx(x, 10, width)] = trap;
+        }
+        map.ensure_spottable_traps(1, &mut GameRng::seeded(3));
+
+        let dcs : Vec<i32> = map.tiles.iter().filter_map(|tile| tile.trap_dc).collect();
+        assert_eq!(dcs.len(), 7, "no trap is revealed, only made easier to spot");
+        assert_eq!(dcs.iter().filter(|dc| **dc <= spottable_trap_dc(1)).count(), 3);
+    }
+
+    #[test]
+    fn digging_a_wall_reconnects_the_lines_around_it() {
+        let (mut map, _rooms) = dungeon_map(&Building, &mut GameRng::seeded(4), 1);
+        apply_wall_glyphs(&mut map); // as the level is drawn in play (ConnectWalls)
+        let dug = (0..map.tiles.len())
+            .find(|idx| map.tiles[*idx].dig_turns.is_some() && is_exposed_wall(&map, *idx as i32 % map.width, *idx as i32 / map.width))
+            .unwrap();
+        assert!(map.dig(dug));
+        refresh_wall_glyphs(&mut map, dug);
+
+        let mut everywhere = map.clone();
+        apply_wall_glyphs(&mut everywhere);
+        let glyphs = |map: &Map| map.tiles.iter().map(|tile| tile.glyph).collect::<Vec<_>>();
+        assert_eq!(glyphs(&map), glyphs(&everywhere));
+    }
+
+    #[test]
+    fn the_digging_beam_stops_at_what_cannot_be_dug() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        for x in 12..15 {
+            map.tiles[xy_idx(x, 10, width)] = Building.get_wall(); // soft rock
+        }
+        map.tiles[xy_idx(17, 10, width)] = Volcano.get_wall(); // obsidian, can't be dug
+        map.tiles[xy_idx(18, 10, width)] = Building.get_wall();
+
+        let carved = map.dig_beam(10, 10, (1, 0), 10);
+
+        assert_eq!(carved.len(), 3);
+        assert!((12..15).all(|x| map.tiles[xy_idx(x, 10, width)].walkable));
+        assert!(!map.tiles[xy_idx(17, 10, width)].walkable);
+        assert!(!map.tiles[xy_idx(18, 10, width)].walkable, "the beam doesn't go through what stopped it");
+    }
+
+    #[test]
+    fn a_map_with_a_missing_tile_is_refused() {
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, Building.get_floor());
+        assert!(map.validate().is_ok());
+
+        map.tiles.pop();
+        assert_eq!(map.validate(), Err("80x50 map: expected 4000 tiles, found 3999".to_string()));
+        map.tiles.push(Building.get_floor());
+        map.revealed_tiles.push(true);
+        assert_eq!(map.validate(), Err("80x50 map: expected 4000 revealed tiles, found 4001".to_string()));
+    }
 }
@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+use crate::map::{Biome, Building, Map};
+use crate::map_builders::{self, BuilderChain, DrunkardsWalkBuilder, MapBorder, MapBuilder, WildernessBuilder};
+use crate::rng::GameRng;
+
+// MAP BENCHMARK --------------------------------------------------------------
+// Banco di prova dei generatori, senza finestra: ogni builder costruisce N mappe dagli stessi
+// semi (seed, seed + 1, ...), così due misure si possono confrontare tra una modifica e l'altra.
+// Si lancia con `rogue --bench-maps N` (flag nascosto) oppure dal test qui sotto.
+
+/// The seed of the first map of a benchmark run; map i is built from FIRST_SEED + i.
+pub const FIRST_SEED : u64 = 1;
+
+/// What a batch of maps from one builder looked like, and how long it took to make them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    pub builder : &'static str,
+    pub maps : u32,
+    pub total : Duration,
+    pub rooms : f32,          // average per map
+    pub open_fraction : f32,  // average share of walkable tiles
+    pub connectivity : f32,   // average share of the walkable tiles reachable from the start
+    pub playable : u32,       // maps passing check_playable
+}
+
+impl BenchReport {
+    pub fn per_map(&self) -> Duration {
+        self.total / self.maps.max(1)
+    }
+
+    /// One line of the benchmark table.
+    pub fn row(&self) -> String {
+        format!("{:<12} {:>5} maps {:>9.2?}/map  rooms {:>5.1}  open {:>5.1}%  connected {:>5.1}%  playable {}/{}",
+                self.builder, self.maps, self.per_map(), self.rooms, self.open_fraction * 100.0,
+                self.connectivity * 100.0, self.playable, self.maps)
+    }
+}
+
+/// The builders under test: rooms and corridors, caves and open wilderness.
+/// (There is no BSP generator yet: add it here once it exists.)
+pub fn builders(biome: &dyn Biome) -> Vec<(&'static str, Box<dyn MapBuilder>)> {
+    vec![
+        ("rooms", Box::new(BuilderChain::dungeon(biome, 1))),
+        ("caves", Box::new(DrunkardsWalkBuilder { coverage: 0.4 })),
+        ("wilderness", Box::new(WildernessBuilder { border: MapBorder::default(), clearings: 5 })),
+    ]
+}
+
+/// Share of the walkable tiles of `map`, and share of those the player can reach from the start.
+fn open_and_connected(map: &Map) -> (f32, f32) {
+    let open = map.tiles.iter().filter(|tile| tile.walkable).count();
+    if open == 0 {
+        return (0.0, 0.0);
+    }
+    let distances = map_builders::distances_from(map, map_builders::start_point(map));
+    let reached = map.tiles.iter().zip(distances.iter())
+        .filter(|(tile, distance)| tile.walkable && distance.is_some())
+        .count();
+    (open as f32 / map.tiles.len() as f32, reached as f32 / open as f32)
+}
+
+/// Builds `maps` maps with `builder`, from FIRST_SEED on, timing the generation alone
+/// (the stats are gathered outside the clock).
+pub fn bench(name: &'static str, builder: &dyn MapBuilder, biome: &dyn Biome, maps: u32) -> BenchReport {
+    let mut report = BenchReport { builder: name, maps, total: Duration::ZERO, rooms: 0.0, open_fraction: 0.0, connectivity: 0.0, playable: 0 };
+    for i in 0..maps as u64 {
+        let start = Instant::now();
+        let (map, rooms) = builder.build(biome, &mut GameRng::seeded(FIRST_SEED + i));
+        report.total += start.elapsed();
+
+        let (open, connected) = open_and_connected(&map);
+        report.rooms += rooms.len() as f32;
+        report.open_fraction += open;
+        report.connectivity += connected;
+        if map_builders::check_playable(&map).is_ok() {
+            report.playable += 1;
+        }
+    }
+    let n = maps.max(1) as f32;
+    report.rooms /= n;
+    report.open_fraction /= n;
+    report.connectivity /= n;
+    report
+}
+
+/// Runs every builder for `maps` maps in the Building biome and prints the table.
+pub fn run(maps: u32) {
+    println!("map generation benchmark: {} maps per builder, seeds {}..{}", maps, FIRST_SEED, FIRST_SEED + maps as u64);
+    for (name, builder) in builders(&Building) {
+        println!("{}", bench(name, &*builder, &Building, maps).row());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_seeds_give_the_same_stats() {
+        for (name, builder) in builders(&Building) {
+            let first = bench(name, &*builder, &Building, 3);
+            let second = bench(name, &*builder, &Building, 3);
+            assert_eq!((first.rooms, first.open_fraction, first.connectivity, first.playable),
+                       (second.rooms, second.open_fraction, second.connectivity, second.playable), "{}", name);
+            assert!(first.open_fraction > 0.0, "{} built no floor", name);
+        }
+    }
+}
@@ -0,0 +1,700 @@
+use std::collections::VecDeque;
+use std::{fs, io};
+use rltk::RGB;
+use crate::map::{Biome, BiomeKind, Map, MAP_HEIGHT, MAP_WIDTH, Tile, apply_wall_glyphs, drunkards_walk_map, dungeon_map, wilderness_map};
+use crate::direction::Direction;
+use crate::rect::Rect;
+use crate::rng::GameRng;
+
+// MAP BUILDERS ---------------------------------------------------------------
+// Un'unica interfaccia per tutti i generatori di mappe: il main sceglie un builder
+// (anche a runtime, come Box<dyn MapBuilder>) senza sapere quale funzione c'è dietro.
+
+/// A map generation strategy. Returns the map and its rooms
+/// (empty for open maps, the first one is where the player starts).
+pub trait MapBuilder {
+    fn build(&self, biome: &dyn Biome, rng: &mut GameRng) -> (Map, Vec<Rect>);
+}
+
+/// Shape of the wall ring around an open map.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BorderShape {
+    Rectangle, // a crisp box
+    Rounded,   // corners cut in a quarter circle
+    Ragged,    // the inner edge wanders in and out, like a tree line
+}
+
+/// The wall ring around an open map, `thickness` tiles deep (on average, for ragged borders).
+#[derive(Clone, Copy, Debug)]
+pub struct MapBorder {
+    pub thickness: i32,
+    pub shape: BorderShape,
+}
+
+impl Default for MapBorder {
+    fn default() -> MapBorder {
+        MapBorder { thickness: 1, shape: BorderShape::Rectangle }
+    }
+}
+
+/// Open map with scattered walls, doors and biome features (see `wilderness_map`), inside a `border`.
+/// `clearings` open spaces are carved among the walls and returned as rooms (0 = none).
+pub struct WildernessBuilder {
+    pub border: MapBorder,
+    pub clearings: i32,
+}
+
+impl MapBuilder for WildernessBuilder {
+    fn build(&self, biome: &dyn Biome, rng: &mut GameRng) -> (Map, Vec<Rect>) {
+        let (mut map, rooms) = wilderness_map(biome, rng, self.clearings);
+        draw_border(&mut map, biome, self.border, rng);
+        (map, rooms)
+    }
+}
+
+/// How deep a ragged border goes at each step along an edge of `length` tiles: a random walk
+/// between one tile less and one tile more than `thickness` (never less than one).
+fn ragged_depths(length: i32, thickness: i32, rng: &mut GameRng) -> Vec<i32> {
+    let (shallowest, deepest) = ((thickness - 1).max(1), thickness + 1);
+    let mut depth = thickness;
+    (0..length).map(|_| {
+        depth = (depth + rng.range(-1, 2)).clamp(shallowest, deepest);
+        depth
+    }).collect()
+}
+
+/// Walls up the edges of the map as described by `border`. The wilderness generator already
+/// draws a one-tile box, this only ever adds walls on top of it.
+fn draw_border(map: &mut Map, biome: &dyn Biome, border: MapBorder, rng: &mut GameRng) {
+    let wall = biome.get_wall();
+    let thickness = border.thickness.max(1);
+    let (width, height) = (map.width, map.height);
+    let (top, bottom, left, right) = match border.shape {
+        BorderShape::Ragged => (ragged_depths(width, thickness, rng), ragged_depths(width, thickness, rng),
+                                ragged_depths(height, thickness, rng), ragged_depths(height, thickness, rng)),
+        BorderShape::Rectangle | BorderShape::Rounded => (vec![thickness; width as usize], vec![thickness; width as usize],
+                                                          vec![thickness; height as usize], vec![thickness; height as usize]),
+    };
+    // rounded corners: quarter circles of this radius, the wall keeps the same depth along the curve
+    let radius = if border.shape == BorderShape::Rounded { thickness * 4 } else { 0 };
+    let corner_centre = |v: i32, size: i32| if v < radius { Some(radius) } else if v >= size - radius { Some(size - 1 - radius) } else { None };
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut in_border = y < top[x as usize] || y >= height - bottom[x as usize]
+                || x < left[y as usize] || x >= width - right[y as usize];
+            if let (Some(cx), Some(cy)) = (corner_centre(x, width), corner_centre(y, height)) {
+                let distance = (((x - cx).pow(2) + (y - cy).pow(2)) as f32).sqrt();
+                in_border |= distance > (radius - thickness) as f32 + 0.5;
+            }
+            if in_border {
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = wall;
+            }
+        }
+    }
+}
+
+/// Rooms connected by L-shaped corridors `corridor_width` tiles wide (see `dungeon_map`),
+/// with no doors: use `BuilderChain::dungeon` for the full pipeline.
+#[allow(dead_code)]
+pub struct DungeonBuilder {
+    pub corridor_width: i32, // 1 = single file, 2-3 = broad halls
+}
+
+impl MapBuilder for DungeonBuilder {
+    fn build(&self, biome: &dyn Biome, rng: &mut GameRng) -> (Map, Vec<Rect>) {
+        dungeon_map(biome, rng, self.corridor_width)
+    }
+}
+
+/// Natural caverns dug by a random walker (see `drunkards_walk_map`).
+#[allow(dead_code)]
+pub struct DrunkardsWalkBuilder {
+    pub coverage: f32, // fraction of the map that ends up as floor
+}
+
+impl MapBuilder for DrunkardsWalkBuilder {
+    fn build(&self, biome: &dyn Biome, rng: &mut GameRng) -> (Map, Vec<Rect>) {
+        drunkards_walk_map(biome, rng, self.coverage)
+    }
+}
+
+// MAP MODIFIERS --------------------------------------------------------------
+// Passi di post-produzione riutilizzabili, applicati in sequenza alla mappa di base.
+// L'ordine conta (es. le porte prima dei glifi dei muri) ed è deciso da chi monta la catena.
+
+/// A post-processing step. Modifiers read the rooms from `map.rooms`, so they also work
+/// on open maps (where it's empty) and can add rooms of their own.
+pub trait MapModifier {
+    fn apply(&self, map: &mut Map, rng: &mut GameRng);
+}
+
+/// A base builder followed by its modifiers, in order, all drawing from the same rng.
+/// Before any modifier runs, the start point gets the biome's start tile, so the player
+/// never lands on whatever the base generator happened to put there.
+pub struct BuilderChain {
+    base: Box<dyn MapBuilder>,
+    modifiers: Vec<Box<dyn MapModifier>>,
+}
+
+impl BuilderChain {
+    pub fn new(base: Box<dyn MapBuilder>) -> BuilderChain {
+        BuilderChain { base, modifiers: Vec::new() }
+    }
+
+    pub fn with(mut self, modifier: impl MapModifier + 'static) -> BuilderChain {
+        self.modifiers.push(Box::new(modifier));
+        self
+    }
+
+    /// The standard dungeon: rooms and corridors, doors where corridors enter rooms,
+    /// no unreachable pockets, stairs in the last room, the traps of each room wired together
+    /// and (for indoor biomes) outlined walls. Corridors are `corridor_width` tiles wide
+    /// (GameConfig::corridor_width).
+    pub fn dungeon(biome: &dyn Biome, corridor_width: i32) -> BuilderChain {
+        let chain = BuilderChain::new(Box::new(DungeonBuilder { corridor_width }))
+            .with(PlaceDoors::new(biome))
+            .with(EnsureConnected::new(biome))
+            .with(PlaceStairs::new(biome))
+            .with(LinkTraps::new(6.0));
+        if biome.connected_walls() { chain.with(ConnectWalls) } else { chain }
+    }
+}
+
+impl MapBuilder for BuilderChain {
+    fn build(&self, biome: &dyn Biome, rng: &mut GameRng) -> (Map, Vec<Rect>) {
+        let (mut map, rooms) = self.base.build(biome, rng);
+        map.rooms = rooms;
+        let start = start_point(&map);
+        let idx = map.xy_idx(start.0, start.1);
+        map.tiles[idx] = biome.get_start_tile();
+        for modifier in self.modifiers.iter() {
+            modifier.apply(&mut map, rng);
+        }
+        let rooms = map.rooms.clone();
+        (map, rooms)
+    }
+}
+
+/// Where the player will start: the centre of the first room, or of the map when there are no rooms.
+/// If that tile can't be stood on (a degenerate generation), the first passable tile of the map instead;
+/// only a map without a single one keeps the centre, and then fails check_playable.
+pub fn start_point(map: &Map) -> (i32, i32) {
+    let preferred = map.rooms.first().map(|room| room.center()).unwrap_or((map.width / 2, map.height / 2));
+    if map.tiles.get(map.xy_idx(preferred.0, preferred.1)).is_some_and(passable) {
+        return preferred;
+    }
+    map.tiles.iter().position(passable)
+        .map(|idx| (idx as i32 % map.width, idx as i32 / map.width))
+        .unwrap_or(preferred)
+}
+
+/// Why a generated map can't be played, if it can't: it must be well formed (see Map::validate),
+/// the player must start on a tile they can stand on, and the way down (portal or stairs,
+/// when there is one) must be reachable from there.
+pub fn check_playable(map: &Map) -> Result<(), String> {
+    map.validate()?;
+    let start = start_point(map);
+    if !passable(&map.tiles[map.xy_idx(start.0, start.1)]) {
+        return Err(format!("the start ({}, {}) is not walkable", start.0, start.1));
+    }
+    let distances = distances_from(map, start);
+    let unreachable = (0..map.tiles.len()).find(|idx| map.tiles[*idx].leads_down()).filter(|portal| distances[*portal].is_none());
+    if let Some(portal) = unreachable {
+        return Err(format!("the portal at ({}, {}) can't be reached from the start", portal as i32 % map.width, portal as i32 / map.width));
+    }
+    Ok(())
+}
+
+/// Builds a map with `builder` from `base_seed`, then `base_seed + 1` and so on, until one passes
+/// check_playable. Returns it with its rooms and the seed that produced it, or why the last
+/// of the `max_attempts` attempts was still unplayable.
+pub fn generate_with_retries(builder: &dyn MapBuilder, biome: &dyn Biome, base_seed: u64, max_attempts: u32) -> Result<(Map, Vec<Rect>, u64), String> {
+    let mut last_error = String::from("no attempt was made");
+    for attempt in 0..max_attempts as u64 {
+        let seed = base_seed.wrapping_add(attempt);
+        let (map, rooms) = builder.build(biome, &mut GameRng::seeded(seed));
+        match check_playable(&map) {
+            Ok(()) => return Ok((map, rooms, seed)),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(format!("no playable map in {} attempts from seed {}: {}", max_attempts, base_seed, last_error))
+}
+
+/// Tiles the player can get through: walkable ones and doors (even locked ones: a key opens them, or a few blows).
+fn passable(tile: &Tile) -> bool {
+    tile.walkable || tile.door_state.is_some()
+}
+
+/// Walking distance (8 directions) from `start` to every tile, None for the unreachable ones.
+pub fn distances_from(map: &Map, start: (i32, i32)) -> Vec<Option<i32>> {
+    distances_through(map, start, &passable)
+}
+
+/// Like `distances_from`, going only through the tiles `passable` accepts.
+pub fn distances_through(map: &Map, start: (i32, i32), passable: &dyn Fn(&Tile) -> bool) -> Vec<Option<i32>> {
+    let mut distances = vec![None; map.tiles.len()];
+    let in_bounds = |x: i32, y: i32| x >= 0 && x < map.width && y >= 0 && y < map.height;
+    if !in_bounds(start.0, start.1) || !passable(&map.tiles[map.xy_idx(start.0, start.1)]) {
+        return distances;
+    }
+
+    let mut queue = VecDeque::new();
+    distances[map.xy_idx(start.0, start.1)] = Some(0);
+    queue.push_back(start);
+    while let Some((x, y)) = queue.pop_front() {
+        let distance = distances[map.xy_idx(x, y)].unwrap_or(0);
+        for (dx, dy) in Direction::ALL.map(Direction::to_delta) {
+            let (nx, ny) = (x + dx, y + dy);
+            if !in_bounds(nx, ny) {
+                continue;
+            }
+            let idx = map.xy_idx(nx, ny);
+            if distances[idx].is_none() && passable(&map.tiles[idx]) {
+                distances[idx] = Some(distance + 1);
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+    distances
+}
+
+/// Puts a door in every gap of a room's wall ring (a walkable tile between two walls),
+/// which is where corridors enter the rooms. Does nothing for biomes without doors.
+pub struct PlaceDoors {
+    door: Option<Tile>,
+}
+
+impl PlaceDoors {
+    pub fn new(biome: &dyn Biome) -> PlaceDoors {
+        PlaceDoors { door: biome.get_door() }
+    }
+}
+
+impl MapModifier for PlaceDoors {
+    fn apply(&self, map: &mut Map, _rng: &mut GameRng) {
+        let door = match self.door {
+            Some(door) => door,
+            None => return,
+        };
+        let blocks = |map: &Map, x: i32, y: i32| !passable(&map.tiles[map.xy_idx(x, y)]);
+
+        // rooms in the order they were generated, each ring walked in a fixed order: with the same
+        // seed the same doors land on the same tiles (no hash sets or maps here, their order varies)
+        for room in map.rooms.clone().iter() {
+            // the floor of a room is x1+1..=x2, so its walls are on x1 and x2+1 (same for y)
+            let mut ring = Vec::new();
+            for x in room.x1..=room.x2 + 1 {
+                ring.push((x, room.y1));
+                ring.push((x, room.y2 + 1));
+            }
+            for y in room.y1 + 1..=room.y2 {
+                ring.push((room.x1, y));
+                ring.push((room.x2 + 1, y));
+            }
+
+            for (x, y) in ring {
+                if x <= 0 || x >= map.width - 1 || y <= 0 || y >= map.height - 1 {
+                    continue;
+                }
+                let idx = map.xy_idx(x, y);
+                if !map.tiles[idx].walkable || map.tiles[idx].door_state.is_some() {
+                    continue;
+                }
+                let gap_between_walls = (blocks(map, x - 1, y) && blocks(map, x + 1, y))
+                    || (blocks(map, x, y - 1) && blocks(map, x, y + 1));
+                if gap_between_walls {
+                    map.tiles[idx] = door;
+                }
+            }
+        }
+    }
+}
+
+/// Walls up every walkable tile that can't be reached from the start,
+/// so nothing (player, monster or loot) can end up in a sealed pocket.
+pub struct EnsureConnected {
+    wall: Tile,
+}
+
+impl EnsureConnected {
+    pub fn new(biome: &dyn Biome) -> EnsureConnected {
+        EnsureConnected { wall: biome.get_wall() }
+    }
+}
+
+impl MapModifier for EnsureConnected {
+    fn apply(&self, map: &mut Map, _rng: &mut GameRng) {
+        let start = start_point(map);
+        if !passable(&map.tiles[map.xy_idx(start.0, start.1)]) {
+            return; // no sensible start to measure from, leave the map alone
+        }
+        let distances = distances_from(map, start);
+        for (idx, distance) in distances.iter().enumerate() {
+            if distance.is_none() && map.tiles[idx].walkable {
+                map.tiles[idx] = self.wall;
+            }
+        }
+    }
+}
+
+/// Marks every tile as already seen, so the whole map shows up from the first frame.
+/// For development only: screenshots and eyeballing the generators (GameConfig::reveal_all).
+pub struct RevealAll;
+
+impl MapModifier for RevealAll {
+    fn apply(&self, map: &mut Map, _rng: &mut GameRng) {
+        map.revealed_tiles = vec![true; map.tiles.len()];
+    }
+}
+
+/// Wires traps together into groups (see `Map::trap_groups`): on maps with rooms, the traps
+/// of each room; on open maps, traps no farther than `distance` from another trap of the group.
+pub struct LinkTraps {
+    distance: f32,
+}
+
+impl LinkTraps {
+    pub fn new(distance: f32) -> LinkTraps {
+        LinkTraps { distance }
+    }
+}
+
+impl MapModifier for LinkTraps {
+    fn apply(&self, map: &mut Map, _rng: &mut GameRng) {
+        let traps : Vec<usize> = (0..map.tiles.len()).filter(|idx| map.tiles[*idx].trap_kind.is_some()).collect();
+        let point = |idx: usize| rltk::Point::new(idx as i32 % map.width, idx as i32 / map.width);
+
+        let mut groups : Vec<Vec<usize>> = Vec::new();
+        if !map.rooms.is_empty() {
+            for room in map.rooms.iter() {
+                groups.push(traps.iter().copied().filter(|idx| { let p = point(*idx); room.contains(p.x, p.y) }).collect());
+            }
+        } else {
+            let mut unassigned = traps.clone();
+            while let Some(first) = unassigned.pop() {
+                let mut group = vec![first];
+                let mut frontier = vec![first];
+                while let Some(current) = frontier.pop() {
+                    let (near, far) : (Vec<usize>, Vec<usize>) = unassigned.iter()
+                        .partition(|other| rltk::DistanceAlg::Pythagoras.distance2d(point(current), point(**other)) <= self.distance);
+                    unassigned = far;
+                    frontier.extend(near.iter().copied());
+                    group.extend(near);
+                }
+                groups.push(group);
+            }
+        }
+        // a lone trap has nothing to set off
+        map.trap_groups = groups.into_iter().filter(|group| group.len() > 1).collect();
+    }
+}
+
+/// Places the down stairs in the centre of the last room or, on maps without rooms,
+/// on the reachable floor tile farthest from the start. Does nothing for biomes without stairs.
+pub struct PlaceStairs {
+    stairs: Option<Tile>,
+}
+
+impl PlaceStairs {
+    pub fn new(biome: &dyn Biome) -> PlaceStairs {
+        PlaceStairs { stairs: biome.get_stairs() }
+    }
+}
+
+impl MapModifier for PlaceStairs {
+    fn apply(&self, map: &mut Map, _rng: &mut GameRng) {
+        let stairs = match self.stairs {
+            Some(stairs) => stairs,
+            None => return,
+        };
+
+        let position = if map.rooms.len() > 1 {
+            map.rooms.last().map(|room| room.center())
+        } else {
+            let distances = distances_from(map, start_point(map));
+            distances.iter().enumerate()
+                .filter(|(idx, distance)| distance.is_some() && map.tiles[*idx].walkable && map.tiles[*idx].door_state.is_none())
+                .max_by_key(|(_idx, distance)| distance.unwrap_or(0))
+                .map(|(idx, _distance)| (idx as i32 % map.width, idx as i32 / map.width))
+        };
+        if let Some((x, y)) = position {
+            let idx = map.xy_idx(x, y);
+            map.tiles[idx] = stairs;
+        }
+    }
+}
+
+/// Stamps a hand-made piece of map. In the template `#` is wall, `.` floor, `+` door
+/// (floor for biomes without doors) and spaces leave the tile underneath untouched.
+/// With no `position` the prefab goes in a random spot that fits inside the border.
+pub struct AddPrefab {
+    template: &'static str,
+    position: Option<(i32, i32)>,
+    floor: Tile,
+    wall: Tile,
+    door: Option<Tile>,
+}
+
+impl AddPrefab {
+    #[allow(dead_code)]
+    pub fn new(biome: &dyn Biome, template: &'static str, position: Option<(i32, i32)>) -> AddPrefab {
+        AddPrefab { template, position, floor: biome.get_floor(), wall: biome.get_wall(), door: biome.get_door() }
+    }
+}
+
+impl MapModifier for AddPrefab {
+    fn apply(&self, map: &mut Map, rng: &mut GameRng) {
+        let rows : Vec<&str> = self.template.lines().filter(|row| !row.is_empty()).collect();
+        let height = rows.len() as i32;
+        let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0) as i32;
+        // the outer border of the map is never touched
+        if width == 0 || width > map.width - 2 || height > map.height - 2 {
+            return;
+        }
+
+        let (left, top) = match self.position {
+            Some(position) => position,
+            None => (rng.range(1, map.width - width), rng.range(1, map.height - height)),
+        };
+        for (dy, row) in rows.iter().enumerate() {
+            for (dx, ch) in row.chars().enumerate() {
+                let (x, y) = (left + dx as i32, top + dy as i32);
+                if x <= 0 || x >= map.width - 1 || y <= 0 || y >= map.height - 1 {
+                    continue;
+                }
+                let tile = match ch {
+                    '#' => self.wall,
+                    '.' => self.floor,
+                    '+' => self.door.unwrap_or(self.floor),
+                    _ => continue,
+                };
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = tile;
+            }
+        }
+    }
+}
+
+/// Reads a whole level drawn in a text file (see `map_from_ascii`).
+/// A drawing that doesn't make a well-formed map is an InvalidData error.
+pub fn load_map_from_ascii(path: &str, biome: &dyn Biome) -> io::Result<Map> {
+    map_from_ascii(&fs::read_to_string(path)?, biome).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Builds a level from its drawing, one text line per row, with the biome's tiles:
+/// `#` wall, `.` floor, `+` door, `>` stairs, `~` water, `^` trap and `@` the player's start.
+/// Doors, stairs, water and traps are plain floor in biomes that don't have them.
+/// The map is as wide as the first row and as tall as the drawing; a row of a different length
+/// leaves the tile count off, and the map is refused (see Map::validate).
+/// Unknown characters become floor, with a warning.
+pub fn map_from_ascii(text: &str, biome: &dyn Biome) -> Result<Map, String> {
+    let mut rows : Vec<&str> = text.lines().collect();
+    while rows.last().is_some_and(|row| row.is_empty()) {
+        rows.pop();
+    }
+    let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+    let mut map = Map::new(width, rows.len() as i32, biome.get_wall());
+    map.tiles.clear();
+    let floor = biome.get_floor();
+    for (y, row) in rows.iter().enumerate() {
+        for (x, ch) in row.chars().enumerate() {
+            let tile = match ch {
+                '#' => biome.get_wall(),
+                '.' => floor,
+                '+' => biome.get_door().unwrap_or(floor),
+                '>' => biome.get_stairs().unwrap_or(floor),
+                '~' => biome.get_water().unwrap_or(floor),
+                '^' => biome.get_trap().unwrap_or(floor),
+                '@' => {
+                    // the start is a room of a single tile, where start_point looks for it
+                    map.rooms.insert(0, Rect::new(x as i32, y as i32, 0, 0));
+                    biome.get_start_tile()
+                }
+                _ => {
+                    eprintln!("Unknown character '{}' at row {}, column {} of the level: using floor", ch, y + 1, x + 1);
+                    floor
+                }
+            };
+            map.tiles.push(tile);
+        }
+    }
+    map.validate()?;
+    if biome.connected_walls() {
+        apply_wall_glyphs(&mut map);
+    }
+    Ok(map)
+}
+
+/// Places a portal on a random reachable floor tile, at least MIN_DISTANCE steps from the start.
+/// Stepping on it takes the party one level down, into a level of the `target` biome.
+pub struct PlacePortal {
+    target: BiomeKind,
+}
+
+impl PlacePortal {
+    const MIN_DISTANCE: i32 = 10;
+
+    pub fn new(target: BiomeKind) -> PlacePortal {
+        PlacePortal { target }
+    }
+}
+
+impl MapModifier for PlacePortal {
+    fn apply(&self, map: &mut Map, rng: &mut GameRng) {
+        let distances = distances_from(map, start_point(map));
+        let candidates : Vec<usize> = distances.iter().enumerate()
+            .filter(|(idx, distance)| distance.is_some_and(|d| d >= Self::MIN_DISTANCE)
+                && map.tiles[*idx].walkable && map.tiles[*idx].door_state.is_none() && map.tiles[*idx].trap_kind.is_none())
+            .map(|(idx, _distance)| idx)
+            .collect();
+        if candidates.is_empty() {
+            return;
+        }
+
+        let idx = candidates[rng.range(0, candidates.len() as i32) as usize];
+        // il portale è il pavimento stesso, solo disegnato diversamente
+        let tile = &mut map.tiles[idx];
+        tile.glyph = rltk::to_cp437('Ω');
+        tile.fg = RGB::named(rltk::MAGENTA);
+        tile.portal = Some(self.target);
+    }
+}
+
+/// Draws walls as connected lines (see `apply_wall_glyphs`). Goes last, after every step that moves walls or doors.
+pub struct ConnectWalls;
+
+impl MapModifier for ConnectWalls {
+    fn apply(&self, map: &mut Map, _rng: &mut GameRng) {
+        apply_wall_glyphs(map);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Building, DoorState, Forest, Stairs, xy_idx};
+
+    fn door_tiles(seed: u64) -> Vec<usize> {
+        let (map, _rooms) = BuilderChain::dungeon(&Building, 1).build(&Building, &mut GameRng::seeded(seed));
+        (0..map.tiles.len()).filter(|idx| map.tiles[*idx].door_state.is_some()).collect()
+    }
+
+    #[test]
+    fn the_down_stairs_wait_in_the_last_room() {
+        let (map, rooms) = BuilderChain::dungeon(&Building, 1).build(&Building, &mut GameRng::seeded(42));
+        assert!(rooms.len() > 1);
+        let (x, y) = rooms[rooms.len() - 1].center();
+        let stairs = map.tiles[map.xy_idx(x, y)];
+        assert_eq!(stairs.stairs, Some(Stairs::Down));
+        assert!(stairs.walkable);
+        assert_eq!(map.tiles.iter().filter(|tile| tile.leads_down()).count(), 1, "the stairs are the only way down");
+        assert!(check_playable(&map).is_ok());
+
+        // the base layout has no stairs of its own, and biomes without stairs get none
+        assert!(DungeonBuilder { corridor_width: 1 }.build(&Building, &mut GameRng::seeded(42)).0.tiles.iter().all(|tile| tile.stairs.is_none()));
+        let (forest, _rooms) = BuilderChain::dungeon(&Forest, 1).build(&Forest, &mut GameRng::seeded(42));
+        assert!(forest.tiles.iter().all(|tile| !tile.leads_down()));
+    }
+
+    #[test]
+    fn wide_corridors_leave_the_room_walls_alone() {
+        let narrow = DungeonBuilder { corridor_width: 1 }.build(&Building, &mut GameRng::seeded(3)).0;
+        let (wide, rooms) = DungeonBuilder { corridor_width: 3 }.build(&Building, &mut GameRng::seeded(3));
+        let floor = |map: &Map| map.tiles.iter().filter(|tile| tile.walkable).count();
+        assert!(floor(&wide) > floor(&narrow), "{} floor tiles with width 3, {} with width 1", floor(&wide), floor(&narrow));
+
+        // same rooms, and their walls open exactly where the single-file corridors open them
+        for room in rooms.iter() {
+            for y in room.y1..=room.y2 + 1 {
+                for x in room.x1..=room.x2 + 1 {
+                    let idx = narrow.xy_idx(x, y);
+                    assert_eq!(wide.tiles[idx].walkable, narrow.tiles[idx].walkable, "room wall changed at ({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_places_the_same_doors() {
+        for seed in [1, 7, 42] {
+            let doors = door_tiles(seed);
+            assert!(!doors.is_empty());
+            assert_eq!(doors, door_tiles(seed));
+        }
+    }
+
+    struct WallsOnly;
+
+    impl MapBuilder for WallsOnly {
+        fn build(&self, biome: &dyn Biome, _rng: &mut GameRng) -> (Map, Vec<Rect>) {
+            (map_from_ascii("###\n###", biome).unwrap(), Vec::new())
+        }
+    }
+
+    #[test]
+    fn retries_stop_at_the_first_playable_map() {
+        let (map, _rooms, seed) = generate_with_retries(&BuilderChain::dungeon(&Building, 1), &Building, 5, 10).unwrap();
+        assert_eq!(seed, 5, "a dungeon is playable at the first attempt");
+        assert!(check_playable(&map).is_ok());
+
+        let error = generate_with_retries(&WallsOnly, &Building, 5, 3).err().unwrap();
+        assert!(error.starts_with("no playable map in 3 attempts from seed 5"), "{}", error);
+    }
+
+    #[test]
+    fn a_map_without_rooms_never_starts_the_player_in_a_wall() {
+        // all walls but for a little cave far from the centre
+        let mut map = Map::new(MAP_WIDTH, MAP_HEIGHT, Building.get_wall());
+        let width = map.width;
+        map.tiles[xy_idx(70, 45, width)] = Building.get_floor();
+        map.tiles[xy_idx(71, 45, width)] = Building.get_floor();
+        assert!(map.rooms.is_empty());
+        assert_eq!(start_point(&map), (70, 45));
+        assert!(check_playable(&map).is_ok());
+
+        map.tiles[xy_idx(70, 45, width)] = Building.get_wall();
+        map.tiles[xy_idx(71, 45, width)] = Building.get_wall();
+        assert!(check_playable(&map).is_err());
+    }
+
+    #[test]
+    fn what_lies_behind_a_locked_door_can_be_left_out() {
+        let mut map = map_from_ascii("#####\n#@+.#\n#####", &Building).unwrap();
+        let door = map.xy_idx(2, 1);
+        map.tiles[door] = Building.get_locked_door().unwrap();
+        let behind = map.xy_idx(3, 1);
+
+        assert_eq!(distances_from(&map, (1, 1))[behind], Some(2));
+        let unlocked = |tile: &Tile| tile.walkable || (tile.door_state.is_some() && tile.door_state != Some(DoorState::Locked));
+        assert_eq!(distances_through(&map, (1, 1), &unlocked)[behind], None);
+    }
+
+    #[test]
+    fn an_ascii_level_is_built_from_the_biome_tiles() {
+        let map = map_from_ascii("#####\n#@.+?\n#####", &Building).unwrap();
+        let width = map.width;
+
+        assert_eq!((map.width, map.height), (5, 3), "the map is the size of the drawing");
+        assert_eq!(start_point(&map), (1, 1));
+        assert!(!map.tiles[xy_idx(0, 0, width)].walkable);
+        assert!(map.tiles[xy_idx(2, 1, width)].walkable);
+        assert_eq!(map.tiles[xy_idx(3, 1, width)].door_state, Some(DoorState::Closed));
+        assert!(map.tiles[xy_idx(4, 1, width)].walkable); // unknown: floor
+    }
+
+    #[test]
+    fn a_ragged_ascii_level_is_refused() {
+        let path = std::env::temp_dir().join("rogue_ragged_level_test.txt");
+        fs::write(&path, "#####\n#@..#\n####\n").unwrap();
+        let result = load_map_from_ascii(path.to_str().unwrap(), &Building);
+        fs::remove_file(&path).ok();
+
+        let error = result.err().expect("a row is one tile short");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("expected 15 tiles, found 14"), "{}", error);
+    }
+}
@@ -0,0 +1,43 @@
+use specs::prelude::*;
+use rltk::Point;
+use super::{LastSeen, PlayerEntity, Position, Renderable, Viewshed};
+
+/// Keeps the player's memory of where things were: every entity in sight records its tile,
+/// and a memory is forgotten once the player sees that tile again without the entity on it.
+pub struct MemorySystem {}
+
+impl<'a> System<'a> for MemorySystem {
+    type SystemData = ( Entities<'a>,
+                        ReadExpect<'a, PlayerEntity>,
+                        ReadStorage<'a, Viewshed>,
+                        ReadStorage<'a, Position>,
+                        ReadStorage<'a, Renderable>,
+                        WriteStorage<'a, LastSeen>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (entities, player, viewsheds, positions, renderables, mut last_seen) = data;
+
+        let viewshed = match viewsheds.get(player.0) {
+            Some(viewshed) => viewshed,
+            None => return,
+        };
+
+        let mut forgotten = Vec::new();
+        for (entity, pos, _render) in (&entities, &positions, &renderables).join() {
+            if entity == player.0 {
+                continue;
+            }
+            if viewshed.visible_tiles.contains(&Point::new(pos.x, pos.y)) {
+                last_seen.insert(entity, LastSeen { x: pos.x, y: pos.y }).expect("Unable to insert memory");
+            } else if let Some(memory) = last_seen.get(entity) {
+                // we are looking at the remembered spot and it's not there anymore
+                if viewshed.visible_tiles.contains(&Point::new(memory.x, memory.y)) {
+                    forgotten.push(entity);
+                }
+            }
+        }
+        for entity in forgotten {
+            last_seen.remove(entity);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{HashMap, HashSet};
+use specs::prelude::*;
+use rltk::{BaseMap, DijkstraMap, DistanceAlg, LineAlg, Point};
+use super::{AggroRange, CombatStats, DoorOpener, DoorTier, Faction, Investigating, Monster, Morale, Name, PackMember, Player, PlayerEntity, Position, Reach, SmashingDoor, SpecialAttack, SpecialAttackKind, Stance, Viewshed};
+use crate::bump::{self, BumpAction, Bumper, Occupant};
+use crate::combat;
+use crate::effects::{EffectQueue, EffectTarget, EffectType};
+use crate::gamelog::GameLog;
+use crate::map::{DoorState, Map, StatusEffect};
+use crate::noise::{self, Noise, NoiseEvents};
+use crate::path_cache::{DIJKSTRA_MAX_DEPTH, DoorAwareMap, PathCache};
+use crate::rng::GameRng;
+
+/// With nothing to do, a pack member farther than this from its leader walks back to it.
+const PACK_RADIUS: f32 = 2.5;
+/// While hunting, a pack member farther than this from its leader gives up the chase to regroup.
+const PACK_LEASH: f32 = 8.0;
+/// How many blows a strong monster needs to break a locked door.
+const DOOR_BREAK_BLOWS: i32 = 2;
+
+/// True if no opaque tile stands between `from` and `to` (the two ends excluded) along a Bresenham line.
+/// Much cheaper than a whole field of view when all we want to know is "can it see the player?".
+pub fn clear_line(map: &Map, from: Point, to: Point) -> bool {
+    rltk::line2d(LineAlg::Bresenham, from, to).iter()
+        .filter(|p| **p != from && **p != to)
+        .all(|p| map.tiles[map.xy_idx(p.x, p.y)].transparent)
+}
+
+/// Monster turn: every monster that sees the player walks towards it along a Dijkstra map
+/// (shared by all of them, and kept across turns by the PathCache) and attacks when adjacent. A monster whose morale breaks walks the same map the other way,
+/// away from the player, and slowly recovers once out of sight.
+/// Door openers follow a second map that goes through closed doors, so they keep chasing
+/// a nearby player even after losing sight of them behind a door; the strong ones
+/// (DoorTier::Breaks) follow a third one that also goes through locked doors, and batter
+/// them down in DOOR_BREAK_BLOWS turns.
+/// Monsters with an AggroRange sleep until the player comes within their radius (with a clear
+/// line between them), COVER_STEALTH_BONUS tiles less while the player hugs cover (see combat::hugs_cover); they have no viewshed and afterwards track the player by line of sight.
+/// Noises (see noise.rs) wake up the sleepers that hear them, and send the monsters that
+/// can't see the player to investigate where the noise came from.
+/// Packs keep together: an idle pack member walks back to its leader when it strays,
+/// a hunting one regroups instead of chasing once it is too far from it, and an idle
+/// leader roams, so the whole pack wanders around as a group.
+/// Monsters of rival factions fight each other: a monster with a Faction goes for the closest
+/// hostile creature it can see, the player or a monster of another faction, whichever is nearer.
+/// Every step goes through bump::resolve_bump, like the player's: it decides whether the monster
+/// walks, opens a door or has to wait.
+/// On an orthogonal_only map every path (and so every step) is a cardinal one.
+/// A monster with a SpecialAttack uses it instead of closing in when it sees the player within its
+/// range (but not next to it) and the attack has recharged; then it waits out the cooldown.
+/// Hits and healing are queued as effects (see effects.rs), applied after every monster has acted.
+pub struct MonsterAI {}
+
+impl<'a> System<'a> for MonsterAI {
+    type SystemData = ( WriteExpect<'a, Map>,
+                        ReadExpect<'a, PlayerEntity>,
+                        WriteExpect<'a, GameLog>,
+                        Entities<'a>,
+                        ReadStorage<'a, Monster>,
+                        ReadStorage<'a, DoorOpener>,
+                        ReadStorage<'a, Name>,
+                        WriteStorage<'a, Viewshed>,
+                        WriteStorage<'a, Position>,
+                        ReadStorage<'a, CombatStats>,
+                        WriteStorage<'a, Morale>,
+                        WriteStorage<'a, AggroRange>,
+                        WriteStorage<'a, Investigating>,
+                        WriteExpect<'a, NoiseEvents>,
+                        WriteExpect<'a, EffectQueue>,
+                        ReadStorage<'a, PackMember>,
+                        WriteExpect<'a, GameRng>,
+                        ReadStorage<'a, Faction>,
+                        ReadStorage<'a, Player>,
+                        ReadStorage<'a, Reach>,
+                        WriteStorage<'a, SmashingDoor>,
+                        ReadStorage<'a, Stance>,
+                        WriteStorage<'a, SpecialAttack>,
+                        WriteExpect<'a, PathCache>);
+
+    fn run(&mut self, data : Self::SystemData) {
+        let (mut map, player, mut log, entities, monsters, door_openers, names, mut viewsheds, mut positions, combat_stats, mut morales, mut aggro_ranges, mut investigating, mut noise_events, mut effects, packs, mut rng, factions, players, reaches, mut smashing, stances, mut specials, mut path_cache) = data;
+
+        let player_pos = match positions.get(player.0) {
+            Some(pos) => Point::new(pos.x, pos.y),
+            None => return,
+        };
+        // the player's stance counts for their defense (see combat::with_stance)
+        let player_stats = match combat_stats.get(player.0) {
+            Some(stats) => crate::combat::with_stance(stats, stances.get(player.0)),
+            None => return,
+        };
+        let player_idx = map.xy_idx(player_pos.x, player_pos.y);
+        // a player hugging a wall has to come closer before a sleeper notices them
+        let stealth = if combat::hugs_cover(&map, player_pos) { combat::COVER_STEALTH_BONUS } else { 0 };
+        // one set of fields for every monster, rebuilt only when the player moved or the map changed
+        let fields = path_cache.fields(&map, player_idx);
+        let (distance_field, door_field, smash_field) = (&fields.walking, &fields.doors, &fields.smashing);
+        let mut doors_opened = false;
+
+        // chi blocca una casella: tutte le creature (giocatore incluso); chi ha i pv ma non è
+        // né un mostro né del gruppo è una barricata
+        let mut occupants : HashMap<usize, (Entity, Occupant)> = (&entities, &positions, &combat_stats).join()
+            .map(|(entity, pos, _stats)| {
+                let occupant = if players.contains(entity) {
+                    Occupant::PartyMember
+                } else if monsters.contains(entity) {
+                    Occupant::Monster { faction: factions.get(entity).copied() }
+                } else {
+                    Occupant::Barricade
+                };
+                (map.xy_idx(pos.x, pos.y), (entity, occupant))
+            })
+            .collect();
+        // the noises of this turn; the ones made by the monsters below are heard on the next one
+        let noises = std::mem::take(&mut noise_events.events);
+        let mut new_noises : Vec<Noise> = Vec::new();
+        let mut investigations : Vec<(Entity, Option<Investigating>)> = Vec::new();
+        // where the pack leaders stand at the start of the turn
+        let leaders : HashSet<Entity> = packs.join().map(|pack| pack.leader).collect();
+        let leader_positions : HashMap<Entity, Point> = leaders.iter()
+            .filter_map(|leader| positions.get(*leader).map(|pos| (*leader, Point::new(pos.x, pos.y))))
+            .collect();
+        // who belongs to a faction, and where they stand at the start of the turn
+        let members : Vec<(Entity, Faction, Point)> = (&entities, &factions, &positions, &combat_stats).join()
+            .map(|(entity, faction, pos, _stats)| (entity, *faction, Point::new(pos.x, pos.y)))
+            .collect();
+        // infighting is only reported when the player can see it
+        let player_sees : HashSet<Point> = viewsheds.get(player.0).map_or(HashSet::new(), |viewshed| viewshed.visible_tiles.iter().copied().collect());
+
+        for (entity, _monster, door_opener, name, viewshed, pos, stats, morale, aggro, investigation, pack, faction, special) in
+            (&entities, &monsters, door_openers.maybe(), &names, (&mut viewsheds).maybe(), &mut positions, &combat_stats,
+             (&mut morales).maybe(), (&mut aggro_ranges).maybe(), investigating.maybe(), packs.maybe(), factions.maybe(), (&mut specials).maybe()).join() {
+            // the special attack recharges whatever the monster is doing, asleep included
+            let special = special.and_then(|special| if special.tick() { Some(special) } else { None });
+            let here = Point::new(pos.x, pos.y);
+            let leader_pos = pack.and_then(|pack| leader_positions.get(&pack.leader)).copied();
+            let leader_farther_than = |distance: f32| leader_pos.filter(|leader| DistanceAlg::Pythagoras.distance2d(here, *leader) > distance);
+            let heard = noises.iter()
+                .filter(|noise| noise.heard_from(here))
+                .max_by_key(|noise| noise.loudness)
+                .map(|noise| Investigating { x: noise.x, y: noise.y });
+
+            if let Some(aggro) = aggro.filter(|aggro| !aggro.awake) {
+                let in_range = DistanceAlg::Pythagoras.distance2d(here, player_pos) <= (aggro.radius - stealth) as f32;
+                if !(in_range && clear_line(&map, here, player_pos)) && heard.is_none() {
+                    continue; // still asleep
+                }
+                aggro.awake = true;
+                log.push(format!("The {} wakes up!", name.name));
+            }
+
+            let (sees_player, sight_range) = match viewshed.as_ref() {
+                Some(viewshed) => (viewshed.visible_tiles.contains(&player_pos), viewshed.range),
+                None => (clear_line(&map, here, player_pos), DIJKSTRA_MAX_DEPTH as i32),
+            };
+            let idx = map.xy_idx(pos.x, pos.y);
+            let door_tier = door_opener.map(|door_opener| door_opener.tier);
+            let (field, breaks) = match door_tier {
+                Some(DoorTier::Breaks) => (Some(smash_field), true),
+                Some(DoorTier::Opens) => (Some(door_field), false),
+                None => (None, false),
+            };
+            // a door opener hears the player through a door, as long as they are within its sight range
+            let hunting = sees_player
+                || field.is_some_and(|field| field.map[idx] <= sight_range as f32);
+            if hunting && investigation.is_some() {
+                investigations.push((entity, None)); // found what made the noise
+            }
+
+            let fleeing = match morale {
+                Some(morale) => {
+                    let hp_fraction = stats.hp as f32 / stats.max_hp.max(1) as f32;
+                    if !morale.fleeing && hp_fraction < morale.flee_threshold {
+                        morale.fleeing = true;
+                        if sees_player {
+                            log.push(format!("The {} flees!", name.name));
+                        }
+                    } else if morale.fleeing && !sees_player {
+                        // safe for now: lick its wounds, and come back once it feels brave again
+                        effects.push(Some(entity), EffectType::Heal { amount: 1 }, EffectTarget::Single(entity));
+                        let recovered = (stats.hp + 1).min(stats.max_hp) as f32 / stats.max_hp.max(1) as f32;
+                        if recovered >= (morale.flee_threshold * 2.0).min(1.0) {
+                            morale.fleeing = false;
+                        }
+                    }
+                    morale.fleeing
+                }
+                None => false,
+            };
+
+            // next to it, or farther down a clear line for a reach weapon
+            // (without diagonal steps there are no diagonal attacks either, see combat::within_reach)
+            let reach = reaches.get(entity).map_or(1, |reach| reach.range);
+            let next_to = |other: Point| crate::combat::within_reach(&map, |p| occupants.contains_key(&map.xy_idx(p.x, p.y)), here, other, reach);
+            let adjacent = next_to(player_pos);
+
+            // the closest rival in sight, unless the player is closer
+            let player_distance = if sees_player { DistanceAlg::Pythagoras.distance2d(here, player_pos) } else { f32::MAX };
+            let rival = faction.and_then(|faction| members.iter()
+                .filter(|(other, other_faction, _at)| *other != entity && faction.hostile_to(*other_faction))
+                .map(|(other, _faction, at)| (*other, *at, DistanceAlg::Pythagoras.distance2d(here, *at)))
+                .filter(|(_other, at, distance)| *distance <= sight_range as f32 && match viewshed.as_ref() {
+                    Some(viewshed) => viewshed.visible_tiles.contains(at),
+                    None => clear_line(&map, here, *at),
+                })
+                .min_by(|a, b| a.2.total_cmp(&b.2).then(a.0.id().cmp(&b.0.id()))))
+                .filter(|(_other, _at, distance)| *distance < player_distance);
+
+            let destination = if let Some((rival, rival_pos, _distance)) = rival.filter(|_| !fleeing) {
+                if next_to(rival_pos) {
+                    if let Some(rival_stats) = combat_stats.get(rival) {
+                        let damage = crate::combat::damage(stats, rival_stats);
+                        effects.push(Some(entity), EffectType::Damage { amount: damage }, EffectTarget::Single(rival));
+                        if player_sees.contains(&rival_pos) {
+                            let rival_name = names.get(rival).map_or("something", |name| name.name.as_str());
+                            log.push(format!("The {} hits the {} for {} hp.", name.name, rival_name, damage));
+                        }
+                        new_noises.push(Noise { x: rival_pos.x, y: rival_pos.y, loudness: noise::FIGHT_LOUDNESS });
+                    }
+                    None
+                } else {
+                    let path = rltk::a_star_search(idx, map.xy_idx(rival_pos.x, rival_pos.y), &*map);
+                    if path.success && path.steps.len() >= 2 { Some(path.steps[1]) } else { None }
+                }
+            } else if !hunting {
+                if fleeing {
+                    continue;
+                }
+                // nothing to chase: go and see where that noise came from
+                if heard.is_some() {
+                    investigations.push((entity, heard));
+                }
+                // or, for a stray of a pack, back to the leader
+                let goal = heard.or(investigation.copied()).map(|goal| Point::new(goal.x, goal.y))
+                    .or(leader_farther_than(PACK_RADIUS));
+                match goal {
+                    Some(goal) => {
+                        let path = rltk::a_star_search(idx, map.xy_idx(goal.x, goal.y), &*map);
+                        if !path.success || path.steps.len() < 2 {
+                            investigations.push((entity, None)); // got there (or can't): nothing to see
+                            continue;
+                        }
+                        Some(path.steps[1])
+                    }
+                    // a leader with nothing to do roams now and then, and its pack follows
+                    None if leaders.contains(&entity) && rng.roll_dice(1, 3) == 1 => {
+                        let exits = map.get_available_exits(idx);
+                        if exits.is_empty() {
+                            continue;
+                        }
+                        Some(exits[rng.range(0, exits.len() as i32) as usize].0)
+                    }
+                    None => continue,
+                }
+            } else if fleeing {
+                DijkstraMap::find_highest_exit(distance_field, idx, &*map)
+            } else if let Some(special) = special.filter(|special| sees_player && !adjacent
+                && DistanceAlg::Pythagoras.distance2d(here, player_pos) <= special.range as f32) {
+                match special.kind {
+                    SpecialAttackKind::Spit { damage, poison_turns } => {
+                        effects.push(Some(entity), EffectType::Damage { amount: damage }, EffectTarget::Single(player.0));
+                        log.push(format!("The {} spits at you for {} hp!", name.name, damage));
+                        if poison_turns > 0 {
+                            effects.push(Some(entity), EffectType::AddStatus { effect: StatusEffect::Poisoned, turns: poison_turns }, EffectTarget::Single(player.0));
+                        }
+                    }
+                    SpecialAttackKind::Web { turns } => {
+                        effects.push(Some(entity), EffectType::AddStatus { effect: StatusEffect::Entangled, turns }, EffectTarget::Single(player.0));
+                        log.push(format!("The {} shoots a web at you!", name.name));
+                    }
+                }
+                special.timer = special.cooldown;
+                None
+            } else if adjacent {
+                let damage = crate::combat::damage(stats, &player_stats);
+                effects.push(Some(entity), EffectType::Damage { amount: damage }, EffectTarget::Single(player.0));
+                log.push(format!("The {} hits you for {} hp.", name.name, damage));
+                new_noises.push(Noise { x: player_pos.x, y: player_pos.y, loudness: noise::FIGHT_LOUDNESS });
+                None
+            } else if let Some(leader) = leader_farther_than(PACK_LEASH) {
+                // too far from the pack: regroup first
+                let path = rltk::a_star_search(idx, map.xy_idx(leader.x, leader.y), &*map);
+                if path.success && path.steps.len() >= 2 { Some(path.steps[1]) } else { None }
+            } else if let Some(field) = field {
+                DijkstraMap::find_lowest_exit(field, idx, &DoorAwareMap { map: &map, breaks })
+            } else {
+                DijkstraMap::find_lowest_exit(distance_field, idx, &*map)
+            };
+
+            let destination = match destination {
+                Some(destination) => destination,
+                None => continue,
+            };
+            let bumper = Bumper::Monster { doors: door_tier, faction: faction.copied() };
+            match bump::resolve_bump(destination, &map.tiles[destination], occupants.get(&destination).copied(), bumper) {
+                // a locked door takes a few blows, each one heard from afar
+                BumpAction::OpenDoor(door) if map.tiles[door].door_state == Some(DoorState::Locked) => {
+                    let blows = match smashing.get_mut(entity) {
+                        Some(smash) if smash.idx == door => {
+                            smash.blows += 1;
+                            smash.blows
+                        }
+                        _ => {
+                            smashing.insert(entity, SmashingDoor { idx: door, blows: 1 }).expect("Unable to insert smashing door");
+                            1
+                        }
+                    };
+                    let (door_x, door_y) = (door as i32 % map.width, door as i32 / map.width);
+                    new_noises.push(Noise { x: door_x, y: door_y, loudness: noise::SMASH_LOUDNESS });
+                    if blows >= DOOR_BREAK_BLOWS && map.break_door(door) {
+                        smashing.remove(entity);
+                        doors_opened = true;
+                        log.push("You hear a door splinter and give way!".to_string());
+                    } else {
+                        log.push("You hear something pounding on a door.".to_string());
+                    }
+                }
+                // opening the door is the whole move for this turn
+                BumpAction::OpenDoor(door) => {
+                    if map.open_door(door) {
+                        doors_opened = true;
+                        log.push("You hear a door creak open.".to_string());
+                    }
+                }
+                BumpAction::Move => {
+                    if let Some(occupant) = occupants.remove(&idx) {
+                        occupants.insert(destination, occupant);
+                    }
+                    pos.x = destination as i32 % map.width;
+                    pos.y = destination as i32 / map.width;
+                    if let Some(viewshed) = viewshed {
+                        viewshed.dirty = true;
+                    }
+                }
+                // attacks were chosen above, when the target was next to it: a step never turns into one
+                // (a fleeing monster cornered against the player just stays put)
+                BumpAction::Attack(_) | BumpAction::Interact(_) | BumpAction::SwapPlaces(_) | BumpAction::Blocked => {}
+            }
+        }
+
+        for (entity, investigation) in investigations {
+            match investigation {
+                Some(investigation) => { investigating.insert(entity, investigation).expect("Unable to insert investigation"); }
+                None => { investigating.remove(entity); }
+            }
+        }
+        noise_events.events.extend(new_noises);
+
+        // an open door changes what everybody can see, and the way to the player
+        if doors_opened {
+            path_cache.invalidate();
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+    }
+}
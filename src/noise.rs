@@ -0,0 +1,37 @@
+use rltk::{DistanceAlg, Point};
+use specs::prelude::*;
+
+// NOISE ----------------------------------------------------------------------
+// Le azioni rumorose (combattere, scavare, sfondare porte) lasciano un evento di rumore
+// che il turno dei mostri consuma: chi lo sente si sveglia e va a vedere cosa succede.
+
+/// How far (in tiles) each kind of noise carries.
+pub const FIGHT_LOUDNESS : i32 = 6;
+pub const DIG_LOUDNESS : i32 = 8;
+pub const SMASH_LOUDNESS : i32 = 12;
+
+/// A sound made at (x, y) this turn.
+#[derive(Debug, Clone, Copy)]
+pub struct Noise {
+    pub x : i32,
+    pub y : i32,
+    pub loudness : i32,
+}
+
+impl Noise {
+    /// True if `listener` is within `loudness` tiles of the noise, in a straight line: walls don't muffle it.
+    pub fn heard_from(&self, listener: Point) -> bool {
+        DistanceAlg::Pythagoras.distance2d(Point::new(self.x, self.y), listener) <= self.loudness as f32
+    }
+}
+
+/// Noises made since the monsters last acted (risorsa).
+#[derive(Default)]
+pub struct NoiseEvents {
+    pub events : Vec<Noise>,
+}
+
+/// Makes a noise at (x, y) that the monsters will hear on their next turn.
+pub fn make_noise(ecs: &World, x: i32, y: i32, loudness: i32) {
+    ecs.write_resource::<NoiseEvents>().events.push(Noise { x, y, loudness });
+}
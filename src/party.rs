@@ -0,0 +1,130 @@
+use specs::prelude::*;
+use super::{Name, PlayerEntity};
+use crate::gamelog::GameLog;
+use crate::player::InputQueue;
+use crate::targeting::Targeting;
+
+// PARTY ----------------------------------------------------------------------
+// Un piccolo gruppo di personaggi (tutti con il tag Player). I comandi muovono solo il membro
+// attivo, che è sempre quello nella risorsa PlayerEntity: il resto del codice (movimento, HUD,
+// mostri che inseguono) continua a ragionare su "il giocatore" senza sapere del gruppo.
+// La mappa rivelata è condivisa: ognuno ha il suo Viewshed, e tutti scoprono le caselle che vedono.
+
+/// The party members, in switching order.
+#[derive(Default)]
+pub struct Party {
+    pub members: Vec<Entity>,
+    active: usize,
+}
+
+impl Party {
+    pub fn new(members: Vec<Entity>) -> Party {
+        Party { members, active: 0 }
+    }
+
+    /// A party with `active` in control (a resumed game); the first member if `active` isn't one of them.
+    pub fn with_active(members: Vec<Entity>, active: Entity) -> Party {
+        let active = members.iter().position(|member| *member == active).unwrap_or(0);
+        Party { members, active }
+    }
+
+    /// The member the next switch hands the controls to, None when there is nobody to switch to.
+    pub fn next_in_line(&self) -> Option<Entity> {
+        if self.members.len() < 2 {
+            return None;
+        }
+        Some(self.members[(self.active + 1) % self.members.len()])
+    }
+
+    /// Makes the next member active (wrapping around) and returns it.
+    fn next_member(&mut self) -> Option<Entity> {
+        if self.members.is_empty() {
+            return None;
+        }
+        self.active = (self.active + 1) % self.members.len();
+        Some(self.members[self.active])
+    }
+
+    /// Takes a (dead) member out of the party, keeping the same member active.
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(index) = self.members.iter().position(|member| *member == entity) {
+            self.members.remove(index);
+            if index < self.active {
+                self.active -= 1;
+            }
+            if self.active >= self.members.len() {
+                self.active = 0;
+            }
+        }
+    }
+}
+
+/// Every party member, or just the player when there's no party.
+pub fn party_members(ecs: &World) -> Vec<Entity> {
+    match ecs.try_fetch::<Party>() {
+        Some(party) if !party.members.is_empty() => party.members.clone(),
+        _ => vec![ecs.fetch::<PlayerEntity>().0],
+    }
+}
+
+/// Hands the controls to the next party member. Doesn't take a turn.
+pub fn switch_active_member(ecs: &mut World) {
+    let next = match ecs.write_resource::<Party>().next_member() {
+        Some(next) => next,
+        None => return,
+    };
+    ecs.insert(PlayerEntity(next));
+    // keys pressed for the previous member and its target don't carry over
+    ecs.write_resource::<InputQueue>().clear();
+    ecs.write_resource::<Targeting>().current_target = None;
+
+    let name = ecs.read_storage::<Name>().get(next).map(|name| name.name.clone()).unwrap_or_default();
+    ecs.write_resource::<GameLog>().push(format!("You now control {}.", name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::register_components;
+
+    fn world_with_party(names: &[&str]) -> (World, Vec<Entity>) {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(InputQueue::default());
+        ecs.insert(Targeting::default());
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 10 });
+        let members : Vec<Entity> = names.iter().map(|name| ecs.create_entity().with(Name { name: name.to_string() }).build()).collect();
+        ecs.insert(PlayerEntity(members[0]));
+        ecs.insert(Party::new(members.clone()));
+        (ecs, members)
+    }
+
+    #[test]
+    fn switching_hands_the_controls_round_the_party() {
+        let (mut ecs, members) = world_with_party(&["Ada", "Bruno", "Carla"]);
+        assert_eq!(ecs.fetch::<Party>().next_in_line(), Some(members[1]));
+
+        switch_active_member(&mut ecs);
+        assert_eq!(ecs.fetch::<PlayerEntity>().0, members[1]);
+        assert_eq!(ecs.fetch::<GameLog>().entries.back().map(String::as_str), Some("You now control Bruno."));
+        switch_active_member(&mut ecs);
+        switch_active_member(&mut ecs);
+        assert_eq!(ecs.fetch::<PlayerEntity>().0, members[0], "after the last member comes the first again");
+    }
+
+    #[test]
+    fn removing_a_member_keeps_the_same_one_in_control() {
+        let (mut ecs, members) = world_with_party(&["Ada", "Bruno", "Carla"]);
+        switch_active_member(&mut ecs);
+        switch_active_member(&mut ecs); // Carla
+
+        ecs.write_resource::<Party>().remove(members[0]);
+        switch_active_member(&mut ecs);
+        assert_eq!(ecs.fetch::<PlayerEntity>().0, members[1], "Carla hands over to Bruno, not to herself");
+
+        // the active member itself: the controls go to the first one left
+        ecs.write_resource::<Party>().remove(members[1]);
+        assert_eq!(ecs.fetch::<Party>().members, vec![members[2]]);
+        assert_eq!(ecs.fetch::<Party>().next_in_line(), None, "nobody left to switch to");
+    }
+}
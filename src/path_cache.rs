@@ -0,0 +1,114 @@
+use rltk::{BaseMap, DijkstraMap};
+use specs::prelude::*;
+use crate::map::{DoorState, Map};
+
+// PATH CACHE -----------------------------------------------------------------
+// Le mappe di Dijkstra verso il giocatore (risorsa): con trenta mostri a caccia ricalcolarle
+// a ogni turno è lavoro buttato, visto che il più delle volte né il giocatore né la mappa
+// sono cambiati. Si ricalcolano solo quando il giocatore si sposta, oppure dopo invalidate():
+// chi apre, chiude o sfonda una porta, scava o trasforma una casella deve chiamarla.
+
+/// How far (in steps) the distance fields from the player are computed: beyond this monsters don't path.
+pub const DIJKSTRA_MAX_DEPTH: f32 = 30.0;
+
+/// The map as seen by a monster with `DoorOpener`: closed doors are just another step
+/// (the monster opens them on the way); locked doors are walls, unless `breaks` is set.
+pub struct DoorAwareMap<'a> {
+    pub map: &'a Map,
+    pub breaks: bool,
+}
+
+impl BaseMap for DoorAwareMap<'_> {
+    fn is_opaque(&self, idx: usize) -> bool {
+        self.map.is_opaque(idx)
+    }
+
+    fn get_available_exits(&self, idx: usize) -> rltk::SmallVec<[(usize, f32); 10]> {
+        let breaks = self.breaks;
+        self.map.exits_where(idx, &|tile| tile.walkable || tile.door_state == Some(DoorState::Closed)
+            || (breaks && tile.door_state == Some(DoorState::Locked)))
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        self.map.get_pathing_distance(idx1, idx2)
+    }
+}
+
+/// The three distance fields from the player's tile: over walkable tiles only, through closed
+/// doors (for door openers) and through locked ones too (for the monsters that break them).
+pub struct PlayerFields {
+    pub walking : DijkstraMap,
+    pub doors : DijkstraMap,
+    pub smashing : DijkstraMap,
+}
+
+/// The player's distance fields, kept from one turn to the next (risorsa).
+#[derive(Default)]
+pub struct PathCache {
+    target : Option<usize>,
+    fields : Option<PlayerFields>,
+    computed : u32, // how many times the fields were built, for measuring
+}
+
+impl PathCache {
+    /// Forgets the fields: the next request builds them again. Call it whenever the map changes.
+    pub fn invalidate(&mut self) {
+        self.fields = None;
+    }
+
+    /// The fields towards `target`, built now only if the player moved or the map changed since last time.
+    pub fn fields(&mut self, map: &Map, target: usize) -> &PlayerFields {
+        if self.target != Some(target) {
+            self.fields = None;
+        }
+        if self.fields.is_none() {
+            self.target = Some(target);
+            self.computed += 1;
+        }
+        self.fields.get_or_insert_with(|| PlayerFields {
+            walking: DijkstraMap::new(map.width, map.height, &[target], map, DIJKSTRA_MAX_DEPTH),
+            doors: DijkstraMap::new(map.width, map.height, &[target], &DoorAwareMap { map, breaks: false }, DIJKSTRA_MAX_DEPTH),
+            smashing: DijkstraMap::new(map.width, map.height, &[target], &DoorAwareMap { map, breaks: true }, DIJKSTRA_MAX_DEPTH),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn computed(&self) -> u32 {
+        self.computed
+    }
+}
+
+/// Invalidates the world's PathCache, if it has one (tests often don't).
+pub fn invalidate(ecs: &World) {
+    if let Some(mut cache) = ecs.try_fetch_mut::<PathCache>() {
+        cache.invalidate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Biome, Building, xy_idx};
+
+    #[test]
+    fn the_fields_are_rebuilt_only_when_the_player_moves_or_the_map_changes() {
+        let (width, height) = (80, 10);
+        let mut map = Map::new(width, height, Building.get_floor());
+        map.tiles[xy_idx(5, 5, width)] = Building.get_wall();
+        let mut cache = PathCache::default();
+
+        for _turn in 0..3 {
+            cache.fields(&map, xy_idx(2, 2, width));
+        }
+        assert_eq!(cache.computed(), 1);
+
+        cache.fields(&map, xy_idx(3, 2, width));
+        assert_eq!(cache.computed(), 2);
+
+        map.tiles[xy_idx(5, 5, width)] = Building.get_floor();
+        cache.invalidate();
+        let distance = cache.fields(&map, xy_idx(3, 2, width)).walking.map[xy_idx(5, 5, width)];
+        assert_eq!(cache.computed(), 3);
+        assert!(distance < DIJKSTRA_MAX_DEPTH, "the cleared tile is still out of reach");
+    }
+}
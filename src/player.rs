@@ -1,20 +1,89 @@
-use specs::prelude::*;
+use std::collections::VecDeque;
+use rltk::VirtualKeyCode;
 use specs_derive::Component;
 use crate::components::Player;
+use crate::config::GameConfig;
+use crate::direction::Direction;
 
 
 
 // these are the actions that the player can take
 #[derive(PartialEq, Copy, Clone)]
 pub enum PlayerAction {
-    Move { dx: i32, dy: i32 },
+    Move(Direction),
+    OpenBestiary,
+    Rest,
+    CycleTarget,
+    Fire,
+    Look,
+    OpenDoors,
+    Dig,
+    SwitchMember,
+    ShowLog,
+    Disarm,
+    PickUp,
+    Settings,
+    CycleHeatmap,
+    SetTrap,
+    Zap,
+    TravelToPortal,
+    Throw,
+    CycleStance,
+    Peek(Direction),
+    ToggleBreadcrumbs,
+    ShowLegend,
+    DebugTeleport,
+    DropItems,
+    CopySetup,
 }
 
-// these are the implied actions that the player wants to take when moving against an object
-// will be useful also for auto attack when the player moves against an enemy
-#[derive(PartialEq, Copy, Clone)]
-pub enum PlayerIntent {
-    Move,
-    OpenDoor(usize),
-    DoNothing,
+/// What carrying out an action costs: a turn, after which the monsters get theirs,
+/// or nothing at all (menus, looking around, switching views).
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ActionCost {
+    Free,
+    Turn,
+}
+
+impl PlayerAction {
+    /// The cost of the action once it is carried out: an action that doesn't happen (no target,
+    /// nothing to pick up) never costs anything. Moves cost what their bump does (see
+    /// BumpAction::cost). Dig, Zap and Throw only open their aiming screen, which spends the
+    /// turn when the action goes through; Rest and TravelToPortal run their own turns.
+    pub fn cost(&self, config: &GameConfig) -> ActionCost {
+        match self {
+            PlayerAction::Move(_) | PlayerAction::Fire | PlayerAction::OpenDoors | PlayerAction::Disarm
+                | PlayerAction::PickUp | PlayerAction::SetTrap => ActionCost::Turn,
+            PlayerAction::CycleStance if config.stance_change_takes_turn => ActionCost::Turn,
+            _ => ActionCost::Free,
+        }
+    }
+}
+
+// coda dei tasti premuti: rltk ci dà un solo tasto per frame, così nessuno va perso
+// se arrivano più velocemente di quanto il gioco li consumi (un'azione per turno)
+// insieme al tasto teniamo se shift era premuto (shift + direzione = sbirciare)
+#[derive(Default)]
+pub struct InputQueue {
+    keys: VecDeque<(VirtualKeyCode, bool)>,
+}
+
+impl InputQueue {
+    /// How many presses we keep: enough for a quick burst of steps, short enough that
+    /// holding a key down doesn't keep the player walking long after it's released.
+    const CAPACITY: usize = 4;
+
+    pub fn push(&mut self, key: VirtualKeyCode, shift: bool) {
+        if self.keys.len() < Self::CAPACITY {
+            self.keys.push_back((key, shift));
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<(VirtualKeyCode, bool)> {
+        self.keys.pop_front()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
 }
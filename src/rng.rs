@@ -0,0 +1,89 @@
+use rltk::RandomNumberGenerator;
+
+// RNG ------------------------------------------------------------------------
+// Un solo generatore per tutta la partita (risorsa), invece di un RandomNumberGenerator::new()
+// a ogni tiro: generazione delle mappe, combattimento, spawn e percezione pescano tutti da qui,
+// così dato il seme la partita è riproducibile.
+// Lo stato interno di rltk non si può leggere, quindi salviamo il seme e quanti numeri
+// sono stati estratti; al caricamento si riparte dal seme e si scartano altrettanti numeri.
+// Ogni tiro consuma esattamente un numero del generatore, così il conto torna sempre.
+
+/// The game's random stream (risorsa).
+pub struct GameRng {
+    seed : u64,
+    draws : u64,
+    rng : RandomNumberGenerator,
+}
+
+impl GameRng {
+    pub fn seeded(seed: u64) -> GameRng {
+        GameRng { seed, draws: 0, rng: RandomNumberGenerator::seeded(seed) }
+    }
+
+    /// A stream with a random seed.
+    pub fn new() -> GameRng {
+        GameRng::seeded(RandomNumberGenerator::new().next_u64())
+    }
+
+    /// The stream `seed` after `draws` numbers have been taken from it: it goes on exactly
+    /// where a saved game left off.
+    pub fn restore(seed: u64, draws: u64) -> GameRng {
+        let mut rng = GameRng::seeded(seed);
+        for _ in 0..draws {
+            rng.next();
+        }
+        rng
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// How many numbers have been taken from the stream so far.
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    fn next(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+
+    /// A number in [min, max); `min` if the range is empty.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        if max <= min {
+            return min;
+        }
+        min + (self.next() % (max - min) as u64) as i32
+    }
+
+    /// The sum of `n` dice with `die_type` faces.
+    pub fn roll_dice(&mut self, n: i32, die_type: i32) -> i32 {
+        (0..n).map(|_| self.range(1, die_type + 1)).sum()
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> GameRng {
+        GameRng::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_restored_stream_rolls_like_the_uninterrupted_one() {
+        let mut uninterrupted = GameRng::seeded(42);
+        for _ in 0..10 {
+            uninterrupted.roll_dice(3, 6);
+        }
+        let mut restored = GameRng::restore(uninterrupted.seed(), uninterrupted.draws());
+
+        for _ in 0..20 {
+            assert_eq!(restored.roll_dice(1, 20), uninterrupted.roll_dice(1, 20));
+        }
+        assert_eq!(restored.draws(), uninterrupted.draws());
+    }
+}
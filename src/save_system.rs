@@ -0,0 +1,257 @@
+use std::fs;
+use std::io;
+use rltk::{FontCharType, RGB};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use super::{CurrentLevel, InBackpack, PlayerEntity, Position, Wallet};
+use crate::config::GameConfig;
+use crate::difficulty::Difficulty;
+use crate::gamelog::GameLog;
+use crate::level_store::{self, EntitySnapshot, LevelStore};
+use crate::map::Map;
+use crate::party::{self, Party};
+use crate::rng::GameRng;
+
+// SAVE ----------------------------------------------------------------------
+// Salvataggio su file JSON: il livello in corso (mappa, CurrentLevel e tutte le entità, compreso
+// il gruppo con i suoi zaini), i livelli congelati del LevelStore, l'oro, la difficoltà e lo stato
+// del GameRng. Le entità vengono fotografate come fa il LevelStore (level_store::snapshot_entities),
+// e il giocatore e il gruppo si ricordano come indici in quella lista.
+
+/// Everything a saved game needs to go on from where it was.
+#[derive(Serialize, Deserialize)]
+struct SaveGame {
+    level: CurrentLevel,
+    map: Map,
+    entities: Vec<EntitySnapshot>, // what is on the current level, plus the backpacks
+    player: usize,                 // index into entities of the active party member
+    party: Vec<usize>,             // the party members, as indices into entities
+    gold: i32,
+    difficulty: Difficulty,
+    levels: LevelStore,
+    rng_seed: u64,
+    rng_draws: u64,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Writes the whole game to `path`.
+pub fn save_game(ecs: &World, path: &str) -> io::Result<()> {
+    let entities : Vec<Entity> = {
+        let all_entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let backpacks = ecs.read_storage::<InBackpack>();
+        (&all_entities).join().filter(|entity| positions.contains(*entity) || backpacks.contains(*entity)).collect()
+    };
+    let index_of = |target: Entity| entities.iter().position(|entity| *entity == target);
+    let player = index_of(ecs.fetch::<PlayerEntity>().0).ok_or_else(|| invalid_data("the player is not on the map".to_string()))?;
+    let rng = ecs.fetch::<GameRng>();
+
+    let save = SaveGame {
+        level: *ecs.fetch::<CurrentLevel>(),
+        map: (*ecs.fetch::<Map>()).clone(),
+        entities: level_store::snapshot_entities(ecs, &entities),
+        player,
+        party: party::party_members(ecs).into_iter().filter_map(index_of).collect(),
+        gold: ecs.fetch::<Wallet>().gold,
+        difficulty: *ecs.fetch::<Difficulty>(),
+        levels: (*ecs.fetch::<LevelStore>()).clone(),
+        rng_seed: rng.seed(),
+        rng_draws: rng.draws(),
+    };
+    fs::write(path, serde_json::to_string(&save)?)
+}
+
+/// Replaces the game in `ecs` with the one saved in `path`. The save is checked before the
+/// world is touched, so on an error the game in `ecs` is still there as it was.
+pub fn load_game(ecs: &mut World, path: &str) -> io::Result<()> {
+    let save : SaveGame = serde_json::from_str(&fs::read_to_string(path)?)?;
+    save.map.validate().map_err(invalid_data)?;
+    let count = save.entities.len();
+    if save.player >= count || save.party.iter().any(|member| *member >= count) {
+        return Err(invalid_data(format!("the party is not among the {} saved entities", count)));
+    }
+    let rng = load_rng(path)?;
+
+    ecs.delete_all();
+    ecs.maintain();
+    let entities = level_store::restore_entities(ecs, save.entities);
+    let player = entities[save.player];
+    ecs.insert(PlayerEntity(player));
+    ecs.insert(Party::with_active(save.party.iter().map(|member| entities[*member]).collect(), player));
+    ecs.insert(save.map);
+    ecs.insert(save.level);
+    ecs.insert(save.levels);
+    ecs.insert(Wallet { gold: save.gold });
+    ecs.insert(save.difficulty);
+    if let Some(rng) = rng {
+        ecs.insert(rng);
+    }
+    crate::path_cache::invalidate(ecs);
+    Ok(())
+}
+
+/// The random stream saved in `path`, continuing exactly where it was when the game was saved.
+/// None if the save has no (valid) rng state.
+pub fn load_rng(path: &str) -> io::Result<Option<GameRng>> {
+    let save : serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    Ok(match (save["rng_seed"].as_u64(), save["rng_draws"].as_u64()) {
+        (Some(seed), Some(draws)) => Some(GameRng::restore(seed, draws)),
+        _ => None,
+    })
+}
+
+/// Called on every level change: saves if `autosave` is on in GameConfig.
+/// A failed write is only logged, losing the autosave is no reason to crash the game.
+pub fn autosave(ecs: &mut World) {
+    let path = {
+        let config = ecs.fetch::<GameConfig>();
+        if !config.autosave {
+            return;
+        }
+        config.save_path.clone()
+    };
+
+    let message = match save_game(ecs, &path) {
+        Ok(()) => "Game saved.".to_string(),
+        Err(e) => format!("Could not save the game: {}", e),
+    };
+    ecs.write_resource::<GameLog>().push(message);
+}
+
+// SERDE WRAPPERS -------------------------------------------------------------
+// RGB e FontCharType sono di rltk, Serialize e Deserialize di serde: non possiamo implementare
+// l'uno per l'altro (orphan rule). Li facciamo passare da questi due tipi nostri, e i campi
+// di Tile e Renderable li usano con #[serde(with = "crate::save_system::ser_rgb")] e simili.
+
+/// A colour as serde sees it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SerRgb {
+    pub r : f32,
+    pub g : f32,
+    pub b : f32,
+}
+
+impl From<RGB> for SerRgb {
+    fn from(rgb: RGB) -> Self {
+        SerRgb { r: rgb.r, g: rgb.g, b: rgb.b }
+    }
+}
+
+impl From<SerRgb> for RGB {
+    fn from(rgb: SerRgb) -> Self {
+        RGB::from_f32(rgb.r, rgb.g, rgb.b)
+    }
+}
+
+/// A glyph (code page 437 index) as serde sees it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SerGlyph(pub u16);
+
+impl From<FontCharType> for SerGlyph {
+    fn from(glyph: FontCharType) -> Self {
+        SerGlyph(glyph)
+    }
+}
+
+impl From<SerGlyph> for FontCharType {
+    fn from(glyph: SerGlyph) -> Self {
+        glyph.0
+    }
+}
+
+/// `#[serde(with)]` module for RGB fields.
+pub mod ser_rgb {
+    use rltk::RGB;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::SerRgb;
+
+    pub fn serialize<S: Serializer>(rgb: &RGB, serializer: S) -> Result<S::Ok, S::Error> {
+        SerRgb::from(*rgb).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RGB, D::Error> {
+        SerRgb::deserialize(deserializer).map(RGB::from)
+    }
+}
+
+/// `#[serde(with)]` module for FontCharType fields.
+pub mod ser_glyph {
+    use rltk::FontCharType;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::SerGlyph;
+
+    pub fn serialize<S: Serializer>(glyph: &FontCharType, serializer: S) -> Result<S::Ok, S::Error> {
+        SerGlyph::from(*glyph).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FontCharType, D::Error> {
+        SerGlyph::deserialize(deserializer).map(FontCharType::from)
+    }
+}
+
+/// `#[serde(with)]` module for the frames of an Animation.
+pub mod ser_frames {
+    use rltk::{FontCharType, RGB};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use super::{SerGlyph, SerRgb};
+
+    pub fn serialize<S: Serializer>(frames: &[(FontCharType, RGB)], serializer: S) -> Result<S::Ok, S::Error> {
+        let frames : Vec<(SerGlyph, SerRgb)> = frames.iter().map(|(glyph, rgb)| (SerGlyph::from(*glyph), SerRgb::from(*rgb))).collect();
+        frames.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(FontCharType, RGB)>, D::Error> {
+        let frames = Vec::<(SerGlyph, SerRgb)>::deserialize(deserializer)?;
+        Ok(frames.into_iter().map(|(glyph, rgb)| (FontCharType::from(glyph), RGB::from(rgb))).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::register_components;
+    use crate::map::{Biome, BiomeKind, Building};
+
+    #[test]
+    fn a_reloaded_game_rolls_like_the_uninterrupted_one() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        let player = ecs.create_entity().with(Position { x: 1, y: 1 }).build();
+        ecs.insert(PlayerEntity(player));
+        ecs.insert(Map::new(10, 10, Building.get_floor()));
+        ecs.insert(CurrentLevel { depth: 1, biome: BiomeKind::Building, seed: Some(7) });
+        ecs.insert(LevelStore::default());
+        ecs.insert(Difficulty::default());
+        ecs.insert(Wallet::default());
+        ecs.insert(GameRng::seeded(7));
+        for _ in 0..5 {
+            ecs.write_resource::<GameRng>().roll_dice(1, 20);
+        }
+
+        let path = std::env::temp_dir().join("rogue_rng_save_test.json");
+        let path = path.to_str().unwrap();
+        save_game(&ecs, path).unwrap();
+        let mut reloaded = World::new();
+        register_components(&mut reloaded);
+        reloaded.insert(GameRng::seeded(99));
+        load_game(&mut reloaded, path).unwrap();
+        fs::remove_file(path).ok();
+
+        let mut reloaded_rng = reloaded.write_resource::<GameRng>();
+        let mut uninterrupted = ecs.write_resource::<GameRng>();
+        for _ in 0..10 {
+            assert_eq!(reloaded_rng.roll_dice(2, 6), uninterrupted.roll_dice(2, 6));
+        }
+    }
+
+    #[test]
+    fn colours_and_glyphs_survive_the_wrappers() {
+        let orange = RGB::named(rltk::ORANGE);
+        assert_eq!(RGB::from(SerRgb::from(orange)), orange);
+        let glyph = rltk::to_cp437('@');
+        assert_eq!(FontCharType::from(SerGlyph::from(glyph)), glyph);
+    }
+}
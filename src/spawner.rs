@@ -0,0 +1,629 @@
+use rltk::RGB;
+use specs::prelude::*;
+use super::{AggroRange, Ammo, AmmoKind, Animation, AutoPickup, Barricade, CanMove, CharacterSize, Charges, CombatStats, DeathEffect, DeployableTrap, DiggingBeam, DoorOpener, DoorTier, DropsLoot, Energy, Faction, Food, FovShape, Gold, GrantsSeeInvisible, Hidden, Hunger, HungerState, Immunities, Item, Key, LeavesCorpse, LightSource, Monster, Morale, Name, PackMember, Perception, Pickaxe, Player, Position, RangedWeapon, Reach, Renderable, SpecialAttack, SpecialAttackKind, Stance, Summoned, SummonsAlly, ThrowableLight, Viewshed};
+use crate::map::{DoorState, Map, StatusEffect, Tile, xy_idx};
+use crate::map_builders;
+use crate::difficulty::Difficulty;
+use crate::rng::GameRng;
+
+// SPAWNER ------------------------------------------------------------------
+// Qui vengono creati i mostri e gli oggetti. Per ora c'è una tabella molto semplice di due tipi.
+
+/// Creates the player and returns its Entity (stored by main as the PlayerEntity resource).
+pub fn player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
+    party_member(ecs, player_x, player_y, "Player", RGB::named(rltk::YELLOW), 0)
+}
+
+/// Creates a second character for the party (see party.rs): sharper eyes, better at spotting traps.
+pub fn companion(ecs: &mut World, x: i32, y: i32) -> Entity {
+    party_member(ecs, x, y, "Companion", RGB::named(rltk::LIGHT_BLUE), 4)
+}
+
+/// A character the player can control: same stats for everybody but perception, name and colour.
+fn party_member(ecs: &mut World, player_x: i32, player_y: i32, name: &str, fg: RGB, perception: i32) -> Entity {
+    ecs.create_entity()
+        .with(Position { x: player_x, y: player_y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('@'),
+            fg,
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Player{}) //identifica il player
+        .with(CanMove{}) // permette al player di muovere
+        .with(CharacterSize::Medium) // definisce la taglia del player
+        .with(Name { name: name.to_string() })
+        .with(CombatStats { max_hp: 30, hp: 30, defense: 2, power: 5 })
+        .with(Energy { current: 0 })
+        .with(Pickaxe {})
+        .with(Perception { bonus: perception })
+        .with(Hunger { satiation: crate::hunger_system::MAX_SATIATION, state: HungerState::WellFed })
+        .with(Stance::default())
+        .with(RangedWeapon { ammo: AmmoKind::Arrows }) // un arco corto
+        .with(Ammo { kind: AmmoKind::Arrows, count: STARTING_ARROWS })
+        // The player's viewshed is initially dirty so it's calculated on the first turn.
+        .with(Viewshed { visible_tiles: Vec::new(), range: 8, dirty: true, fov_shape: FovShape::Circle }) // definisce il campo visivo del player
+        .build()
+}
+
+/// Arrows in the quiver of a new party member.
+const STARTING_ARROWS: i32 = 10;
+
+/// Monsters never spawn closer than this (Chebyshev distance) to the player's start,
+/// so the first turn isn't spent already in a fight.
+pub const SAFE_SPAWN_DISTANCE: i32 = 6;
+
+/// How far apart two tiles are counting diagonal steps as one (Chebyshev distance).
+fn chebyshev(a: (i32, i32), b: (i32, i32)) -> i32 {
+    i32::max((a.0 - b.0).abs(), (a.1 - b.1).abs())
+}
+
+/// Picks up to `count` distinct random walkable tiles at least `min_distance` tiles
+/// from the player's start (1 only keeps the start tile itself free).
+fn random_spawn_points(ecs: &World, count: i32, player_start: (i32, i32), min_distance: i32) -> Vec<(i32, i32)> {
+    let mut spawn_points : Vec<(i32, i32)> = Vec::new();
+    let map = ecs.fetch::<Map>();
+    let mut rng = ecs.write_resource::<GameRng>();
+    let mut tries = 0;
+    while (spawn_points.len() as i32) < count && tries < 1000 {
+        tries += 1;
+        let x = rng.roll_dice(1, map.width - 2);
+        let y = rng.roll_dice(1, map.height - 2);
+        if chebyshev((x, y), player_start) < min_distance || spawn_points.contains(&(x, y)) {
+            continue;
+        }
+        if map.tiles[map.xy_idx(x, y)].walkable {
+            spawn_points.push((x, y));
+        }
+    }
+    spawn_points
+}
+
+/// A camp in every room of the map but the first (the player's): `size` monsters
+/// around its centre. Open maps only have rooms when the generator carved clearings.
+/// A camp member that would land too close to the player's start is left out.
+pub fn spawn_camps(ecs: &mut World, size: i32, player_start: (i32, i32)) {
+    let centres : Vec<(i32, i32)> = ecs.fetch::<Map>().rooms.iter().skip(1).map(|room| room.center()).collect();
+    for (x, y) in centres {
+        for _ in 0..size {
+            let spot = find_valid_spawn(ecs, x, y, 2).filter(|spot| chebyshev(*spot, player_start) >= SAFE_SPAWN_DISTANCE);
+            if let Some((mx, my)) = spot {
+                random_monster(ecs, mx, my);
+            }
+        }
+    }
+}
+
+/// Gives each room a brazier, with `chance` in 100, in the first free corner tile of the room
+/// (a room whose corners are all taken goes without).
+pub fn spawn_braziers(ecs: &mut World, chance: i32) {
+    if chance <= 0 {
+        return;
+    }
+    let rooms = ecs.fetch::<Map>().rooms.clone();
+    for room in rooms {
+        if ecs.write_resource::<GameRng>().roll_dice(1, 100) > chance {
+            continue;
+        }
+        let corners = [(room.x1 + 1, room.y1 + 1), (room.x2 - 1, room.y1 + 1), (room.x1 + 1, room.y2 - 1), (room.x2 - 1, room.y2 - 1)];
+        let free = {
+            let map = ecs.fetch::<Map>();
+            let positions = ecs.read_storage::<Position>();
+            corners.into_iter().find(|(x, y)| {
+                map.tiles[map.xy_idx(*x, *y)].walkable && !positions.join().any(|pos| pos.x == *x && pos.y == *y)
+            })
+        };
+        if let Some((x, y)) = free {
+            brazier(ecs, x, y);
+        }
+    }
+}
+
+// il braciere: una luce che non si spegne mai e tremola (vedi Animation)
+fn brazier(ecs: &mut World, x: i32, y: i32) {
+    let glyph = rltk::to_cp437('☼');
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable { glyph, fg: RGB::named(rltk::ORANGE), bg: RGB::named(rltk::BLACK) })
+        .with(Animation {
+            frames: vec![(glyph, RGB::named(rltk::ORANGE)), (glyph, RGB::named(rltk::YELLOW)), (glyph, RGB::named(rltk::ORANGE_RED))],
+            period_ms: 180.0,
+        })
+        .with(Name { name: "Brazier".to_string() })
+        .with(LightSource { turns_left: None })
+        .with(Viewshed { visible_tiles: Vec::new(), range: 4, dirty: true, fov_shape: FovShape::Circle })
+        .build();
+}
+
+/// Places `count` wolf packs (see `wolf_pack`) on walkable tiles away from the player's start:
+/// the leader stands a bit further out, since the followers gather within 2 tiles of it.
+pub fn spawn_packs(ecs: &mut World, count: i32, player_start: (i32, i32)) {
+    let spawn_points = random_spawn_points(ecs, count, player_start, SAFE_SPAWN_DISTANCE + 2);
+
+    for (x, y) in spawn_points {
+        let size = ecs.write_resource::<GameRng>().roll_dice(1, 3) + 2;
+        wolf_pack(ecs, x, y, size);
+    }
+}
+
+/// `size` wolves around (x, y): the first one leads the pack, the others follow it.
+pub fn wolf_pack(ecs: &mut World, x: i32, y: i32, size: i32) {
+    let leader = wolf(ecs, x, y);
+    for _ in 1..size {
+        if let Some((wx, wy)) = find_valid_spawn(ecs, x, y, 2) {
+            let follower = wolf(ecs, wx, wy);
+            ecs.write_storage::<PackMember>().insert(follower, PackMember { leader }).expect("Unable to insert pack member");
+        }
+    }
+}
+
+/// Places `count` random monsters on walkable tiles, at least SAFE_SPAWN_DISTANCE from the player's start.
+pub fn spawn_monsters(ecs: &mut World, count: i32, player_start: (i32, i32)) {
+    let spawn_points = random_spawn_points(ecs, count, player_start, SAFE_SPAWN_DISTANCE);
+
+    for (x, y) in spawn_points {
+        random_monster(ecs, x, y);
+    }
+}
+
+/// Spawns one monster picked at random from the table.
+pub fn random_monster(ecs: &mut World, x: i32, y: i32) {
+    let roll = ecs.write_resource::<GameRng>().roll_dice(1, 10);
+    match roll {
+        1 | 2 => orc(ecs, x, y),
+        3 | 4 => goblin(ecs, x, y),
+        5 => ghost(ecs, x, y),
+        6 => bomber(ecs, x, y),
+        7 => fire_elemental(ecs, x, y),
+        8 => spider(ecs, x, y),
+        9 => cobra(ecs, x, y),
+        _ => ogre(ecs, x, y),
+    }
+}
+
+/// Finds the walkable, unoccupied tile closest to (x, y) within `radius` tiles (the centre excluded).
+pub fn find_valid_spawn(ecs: &World, x: i32, y: i32, radius: i32) -> Option<(i32, i32)> {
+    let map = ecs.fetch::<Map>();
+    let positions = ecs.read_storage::<Position>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let occupied = |tx: i32, ty: i32| (&positions, &combat_stats).join().any(|(pos, _stats)| pos.x == tx && pos.y == ty);
+
+    for r in 1..=radius {
+        for ty in y - r..=y + r {
+            for tx in x - r..=x + r {
+                // only the ring at distance r, inner rings were already checked
+                if (tx - x).abs() != r && (ty - y).abs() != r {
+                    continue;
+                }
+                if tx < 0 || tx >= map.width || ty < 0 || ty >= map.height {
+                    continue;
+                }
+                if map.tiles[map.xy_idx(tx, ty)].walkable && !occupied(tx, ty) {
+                    return Some((tx, ty));
+                }
+            }
+        }
+    }
+    None
+}
+
+// gli orchi combattono quasi fino alla morte e sanno aprire le porte, i goblin scappano appena feriti
+fn orc(ecs: &mut World, x: i32, y: i32) {
+    let orc = monster(ecs, x, y, rltk::to_cp437('o'), "Orc", CombatStats { max_hp: 16, hp: 16, defense: 1, power: 4 }, 0.2);
+    ecs.write_storage::<DoorOpener>().insert(orc, DoorOpener { tier: DoorTier::Opens }).expect("Unable to insert door opener");
+    ecs.write_storage::<Faction>().insert(orc, Faction::Greenskins).expect("Unable to insert faction");
+}
+
+// gli ogri sono lenti di testa ma fortissimi: una porta chiusa a chiave li ferma solo per un paio di turni
+fn ogre(ecs: &mut World, x: i32, y: i32) {
+    let ogre = monster(ecs, x, y, rltk::to_cp437('O'), "Ogre", CombatStats { max_hp: 24, hp: 24, defense: 2, power: 6 }, 0.1);
+    ecs.write_storage::<DoorOpener>().insert(ogre, DoorOpener { tier: DoorTier::Breaks }).expect("Unable to insert door opener");
+    ecs.write_storage::<Faction>().insert(ogre, Faction::Greenskins).expect("Unable to insert faction");
+    // l'ogre guarda tutto intorno ma non lontano: un campo visivo largo e basso
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(ogre) {
+        viewshed.fov_shape = FovShape::Ellipse { radius_x: 8, radius_y: 4 };
+    }
+}
+
+// i goblin dormono: niente campo visivo, si svegliano quando il giocatore passa a meno di 4 caselle
+fn goblin(ecs: &mut World, x: i32, y: i32) {
+    let goblin = monster(ecs, x, y, rltk::to_cp437('g'), "Goblin", CombatStats { max_hp: 8, hp: 8, defense: 0, power: 3 }, 0.5);
+    ecs.write_storage::<Viewshed>().remove(goblin);
+    ecs.write_storage::<AggroRange>().insert(goblin, AggroRange { radius: 4, awake: false }).expect("Unable to insert aggro range");
+    ecs.write_storage::<Faction>().insert(goblin, Faction::Greenskins).expect("Unable to insert faction");
+}
+
+// i fantasmi sono invisibili: senza SeeInvisible non si vedono e non si possono mirare
+fn ghost(ecs: &mut World, x: i32, y: i32) {
+    let ghost = monster(ecs, x, y, rltk::to_cp437('G'), "Ghost", CombatStats { max_hp: 6, hp: 6, defense: 0, power: 3 }, 0.0);
+    ecs.write_storage::<Hidden>().insert(ghost, Hidden {}).expect("Unable to insert hidden");
+    ecs.write_storage::<Faction>().insert(ghost, Faction::Undead).expect("Unable to insert faction");
+}
+
+// i lupi girano in branco (vedi wolf_pack) e scappano presto, tanto ci pensano gli altri
+fn wolf(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let wolf = monster(ecs, x, y, rltk::to_cp437('w'), "Wolf", CombatStats { max_hp: 7, hp: 7, defense: 0, power: 3 }, 0.3);
+    ecs.write_storage::<Faction>().insert(wolf, Faction::Beasts).expect("Unable to insert faction");
+    ecs.write_storage::<LeavesCorpse>().insert(wolf, LeavesCorpse { nutrition: 400 }).expect("Unable to insert corpse");
+    wolf
+}
+
+// i bombaroli sono fragili, ma quando muoiono esplodono: meglio finirli da lontano
+fn bomber(ecs: &mut World, x: i32, y: i32) {
+    let bomber = monster(ecs, x, y, rltk::to_cp437('b'), "Bomber", CombatStats { max_hp: 6, hp: 6, defense: 0, power: 2 }, 0.0);
+    ecs.write_storage::<DeathEffect>().insert(bomber, DeathEffect::Explode { radius: 1, damage: 8 }).expect("Unable to insert death effect");
+}
+
+// gli elementali del fuoco camminano nella lava: bruciare non li tocca
+fn fire_elemental(ecs: &mut World, x: i32, y: i32) {
+    let elemental = monster(ecs, x, y, rltk::to_cp437('E'), "Fire Elemental", CombatStats { max_hp: 12, hp: 12, defense: 1, power: 4 }, 0.0);
+    ecs.write_storage::<Renderable>().insert(elemental, Renderable { glyph: rltk::to_cp437('E'), fg: RGB::named(rltk::ORANGE), bg: RGB::named(rltk::BLACK) }).expect("Unable to insert renderable");
+    ecs.write_storage::<Immunities>().insert(elemental, Immunities { effects: vec![StatusEffect::Burning] }).expect("Unable to insert immunities");
+}
+
+// i ragni tessono da lontano: una ragnatela ogni tanto, e nel frattempo mordono
+fn spider(ecs: &mut World, x: i32, y: i32) {
+    let spider = monster(ecs, x, y, rltk::to_cp437('s'), "Spider", CombatStats { max_hp: 8, hp: 8, defense: 0, power: 3 }, 0.3);
+    let web = SpecialAttack { kind: SpecialAttackKind::Web { turns: 3 }, range: 5, cooldown: 8, timer: 0 };
+    ecs.write_storage::<SpecialAttack>().insert(spider, web).expect("Unable to insert special attack");
+    ecs.write_storage::<Faction>().insert(spider, Faction::Beasts).expect("Unable to insert faction");
+    ecs.write_storage::<LeavesCorpse>().insert(spider, LeavesCorpse { nutrition: 150 }).expect("Unable to insert corpse");
+}
+
+// i cobra sputano veleno da lontano prima di venire a mordere: il colpo ferisce poco, il veleno continua
+fn cobra(ecs: &mut World, x: i32, y: i32) {
+    let cobra = monster(ecs, x, y, rltk::to_cp437('c'), "Cobra", CombatStats { max_hp: 6, hp: 6, defense: 0, power: 2 }, 0.4);
+    let spit = SpecialAttack { kind: SpecialAttackKind::Spit { damage: 2, poison_turns: 4 }, range: 4, cooldown: 6, timer: 0 };
+    ecs.write_storage::<SpecialAttack>().insert(cobra, spit).expect("Unable to insert special attack");
+    ecs.write_storage::<Faction>().insert(cobra, Faction::Beasts).expect("Unable to insert faction");
+}
+
+fn monster<S : ToString>(ecs: &mut World, x: i32, y: i32, glyph: rltk::FontCharType, name: S, stats: CombatStats, flee_threshold: f32) -> Entity {
+    // i punti vita dipendono dalla difficoltà scelta
+    let hp = ecs.fetch::<Difficulty>().monster_hp(stats.max_hp);
+    let stats = CombatStats { max_hp: hp, hp, ..stats };
+    // la tabella del bottino porta il nome del mostro, in minuscolo (vedi loot.rs)
+    let name = name.to_string();
+    let loot_table = name.to_lowercase();
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph,
+            fg: RGB::named(rltk::RED),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Monster{})
+        .with(Name { name })
+        .with(stats)
+        .with(Viewshed { visible_tiles: Vec::new(), range: 8, dirty: true, fov_shape: FovShape::Circle })
+        .with(Morale { flee_threshold, fleeing: false })
+        .with(DropsLoot { table: loot_table })
+        .build()
+}
+
+/// Scatters `count` piles of gold, each worth 1d20 coins.
+pub fn spawn_gold(ecs: &mut World, count: i32, player_start: (i32, i32)) {
+    let spawn_points = random_spawn_points(ecs, count, player_start, 1);
+
+    for (x, y) in spawn_points {
+        let amount = ecs.write_resource::<GameRng>().roll_dice(1, 20);
+        gold_pile(ecs, x, y, amount);
+    }
+}
+
+fn gold_pile(ecs: &mut World, x: i32, y: i32, amount: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('$'),
+            fg: RGB::named(rltk::GOLD),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Gold".to_string() })
+        .with(Gold { amount })
+        .with(AutoPickup {})
+        .build();
+}
+
+fn health_potion(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('!'),
+            fg: RGB::named(rltk::MAGENTA),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Health Potion".to_string() })
+        .with(Item {})
+        .build();
+}
+
+/// Scatters `count` rations, so that a player who explores doesn't starve.
+pub fn spawn_food(ecs: &mut World, count: i32, player_start: (i32, i32)) {
+    let spawn_points = random_spawn_points(ecs, count, player_start, 1);
+
+    for (x, y) in spawn_points {
+        ration(ecs, x, y);
+    }
+}
+
+fn ration(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('%'),
+            fg: RGB::named(rltk::GREEN),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Ration".to_string() })
+        .with(Item {})
+        .with(Food { nutrition: 800 })
+        .build();
+}
+
+/// What a dead animal leaves where it fell: its meat, eaten like any other food.
+pub fn corpse(ecs: &mut World, x: i32, y: i32, name: &str, nutrition: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('%'),
+            fg: RGB::named(rltk::INDIANRED),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: format!("{} Meat", name) })
+        .with(Item {})
+        .with(Food { nutrition })
+        .build();
+}
+
+/// Puts up `count` wooden barricades: cover against shots until someone hacks them down.
+pub fn spawn_barricades(ecs: &mut World, count: i32, player_start: (i32, i32)) {
+    let spawn_points = random_spawn_points(ecs, count, player_start, 1);
+
+    for (x, y) in spawn_points {
+        barricade(ecs, x, y);
+    }
+}
+
+fn barricade(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('#'),
+            fg: RGB::named(rltk::SADDLEBROWN),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Barricade".to_string() })
+        .with(CombatStats { max_hp: 12, hp: 12, defense: 1, power: 0 })
+        .with(Barricade {})
+        .build();
+}
+
+/// Places one Amulet of Sight (see invisible) somewhere on the level.
+pub fn spawn_amulet_of_sight(ecs: &mut World, player_start: (i32, i32)) {
+    if let Some((x, y)) = random_spawn_points(ecs, 1, player_start, 1).first().copied() {
+        amulet_of_sight(ecs, x, y);
+    }
+}
+
+fn amulet_of_sight(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('"'),
+            fg: RGB::named(rltk::CYAN),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Amulet of Sight".to_string() })
+        .with(Item {})
+        .with(GrantsSeeInvisible {})
+        .build();
+}
+
+/// Leaves one spear somewhere on the level.
+pub fn spawn_spear(ecs: &mut World, player_start: (i32, i32)) {
+    if let Some((x, y)) = random_spawn_points(ecs, 1, player_start, 1).first().copied() {
+        spear(ecs, x, y);
+    }
+}
+
+// la lancia: chi la raccoglie colpisce in mischia fino a due caselle di distanza
+fn spear(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('/'),
+            fg: RGB::named(rltk::BURLYWOOD),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Spear".to_string() })
+        .with(Item {})
+        .with(Reach { range: 2 })
+        .build();
+}
+
+/// How many random tiles are tried for the key before giving up.
+const KEY_SPOTS: i32 = 20;
+
+/// Leaves a key where the player can walk to without one, if the level has locked doors.
+pub fn spawn_key(ecs: &mut World, player_start: (i32, i32)) {
+    let (reachable, width) = {
+        let map = ecs.fetch::<Map>();
+        if !map.tiles.iter().any(|tile| tile.door_state == Some(DoorState::Locked)) {
+            return;
+        }
+        let unlocked = |tile: &Tile| tile.walkable || matches!(tile.door_state, Some(DoorState::Closed | DoorState::Open | DoorState::Broken));
+        (map_builders::distances_through(&map, player_start, &unlocked), map.width)
+    };
+    let spot = random_spawn_points(ecs, KEY_SPOTS, player_start, 1).into_iter()
+        .find(|(x, y)| reachable[xy_idx(*x, *y, width)].is_some());
+    if let Some((x, y)) = spot {
+        key(ecs, x, y);
+    }
+}
+
+// la chiave: apre una porta chiusa a chiave (vedi try_open_door)
+fn key(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('-'),
+            fg: RGB::named(rltk::GOLD),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Key".to_string() })
+        .with(Item {})
+        .with(Key {})
+        .build();
+}
+
+/// Leaves one wand of digging somewhere on the level.
+pub fn spawn_wand_of_digging(ecs: &mut World, player_start: (i32, i32)) {
+    if let Some((x, y)) = random_spawn_points(ecs, 1, player_start, 1).first().copied() {
+        wand_of_digging(ecs, x, y);
+    }
+}
+
+/// How many turns a wand takes to get one charge back by itself.
+const WAND_RECHARGE_TURNS: i32 = 60;
+
+// la bacchetta dello scavo: apre un tunnel dritto nella roccia tenera, finché ha cariche
+fn wand_of_digging(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('/'),
+            fg: RGB::named(rltk::ORANGE),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Wand of Digging".to_string() })
+        .with(Item {})
+        .with(DiggingBeam { range: 8 })
+        .with(Charges { current: 3, max: 3, recharge_turns: WAND_RECHARGE_TURNS, timer: 0 })
+        .build();
+}
+
+/// Leaves `count` bundles of arrows around the level, for the quiver (see Ammo).
+pub fn spawn_arrows(ecs: &mut World, count: i32, player_start: (i32, i32)) {
+    for (x, y) in random_spawn_points(ecs, count, player_start, 1) {
+        arrows(ecs, x, y);
+    }
+}
+
+fn arrows(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437(')'),
+            fg: RGB::named(rltk::BURLYWOOD),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Arrows".to_string() })
+        .with(Item {})
+        .with(Ammo { kind: AmmoKind::Arrows, count: 6 })
+        .with(AutoPickup {})
+        .build();
+}
+
+/// Leaves `count` lightsticks around the level, to be thrown into the dark (see ThrowableLight).
+pub fn spawn_lightsticks(ecs: &mut World, count: i32, player_start: (i32, i32)) {
+    for (x, y) in random_spawn_points(ecs, count, player_start, 1) {
+        lightstick(ecs, x, y);
+    }
+}
+
+// il bastoncino luminoso: lanciato, rischiara una stanza buia per una trentina di turni
+fn lightstick(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('|'),
+            fg: RGB::named(rltk::LIGHT_GREEN),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Lightstick".to_string() })
+        .with(Item {})
+        .with(ThrowableLight { radius: 5, turns: 30 })
+        .build();
+}
+
+/// Leaves one scroll of summoning somewhere on the level.
+pub fn spawn_scroll_of_summoning(ecs: &mut World, player_start: (i32, i32)) {
+    if let Some((x, y)) = random_spawn_points(ecs, 1, player_start, 1).first().copied() {
+        scroll_of_summoning(ecs, x, y);
+    }
+}
+
+// la pergamena di evocazione: chiama un guardiano che combatte al fianco del giocatore per un po'
+fn scroll_of_summoning(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('?'),
+            fg: RGB::named(rltk::LIGHT_GREEN),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Scroll of Summoning".to_string() })
+        .with(Item {})
+        .with(SummonsAlly { turns: 50 })
+        .build();
+}
+
+/// A guardian fighting for the player for `turns` turns (see ally_ai_system.rs).
+pub fn guardian(ecs: &mut World, x: i32, y: i32, turns: i32) -> Entity {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('G'),
+            fg: RGB::named(rltk::LIGHT_GREEN),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Guardian".to_string() })
+        .with(CombatStats { max_hp: 20, hp: 20, defense: 2, power: 6 })
+        .with(Faction::Friendly)
+        .with(Summoned { turns_left: turns })
+        .with(Viewshed { visible_tiles: Vec::new(), range: 8, dirty: true, fov_shape: FovShape::Circle })
+        .build()
+}
+
+/// Leaves one bear trap somewhere on the level, for the player to set (see DeployableTrap).
+pub fn spawn_bear_trap(ecs: &mut World, player_start: (i32, i32)) {
+    if let Some((x, y)) = random_spawn_points(ecs, 1, player_start, 1).first().copied() {
+        bear_trap(ecs, x, y);
+    }
+}
+
+// la tagliola: raccolta finisce nello zaino, piazzata fa male al primo mostro che ci mette piede
+fn bear_trap(ecs: &mut World, x: i32, y: i32) {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            glyph: rltk::to_cp437('^'),
+            fg: RGB::named(rltk::SILVER),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name { name: "Bear Trap".to_string() })
+        .with(Item {})
+        .with(DeployableTrap { damage: 8 })
+        .build();
+}
+
+/// Spawns the item called `name` (as written in the loot tables) at (x, y).
+pub fn spawn_named_item(ecs: &mut World, name: &str, x: i32, y: i32) {
+    match name {
+        "Health Potion" => health_potion(ecs, x, y),
+        "Ration" => ration(ecs, x, y),
+        "Amulet of Sight" => amulet_of_sight(ecs, x, y),
+        "Spear" => spear(ecs, x, y),
+        "Key" => key(ecs, x, y),
+        "Bear Trap" => bear_trap(ecs, x, y),
+        "Wand of Digging" => wand_of_digging(ecs, x, y),
+        "Scroll of Summoning" => scroll_of_summoning(ecs, x, y),
+        "Lightstick" => lightstick(ecs, x, y),
+        "Arrows" => arrows(ecs, x, y),
+        "Gold" => {
+            let amount = ecs.write_resource::<GameRng>().roll_dice(1, 20);
+            gold_pile(ecs, x, y, amount);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,144 @@
+use specs::prelude::*;
+use rltk::{DistanceAlg, Point};
+use super::{Hidden, Monster, Name, PlayerEntity, Position, SeeInvisible, Viewshed};
+
+/// The monster currently selected for ranged attacks (Tab cycles it, Fire shoots it).
+#[derive(Default)]
+pub struct Targeting {
+    pub current_target : Option<Entity>
+}
+
+/// What an action does to whatever it lands on.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetIntent {
+    Harmful,    // shots, attacks: never aimed at the party
+    Beneficial, // healing, buffs: the party is a fine target
+}
+
+/// Checks a target before the action resolves: a harmful action can't land on the player,
+/// on a party member or on anyone standing on the player's tile.
+/// Err carries the message to log; refusing costs no turn.
+pub fn validate_target(ecs: &World, target: Entity, intent: TargetIntent) -> Result<(), String> {
+    if intent == TargetIntent::Beneficial {
+        return Ok(());
+    }
+    let player = ecs.fetch::<PlayerEntity>().0;
+    if target == player {
+        return Err("You won't aim at yourself.".to_string());
+    }
+    if crate::party::party_members(ecs).contains(&target) {
+        let name = ecs.read_storage::<Name>().get(target).map(|n| n.name.clone()).unwrap_or_default();
+        return Err(format!("You won't aim at {}.", name));
+    }
+    let positions = ecs.read_storage::<Position>();
+    let on_player_tile = match (positions.get(player), positions.get(target)) {
+        (Some(player_pos), Some(pos)) => player_pos.x == pos.x && player_pos.y == pos.y,
+        _ => false,
+    };
+    if on_player_tile {
+        return Err("That would hit you too.".to_string());
+    }
+    Ok(())
+}
+
+/// Every positioned entity accepted by `filter`, sorted by distance from `from` (nearest first).
+/// Ties are broken by entity id, so the order is stable between frames.
+/// Shared by targeting (nearest monster) and, later, by AI (nearest player/ally).
+pub fn entities_by_distance<F>(ecs: &World, from: Point, filter: F) -> Vec<(Entity, f32)>
+    where F: Fn(Entity) -> bool
+{
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+
+    let mut found : Vec<(Entity, f32)> = (&entities, &positions).join()
+        .filter(|(entity, _pos)| filter(*entity))
+        .map(|(entity, pos)| (entity, DistanceAlg::Pythagoras.distance2d(from, Point::new(pos.x, pos.y))))
+        .collect();
+
+    found.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.id().cmp(&b.0.id())));
+    found
+}
+
+/// True if someone in the party can see invisible things.
+pub fn detects_hidden(ecs: &World) -> bool {
+    let see_invisible = ecs.read_storage::<SeeInvisible>();
+    crate::party::party_members(ecs).iter().any(|member| see_invisible.get(*member).is_some())
+}
+
+/// True if `entity` isn't Hidden, or the party can see it anyway.
+pub fn is_perceived(ecs: &World, entity: Entity) -> bool {
+    ecs.read_storage::<Hidden>().get(entity).is_none() || detects_hidden(ecs)
+}
+
+/// Monsters inside the player's viewshed, nearest first. Hidden ones only with see invisible.
+pub fn visible_monsters(ecs: &World) -> Vec<Entity> {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let monsters = ecs.read_storage::<Monster>();
+    let positions = ecs.read_storage::<Position>();
+
+    let (viewshed, player_pos) = match (viewsheds.get(player), positions.get(player)) {
+        (Some(viewshed), Some(pos)) => (viewshed, pos),
+        _ => return Vec::new(),
+    };
+
+    let detection = detects_hidden(ecs);
+    let hiddens = ecs.read_storage::<Hidden>();
+    let in_view = |entity: Entity| {
+        monsters.get(entity).is_some() && (detection || hiddens.get(entity).is_none()) && positions.get(entity)
+            .is_some_and(|pos| viewshed.visible_tiles.iter().any(|p| p.x == pos.x && p.y == pos.y))
+    };
+    entities_by_distance(ecs, Point::new(player_pos.x, player_pos.y), in_view)
+        .into_iter()
+        .map(|(entity, _distance)| entity)
+        .collect()
+}
+
+/// Selects the next visible monster, wrapping around at the end of the list.
+/// The target is cleared when no monster is visible.
+pub fn cycle_target(ecs: &mut World) {
+    let visible = visible_monsters(ecs);
+    let mut targeting = ecs.write_resource::<Targeting>();
+
+    let current_index = targeting.current_target.and_then(|target| visible.iter().position(|e| *e == target));
+    targeting.current_target = match current_index {
+        Some(i) => Some(visible[(i + 1) % visible.len()]),
+        None => visible.first().copied(),
+    };
+}
+
+/// Where the look reticle should snap: the visible named entity after the one at `cursor`
+/// (nearest to the player first, wrapping around). Without entities in sight it stays on
+/// the cursor, or on the player when there's no cursor yet.
+pub fn next_look_position(ecs: &World, cursor: Option<(i32, i32)>) -> (i32, i32) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let viewsheds = ecs.read_storage::<Viewshed>();
+    let names = ecs.read_storage::<Name>();
+    let positions = ecs.read_storage::<Position>();
+
+    let (viewshed, player_pos) = match (viewsheds.get(player), positions.get(player)) {
+        (Some(viewshed), Some(pos)) => (viewshed, pos),
+        _ => return cursor.unwrap_or((0, 0)),
+    };
+    let fallback = cursor.unwrap_or((player_pos.x, player_pos.y));
+
+    let detection = detects_hidden(ecs);
+    let hiddens = ecs.read_storage::<Hidden>();
+    let lookable = |entity: Entity| {
+        entity != player && names.get(entity).is_some() && (detection || hiddens.get(entity).is_none()) && positions.get(entity)
+            .is_some_and(|pos| viewshed.visible_tiles.iter().any(|p| p.x == pos.x && p.y == pos.y))
+    };
+    let candidates : Vec<(i32, i32)> = entities_by_distance(ecs, Point::new(player_pos.x, player_pos.y), lookable)
+        .iter()
+        .filter_map(|(entity, _distance)| positions.get(*entity).map(|pos| (pos.x, pos.y)))
+        .collect();
+    if candidates.is_empty() {
+        return fallback;
+    }
+
+    match cursor.and_then(|c| candidates.iter().position(|p| *p == c)) {
+        Some(i) => candidates[(i + 1) % candidates.len()],
+        None => candidates[0],
+    }
+}
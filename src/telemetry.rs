@@ -0,0 +1,122 @@
+use std::fs;
+use std::io;
+use specs::prelude::*;
+use crate::clock::Clock;
+
+// TELEMETRY ------------------------------------------------------------------
+// Registro degli eventi per il bilanciamento, separato dal GameLog del giocatore:
+// eventi tipizzati con il turno in cui sono successi, da interrogare durante la partita
+// o da esportare in JSON alla fine per analizzarli.
+
+/// Something worth counting when tuning the game.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryEvent {
+    DamageDealt { source: Option<String>, target: String, amount: i32 }, // source None = the environment
+    MonsterKilled { name: String },
+    LevelDescended { depth: i32, biome: String },
+    ItemUsed { name: String },
+}
+
+impl TelemetryEvent {
+    fn kind(&self) -> &'static str {
+        match self {
+            TelemetryEvent::DamageDealt { .. } => "DamageDealt",
+            TelemetryEvent::MonsterKilled { .. } => "MonsterKilled",
+            TelemetryEvent::LevelDescended { .. } => "LevelDescended",
+            TelemetryEvent::ItemUsed { .. } => "ItemUsed",
+        }
+    }
+
+    /// The event's own fields as JSON members (without the braces).
+    fn json_fields(&self) -> String {
+        match self {
+            TelemetryEvent::DamageDealt { source, target, amount } => {
+                let source = source.as_deref().map_or("null".to_string(), json_string);
+                format!("\"source\":{},\"target\":{},\"amount\":{}", source, json_string(target), amount)
+            }
+            TelemetryEvent::MonsterKilled { name } => format!("\"name\":{}", json_string(name)),
+            TelemetryEvent::LevelDescended { depth, biome } => format!("\"depth\":{},\"biome\":{}", depth, json_string(biome)),
+            TelemetryEvent::ItemUsed { name } => format!("\"name\":{}", json_string(name)),
+        }
+    }
+}
+
+/// One event and the turn it happened on.
+#[derive(Debug, Clone)]
+pub struct TelemetryRecord {
+    pub turn : u64,
+    pub event : TelemetryEvent,
+}
+
+/// Every event recorded so far (risorsa).
+#[derive(Default)]
+pub struct Telemetry {
+    pub records : Vec<TelemetryRecord>,
+}
+
+impl Telemetry {
+    pub fn record(&mut self, turn: u64, event: TelemetryEvent) {
+        self.records.push(TelemetryRecord { turn, event });
+    }
+
+    /// The records whose event satisfies `filter`, oldest first.
+    #[allow(dead_code)]
+    pub fn query<'a>(&'a self, filter: impl Fn(&TelemetryEvent) -> bool + 'a) -> impl Iterator<Item = &'a TelemetryRecord> + 'a {
+        self.records.iter().filter(move |record| filter(&record.event))
+    }
+
+    /// All the records as a JSON array of objects: `{"turn":..,"event":"DamageDealt",...}`.
+    pub fn to_json(&self) -> String {
+        let records : Vec<String> = self.records.iter()
+            .map(|record| format!("{{\"turn\":{},\"event\":\"{}\",{}}}", record.turn, record.event.kind(), record.event.json_fields()))
+            .collect();
+        format!("[\n  {}\n]\n", records.join(",\n  "))
+    }
+}
+
+/// `text` as a quoted JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Records `event` at the current turn of the Clock.
+pub fn record(ecs: &World, event: TelemetryEvent) {
+    let turn = ecs.fetch::<Clock>().turn;
+    ecs.write_resource::<Telemetry>().record(turn, event);
+}
+
+/// Writes every recorded event to `path` as JSON.
+pub fn dump(ecs: &World, path: &str) -> io::Result<()> {
+    fs::write(path, ecs.fetch::<Telemetry>().to_json())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_the_events_as_json() {
+        let mut telemetry = Telemetry::default();
+        telemetry.record(3, TelemetryEvent::DamageDealt { source: None, target: "Player".to_string(), amount: 10 });
+        telemetry.record(7, TelemetryEvent::MonsterKilled { name: "Orc \"Bob\"".to_string() });
+
+        assert_eq!(telemetry.to_json(), concat!(
+            "[\n",
+            "  {\"turn\":3,\"event\":\"DamageDealt\",\"source\":null,\"target\":\"Player\",\"amount\":10},\n",
+            "  {\"turn\":7,\"event\":\"MonsterKilled\",\"name\":\"Orc \\\"Bob\\\"\"}\n",
+            "]\n"));
+        assert_eq!(telemetry.query(|event| matches!(event, TelemetryEvent::MonsterKilled { .. })).count(), 1);
+    }
+}
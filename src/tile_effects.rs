@@ -0,0 +1,90 @@
+use specs::prelude::*;
+use crate::audio::{self, SoundEvent};
+use crate::components::{PlayerEntity, Position};
+use crate::effects::{self, EffectTarget, EffectType};
+use crate::gamelog::GameLog;
+use crate::map::Map;
+
+// TILE EFFECTS ---------------------------------------------------------------
+// Quello che la casella fa a chi ci entra: danno diretto (lava, spine), uno stato (Burning,
+// Entangled) e il lavaggio dell'acqua bassa. Passa tutto dalla coda degli effetti, come un
+// attacco qualsiasi: il danno arriva una volta all'ingresso, lo stato poi si consuma turno
+// per turno in effects::tick_statuses, e se i punti ferita scendono a zero ci pensa delete_the_dead
+// (RunState::GameOver). Le trappole calpestabili (i rovi) contano come ogni altra casella.
+
+/// Queues the damage and the status of the tile under the player (spikes, lava, ...):
+/// they hit whoever stands there, through the same effects as any attack.
+/// A tile that cleanses (shallow water) washes its status off them the same way.
+/// Call it once per tile entered (see step_player), not every turn spent on it.
+pub fn hurt_by_terrain(ecs: &mut World) {
+    let idx = {
+        let player = ecs.fetch::<PlayerEntity>().0;
+        let map = ecs.fetch::<Map>();
+        match ecs.read_storage::<Position>().get(player) {
+            Some(pos) => map.xy_idx(pos.x, pos.y),
+            None => return,
+        }
+    };
+    let (damage, status, glyph, cleanses) = {
+        let map = ecs.fetch::<Map>();
+        (map.tiles[idx].direct_damage, map.tiles[idx].status_effect, map.tiles[idx].glyph, map.tiles[idx].cleanses)
+    };
+    if damage > 0 || status.is_some() {
+        audio::play(ecs, SoundEvent::HazardStep { glyph, status });
+    }
+    if damage > 0 {
+        ecs.write_resource::<GameLog>().push(format!("The ground hurts you for {} hp.", damage));
+        effects::add_effect(ecs, None, EffectType::Damage { amount: damage }, EffectTarget::Tile(idx));
+    }
+    if let Some(status) = status {
+        effects::add_effect(ecs, None, EffectType::AddStatus { effect: status, turns: effects::HAZARD_STATUS_TURNS }, EffectTarget::Tile(idx));
+    }
+    if let Some(status) = cleanses {
+        effects::add_effect(ecs, None, EffectType::Cleanse { effect: status }, EffectTarget::Tile(idx));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::{register_components, CombatStats, StatusEffects};
+    use crate::effects::{EffectQueue, EffectsSystem};
+    use crate::map::{Biome, Building, Forest, StatusEffect, Volcano};
+
+    #[test]
+    fn lava_burns_and_the_vines_tangle_whoever_walks_in() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+
+        let mut map = Map::new(20, 10, Building.get_floor());
+        let (lava, vines) = (map.xy_idx(5, 5), map.xy_idx(6, 5));
+        map.tiles[lava] = Volcano.get_water().unwrap();
+        map.tiles[vines] = Forest.get_trap().unwrap();
+        assert!(map.tiles[vines].walkable);
+        ecs.insert(map);
+        ecs.insert(GameLog { entries: Default::default(), max_entries: 100 });
+        ecs.insert(EffectQueue::default());
+        let player = ecs.create_entity()
+            .with(Position { x: 4, y: 5 })
+            .with(CombatStats { max_hp: 30, hp: 30, defense: 0, power: 5 })
+            .build();
+        ecs.insert(PlayerEntity(player));
+        let step_on = |ecs: &mut World, x: i32| {
+            ecs.write_storage::<Position>().get_mut(player).unwrap().x = x;
+            hurt_by_terrain(ecs);
+            EffectsSystem {}.run_now(ecs);
+            ecs.maintain();
+        };
+
+        step_on(&mut ecs, 4);
+        assert_eq!(ecs.read_storage::<CombatStats>().get(player).unwrap().hp, 30, "plain floor does nothing");
+
+        step_on(&mut ecs, 5);
+        assert_eq!(ecs.read_storage::<CombatStats>().get(player).unwrap().hp, 20);
+        assert!(ecs.read_storage::<StatusEffects>().get(player).is_some_and(|status| status.has(StatusEffect::Burning)));
+
+        step_on(&mut ecs, 6);
+        assert_eq!(ecs.read_storage::<CombatStats>().get(player).unwrap().hp, 19);
+        assert!(ecs.read_storage::<StatusEffects>().get(player).is_some_and(|status| status.has(StatusEffect::Entangled)));
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use specs::prelude::*;
+use crate::components::{CombatStats, CurrentLevel, Position, Viewshed};
+use crate::direction::Direction;
+use crate::map::{Map, Tile, refresh_wall_glyphs};
+use crate::rng::GameRng;
+
+// TILE TIMER -----------------------------------------------------------------
+// Caselle che cambiano da sole dopo qualche turno: il ghiaccio scavato che si richiude,
+// i rampicanti che si allargano. La mappa non sa niente dei timer: questa risorsa tiene,
+// per indice di casella, quanti turni mancano e in cosa si trasforma; tick_tile_timers
+// la fa avanzare alla fine di ogni turno.
+
+/// How many turns a path dug through ice stays open.
+pub const REFREEZE_TURNS : i32 = 12;
+
+/// What happens to a tile when its countdown runs out.
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum Transformation {
+    /// The tile turns into `tile`; waits while a creature stands on it, nobody gets walled in.
+    Become(Tile),
+    /// A random walkable neighbour turns into `tile`, and the timer starts again from `every`.
+    Spread { tile: Tile, every: i32 },
+}
+
+/// The countdowns of the current level (risorsa); a new level starts with none.
+#[derive(Default)]
+pub struct TileTimer {
+    pub timers : HashMap<usize, (i32, Transformation)>,
+}
+
+impl TileTimer {
+    /// Starts (or restarts) the countdown of tile `idx`.
+    pub fn set(&mut self, idx: usize, turns: i32, transformation: Transformation) {
+        self.timers.insert(idx, (turns, transformation));
+    }
+}
+
+/// Counts every timer down by one turn and applies the transformations that are due.
+/// Whenever the layout changes every viewshed is marked dirty.
+pub fn tick_tile_timers(ecs: &mut World) {
+    let due : Vec<(usize, Transformation)> = {
+        let mut timer = ecs.write_resource::<TileTimer>();
+        for (turns, _transformation) in timer.timers.values_mut() {
+            *turns -= 1;
+        }
+        let due_tiles : Vec<usize> = timer.timers.iter().filter(|(_idx, (turns, _t))| *turns <= 0).map(|(idx, _timer)| *idx).collect();
+        due_tiles.into_iter().filter_map(|idx| timer.timers.remove(&idx).map(|(_turns, t)| (idx, t))).collect()
+    };
+    if due.is_empty() {
+        return;
+    }
+
+    let occupied : Vec<usize> = {
+        let positions = ecs.read_storage::<Position>();
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        let map = ecs.fetch::<Map>();
+        (&positions, &combat_stats).join().map(|(pos, _stats)| map.xy_idx(pos.x, pos.y)).collect()
+    };
+    let mut changed = false;
+    for (idx, transformation) in due {
+        match transformation {
+            Transformation::Become(tile) => {
+                if occupied.contains(&idx) {
+                    // riprova al prossimo turno
+                    ecs.write_resource::<TileTimer>().set(idx, 1, Transformation::Become(tile));
+                    continue;
+                }
+                ecs.write_resource::<Map>().tiles[idx] = tile;
+                // a wall coming back has to rejoin the lines of the walls around it
+                if ecs.try_fetch::<CurrentLevel>().is_some_and(|level| level.biome.biome().connected_walls()) {
+                    refresh_wall_glyphs(&mut ecs.write_resource::<Map>(), idx);
+                }
+                changed = true;
+            }
+            Transformation::Spread { tile, every } => {
+                let candidates : Vec<usize> = {
+                    let map = ecs.fetch::<Map>();
+                    let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+                    Direction::ALL.iter()
+                        .map(|direction| direction.to_delta())
+                        .map(|(dx, dy)| (x + dx, y + dy))
+                        .filter(|(nx, ny)| *nx > 0 && *nx < map.width - 1 && *ny > 0 && *ny < map.height - 1)
+                        .map(|(nx, ny)| map.xy_idx(nx, ny))
+                        .filter(|n| map.tiles[*n].walkable && map.tiles[*n].glyph != tile.glyph && map.tiles[*n].door_state.is_none())
+                        .collect()
+                };
+                if !candidates.is_empty() {
+                    let roll = ecs.write_resource::<GameRng>().range(0, candidates.len() as i32) as usize;
+                    ecs.write_resource::<Map>().tiles[candidates[roll]] = tile;
+                    changed = true;
+                }
+                ecs.write_resource::<TileTimer>().set(idx, every, Transformation::Spread { tile, every });
+            }
+        }
+    }
+
+    if changed {
+        crate::path_cache::invalidate(ecs);
+        for viewshed in (&mut ecs.write_storage::<Viewshed>()).join() {
+            viewshed.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::register_components;
+    use crate::map::{Biome, SnowyMountains, xy_idx};
+
+    #[test]
+    fn a_path_dug_through_ice_refreezes() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        let (width, height) = (80, 50);
+        let mut map = Map::new(width, height, SnowyMountains.get_floor());
+        let idx = xy_idx(10, 10, width);
+        map.tiles[idx] = SnowyMountains.get_wall();
+        ecs.insert(map);
+        ecs.insert(GameRng::seeded(1));
+        ecs.insert(TileTimer::default());
+
+        let ice = ecs.fetch::<Map>().tiles[idx];
+        assert!(ecs.write_resource::<Map>().dig(idx));
+        ecs.write_resource::<TileTimer>().set(idx, 2, Transformation::Become(ice));
+
+        tick_tile_timers(&mut ecs);
+        assert!(ecs.fetch::<Map>().tiles[idx].walkable);
+        tick_tile_timers(&mut ecs);
+        assert!(!ecs.fetch::<Map>().tiles[idx].walkable);
+        assert!(ecs.fetch::<TileTimer>().timers.is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::{HashMap, HashSet};
+use rltk::{DistanceAlg, Point};
+use specs::prelude::*;
+use super::{Armed, CurrentLevel, DeployableTrap, Monster, Name, Perception, PlayerEntity, Position, Viewshed};
+use crate::clock::{Clock, ScheduledEvent};
+use crate::effects::{self, EffectTarget, EffectType};
+use crate::gamelog::GameLog;
+use crate::map::{Map, TrapKind};
+use crate::rng::GameRng;
+use crate::spawner;
+
+// TRAPS ----------------------------------------------------------------------
+// Scattare e disinnescare le trappole. Un disinnesco fallito fa scattare la trappola e,
+// a volte, il trambusto svela le trappole vicine o fa partire tutte quelle collegate
+// (Map::trap_groups) in una reazione a catena.
+
+/// Turns after an alarm before the second monster it called shows up (see Clock::schedule).
+const ALARM_REINFORCEMENT_DELAY : u64 = 5;
+
+/// Radius (in tiles) within which a fumbled disarm may reveal the other traps.
+const COMMOTION_RADIUS : f32 = 6.0;
+/// DC of a trap the player has already spotted for good.
+const REVEALED_TRAP_DC : i32 = 10;
+/// d20 + Perception needed to feel that a hidden trap is close (30% with no bonus).
+const DANGER_SENSE_DC : i32 = 15;
+
+/// How far the passive search of a hidden trap gets in each turn spent next to it, before Perception.
+const PASSIVE_SEARCH_RATE : i32 = 1;
+
+/// How close the player has come to spotting each hidden trap just by standing near it
+/// (risorsa, per indice di casella): a trap is revealed once its progress reaches its DC.
+/// A new level starts from scratch.
+#[derive(Default)]
+pub struct SearchProgress {
+    pub progress : HashMap<usize, i32>,
+    pub glimpsed : HashSet<usize>, // hidden traps caught sight of this turn (see glimpse_traps)
+}
+
+/// Springs the trap at `idx`. Hazard traps hurt `victim` if given (someone fumbling with it
+/// from the next tile), otherwise whoever stands on the trap; they stay armed and in plain sight.
+/// Alarm traps summon a monster nearby, and another one ALARM_REINFORCEMENT_DELAY turns later;
+/// they are spent afterwards (the plate stays visible).
+pub fn spring_trap(ecs: &mut World, idx: usize, victim: Option<Entity>) {
+    let (trap_kind, damage, status, x, y) = {
+        let map = ecs.fetch::<Map>();
+        let tile = &map.tiles[idx];
+        (tile.trap_kind, tile.direct_damage, tile.status_effect, idx as i32 % map.width, idx as i32 / map.width)
+    };
+    match trap_kind {
+        Some(TrapKind::Summon) => {
+            {
+                let mut map = ecs.write_resource::<Map>();
+                map.tiles[idx].trap_kind = None;
+                map.tiles[idx].trap_dc = None;
+            }
+            ecs.write_resource::<GameLog>().push("You hear a shriek - something approaches!".to_string());
+            if let Some((x, y)) = spawner::find_valid_spawn(ecs, x, y, 4) {
+                spawner::random_monster(ecs, x, y);
+            }
+            if let Some(mut clock) = ecs.try_fetch_mut::<Clock>() {
+                let at = clock.turn + ALARM_REINFORCEMENT_DELAY;
+                clock.schedule(at, ScheduledEvent::Message("More footsteps are coming your way!".to_string()));
+                clock.schedule(at, ScheduledEvent::SpawnMonster { x, y });
+            }
+        }
+        Some(TrapKind::Hazard) => {
+            ecs.write_resource::<Map>().tiles[idx].trap_dc = None;
+            let target = victim.map(EffectTarget::Single).unwrap_or(EffectTarget::Tile(idx));
+            if damage > 0 {
+                effects::add_effect(ecs, None, EffectType::Damage { amount: damage }, target);
+            }
+            if let Some(status) = status {
+                effects::add_effect(ecs, None, EffectType::AddStatus { effect: status, turns: effects::HAZARD_STATUS_TURNS }, target);
+            }
+        }
+        None => {}
+    }
+}
+
+/// Springs the traps set by the player (see DeployableTrap) under the monsters standing on them:
+/// each hurts its monster once and is spent. Monsters only, the party walks over them safely.
+pub fn spring_player_traps(ecs: &mut World) {
+    let sprung : Vec<(Entity, Entity, i32, String)> = {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let traps = ecs.read_storage::<DeployableTrap>();
+        let armed = ecs.read_storage::<Armed>();
+        let monsters = ecs.read_storage::<Monster>();
+        let names = ecs.read_storage::<Name>();
+        (&entities, &traps, &armed, &positions).join()
+            .filter_map(|(trap, deployable, _armed, at)| {
+                (&entities, &monsters, &positions).join()
+                    .find(|(_monster, _m, pos)| pos.x == at.x && pos.y == at.y)
+                    .map(|(monster, _m, _pos)| (trap, monster, deployable.damage, names.get(monster).map_or("monster".to_string(), |name| name.name.clone())))
+            })
+            .collect()
+    };
+    for (trap, monster, damage, name) in sprung {
+        ecs.write_resource::<GameLog>().push(format!("The {} steps into your trap!", name));
+        effects::add_effect(ecs, None, EffectType::Damage { amount: damage }, EffectTarget::Single(monster));
+        ecs.delete_entity(trap).expect("Unable to delete");
+    }
+}
+
+/// Where every monster stands, taken before they act so that `spring_traps_under_monsters`
+/// can tell who has just stepped onto a trap from who was already there.
+pub fn monster_positions(ecs: &World) -> Vec<(Entity, i32, i32)> {
+    let entities = ecs.entities();
+    let monsters = ecs.read_storage::<Monster>();
+    let positions = ecs.read_storage::<Position>();
+    (&entities, &monsters, &positions).join()
+        .map(|(entity, _monster, pos)| (entity, pos.x, pos.y))
+        .collect()
+}
+
+/// Springs the map traps (see spring_trap) under the monsters that have moved onto them since
+/// `before` was taken: monsters don't look where they step, so a chase can end on the spikes.
+/// The player reads about it only if they can see the trap's tile.
+pub fn spring_traps_under_monsters(ecs: &mut World, before: &[(Entity, i32, i32)]) {
+    let stepped : Vec<(Entity, usize, bool, String)> = {
+        let map = ecs.fetch::<Map>();
+        let player = ecs.fetch::<PlayerEntity>().0;
+        let viewsheds = ecs.read_storage::<Viewshed>();
+        let names = ecs.read_storage::<Name>();
+        let positions = ecs.read_storage::<Position>();
+        before.iter()
+            .filter_map(|(monster, old_x, old_y)| positions.get(*monster).map(|pos| (*monster, pos, (*old_x, *old_y))))
+            .filter(|(_monster, pos, old)| (pos.x, pos.y) != *old && map.tiles[map.xy_idx(pos.x, pos.y)].trap_kind.is_some())
+            .map(|(monster, pos, _old)| {
+                let seen = viewsheds.get(player).is_some_and(|viewshed| viewshed.visible_tiles.contains(&Point::new(pos.x, pos.y)));
+                (monster, map.xy_idx(pos.x, pos.y), seen, names.get(monster).map_or("monster".to_string(), |name| name.name.clone()))
+            })
+            .collect()
+    };
+    for (monster, idx, seen, name) in stepped {
+        if seen {
+            ecs.write_resource::<GameLog>().push(format!("The {} blunders into a trap!", name));
+        }
+        spring_trap(ecs, idx, Some(monster));
+    }
+}
+
+/// Marks every trap within `radius` of (x, y) as spotted for good. Returns how many were still hidden.
+pub fn reveal_traps_near(ecs: &mut World, x: i32, y: i32, radius: f32) -> i32 {
+    let mut map = ecs.write_resource::<Map>();
+    let width = map.width;
+    let mut revealed = 0;
+    for (idx, tile) in map.tiles.iter_mut().enumerate() {
+        let here = Point::new(idx as i32 % width, idx as i32 / width);
+        if tile.trap_kind.is_some() && tile.trap_dc.is_some() && DistanceAlg::Pythagoras.distance2d(Point::new(x, y), here) <= radius {
+            tile.trap_dc = None;
+            revealed += 1;
+        }
+    }
+    revealed
+}
+
+/// Danger sense: if the player ends the turn next to a trap they haven't spotted, a
+/// d20 + Perception roll may warn them that something is off - without saying which tile.
+pub fn sense_danger(ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let (player_x, player_y) = match ecs.read_storage::<Position>().get(player) {
+        Some(pos) => (pos.x, pos.y),
+        None => return,
+    };
+    let hidden_trap_nearby = {
+        let map = ecs.fetch::<Map>();
+        (player_y - 1..=player_y + 1)
+            .flat_map(|y| (player_x - 1..=player_x + 1).map(move |x| (x, y)))
+            .filter(|(x, y)| (*x, *y) != (player_x, player_y) && *x >= 0 && *x < map.width && *y >= 0 && *y < map.height)
+            .map(|(x, y)| &map.tiles[map.xy_idx(x, y)])
+            .any(|tile| tile.trap_kind.is_some() && tile.trap_dc.is_some())
+    };
+    if !hidden_trap_nearby {
+        return;
+    }
+    let perception = ecs.read_storage::<Perception>().get(player).map_or(0, |p| p.bonus);
+    let roll = ecs.write_resource::<GameRng>().roll_dice(1, 20);
+    if roll + perception >= DANGER_SENSE_DC {
+        ecs.write_resource::<GameLog>().push("You sense something is off nearby.".to_string());
+    }
+}
+
+/// Passive search: every turn the player ends next to a hidden trap brings them closer to
+/// spotting it (PASSIVE_SEARCH_RATE plus their Perception, see SearchProgress). Once the
+/// progress reaches the trap's DC it's revealed for good, with no search action needed.
+pub fn passive_search(ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let (player_x, player_y) = match ecs.read_storage::<Position>().get(player) {
+        Some(pos) => (pos.x, pos.y),
+        None => return,
+    };
+    let gain = (PASSIVE_SEARCH_RATE + ecs.read_storage::<Perception>().get(player).map_or(0, |p| p.bonus)).max(1);
+    let mut spotted = 0;
+    {
+        let mut map = ecs.write_resource::<Map>();
+        let mut search = ecs.write_resource::<SearchProgress>();
+        let (width, height) = (map.width, map.height);
+        let around : Vec<usize> = (player_y - 1..=player_y + 1)
+            .flat_map(|y| (player_x - 1..=player_x + 1).map(move |x| (x, y)))
+            .filter(|(x, y)| (*x, *y) != (player_x, player_y) && *x >= 0 && *x < width && *y >= 0 && *y < height)
+            .map(|(x, y)| map.xy_idx(x, y))
+            .collect();
+        for idx in around {
+            let tile = &mut map.tiles[idx];
+            let dc = match (tile.trap_kind, tile.trap_dc) {
+                (Some(_), Some(dc)) => dc,
+                _ => continue,
+            };
+            let progress = search.progress.entry(idx).or_insert(0);
+            *progress += gain;
+            if *progress >= dc {
+                tile.trap_dc = None;
+                search.progress.remove(&idx);
+                spotted += 1;
+            }
+        }
+    }
+    if spotted > 0 {
+        ecs.write_resource::<GameLog>().push("Looking around, you notice a trap nearby!".to_string());
+    }
+}
+
+/// Every turn the player gets a chance (d20 + Perception against the DC) to catch sight of each
+/// hidden trap in view. A glimpsed trap is drawn until the next turn, then it is rolled for again;
+/// only passive_search (or a fumbled disarm) reveals it for good.
+pub fn glimpse_traps(ecs: &mut World) {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let perception = ecs.read_storage::<Perception>().get(player).map_or(0, |p| p.bonus);
+    let map = ecs.fetch::<Map>();
+    let mut search = ecs.write_resource::<SearchProgress>();
+    let mut rng = ecs.write_resource::<GameRng>();
+    search.glimpsed.clear();
+    for (idx, tile) in map.tiles.iter().enumerate() {
+        let visible = map.visible_tiles.get(idx).copied().unwrap_or(false);
+        if visible && tile.trap_dc.is_some_and(|dc| rng.roll_dice(1, 20) + perception >= dc) {
+            search.glimpsed.insert(idx);
+        }
+    }
+}
+
+/// Tries to disarm a trap next to (or under) the player: d20 + Perception against the trap's DC.
+/// A disarmed trap becomes plain floor. On a failure the trap springs on the player, and then
+/// the commotion may reveal the traps around (1 in 3) or set off the whole linked group (1 in 6).
+/// Returns true if there was a trap to work on (and the turn is spent).
+pub fn disarm_adjacent_trap(ecs: &mut World) -> bool {
+    let player = ecs.fetch::<PlayerEntity>().0;
+    let (player_x, player_y) = match ecs.read_storage::<Position>().get(player) {
+        Some(pos) => (pos.x, pos.y),
+        None => return false,
+    };
+    let trap = {
+        let map = ecs.fetch::<Map>();
+        (player_y - 1..=player_y + 1)
+            .flat_map(|y| (player_x - 1..=player_x + 1).map(move |x| (x, y)))
+            .filter(|(x, y)| *x >= 0 && *x < map.width && *y >= 0 && *y < map.height)
+            .map(|(x, y)| map.xy_idx(x, y))
+            .find(|idx| map.tiles[*idx].trap_kind.is_some())
+    };
+    let idx = match trap {
+        Some(idx) => idx,
+        None => {
+            ecs.write_resource::<GameLog>().push("There is no trap within reach.".to_string());
+            return false;
+        }
+    };
+
+    let dc = ecs.fetch::<Map>().tiles[idx].trap_dc.unwrap_or(REVEALED_TRAP_DC);
+    let perception = ecs.read_storage::<Perception>().get(player).map_or(0, |p| p.bonus);
+    let roll = ecs.write_resource::<GameRng>().roll_dice(1, 20);
+    if roll + perception >= dc {
+        let floor = ecs.fetch::<CurrentLevel>().biome.biome().get_floor();
+        ecs.write_resource::<Map>().tiles[idx] = floor;
+        ecs.write_resource::<GameLog>().push("You disarm the trap.".to_string());
+        return true;
+    }
+
+    ecs.write_resource::<GameLog>().push("You fumble the disarm and the trap goes off!".to_string());
+    spring_trap(ecs, idx, Some(player));
+    let commotion = ecs.write_resource::<GameRng>().roll_dice(1, 6);
+    match commotion {
+        6 => {
+            let linked = ecs.fetch::<Map>().linked_traps(idx);
+            if !linked.is_empty() {
+                ecs.write_resource::<GameLog>().push(format!("Click, click, click... {} more traps go off around you!", linked.len()));
+                for other in linked {
+                    spring_trap(ecs, other, None);
+                }
+            }
+        }
+        4 | 5 => {
+            let revealed = reveal_traps_near(ecs, player_x, player_y, COMMOTION_RADIUS);
+            if revealed > 0 {
+                ecs.write_resource::<GameLog>().push(format!("In the commotion you notice {} more traps.", revealed));
+            }
+        }
+        _ => {}
+    }
+    true
+}
@@ -1,8 +1,18 @@
 use specs::prelude::*;
-use super::{Viewshed, Position, Player};
-use crate::map::{Map, xy_idx};
+use super::{Exploration, FovShape, LightSource, Name, Peeking, Viewshed, Position, PlayerEntity};
+use crate::gamelog::GameLog;
+use crate::map::Map;
+use crate::party::Party;
+use crate::monster_ai_system::clear_line;
 use rltk::{field_of_view, Point};
 
+/// Recomputes the dirty viewsheds. A peeking entity (see Peeking) also sees what can be seen
+/// from the tile it leans into, as long as it keeps peeking. What a LightSource lights up
+/// is revealed as if the party saw it, once a member has a line of sight to the light itself
+/// however far it is (see `light_in_view`): a lit room shows from its doorway.
+/// Whenever the party looks around again the Exploration percentage is brought up to date,
+/// and Map::visible_tiles is rebuilt: what is in sight now, while revealed_tiles remembers
+/// everything ever seen (draw_map greys out the difference).
 pub struct VisibilitySystem {}
 
 impl<'a> System<'a> for VisibilitySystem {
@@ -10,31 +20,257 @@ impl<'a> System<'a> for VisibilitySystem {
                         Entities<'a>,
                         WriteStorage<'a, Viewshed>,
                         WriteStorage<'a, Position>,
-                        ReadStorage<'a, Player>);
+                        ReadExpect<'a, PlayerEntity>,
+                        Option<Read<'a, Party>>,
+                        ReadStorage<'a, Peeking>,
+                        ReadStorage<'a, LightSource>,
+                        Option<Write<'a, Exploration>>);
 
     fn run(&mut self, data : Self::SystemData) {
-        let (mut map, entities, mut viewshed, pos, player) = data;
+        let (mut map, entities, mut viewshed, pos, player, party, peeking, lights, exploration) = data;
+        let in_party = |entity: Entity| entity == player.0
+            || party.as_ref().is_some_and(|party| party.members.contains(&entity));
 
-        for (ent,viewshed,pos) in (&entities, &mut viewshed, &pos).join() {
+        let mut party_looked = false;
+        for (ent,viewshed,pos,peek) in (&entities, &mut viewshed, &pos, peeking.maybe()).join() {
             // We only recalculate the field of view if the 'dirty' flag is set.
             // This is an optimization to avoid recalculating every frame.
             if viewshed.dirty {
                 viewshed.visible_tiles.clear();
-                viewshed.visible_tiles = field_of_view(Point::new(pos.x, pos.y), viewshed.range, &*map);
+                let range = match viewshed.fov_shape {
+                    FovShape::Circle => viewshed.range,
+                    FovShape::Ellipse { radius_x, radius_y } => radius_x.max(radius_y),
+                };
+                viewshed.visible_tiles = field_of_view(Point::new(pos.x, pos.y), range, &*map);
                 viewshed.visible_tiles.retain(|p| p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height );
 
+                // For an elliptical viewshed we compute the circle that contains it, then keep only
+                // the tiles inside the ellipse: scaling dx and dy by the two radii turns it into a unit circle.
+                if let FovShape::Ellipse { radius_x, radius_y } = viewshed.fov_shape {
+                    let (rx, ry) = (radius_x.max(1) as f32, radius_y.max(1) as f32);
+                    viewshed.visible_tiles.retain(|p| {
+                        let dx = (p.x - pos.x) as f32 / rx;
+                        let dy = (p.y - pos.y) as f32 / ry;
+                        dx * dx + dy * dy <= 1.0
+                    });
+                }
+
+                // leaning around a corner: add what is in sight from there
+                if let Some(peek) = peek {
+                    let extra = field_of_view(Point::new(peek.x, peek.y), range, &*map);
+                    for p in extra.into_iter().filter(|p| p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height) {
+                        if !viewshed.visible_tiles.contains(&p) {
+                            viewshed.visible_tiles.push(p);
+                        }
+                    }
+                }
+
                 // Once the viewshed is recalculated, we set the dirty flag to false.
                 viewshed.dirty = false;
 
-                // If this is the player, reveal what they can see
-                let p : Option<&Player> = player.get(ent);
-                if let Some(_p) = p {
+                // If this is the player (or one of their party), reveal what they can see
+                if in_party(ent) {
+                    party_looked = true;
+                    for vis in viewshed.visible_tiles.iter() {
+                        let idx = map.xy_idx(vis.x, vis.y);
+                        map.revealed_tiles[idx] = true;
+                    }
+                }
+            }
+        }
+
+        if party_looked {
+            // what is in sight right now: the party's viewsheds (fresh or not) and whatever the lights in view shine on
+            map.visible_tiles.iter_mut().for_each(|visible| *visible = false);
+            for (_entity, viewshed) in (&entities, &viewshed).join().filter(|(entity, _viewshed)| in_party(*entity)) {
+                for vis in viewshed.visible_tiles.iter() {
+                    let idx = map.xy_idx(vis.x, vis.y);
+                    map.visible_tiles[idx] = true;
+                }
+            }
+            let watchers : Vec<Point> = (&entities, &pos).join()
+                .filter(|(entity, _pos)| in_party(*entity))
+                .map(|(_entity, pos)| Point::new(pos.x, pos.y))
+                .collect();
+            for (_light, viewshed, light_pos) in (&lights, &viewshed, &pos).join() {
+                if light_in_view(&map, &watchers, Point::new(light_pos.x, light_pos.y)) {
                     for vis in viewshed.visible_tiles.iter() {
-                        let idx = xy_idx(vis.x, vis.y);
+                        let idx = map.xy_idx(vis.x, vis.y);
                         map.revealed_tiles[idx] = true;
+                        map.visible_tiles[idx] = true;
                     }
                 }
             }
+            if let Some(mut exploration) = exploration {
+                exploration.percent = map.explored_percent();
+            }
+        }
+    }
+}
+/// True if one of the `watchers` has a clear line of sight to the light at `light`:
+/// then whatever the light shines on is in sight too.
+pub fn light_in_view(map: &Map, watchers: &[Point], light: Point) -> bool {
+    watchers.iter().any(|watcher| *watcher == light || clear_line(map, *watcher, light))
+}
+
+/// Burns the lit light sources down by one turn; the spent ones go out and disappear.
+/// Lights without a duration (braziers) burn for good.
+pub fn burn_down_lights(ecs: &mut World) {
+    let spent : Vec<(Entity, String)> = {
+        let entities = ecs.entities();
+        let names = ecs.read_storage::<Name>();
+        let mut lights = ecs.write_storage::<LightSource>();
+        (&entities, &mut lights).join()
+            .filter_map(|(entity, light)| {
+                let turns_left = light.turns_left.as_mut()?;
+                *turns_left -= 1;
+                (*turns_left <= 0).then(|| (entity, names.get(entity).map_or("light".to_string(), |name| name.name.clone())))
+            })
+            .collect()
+    };
+    for (entity, name) in spent {
+        ecs.write_resource::<GameLog>().push(format!("The {} fizzles out.", name));
+        ecs.delete_entity(entity).expect("Unable to delete");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::register_components;
+    use crate::map::{Biome, Building, xy_idx};
+    use rltk::{BaseMap, Point};
+
+    /// A 3-row map, 80 wide: a corridor from x=1 to x=5 closed by walls,
+    /// and open floor again from x=7 on, behind the wall at x=6.
+    fn corridor_map() -> Map {
+        let biome = Building;
+        let (width, height) = (80, 3);
+        let mut map = Map::new(width, height, biome.get_floor());
+        for x in 0..=6 {
+            map.tiles[xy_idx(x, 0, width)] = biome.get_wall();
+            map.tiles[xy_idx(x, 2, width)] = biome.get_wall();
+        }
+        map.tiles[xy_idx(0, 1, width)] = biome.get_wall();
+        map.tiles[xy_idx(6, 1, width)] = biome.get_wall();
+        map
+    }
+
+    #[test]
+    fn a_light_is_seen_from_afar_but_not_through_walls() {
+        let map = corridor_map();
+        let watchers = [Point::new(1, 1)];
+        assert!(light_in_view(&map, &watchers, Point::new(5, 1)));
+        assert!(!light_in_view(&map, &watchers, Point::new(30, 1)));
+        assert!(light_in_view(&map, &[Point::new(8, 1)], Point::new(70, 1)));
+    }
+
+    #[test]
+    fn peeking_shows_the_corridor_around_the_corner() {
+        // a corridor going up along x=2 that turns right at y=1
+        let biome = Building;
+        let (width, height) = (80, 5);
+        let mut map = Map::new(width, height, biome.get_wall());
+        for y in 1..=3 {
+            map.tiles[xy_idx(2, y, width)] = biome.get_floor();
+        }
+        for x in 3..=8 {
+            map.tiles[xy_idx(x, 1, width)] = biome.get_floor();
+        }
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(map);
+        let player = ecs.create_entity()
+            .with(Position { x: 2, y: 2 })
+            .with(Viewshed { visible_tiles: Vec::new(), range: 8, dirty: true, fov_shape: FovShape::Circle })
+            .build();
+        ecs.insert(PlayerEntity(player));
+
+        VisibilitySystem {}.run_now(&ecs);
+        assert!(!ecs.read_storage::<Viewshed>().get(player).unwrap().visible_tiles.contains(&Point::new(8, 1)));
+
+        ecs.write_storage::<Peeking>().insert(player, Peeking { x: 2, y: 1 }).unwrap();
+        ecs.write_storage::<Viewshed>().get_mut(player).unwrap().dirty = true;
+        VisibilitySystem {}.run_now(&ecs);
+        assert!(ecs.read_storage::<Viewshed>().get(player).unwrap().visible_tiles.contains(&Point::new(8, 1)));
+        {
+            let map = ecs.fetch::<Map>();
+            assert!(map.revealed_tiles[map.xy_idx(8, 1)]);
+            assert!(map.visible_tiles[map.xy_idx(8, 1)]);
+        }
+
+        // back from the corner: the far end is remembered, no longer in sight
+        ecs.write_storage::<Peeking>().remove(player);
+        ecs.write_storage::<Viewshed>().get_mut(player).unwrap().dirty = true;
+        VisibilitySystem {}.run_now(&ecs);
+        let map = ecs.fetch::<Map>();
+        assert!(map.revealed_tiles[map.xy_idx(8, 1)]);
+        assert!(!map.visible_tiles[map.xy_idx(8, 1)]);
+        assert!(map.visible_tiles[map.xy_idx(2, 3)]);
+    }
+
+    #[test]
+    fn sees_through_glass_but_cannot_walk_through_it() {
+        let mut map = corridor_map();
+        let glass = Building.get_glass().unwrap();
+        let idx = map.xy_idx(6, 1);
+        map.tiles[idx] = glass;
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(map);
+        let player = ecs.create_entity()
+            .with(Position { x: 5, y: 1 })
+            .with(Viewshed { visible_tiles: Vec::new(), range: 8, dirty: true, fov_shape: FovShape::Circle })
+            .build();
+        ecs.insert(PlayerEntity(player));
+
+        VisibilitySystem {}.run_now(&ecs);
+
+        let viewsheds = ecs.read_storage::<Viewshed>();
+        let viewshed = viewsheds.get(player).unwrap();
+        // the pane and the floor behind it are in sight...
+        assert!(viewshed.visible_tiles.contains(&Point::new(6, 1)));
+        assert!(viewshed.visible_tiles.contains(&Point::new(8, 1)), "glass blocked sight: {:?}", viewshed.visible_tiles);
+        // ...but there's no way through it
+        let map = ecs.fetch::<Map>();
+        let exits = map.get_available_exits(map.xy_idx(5, 1));
+        assert!(exits.iter().all(|(idx, _cost)| *idx != map.xy_idx(6, 1)));
+    }
+
+    #[test]
+    fn reveals_the_corridor_but_not_what_is_behind_the_wall() {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+        ecs.insert(corridor_map());
+        let player = ecs.create_entity()
+            .with(Position { x: 1, y: 1 })
+            .with(Viewshed { visible_tiles: Vec::new(), range: 8, dirty: true, fov_shape: FovShape::Circle })
+            .build();
+        ecs.insert(PlayerEntity(player));
+
+        VisibilitySystem {}.run_now(&ecs);
+
+        let viewsheds = ecs.read_storage::<Viewshed>();
+        let viewshed = viewsheds.get(player).unwrap();
+        assert!(!viewshed.dirty);
+
+        // every corridor tile and the wall closing it are in sight
+        let mut expected : Vec<Point> = (1..=5).map(|x| Point::new(x, 1)).collect();
+        expected.push(Point::new(6, 1));
+        for point in expected.iter() {
+            assert!(viewshed.visible_tiles.contains(point), "{:?} should be visible", point);
+        }
+        // nothing past the wall, even though those tiles are in range
+        assert!(viewshed.visible_tiles.iter().all(|p| p.x <= 6), "saw through the wall: {:?}", viewshed.visible_tiles);
+
+        // the revealed tiles are exactly the visible ones
+        let map = ecs.fetch::<Map>();
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let visible = viewshed.visible_tiles.contains(&Point::new(x, y));
+                assert_eq!(map.revealed_tiles[map.xy_idx(x, y)], visible, "revealed mismatch at ({}, {})", x, y);
+                assert_eq!(map.visible_tiles[map.xy_idx(x, y)], visible, "visible mismatch at ({}, {})", x, y);
+            }
         }
     }
-}
\ No newline at end of file
+}